@@ -0,0 +1,11 @@
+# Freeze piper's HTTP server into piper-server.exe, so users don't need python
+# Run from this directory, the result is written to dist\piper-server.exe
+$ErrorActionPreference = "Stop"
+
+py -3.11 -m venv build-env
+.\build-env\Scripts\python.exe -m pip install --upgrade pip piper-tts flask pyinstaller
+
+# PyInstaller needs a script to start from
+Set-Content -Path piper_server.py -Value "from piper.http_server import main`nmain()"
+.\build-env\Scripts\python.exe -m PyInstaller --onefile --name piper-server `
+    --collect-all piper --collect-all onnxruntime piper_server.py
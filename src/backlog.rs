@@ -0,0 +1,130 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BacklogConfig {
+    #[serde(default = "default_directory")]
+    pub directory: String, // Where recordings wait while whisper is unavailable
+    #[serde(default = "default_retry")]
+    pub retry: u64, // Seconds between attempts to load whisper again
+}
+
+fn default_directory() -> String {
+    "backlog".to_owned()
+}
+
+fn default_retry() -> u64 {
+    30
+}
+
+// A recording waiting to be transcribed
+pub struct Saved {
+    pub path: PathBuf,
+    pub timestamp: u64, // Milliseconds since the unix epoch when it was recorded
+}
+
+// Recordings kept on disk while whisper can't transcribe them, so nothing said is lost
+pub struct Backlog {
+    directory: PathBuf,
+    pipeline: String,
+    waiting: VecDeque<Saved>, // Oldest first
+}
+
+impl Backlog {
+    // Pick up recordings left over from earlier sessions too
+    pub fn new(config: &BacklogConfig, pipeline: &str) -> Result<Self, std::io::Error> {
+        let directory = PathBuf::from(&config.directory);
+        std::fs::create_dir_all(&directory)?;
+
+        let suffix = format!("_{}.wav", pipeline);
+        let mut waiting = std::fs::read_dir(&directory)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let timestamp = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_suffix(&suffix)?
+                    .parse()
+                    .ok()?;
+                Some(Saved { path, timestamp })
+            })
+            .collect::<Vec<_>>();
+        waiting.sort_by_key(|saved| saved.timestamp);
+        if !waiting.is_empty() {
+            info!(
+                "{} recordings from earlier are waiting to be transcribed",
+                waiting.len()
+            );
+        }
+
+        Ok(Self {
+            directory,
+            pipeline: pipeline.to_owned(),
+            waiting: waiting.into(),
+        })
+    }
+
+    // Write a recording to disk, named by when it was recorded
+    pub fn save(&mut self, samples: &[f32], sample_rate: usize) -> Result<(), hound::Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+        let path = self
+            .directory
+            .join(format!("{}_{}.wav", timestamp, self.pipeline));
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec)?;
+        for sample in samples {
+            writer.write_sample(*sample)?;
+        }
+        writer.finalize()?;
+
+        self.waiting.push_back(Saved { path, timestamp });
+        info!(
+            "Saved recording for when whisper is back, {} waiting",
+            self.waiting.len()
+        );
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    // Take the oldest recording
+    pub fn pop(&mut self) -> Option<Saved> {
+        self.waiting.pop_front()
+    }
+
+    // Put a recording back to be tried again first
+    pub fn push_front(&mut self, saved: Saved) {
+        self.waiting.push_front(saved);
+    }
+
+    // Samples of a recording, with their rate
+    pub fn load(saved: &Saved) -> Result<(Vec<f32>, usize), hound::Error> {
+        let mut reader = hound::WavReader::open(&saved.path)?;
+        let sample_rate = reader.spec().sample_rate as usize;
+        let samples = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
+
+        Ok((samples, sample_rate))
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::data_dir;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Live speech translation for JACK")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Log to the terminal instead of showing the interactive interface
+    #[arg(long)]
+    pub no_tui: bool,
+    /// Run headless as a service, logging to stderr and notifying systemd once started
+    #[arg(long, conflicts_with = "kiosk")]
+    pub daemon: bool,
+    /// Keep the profile running unattended, restarting it whenever it fails
+    #[arg(long)]
+    pub kiosk: bool,
+    /// File the kiosk writes the health of the instance to, for an external indicator
+    #[arg(long, requires = "kiosk")]
+    pub health_file: Option<PathBuf>,
+    /// GPIO pin, by its sysfs number, the kiosk drives high while the instance is healthy
+    #[arg(long, requires = "kiosk")]
+    pub gpio_pin: Option<u32>,
+    /// Feed synthetic speech through the pipeline on a mock audio client, checking it's transcribed and spoken
+    #[arg(long, conflicts_with_all = ["kiosk", "daemon"])]
+    pub selftest: bool,
+    /// Install piper's python packages again, even if they're already installed
+    #[arg(long, conflicts_with = "offline")]
+    pub force_reinstall: bool,
+    /// Never download models or install packages, and check everything needed is there first
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Config file to use, by default config.toml in the config, working or data directory
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Directory for models, voices and the python environment, by default a per-user directory
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+    /// Minimum level of log messages to show: off, error, warn, info, debug or trace
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: log::LevelFilter,
+    /// Name of the instance, only one instance can run per profile
+    #[arg(long, global = true, default_value = "default")]
+    pub profile: String,
+}
+
+impl Cli {
+    // Config from the command line, else found in the usual places
+    pub fn config_path(&self) -> PathBuf {
+        data_dir::config_path(self.config.as_deref())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Transcribe a single WAV file, print the result to stdout and exit
+    Transcribe {
+        /// WAV file to transcribe
+        file: PathBuf,
+        /// Have whisper translate the speech into english
+        #[arg(long)]
+        translate: bool,
+        /// Translate the transcript into this language using the configured translation engine
+        #[arg(long)]
+        to: Option<String>,
+        /// Speak the result with piper and write the speech to this WAV file
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Stop the instance running with this profile
+    Stop,
+    /// Have the instance running with this profile read its config file again
+    Reload,
+    /// Print the status of the instance running with this profile as JSON
+    Status,
+    /// Check the audio server, GPU, whisper and piper work with the config, without starting
+    Doctor,
+    /// Reconnect the jack ports a crashed run left disconnected, which the next start also does
+    RestoreConnections,
+    /// List the PulseAudio sources and sinks audio.pulse can name
+    #[cfg(feature = "pulse")]
+    Devices,
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage whisper models
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Manage piper voices, kept in piper.voice_dir if the config sets it
+    Voices {
+        #[command(subcommand)]
+        command: VoicesCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Interactively create a config file from the available ports, models and voices
+    Init,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ModelsCommand {
+    /// List the models which can be downloaded, including quantized variants
+    List,
+    /// Download a model, carrying on from an earlier partial download
+    Download {
+        /// Model name, e.g. large-v3-turbo-q5_0
+        model: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum VoicesCommand {
+    /// List the voices in the piper catalog
+    List {
+        /// Only show voices for a language, e.g. de or de_DE
+        #[arg(long)]
+        language: Option<String>,
+    },
+    /// Download a voice and its config
+    Download {
+        /// Voice name, e.g. de_DE-thorsten-medium
+        voice: String,
+    },
+}
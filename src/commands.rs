@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+use crate::util::normalize_word;
+
+// A phrase which changes the pipeline when said, instead of being translated
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct CommandConfig {
+    pub phrase: String, // e.g. "switch to japanese", the whole utterance in any case or punctuation
+    pub target: Option<String>, // Translation target language to switch to
+    pub voice: Option<String>, // Piper voice to switch to
+}
+
+// What a spoken command changes
+pub enum Action {
+    SetTarget(String),
+    SetVoice(String),
+}
+
+// Normalized words of a phrase
+fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(normalize_word)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// Recognises commands in what was said
+pub struct Commands {
+    commands: Vec<(Vec<String>, CommandConfig)>,
+}
+
+impl Commands {
+    pub fn new(configs: &[CommandConfig]) -> Self {
+        Self {
+            commands: configs
+                .iter()
+                .map(|config| (words(&config.phrase), config.clone()))
+                .filter(|(phrase, _)| !phrase.is_empty())
+                .collect(),
+        }
+    }
+
+    // Actions of the command an utterance is, None if it isn't one
+    pub fn actions(&self, text: &str) -> Option<Vec<Action>> {
+        let said = words(text);
+        let (_, command) = self.commands.iter().find(|(phrase, _)| *phrase == said)?;
+
+        Some(
+            command
+                .target
+                .iter()
+                .map(|target| Action::SetTarget(target.clone()))
+                .chain(
+                    command
+                        .voice
+                        .iter()
+                        .map(|voice| Action::SetVoice(voice.clone())),
+                )
+                .collect(),
+        )
+    }
+}
@@ -5,12 +5,33 @@ use serde::Deserialize;
 
 use crate::sound::AudioClientType;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct GeneralConfig {
     pub push_to_talk: bool,
     #[serde(deserialize_with = "deserialize_keycode")]
     pub ptt_key: Keycode,
     pub audio_client: AudioClientType,
+    #[serde(default)]
+    pub sync_captions_to_tts: bool, // Hold captions back until their speech starts playing
+    #[serde(default = "default_history")]
+    pub history: usize, // Utterances kept for clients of the control socket which connect late
+    #[serde(default)]
+    pub error_cues: bool, // Beep on the monitor ports when an utterance is dropped
+    pub metrics: Option<String>, // Address to serve prometheus metrics on, e.g. "127.0.0.1:9184"
+    pub control: Option<String>, // Address to serve the HTTP control API on, e.g. "127.0.0.1:9185"
+    pub control_token: Option<String>, // Bearer token the control API's commands have to carry
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: f32, // Seconds given to finish transcribing and speaking when exiting
+    #[serde(default)]
+    pub offline: bool, // Never download or install anything, checking everything is there up front
+}
+
+fn default_history() -> usize {
+    100
+}
+
+fn default_shutdown_timeout() -> f32 {
+    10.0
 }
 
 fn deserialize_keycode<'de, D>(deserializer: D) -> Result<Keycode, D::Error>
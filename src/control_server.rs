@@ -0,0 +1,461 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use serde_json::{Value, json};
+
+use crate::{
+    controls::Controls,
+    piper,
+    rundir::{self, DEFAULT_HISTORY_LIMIT, PipelineCommand},
+    status::Status,
+    util::lock,
+};
+
+// How often the listener is checked for connections
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Longest an event stream stays quiet, so clients which went away are noticed
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+// Largest request body read, commands only need a few parameters
+const MAX_BODY: usize = 64 * 1024;
+
+// Longest a client may take to send its request or to take the response
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Why a call couldn't be made
+#[derive(Debug)]
+enum ErrCall {
+    UnknownMethod(String),
+    InvalidParams(String),
+    UnknownPipeline(String),
+    NotRunning,
+}
+
+impl Display for ErrCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMethod(method) => write!(f, "Unknown method {}", method),
+            Self::InvalidParams(message) => write!(f, "{}", message),
+            Self::UnknownPipeline(name) => write!(f, "Unknown pipeline {}", name),
+            Self::NotRunning => write!(f, "Not running"),
+        }
+    }
+}
+
+impl ErrCall {
+    // JSON-RPC error code
+    fn code(&self) -> i64 {
+        match self {
+            Self::UnknownMethod(_) => -32601,
+            Self::InvalidParams(_) | Self::UnknownPipeline(_) => -32602,
+            Self::NotRunning => -32603,
+        }
+    }
+
+    fn http_status(&self) -> &'static str {
+        match self {
+            Self::UnknownMethod(_) => "404 Not Found",
+            Self::InvalidParams(_) | Self::UnknownPipeline(_) => "400 Bad Request",
+            Self::NotRunning => "503 Service Unavailable",
+        }
+    }
+}
+
+// State the server answers from and the channel to the pipelines, which the main thread owns
+struct Server {
+    controls: Arc<Controls>,
+    running: Arc<AtomicBool>,
+    statuses: Vec<(String, Arc<Status>)>,
+    status: Arc<Mutex<Value>>, // Rendered by the main thread along with the status file
+    voice_dir: PathBuf,        // Voices have to be downloaded here to switch to them
+    token: Option<String>,     // Bearer token commands have to carry, if one is set
+    command_tx: Sender<PipelineCommand>,
+}
+
+// Headers a request is checked by before it can change anything
+#[derive(Default)]
+struct Headers {
+    length: usize,
+    json: bool,   // Content-Type is application/json
+    origin: bool, // Sent by a browser, e.g. a page posting to localhost
+    authorization: Option<String>,
+}
+
+impl Server {
+    // Pipeline named in the params, None for all of them
+    fn pipeline(&self, params: &Value) -> Result<Option<String>, ErrCall> {
+        match params.get("pipeline").and_then(Value::as_str) {
+            None | Some("all") => Ok(None),
+            Some(name) if self.statuses.iter().any(|(pipeline, _)| pipeline == name) => {
+                Ok(Some(name.to_owned()))
+            }
+            Some(name) => Err(ErrCall::UnknownPipeline(name.to_owned())),
+        }
+    }
+
+    fn send(&self, command: PipelineCommand) -> Result<Value, ErrCall> {
+        self.command_tx
+            .send(command)
+            .map(|()| json!("ok"))
+            .map_err(|_| ErrCall::NotRunning)
+    }
+
+    // Run a method, the same for REST endpoints and JSON-RPC
+    fn call(&self, method: &str, params: &Value) -> Result<Value, ErrCall> {
+        let string = |key: &str| {
+            params
+                .get(key)
+                .and_then(Value::as_str)
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.trim().to_owned())
+                .ok_or_else(|| ErrCall::InvalidParams(format!("Missing {}", key)))
+        };
+
+        match method {
+            "status" => Ok(lock(&self.status).clone()),
+            "history" => {
+                let limit = params
+                    .get("limit")
+                    .and_then(|limit| match limit {
+                        Value::String(limit) => limit.parse().ok(),
+                        limit => limit.as_u64().map(|limit| limit as usize),
+                    })
+                    .unwrap_or(DEFAULT_HISTORY_LIMIT);
+                Ok(json!(rundir::history(&self.statuses, limit)))
+            }
+            "pause" => self.send(PipelineCommand::Pause(self.pipeline(params)?)),
+            "resume" => self.send(PipelineCommand::Resume(self.pipeline(params)?)),
+            "mute" => Ok(json!({ "muted": self.controls.toggle_mute() })),
+            "translate" => Ok(json!({ "translating": self.controls.toggle_translation() })),
+            "discard" => {
+                self.controls.request_discard();
+                Ok(json!("ok"))
+            }
+            "finish" => {
+                self.controls.request_finish();
+                Ok(json!("ok"))
+            }
+            "replay" => {
+                self.controls.request_replay();
+                Ok(json!("ok"))
+            }
+            "output_mute" => Ok(json!({ "output_muted": self.controls.toggle_output_mute() })),
+            "gain" => {
+                let gain = |key: &str| match params.get(key) {
+                    None => Ok(None),
+                    Some(db) => db
+                        .as_f64()
+                        .or_else(|| db.as_str().and_then(|db| db.parse().ok()))
+                        .map(|db| Some(db as f32))
+                        .ok_or_else(|| {
+                            ErrCall::InvalidParams(format!("{} isn't a number of dB", key))
+                        }),
+                };
+                if let Some(db) = gain("input")? {
+                    self.controls.set_input_gain(db);
+                }
+                if let Some(db) = gain("output")? {
+                    self.controls.set_output_gain(db);
+                }
+                Ok(json!({
+                    "input": self.controls.input_gain(),
+                    "output": self.controls.output_gain(),
+                }))
+            }
+            "language" => {
+                let language = string("language")?;
+                info!(
+                    "Translating into {} as asked through the control API",
+                    language
+                );
+                self.send(PipelineCommand::SetTarget(self.pipeline(params)?, language))
+            }
+            "voice" => {
+                let voice = string("voice")?;
+                if !piper::voice_downloaded(&self.voice_dir, &voice) {
+                    return Err(ErrCall::InvalidParams(format!(
+                        "Voice {} isn't downloaded",
+                        voice
+                    )));
+                }
+                self.send(PipelineCommand::SetVoice(self.pipeline(params)?, voice))
+            }
+            "shutdown" => {
+                info!("Stop requested through control API");
+                self.running.store(false, Ordering::SeqCst);
+                Ok(json!("ok"))
+            }
+            method => Err(ErrCall::UnknownMethod(method.to_owned())),
+        }
+    }
+
+    // Answer a JSON-RPC 2.0 request, or a batch of them
+    fn rpc(&self, request: &Value) -> Value {
+        if let Value::Array(requests) = request {
+            return Value::Array(requests.iter().map(|request| self.rpc(request)).collect());
+        }
+
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+
+        match self.call(method, &params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(err) => json!({
+                "jsonrpc": "2.0",
+                "error": { "code": err.code(), "message": err.to_string() },
+                "id": id,
+            }),
+        }
+    }
+
+    // Why a request which changes state is refused, None if it may go ahead
+    // A web page can post to localhost without a preflight, but only without a JSON content type,
+    // and its browser always adds an Origin
+    fn refuse(&self, headers: &Headers) -> Option<(&'static str, Value)> {
+        if headers.origin {
+            return Some((
+                "403 Forbidden",
+                json!({ "error": "requests from web pages aren't allowed" }),
+            ));
+        }
+        if !headers.json {
+            return Some((
+                "415 Unsupported Media Type",
+                json!({ "error": "Content-Type has to be application/json" }),
+            ));
+        }
+        if let Some(token) = &self.token
+            && headers.authorization.as_deref() != Some(format!("Bearer {}", token).as_str())
+        {
+            return Some((
+                "401 Unauthorized",
+                json!({ "error": "missing or wrong token" }),
+            ));
+        }
+
+        None
+    }
+
+    // Answer one HTTP request
+    fn handle(&self, stream: TcpStream) -> Result<(), std::io::Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut parts = request.split_whitespace();
+        let method = parts.next().unwrap_or("GET").to_owned();
+        let target = parts.next().unwrap_or("/").to_owned();
+
+        let mut headers = Headers::default();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => headers.length = value.parse().unwrap_or(0),
+                "content-type" => {
+                    let media_type = value.split(';').next().unwrap_or("").trim();
+                    headers.json = media_type.eq_ignore_ascii_case("application/json");
+                }
+                "origin" => headers.origin = true,
+                "authorization" => headers.authorization = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; headers.length.min(MAX_BODY)];
+        reader.read_exact(&mut body)?;
+
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        if method == "GET" && path == "/events" {
+            return self.stream_events(stream);
+        }
+
+        // Reading is open to anything, overlays included, changing state isn't
+        if method == "POST"
+            && let Some((status, body)) = self.refuse(&headers)
+        {
+            return respond(&stream, status, &body);
+        }
+
+        let (status, body) = match (method.as_str(), path) {
+            ("POST", "/rpc") => match serde_json::from_slice(&body) {
+                Ok(request) => ("200 OK", self.rpc(&request)),
+                Err(err) => (
+                    "200 OK",
+                    json!({
+                        "jsonrpc": "2.0",
+                        "error": { "code": -32700, "message": err.to_string() },
+                        "id": null,
+                    }),
+                ),
+            },
+            (method @ ("GET" | "POST"), path) => {
+                // Params of a GET come from the query, a POST only takes them from its JSON body
+                let params = match method {
+                    "GET" => Ok(query
+                        .split('&')
+                        .filter_map(|param| param.split_once('='))
+                        .map(|(key, value)| (key.to_owned(), json!(value)))
+                        .collect()),
+                    _ if body.is_empty() => Ok(serde_json::Map::new()),
+                    _ => match serde_json::from_slice(&body) {
+                        Ok(Value::Object(params)) => Ok(params),
+                        _ => Err(ErrCall::InvalidParams(
+                            "Body isn't a JSON object".to_owned(),
+                        )),
+                    },
+                };
+
+                // Reading is safe to do with GET, everything else changes state
+                let name = path.trim_start_matches('/');
+                let result = params.and_then(|params| match (method, name) {
+                    ("GET", "status" | "history") | ("POST", _) => {
+                        self.call(name, &Value::Object(params))
+                    }
+                    _ => Err(ErrCall::UnknownMethod(format!("{} {}", method, path))),
+                });
+                match result {
+                    Ok(result) => ("200 OK", result),
+                    Err(err) => (err.http_status(), json!({ "error": err.to_string() })),
+                }
+            }
+            _ => (
+                "405 Method Not Allowed",
+                json!({ "error": "method not allowed" }),
+            ),
+        };
+
+        respond(&stream, status, &body)
+    }
+
+    // Stream the events of every pipeline as server-sent events,
+    // until the client goes away or the server stops
+    fn stream_events(&self, stream: TcpStream) -> Result<(), std::io::Error> {
+        let receivers = self
+            .statuses
+            .iter()
+            .map(|(name, status)| (name.clone(), status.events.subscribe()))
+            .collect::<Vec<_>>();
+
+        write!(
+            &stream,
+            "HTTP/1.0 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        )?;
+
+        // A client which went away or stopped reading ends the stream
+        let mut last_write = Instant::now();
+        while self.running.load(Ordering::SeqCst) {
+            for (pipeline, events) in &receivers {
+                for event in events.try_iter() {
+                    let Ok(mut data) = serde_json::to_value(&event) else {
+                        continue;
+                    };
+                    data["pipeline"] = json!(pipeline);
+                    if write!(&stream, "data: {}\n\n", data).is_err() {
+                        return Ok(());
+                    }
+                    last_write = Instant::now();
+                }
+            }
+
+            if last_write.elapsed() >= KEEPALIVE_INTERVAL {
+                if write!(&stream, ": keepalive\n\n").is_err() {
+                    return Ok(());
+                }
+                last_write = Instant::now();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+// Write a JSON response, the connection is closed after it
+fn respond(mut stream: &TcpStream, status: &str, body: &Value) -> Result<(), std::io::Error> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// Serve the control API over HTTP until told to stop, for stream decks and scripts
+// Commands for the pipelines are sent back to the main thread, as it owns them
+pub fn serve(
+    address: &str,
+    controls: Arc<Controls>,
+    running: Arc<AtomicBool>,
+    statuses: Vec<(String, Arc<Status>)>,
+    status: Arc<Mutex<Value>>,
+    voice_dir: PathBuf,
+    token: Option<String>,
+) -> Result<(Receiver<PipelineCommand>, JoinHandle<()>), std::io::Error> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    let (command_tx, command_rx) = channel();
+
+    let server = Arc::new(Server {
+        controls,
+        running,
+        statuses,
+        status,
+        voice_dir,
+        token,
+        command_tx,
+    });
+
+    // Each connection is answered on its own thread, so a slow client doesn't hold up the others
+    let thread = thread::Builder::new()
+        .name("control_server".to_owned())
+        .spawn(move || {
+            while server.running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let server = server.clone();
+                        if let Err(err) = thread::Builder::new()
+                            .name("control_request".to_owned())
+                            .spawn(move || {
+                                if let Err(err) = server.handle(stream) {
+                                    error!("Could not answer control request!\n{}", err);
+                                }
+                            })
+                        {
+                            error!("Could not start control request thread!\n{}", err);
+                        }
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(err) => error!("Could not accept control connection!\n{}", err),
+                }
+            }
+        })?;
+
+    Ok((command_rx, thread))
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+};
+
+use crate::{util::lock, whisper::Preset};
+
+// Range gains can be set in, in dB
+const MIN_GAIN: f32 = -60.0;
+const MAX_GAIN: f32 = 20.0;
+
+// Runtime controls shared between the hotkey, processing and audio threads
+#[derive(Debug, Default)]
+pub struct Controls {
+    muted: AtomicBool,                     // Input is ignored while muted
+    paused: AtomicBool,                    // Output is held while paused
+    untranslated: AtomicBool,              // Translation engine is skipped while set
+    preset: Mutex<Option<Preset>>, // Decoding preset chosen at runtime, overriding the config
+    tags: Mutex<BTreeMap<String, String>>, // Attached to utterances from now on, e.g. the current slide
+    calibrations: AtomicU64,               // Times the noise floor was asked to be measured again
+    discards: AtomicU64,                   // Times the current recording was asked to be dropped
+    finishes: AtomicU64,                   // Times the current recording was asked to end now
+    replays: AtomicU64,                    // Times the last speech was asked to be played again
+    reloads: AtomicU64,                    // Times the config was asked to be read again
+    input_gain: AtomicU32,                 // In dB, stored as f32 bits
+    output_gain: AtomicU32,                // In dB applied to the voice, stored as f32 bits
+    output_muted: AtomicBool,              // Voice plays silently while muted
+}
+
+impl Controls {
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn translating(&self) -> bool {
+        !self.untranslated.load(Ordering::Relaxed)
+    }
+
+    pub fn preset(&self) -> Option<Preset> {
+        *lock(&self.preset)
+    }
+
+    pub fn set_preset(&self, preset: Option<Preset>) {
+        *lock(&self.preset) = preset;
+    }
+
+    pub fn tags(&self) -> BTreeMap<String, String> {
+        lock(&self.tags).clone()
+    }
+
+    // Set a tag, or remove it if value is None
+    pub fn set_tag(&self, key: &str, value: Option<&str>) {
+        let mut tags = lock(&self.tags);
+        match value {
+            Some(value) => tags.insert(key.to_owned(), value.to_owned()),
+            None => tags.remove(key),
+        };
+    }
+
+    pub fn clear_tags(&self) {
+        lock(&self.tags).clear();
+    }
+
+    // Every pipeline compares this to the count it last saw, so none of them miss a request
+    pub fn calibrations(&self) -> u64 {
+        self.calibrations.load(Ordering::Relaxed)
+    }
+
+    pub fn request_calibration(&self) {
+        self.calibrations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn discards(&self) -> u64 {
+        self.discards.load(Ordering::Relaxed)
+    }
+
+    pub fn request_discard(&self) {
+        self.discards.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finishes(&self) -> u64 {
+        self.finishes.load(Ordering::Relaxed)
+    }
+
+    pub fn request_finish(&self) {
+        self.finishes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn replays(&self) -> u64 {
+        self.replays.load(Ordering::Relaxed)
+    }
+
+    pub fn request_replay(&self) {
+        self.replays.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    pub fn request_reload(&self) {
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn input_gain(&self) -> f32 {
+        f32::from_bits(self.input_gain.load(Ordering::Relaxed))
+    }
+
+    pub fn set_input_gain(&self, db: f32) {
+        let db = db.clamp(MIN_GAIN, MAX_GAIN);
+        self.input_gain.store(db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn output_gain(&self) -> f32 {
+        f32::from_bits(self.output_gain.load(Ordering::Relaxed))
+    }
+
+    pub fn set_output_gain(&self, db: f32) {
+        let db = db.clamp(MIN_GAIN, MAX_GAIN);
+        self.output_gain.store(db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn output_muted(&self) -> bool {
+        self.output_muted.load(Ordering::Relaxed)
+    }
+
+    // Gain the voice is played with, silent while muted
+    pub fn voice_gain(&self) -> f32 {
+        if self.output_muted() {
+            f32::NEG_INFINITY
+        } else {
+            self.output_gain()
+        }
+    }
+
+    // Flip mute state of the voice, returning the new state
+    pub fn toggle_output_mute(&self) -> bool {
+        !self.output_muted.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    // Flip mute state, returning the new state
+    pub fn toggle_mute(&self) -> bool {
+        !self.muted.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    // Flip pause state, returning the new state
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    // Turn translation on or off, returning whether it is now on
+    pub fn toggle_translation(&self) -> bool {
+        self.untranslated.fetch_xor(true, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    os::unix::net::UnixDatagram,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error};
+
+// Tell systemd about the state of the service, when it was started with Type=notify
+// Does nothing when not running under systemd
+fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        // Names starting with @ are in the abstract namespace
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &address);
+        }
+        socket.send_to(state.as_bytes(), &path)
+    });
+    match result {
+        Ok(_) => debug!("Notified systemd: {}", state),
+        Err(err) => error!("Could not notify systemd!\n{}", err),
+    }
+}
+
+// Running headless as a service, reporting to systemd
+pub struct Daemon {
+    watchdog: Option<Duration>, // How often systemd expects to hear from us
+    last_ping: Instant,
+}
+
+impl Daemon {
+    pub fn from_env() -> Self {
+        // Pinging twice per interval, so a slow loop doesn't get us killed
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .map(|usec: u64| Duration::from_micros(usec) / 2);
+
+        Self {
+            watchdog,
+            last_ping: Instant::now(),
+        }
+    }
+
+    // Every pipeline is running
+    pub fn ready(&self) {
+        notify(&format!("READY=1\nMAINPID={}", std::process::id()));
+    }
+
+    // Still alive, called from the main loop
+    pub fn ping(&mut self) {
+        if let Some(watchdog) = self.watchdog
+            && self.last_ping.elapsed() >= watchdog
+        {
+            notify("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+
+    // Followed by ready once the new config is applied
+    pub fn reloading(&self) {
+        notify("RELOADING=1");
+    }
+
+    pub fn stopping(&self) {
+        notify("STOPPING=1");
+    }
+}
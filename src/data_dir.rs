@@ -0,0 +1,95 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use log::{error, info};
+
+// Directory holding models, voices and the python environment
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Per-user directory, which can be written to without admin rights
+// Linux follows the XDG base directories, falling back to their default under $HOME
+fn user_dir(windows_var: &str, xdg_var: &str, xdg_default: &str) -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os(windows_var).map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else {
+        std::env::var_os(xdg_var)
+            .map(PathBuf::from)
+            .filter(|base| base.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(xdg_default)))
+    };
+
+    base.map(|base| base.join("live-translate-rs"))
+}
+
+// Per-user directory for the config, $XDG_CONFIG_HOME/live-translate-rs on linux
+pub fn config_dir() -> Option<PathBuf> {
+    user_dir("APPDATA", "XDG_CONFIG_HOME", ".config")
+}
+
+// Config file to use, searched for when not given on the command line
+// The config directory comes first, then the working directory, then the data directory
+// of older installs, a new config goes in the config directory
+pub fn config_path(explicit: Option<&Path>) -> PathBuf {
+    if let Some(explicit) = explicit {
+        return explicit.to_owned();
+    }
+
+    let in_config_dir = config_dir().map(|dir| dir.join("config.toml"));
+    [
+        in_config_dir.clone(),
+        Some(PathBuf::from("config.toml")),
+        Some(path("config.toml")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.exists())
+    .or(in_config_dir)
+    .unwrap_or_else(|| path("config.toml"))
+}
+
+// Choose the data directory, called once at startup
+// The working directory is kept if it already has models from an older install
+pub fn init(explicit: Option<PathBuf>) {
+    let path = match explicit {
+        Some(path) => path,
+        None if Path::new("whisper").is_dir() || Path::new("env").is_dir() => PathBuf::from("."),
+        None => user_dir("LOCALAPPDATA", "XDG_DATA_HOME", ".local/share")
+            .unwrap_or_else(|| PathBuf::from(".")),
+    };
+
+    // Fall back to the working directory so a bad path doesn't stop startup
+    let path = match std::fs::create_dir_all(&path) {
+        Ok(()) => path,
+        Err(err) => {
+            error!(
+                "Could not create data directory {}, using the working directory!\n{}",
+                path.display(),
+                err
+            );
+            PathBuf::from(".")
+        }
+    };
+    if path != Path::new(".") {
+        info!("Keeping models and voices in {}", path.display());
+    }
+
+    let _ = DATA_DIR.set(path);
+}
+
+// The data directory, the working directory if init wasn't called
+pub fn get() -> &'static Path {
+    DATA_DIR.get_or_init(|| PathBuf::from("."))
+}
+
+// Path of a file inside the data directory
+pub fn path(relative: impl AsRef<Path>) -> PathBuf {
+    get().join(relative)
+}
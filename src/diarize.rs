@@ -0,0 +1,142 @@
+use serde::Deserialize;
+
+use crate::dsp::filter::Biquad;
+
+// Frames the voice print is measured over, 32ms
+const FRAME_SECONDS: f32 = 0.032;
+
+// Edges of the frequency bands compared between voices, in Hz
+const BAND_EDGES: [f32; 4] = [300.0, 700.0, 1500.0, 3000.0];
+
+// Range of pitch searched for, in Hz
+const MIN_PITCH: f32 = 60.0;
+const MAX_PITCH: f32 = 400.0;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DiarizationConfig {
+    #[serde(default = "default_max_speakers")]
+    pub max_speakers: usize, // Most speakers told apart, later voices go to the closest one
+    #[serde(default = "default_threshold")]
+    pub threshold: f32, // How different a voice has to be from every known one to be a new speaker
+}
+
+fn default_max_speakers() -> usize {
+    2
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+// Pitch of a frame from its autocorrelation, None if it isn't voiced
+fn pitch(frame: &[f32], sample_rate: usize) -> Option<f32> {
+    let energy: f32 = frame.iter().map(|x| x * x).sum();
+    if energy <= f32::EPSILON {
+        return None;
+    }
+
+    let min_lag = (sample_rate as f32 / MAX_PITCH) as usize;
+    let max_lag = ((sample_rate as f32 / MIN_PITCH) as usize).min(frame.len() - 1);
+    let (lag, correlation) = (min_lag..=max_lag)
+        .map(|lag| {
+            let correlation: f32 = frame.iter().zip(&frame[lag..]).map(|(a, b)| a * b).sum();
+            (lag, correlation)
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    // Unvoiced frames like "s" barely correlate with themselves
+    (correlation / energy > 0.3).then(|| sample_rate as f32 / lag as f32)
+}
+
+// Rough description of a voice: its median pitch and how its energy is spread over the bands
+// Good enough to tell apart distinct voices, like the two sides of an interview
+pub fn voice_print(samples: &[f32], sample_rate: usize) -> Vec<f32> {
+    let frame_length = (FRAME_SECONDS * sample_rate as f32) as usize;
+
+    let mut pitches: Vec<f32> = samples
+        .chunks_exact(frame_length.max(1))
+        .filter_map(|frame| pitch(frame, sample_rate))
+        .collect();
+    pitches.sort_by(f32::total_cmp);
+    let pitch = pitches.get(pitches.len() / 2).copied().unwrap_or(MIN_PITCH);
+
+    // Energy below each edge, the bands are the differences between them
+    let mut below = BAND_EDGES.map(|edge| {
+        let mut lowpass =
+            Biquad::lowpass(sample_rate as f32, edge, std::f32::consts::FRAC_1_SQRT_2);
+        samples
+            .iter()
+            .map(|x| lowpass.process(*x).powi(2))
+            .sum::<f32>()
+    });
+    let total = samples.iter().map(|x| x * x).sum::<f32>().max(f32::EPSILON);
+    for i in (1..below.len()).rev() {
+        below[i] = (below[i] - below[i - 1]).max(0.0);
+    }
+
+    // Pitch in octaves and band shares in decades, so both move similarly between voices
+    let mut print = vec![pitch.log2()];
+    print.extend(
+        below
+            .iter()
+            .map(|energy| (energy / total).max(1e-4).log10()),
+    );
+
+    print
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
+// Tells speakers on one input apart by clustering the voice prints of their utterances
+pub struct Diarizer {
+    config: DiarizationConfig,
+    speakers: Vec<(Vec<f32>, usize)>, // Average voice print of each speaker and utterances it's from
+}
+
+impl Diarizer {
+    pub fn new(config: &DiarizationConfig) -> Self {
+        Self {
+            config: config.clone(),
+            speakers: vec![],
+        }
+    }
+
+    // Speaker of a recording, counting from 1
+    pub fn speaker(&mut self, samples: &[f32], sample_rate: usize) -> usize {
+        let print = voice_print(samples, sample_rate);
+
+        let closest = self
+            .speakers
+            .iter()
+            .enumerate()
+            .map(|(i, (average, _))| (i, distance(average, &print)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let index = match closest {
+            Some((i, distance))
+                if distance <= self.config.threshold
+                    || self.speakers.len() >= self.config.max_speakers =>
+            {
+                // Move the speaker's average towards the new utterance
+                let (average, count) = &mut self.speakers[i];
+                *count += 1;
+                for (average, value) in average.iter_mut().zip(&print) {
+                    *average += (value - *average) / *count as f32;
+                }
+                i
+            }
+            _ => {
+                self.speakers.push((print, 1));
+                self.speakers.len() - 1
+            }
+        };
+
+        index + 1
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    process::Command,
+    sync::{Arc, Mutex, atomic::AtomicBool},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::error;
+use whisper_rs::WhisperContext;
+
+use crate::{
+    Config,
+    dsp::Chain,
+    execution::Provider,
+    pipeline::Pipeline,
+    piper::{self, play_tts, tts_client},
+    sink::SinkConfig,
+    sound::{
+        audio_jack,
+        audio_mock::{MockClient, MockConfig},
+        play_buffer::{LIVE_CAPACITY, PlayBuffer},
+    },
+    util::lock,
+    utterance::Utterance,
+    whisper::{self, Transcriber},
+};
+
+// Spoken by piper and transcribed again by whisper, checking both ends of the pipeline
+const ROUND_TRIP: &str = "The quick brown fox jumps over the lazy dog.";
+
+// How long piper gets to start
+const PIPER_TIMEOUT: Duration = Duration::from_secs(60);
+
+// How long the self-test waits for the pipeline to hear and speak the sentence
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Print how a check went, returning whether it passed
+fn report(name: &str, result: Result<String, String>) -> bool {
+    match &result {
+        Ok(detail) => println!("[ ok ] {}: {}", name, detail),
+        Err(detail) => println!("[FAIL] {}: {}", name, detail),
+    }
+
+    result.is_ok()
+}
+
+// Lowercase words without punctuation, for comparing what was said with what was heard
+fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// CUDA GPUs the driver reports, None if there is no driver
+fn cuda_gpus() -> Option<Vec<String>> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=name", "--format=csv,noheader"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect(),
+    )
+}
+
+fn check_audio_server() -> bool {
+    // PipeWire serves the same API through pipewire-jack
+    let ports = audio_jack::list_ports();
+    let passed = report(
+        "Audio server",
+        match &ports {
+            Ok((sources, sinks)) => Ok(format!(
+                "{} ports to record from, {} to play to",
+                sources.len(),
+                sinks.len()
+            )),
+            Err(err) => Err(format!(
+                "could not connect, is JACK or pipewire-jack running? {}",
+                err
+            )),
+        },
+    );
+
+    if let Ok((sources, sinks)) = &ports {
+        for source in sources {
+            println!("       record {}", source);
+        }
+        for sink in sinks {
+            println!("       play   {}", sink);
+        }
+    }
+
+    passed
+}
+
+fn check_gpu(config: &Config) -> bool {
+    let cuda_requested = config.whisper.execution.provider == Provider::Cuda;
+
+    report(
+        "GPU",
+        match cuda_gpus() {
+            Some(gpus) if !gpus.is_empty() => Ok(gpus.join(", ")),
+            _ if cuda_requested => {
+                Err("no CUDA GPU found, but whisper is set to use one".to_owned())
+            }
+            _ => Ok("no CUDA GPU found, whisper runs on the CPU".to_owned()),
+        },
+    )
+}
+
+fn check_whisper(config: &Config) -> Option<WhisperContext> {
+    let start = Instant::now();
+    let result = whisper::setup_whisper(config.whisper.clone());
+
+    let detail = match &result {
+        Ok(_) => Ok(format!(
+            "loaded {} in {:.1}s",
+            config.whisper.model,
+            start.elapsed().as_secs_f32()
+        )),
+        Err(err) => Err(err.to_string()),
+    };
+    report("Whisper", detail);
+
+    result.ok()
+}
+
+// Speak the round trip sentence, returning the speech at the play buffer's rate
+fn check_piper(config: &Config, play_buffer: &Arc<PlayBuffer>) -> Option<Vec<f32>> {
+    let mut server = match piper::setup_piper(&config.piper, &[], false) {
+        Ok(server) => server,
+        Err(err) => {
+            report("Piper", Err(err.to_string()));
+            return None;
+        }
+    };
+
+    let start = Instant::now();
+    let result = piper::wait_for_piper(config.piper.port, PIPER_TIMEOUT)
+        .map_err(|err| err.to_string())
+        .and_then(|()| tts_client(&config.piper).map_err(|err| err.to_string()))
+        .and_then(|http_client| {
+            play_tts(
+                &http_client,
+                &config.piper.url(),
+                play_buffer.clone(),
+                ROUND_TRIP.to_owned(),
+                config.piper.voice_for(Some("en")),
+                1.0,
+                0.0,
+                config.piper.fade,
+                &mut Chain::new(&[], play_buffer.sample_rate()),
+            )
+            .map_err(|err| err.to_string())
+        });
+    if let Err(err) = server.kill() {
+        error!("Could not kill piper server!\n{}", err);
+    }
+
+    let clip = match result {
+        Ok((_, clip)) if clip.is_empty() => Err("server returned no speech".to_owned()),
+        Ok((_, clip)) => Ok(clip),
+        Err(err) => Err(err),
+    };
+    let detail = match &clip {
+        Ok(clip) => Ok(format!(
+            "started and spoke {:.1}s of speech in {:.1}s",
+            clip.len() as f32 / play_buffer.sample_rate() as f32,
+            start.elapsed().as_secs_f32()
+        )),
+        Err(err) => Err(err.clone()),
+    };
+    report("Piper", detail);
+
+    clip.ok()
+}
+
+// Transcribe piper's speech again, which should come back as the sentence it was given
+fn check_round_trip(config: &Config, ctx: WhisperContext, speech: Vec<f32>, rate: usize) -> bool {
+    let mut whisper_config = config.whisper.clone();
+    whisper_config.language = Some("en".to_owned());
+    whisper_config.translate = false;
+
+    let result = Transcriber::new(Arc::new(ctx), Arc::new(AtomicBool::new(false)))
+        .map_err(|err| err.to_string())
+        .and_then(|mut transcriber| {
+            transcriber
+                .transcribe(&whisper_config, speech, rate)
+                .map_err(|err| err.to_string())
+        });
+
+    report(
+        "Round trip",
+        match result {
+            Ok(Some(transcription)) => {
+                let expected = words(ROUND_TRIP);
+                let heard = words(&transcription.text);
+                let matched = expected.iter().filter(|word| heard.contains(word)).count();
+
+                // Voices for other languages mangle some english words
+                if matched * 2 >= expected.len() {
+                    Ok(format!("heard \"{}\"", transcription.text.trim()))
+                } else {
+                    Err(format!(
+                        "heard \"{}\", expected \"{}\"",
+                        transcription.text.trim(),
+                        ROUND_TRIP
+                    ))
+                }
+            }
+            Ok(None) => Err("whisper heard nothing".to_owned()),
+            Err(err) => Err(err),
+        },
+    )
+}
+
+// Check every component the config uses, printing whether each one works
+// Returns whether all of them do
+pub fn run(config: &Config) -> bool {
+    let mut passed = check_audio_server();
+    passed &= check_gpu(config);
+
+    let ctx = check_whisper(config);
+    passed &= ctx.is_some();
+
+    let (play_buffer, _consumer) = PlayBuffer::new(LIVE_CAPACITY);
+    let speech = check_piper(config, &play_buffer);
+    passed &= speech.is_some();
+
+    match (ctx, speech) {
+        (Some(ctx), Some(speech)) => {
+            passed &= check_round_trip(config, ctx, speech, play_buffer.sample_rate())
+        }
+        _ => {
+            report(
+                "Round trip",
+                Err("skipped, it needs both whisper and piper".to_owned()),
+            );
+            passed = false;
+        }
+    }
+
+    passed
+}
+
+// Feed piper's speech through a whole pipeline on a mock audio client, without an audio server
+// Returns whether it was transcribed and something was spoken back
+pub fn selftest(config: &Config) -> bool {
+    let (play_buffer, _consumer) = PlayBuffer::new(LIVE_CAPACITY);
+    let Some(speech) = check_piper(config, &play_buffer) else {
+        return false;
+    };
+
+    // Silence around the speech so voice activity is detected, then long enough after it to end the recording
+    let mock_config = MockConfig {
+        sample_rate: play_buffer.sample_rate(),
+        ..Default::default()
+    };
+    let lead = mock_config.sample_rate / 2;
+    let trail = mock_config.block_size * (config.whisper.silence_length as usize * 2)
+        + mock_config.sample_rate;
+    let mut input = vec![0.0; lead];
+    input.extend_from_slice(&speech);
+    input.resize(input.len() + trail, 0.0);
+
+    // Only the pipeline's own speech, nothing written to disk or played to other rooms
+    let mut pipeline_config = config.clone();
+    pipeline_config.sinks = vec![SinkConfig::Tts];
+    pipeline_config.transcript = None;
+    pipeline_config.recording = None;
+    pipeline_config.rooms.clear();
+    pipeline_config.pipelines.clear();
+
+    let mut server = match piper::setup_piper(&config.piper, &[], false) {
+        Ok(server) => server,
+        Err(err) => {
+            report("Pipeline", Err(err.to_string()));
+            return false;
+        }
+    };
+
+    let client = MockClient::with_input(&mock_config, input);
+    let output = client.output();
+    let heard: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let heard_cloned = heard.clone();
+    let pipeline = piper::wait_for_piper(config.piper.port, PIPER_TIMEOUT)
+        .map_err(|err| err.to_string())
+        .and_then(|()| {
+            Pipeline::builder(Arc::new(pipeline_config))
+                .name("selftest")
+                .audio_client(client)
+                .on_transcription(move |utterance: &Utterance| {
+                    lock(&heard_cloned).get_or_insert_with(|| utterance.text.clone());
+                })
+                .start()
+                .map_err(|err| err.to_string())
+        });
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            report("Pipeline", Err(err));
+            if let Err(err) = server.kill() {
+                error!("Could not kill piper server!\n{}", err);
+            }
+            return false;
+        }
+    };
+    report("Pipeline", Ok("started on a mock audio client".to_owned()));
+
+    // The transcription arrives before its speech has been played
+    let start = Instant::now();
+    let spoken = |output: &Mutex<Vec<f32>>| lock(output).iter().any(|sample| *sample != 0.0);
+    while start.elapsed() < SELFTEST_TIMEOUT && !(lock(&heard).is_some() && spoken(&output)) {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    pipeline.stop();
+    if let Err(err) = server.kill() {
+        error!("Could not kill piper server!\n{}", err);
+    }
+
+    let mut passed = report(
+        "Transcription",
+        match lock(&heard).as_deref().map(str::trim) {
+            Some("") => Err("whisper returned empty text".to_owned()),
+            Some(text) => Ok(format!("heard \"{}\"", text)),
+            None => Err("nothing was transcribed".to_owned()),
+        },
+    );
+
+    let output = lock(&output);
+    let played = output.iter().filter(|sample| **sample != 0.0).count();
+    passed &= report(
+        "Speech output",
+        if played > 0 {
+            Ok(format!(
+                "played {:.1}s of audio",
+                played as f32 / mock_config.sample_rate as f32
+            ))
+        } else {
+            Err("nothing was played".to_owned())
+        },
+    );
+
+    passed
+}
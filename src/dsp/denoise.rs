@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+
+use log::warn;
+use nnnoiseless::DenoiseState;
+use serde::Deserialize;
+
+use crate::dsp::AudioStage;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DenoiseConfig {
+    #[serde(default = "default_denoise_mix")]
+    pub mix: f32, // Share of the denoised audio in the output, lower keeps more of the original
+}
+
+fn default_denoise_mix() -> f32 {
+    1.0
+}
+
+impl Default for DenoiseConfig {
+    fn default() -> Self {
+        Self {
+            mix: default_denoise_mix(),
+        }
+    }
+}
+
+// RNNoise only works at this rate
+const DENOISE_RATE: usize = 48000;
+
+// Samples are scaled to the range of i16 for RNNoise
+const SCALE: f32 = i16::MAX as f32;
+
+// Noise suppression with RNNoise, adding one frame (10ms) of latency
+// Blocks rarely line up with its frames, so input and output are buffered
+pub struct Denoise {
+    state: Option<Box<DenoiseState<'static>>>, // Not set at unsupported rates
+    mix: f32,
+    input: VecDeque<f32>,
+    output: VecDeque<f32>,
+    frame_in: Vec<f32>,
+    frame_out: Vec<f32>,
+}
+
+impl Denoise {
+    pub fn new(config: &DenoiseConfig, sample_rate: usize) -> Self {
+        let state = if sample_rate == DENOISE_RATE {
+            Some(DenoiseState::new())
+        } else {
+            warn!(
+                "Noise suppression only works at {} Hz, not denoising {} Hz input",
+                DENOISE_RATE, sample_rate
+            );
+            None
+        };
+
+        Self {
+            state,
+            mix: config.mix.clamp(0.0, 1.0),
+            input: VecDeque::new(),
+            // Start a frame behind, so there is always enough output for the input
+            output: VecDeque::from(vec![0.0; DenoiseState::FRAME_SIZE]),
+            frame_in: vec![0.0; DenoiseState::FRAME_SIZE],
+            frame_out: vec![0.0; DenoiseState::FRAME_SIZE],
+        }
+    }
+}
+
+impl AudioStage for Denoise {
+    fn process(&mut self, samples: &mut [f32]) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+
+        self.input
+            .extend(samples.iter().map(|sample| sample * SCALE));
+        while self.input.len() >= DenoiseState::FRAME_SIZE {
+            for (frame, sample) in self
+                .frame_in
+                .iter_mut()
+                .zip(self.input.drain(..DenoiseState::FRAME_SIZE))
+            {
+                *frame = sample;
+            }
+            state.process_frame(&mut self.frame_out, &self.frame_in);
+
+            // Blend with the original, delayed by the same frame
+            for (denoised, original) in self.frame_out.iter().zip(&self.frame_in) {
+                self.output
+                    .push_back((denoised * self.mix + original * (1.0 - self.mix)) / SCALE);
+            }
+        }
+
+        for sample in samples.iter_mut() {
+            *sample = self.output.pop_front().unwrap_or(0.0);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use serde::Deserialize;
+
+use crate::dsp::AudioStage;
+
+// Convert decibels to a linear gain
+pub fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+// Root mean square level of a block
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+// Gain changed at runtime, moved to its new value over a block so the change doesn't click
+pub struct GainRamp {
+    gain: f32,
+}
+
+impl Default for GainRamp {
+    fn default() -> Self {
+        Self { gain: 1.0 }
+    }
+}
+
+impl GainRamp {
+    pub fn process(&mut self, samples: &mut [f32], db: f32) {
+        let target = db_to_gain(db);
+        let step = (target - self.gain) / samples.len().max(1) as f32;
+        for sample in samples.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target;
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct GainConfig {
+    #[serde(default)]
+    pub db: f32,
+}
+
+// Fixed gain
+pub struct Gain {
+    gain: f32,
+}
+
+impl Gain {
+    pub fn new(config: &GainConfig) -> Self {
+        Self {
+            gain: db_to_gain(config.db),
+        }
+    }
+}
+
+impl AudioStage for Gain {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct GateConfig {
+    #[serde(default = "default_gate_threshold")]
+    pub threshold: f32, // Level in dBFS below which audio is attenuated
+    #[serde(default = "default_gate_reduction")]
+    pub reduction: f32, // Attenuation in dB while closed
+}
+
+fn default_gate_threshold() -> f32 {
+    -50.0
+}
+
+fn default_gate_reduction() -> f32 {
+    -40.0
+}
+
+impl Default for GateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_gate_threshold(),
+            reduction: default_gate_reduction(),
+        }
+    }
+}
+
+// Attenuate blocks quieter than the threshold
+pub struct Gate {
+    threshold: f32,
+    closed_gain: f32,
+    gain: f32, // Current gain, ramped to avoid clicks
+}
+
+impl Gate {
+    pub fn new(config: &GateConfig) -> Self {
+        Self {
+            threshold: db_to_gain(config.threshold),
+            closed_gain: db_to_gain(config.reduction),
+            gain: 1.0,
+        }
+    }
+}
+
+impl AudioStage for Gate {
+    fn process(&mut self, samples: &mut [f32]) {
+        let target = if rms(samples) >= self.threshold {
+            1.0
+        } else {
+            self.closed_gain
+        };
+
+        // Ramp linearly across the block
+        let step = (target - self.gain) / samples.len().max(1) as f32;
+        for sample in samples.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target;
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct AgcConfig {
+    #[serde(default = "default_agc_target")]
+    pub target: f32, // Target level in dBFS
+    #[serde(default = "default_agc_max_gain")]
+    pub max_gain: f32, // Maximum boost in dB
+    #[serde(default = "default_agc_speed")]
+    pub speed: f32, // Fraction of the remaining gain change applied per block
+}
+
+fn default_agc_target() -> f32 {
+    -20.0
+}
+
+fn default_agc_max_gain() -> f32 {
+    30.0
+}
+
+fn default_agc_speed() -> f32 {
+    0.05
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self {
+            target: default_agc_target(),
+            max_gain: default_agc_max_gain(),
+            speed: default_agc_speed(),
+        }
+    }
+}
+
+// Slowly adjust gain so speech sits around the target level
+pub struct Agc {
+    target: f32,
+    max_gain: f32,
+    speed: f32,
+    gain: f32,
+}
+
+impl Agc {
+    // Don't adapt to blocks quieter than this, so silence isn't boosted
+    const SILENCE: f32 = 0.001; // -60 dBFS
+
+    pub fn new(config: &AgcConfig) -> Self {
+        Self {
+            target: db_to_gain(config.target),
+            max_gain: db_to_gain(config.max_gain),
+            speed: config.speed.clamp(0.0, 1.0),
+            gain: 1.0,
+        }
+    }
+}
+
+impl AudioStage for Agc {
+    fn process(&mut self, samples: &mut [f32]) {
+        let level = rms(samples);
+
+        if level > Self::SILENCE {
+            let desired = (self.target / level).min(self.max_gain);
+            self.gain += (desired - self.gain) * self.speed;
+        }
+
+        for sample in samples.iter_mut() {
+            *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NormalizeConfig {
+    #[serde(default = "default_normalize_target")]
+    pub target: f32, // Peak level in dBFS
+}
+
+fn default_normalize_target() -> f32 {
+    -3.0
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        Self {
+            target: default_normalize_target(),
+        }
+    }
+}
+
+// Scale each processed buffer so its peak hits the target
+// Meant for whole clips such as TTS output, not short input blocks
+pub struct Normalize {
+    target: f32,
+}
+
+impl Normalize {
+    pub fn new(config: &NormalizeConfig) -> Self {
+        Self {
+            target: db_to_gain(config.target),
+        }
+    }
+}
+
+impl AudioStage for Normalize {
+    fn process(&mut self, samples: &mut [f32]) {
+        let peak = samples.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+
+        // Leave silence alone
+        if peak <= f32::EPSILON {
+            return;
+        }
+
+        let gain = self.target / peak;
+        for sample in samples.iter_mut() {
+            *sample *= gain;
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct LoudnessConfig {
+    #[serde(default = "default_loudness_target")]
+    pub target: f32, // RMS level in dBFS
+    #[serde(default = "default_loudness_max_gain")]
+    pub max_gain: f32, // Maximum boost in dB
+}
+
+fn default_loudness_target() -> f32 {
+    -20.0
+}
+
+fn default_loudness_max_gain() -> f32 {
+    30.0
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            target: default_loudness_target(),
+            max_gain: default_loudness_max_gain(),
+        }
+    }
+}
+
+// Scale each processed buffer so its RMS hits the target, without letting the peak clip
+// Meant for whole clips such as recordings and TTS output, evens out quiet and loud speakers
+pub struct Loudness {
+    target: f32,
+    max_gain: f32,
+}
+
+impl Loudness {
+    pub fn new(config: &LoudnessConfig) -> Self {
+        Self {
+            target: db_to_gain(config.target),
+            max_gain: db_to_gain(config.max_gain),
+        }
+    }
+}
+
+impl AudioStage for Loudness {
+    fn process(&mut self, samples: &mut [f32]) {
+        let level = rms(samples);
+
+        // Leave silence alone
+        if level <= Agc::SILENCE {
+            return;
+        }
+
+        let peak = samples.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        let gain = (self.target / level).min(self.max_gain).min(1.0 / peak);
+        for sample in samples.iter_mut() {
+            *sample *= gain;
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct LimiterConfig {
+    #[serde(default = "default_limiter_threshold")]
+    pub threshold: f32, // Maximum output level in dBFS
+    #[serde(default = "default_limiter_release")]
+    pub release: f32, // Time in milliseconds to recover from gain reduction
+}
+
+fn default_limiter_threshold() -> f32 {
+    -1.0
+}
+
+fn default_limiter_release() -> f32 {
+    50.0
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_limiter_threshold(),
+            release: default_limiter_release(),
+        }
+    }
+}
+
+// Keep peaks below the threshold
+pub struct Limiter {
+    threshold: f32,
+    release: f32, // Per sample recovery coefficient
+    gain: f32,
+}
+
+impl Limiter {
+    pub fn new(config: &LimiterConfig, sample_rate: usize) -> Self {
+        let release_samples = (config.release / 1000.0 * sample_rate as f32).max(1.0);
+
+        Self {
+            threshold: db_to_gain(config.threshold),
+            release: (-1.0 / release_samples).exp(),
+            gain: 1.0,
+        }
+    }
+}
+
+impl AudioStage for Limiter {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            // Recover towards unity gain
+            self.gain = 1.0 - (1.0 - self.gain) * self.release;
+
+            // Reduce instantly if the sample would exceed the threshold
+            let level = sample.abs() * self.gain;
+            if level > self.threshold {
+                self.gain = self.threshold / sample.abs();
+            }
+
+            *sample *= self.gain;
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Deserialize;
+
+// Longest echo cancelled in ms, each sample costs a multiply and add per tap twice over, so a
+// 100 ms tail at 48 kHz already takes around 460 million operations a second
+pub const MAX_TAIL: f32 = 200.0;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct EchoConfig {
+    #[serde(default = "default_tail")]
+    pub tail: f32, // Length of the echo in ms up to MAX_TAIL, longer costs more CPU
+    #[serde(default)]
+    pub delay: f32, // Time in ms before the output reaches the mic, e.g. from the PA's own latency
+    #[serde(default = "default_step")]
+    pub step: f32, // How fast the filter adapts, between 0.0 and 1.0
+    #[serde(default = "default_double_talk")]
+    pub double_talk: f32, // Input louder than the output times this is someone speaking, raise it if the PA is loud at the mic
+}
+
+fn default_tail() -> f32 {
+    100.0
+}
+
+fn default_step() -> f32 {
+    0.3
+}
+
+fn default_double_talk() -> f32 {
+    0.5
+}
+
+// Keeps the filter from blowing up while the output is silent
+const REGULARIZATION: f32 = 1e-3;
+
+// Removes what was played from the input with an NLMS adaptive filter
+// The filter learns the path from the output to the mic, so its estimate of the echo can be subtracted
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    history: Vec<f32>, // Reference samples, stored twice so the newest taps are always one slice
+    position: usize,   // Start of the newest taps in history
+    power: f32,        // Sum of squares of the reference samples in the taps
+    peak: f32,         // Loudest recent reference sample, decaying over the tail
+    decay: f32,
+    delay_line: Vec<f32>,
+    delay_position: usize,
+    step: f32,
+    double_talk: f32,
+}
+
+impl EchoCanceller {
+    pub fn new(config: &EchoConfig, sample_rate: usize) -> Self {
+        let tail = config.tail.clamp(0.0, MAX_TAIL);
+        let taps = ((tail / 1000.0 * sample_rate as f32) as usize).max(1);
+        let delay = (config.delay / 1000.0 * sample_rate as f32) as usize;
+
+        Self {
+            weights: vec![0.0; taps],
+            history: vec![0.0; taps * 2],
+            position: 0,
+            power: 0.0,
+            peak: 0.0,
+            decay: (-1.0 / taps as f32).exp(),
+            delay_line: vec![0.0; delay],
+            delay_position: 0,
+            step: config.step.clamp(0.0, 1.0),
+            double_talk: config.double_talk,
+        }
+    }
+
+    // Delay a reference sample by the time it takes to reach the mic
+    fn delay(&mut self, sample: f32) -> f32 {
+        let Some(delayed) = self.delay_line.get_mut(self.delay_position) else {
+            return sample;
+        };
+        let out = std::mem::replace(delayed, sample);
+        self.delay_position = (self.delay_position + 1) % self.delay_line.len();
+        out
+    }
+
+    // Add a reference sample as the newest tap
+    fn push(&mut self, sample: f32) {
+        let taps = self.weights.len();
+        let oldest = self.history[self.position + taps - 1];
+
+        self.position = self.position.checked_sub(1).unwrap_or(taps - 1);
+        self.history[self.position] = sample;
+        self.history[self.position + taps] = sample;
+
+        self.power = (self.power + sample * sample - oldest * oldest).max(0.0);
+        self.peak = sample.abs().max(self.peak * self.decay);
+    }
+
+    // Cancel the echo of reference, played at the same time as input was captured
+    pub fn process(&mut self, input: &mut [f32], reference: &[f32]) {
+        let taps = self.weights.len();
+
+        // Start from the exact power, so rounding doesn't build up
+        self.power = self.history[self.position..self.position + taps]
+            .iter()
+            .map(|x| x * x)
+            .sum();
+
+        for (sample, &played) in input.iter_mut().zip(reference) {
+            let played = self.delay(played);
+            self.push(played);
+            let window = &self.history[self.position..self.position + taps];
+
+            // Subtract the estimated echo
+            let echo = self
+                .weights
+                .iter()
+                .zip(window)
+                .map(|(weight, x)| weight * x)
+                .sum::<f32>();
+            let error = *sample - echo;
+
+            // Only adapt while the input is mostly echo, speech at the mic would throw the filter off
+            if sample.abs() <= self.double_talk * self.peak {
+                let rate = self.step * error / (self.power + REGULARIZATION);
+                for (weight, x) in self.weights.iter_mut().zip(window) {
+                    *weight += rate * x;
+                }
+            }
+
+            *sample = error;
+        }
+    }
+}
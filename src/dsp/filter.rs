@@ -0,0 +1,234 @@
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use crate::dsp::AudioStage;
+
+// Second order IIR filter using the RBJ audio EQ cookbook formulas
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    // Create from unnormalised coefficients
+    fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn highpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+
+        Self::new(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn lowpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn peaking(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+
+        Self::new(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    pub fn low_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+
+        Self::new(
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+            (a + 1.0) + (a - 1.0) * cos + sqrt_a,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - sqrt_a,
+        )
+    }
+
+    pub fn high_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+
+        Self::new(
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+            (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+        )
+    }
+
+    // Filter a single sample
+    pub fn process(&mut self, x: f32) -> f32 {
+        // Transposed direct form II
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct HighpassConfig {
+    #[serde(default = "default_highpass_cutoff")]
+    pub cutoff: f32, // Cutoff frequency in Hz
+}
+
+fn default_highpass_cutoff() -> f32 {
+    80.0
+}
+
+impl Default for HighpassConfig {
+    fn default() -> Self {
+        Self {
+            cutoff: default_highpass_cutoff(),
+        }
+    }
+}
+
+// Remove rumble and DC offset below the cutoff
+pub struct Highpass {
+    filter: Biquad,
+}
+
+impl Highpass {
+    pub fn new(config: &HighpassConfig, sample_rate: usize) -> Self {
+        Self {
+            filter: Biquad::highpass(sample_rate as f32, config.cutoff, 0.707),
+        }
+    }
+}
+
+impl AudioStage for Highpass {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample = self.filter.process(*sample);
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BandType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    Lowpass,
+    Highpass,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BandConfig {
+    #[serde(rename = "type", default = "default_band_type")]
+    pub band_type: BandType,
+    pub frequency: f32, // Centre or corner frequency in Hz
+    #[serde(default)]
+    pub gain: f32, // Boost or cut in dB, unused by pass filters
+    #[serde(default = "default_band_q")]
+    pub q: f32,
+}
+
+fn default_band_type() -> BandType {
+    BandType::Peak
+}
+
+fn default_band_q() -> f32 {
+    0.707
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct EqConfig {
+    #[serde(default)]
+    pub bands: Vec<BandConfig>,
+}
+
+// Parametric EQ made of a biquad per band
+pub struct Eq {
+    filters: Vec<Biquad>,
+}
+
+impl Eq {
+    pub fn new(config: &EqConfig, sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        let filters = config
+            .bands
+            .iter()
+            .map(|band| match band.band_type {
+                BandType::Peak => Biquad::peaking(sample_rate, band.frequency, band.q, band.gain),
+                BandType::LowShelf => {
+                    Biquad::low_shelf(sample_rate, band.frequency, band.q, band.gain)
+                }
+                BandType::HighShelf => {
+                    Biquad::high_shelf(sample_rate, band.frequency, band.q, band.gain)
+                }
+                BandType::Lowpass => Biquad::lowpass(sample_rate, band.frequency, band.q),
+                BandType::Highpass => Biquad::highpass(sample_rate, band.frequency, band.q),
+            })
+            .collect();
+
+        Self { filters }
+    }
+}
+
+impl AudioStage for Eq {
+    fn process(&mut self, samples: &mut [f32]) {
+        for filter in self.filters.iter_mut() {
+            for sample in samples.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+    }
+}
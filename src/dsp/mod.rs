@@ -0,0 +1,121 @@
+use serde::Deserialize;
+
+#[cfg(feature = "denoise")]
+use crate::dsp::denoise::{Denoise, DenoiseConfig};
+use crate::dsp::{
+    dynamics::{
+        Agc, AgcConfig, Gain, GainConfig, Gate, GateConfig, Limiter, LimiterConfig, Loudness,
+        LoudnessConfig, Normalize, NormalizeConfig,
+    },
+    filter::{Eq, EqConfig, Highpass, HighpassConfig},
+};
+
+#[cfg(feature = "denoise")]
+pub mod denoise;
+pub mod dynamics;
+pub mod echo;
+pub mod filter;
+pub mod noise_floor;
+pub mod stretch;
+
+pub trait AudioStage: Send {
+    // Process a block of samples in place
+    fn process(&mut self, samples: &mut [f32]);
+}
+
+// A stage and its parameters
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StageParams {
+    Highpass(HighpassConfig),
+    Gate(GateConfig),
+    Agc(AgcConfig),
+    Gain(GainConfig),
+    Normalize(NormalizeConfig),
+    Loudness(LoudnessConfig),
+    Limiter(LimiterConfig),
+    Eq(EqConfig),
+    #[cfg(feature = "denoise")]
+    Denoise(DenoiseConfig),
+}
+
+impl StageParams {
+    // Get a stage with default parameters from its name
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "highpass" => Ok(Self::Highpass(HighpassConfig::default())),
+            "gate" => Ok(Self::Gate(GateConfig::default())),
+            "agc" => Ok(Self::Agc(AgcConfig::default())),
+            "gain" => Ok(Self::Gain(GainConfig::default())),
+            "normalize" => Ok(Self::Normalize(NormalizeConfig::default())),
+            "loudness" => Ok(Self::Loudness(LoudnessConfig::default())),
+            "limiter" => Ok(Self::Limiter(LimiterConfig::default())),
+            "eq" => Ok(Self::Eq(EqConfig::default())),
+            #[cfg(feature = "denoise")]
+            "denoise" => Ok(Self::Denoise(DenoiseConfig::default())),
+            _ => Err(format!("Unknown audio stage {}", name)),
+        }
+    }
+}
+
+// Stages can be given as just a name, or a table with parameters
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StageEntry {
+    Name(String),
+    Params(StageParams),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageConfig(pub StageParams);
+
+impl<'de> Deserialize<'de> for StageConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match StageEntry::deserialize(deserializer)? {
+            StageEntry::Name(name) => StageParams::from_name(&name)
+                .map(StageConfig)
+                .map_err(serde::de::Error::custom),
+            StageEntry::Params(params) => Ok(StageConfig(params)),
+        }
+    }
+}
+
+// An ordered list of stages
+pub struct Chain {
+    stages: Vec<Box<dyn AudioStage>>,
+}
+
+impl Chain {
+    pub fn new(configs: &[StageConfig], sample_rate: usize) -> Self {
+        let stages = configs
+            .iter()
+            .map(|StageConfig(params)| -> Box<dyn AudioStage> {
+                match params {
+                    StageParams::Highpass(config) => Box::new(Highpass::new(config, sample_rate)),
+                    StageParams::Gate(config) => Box::new(Gate::new(config)),
+                    StageParams::Agc(config) => Box::new(Agc::new(config)),
+                    StageParams::Gain(config) => Box::new(Gain::new(config)),
+                    StageParams::Normalize(config) => Box::new(Normalize::new(config)),
+                    StageParams::Loudness(config) => Box::new(Loudness::new(config)),
+                    StageParams::Limiter(config) => Box::new(Limiter::new(config, sample_rate)),
+                    StageParams::Eq(config) => Box::new(Eq::new(config, sample_rate)),
+                    #[cfg(feature = "denoise")]
+                    StageParams::Denoise(config) => Box::new(Denoise::new(config, sample_rate)),
+                }
+            })
+            .collect();
+
+        Self { stages }
+    }
+}
+
+impl AudioStage for Chain {
+    fn process(&mut self, samples: &mut [f32]) {
+        for stage in self.stages.iter_mut() {
+            stage.process(samples);
+        }
+    }
+}
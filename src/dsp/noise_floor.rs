@@ -0,0 +1,105 @@
+use log::info;
+use serde::Deserialize;
+
+use super::dynamics;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NoiseGateConfig {
+    #[serde(default = "default_calibration")]
+    pub calibration: f32, // Seconds of ambient noise measured at startup, nothing is recorded meanwhile
+    #[serde(default = "default_margin")]
+    pub margin: f32, // dB above the noise floor input has to be to count as voice
+    #[serde(default = "default_adapt")]
+    pub adapt: bool, // Keep following the noise floor while nobody speaks
+}
+
+fn default_calibration() -> f32 {
+    3.0
+}
+
+fn default_margin() -> f32 {
+    10.0
+}
+
+fn default_adapt() -> bool {
+    true
+}
+
+// How much of each quiet block's level goes into the noise floor, about 2 seconds to follow a change
+const ADAPT_RATE: f32 = 0.01;
+
+// Measures the ambient noise level, so only input clearly above it can start a recording
+// Fans and hum often fool the VAD without noise cancellation, but are too quiet to pass the gate
+pub struct NoiseFloor {
+    config: NoiseGateConfig,
+    sample_rate: usize,
+    floor: Option<f32>,        // RMS of the noise, not known until calibrated
+    calibrating: Option<Sums>, // Measurements so far while calibrating
+}
+
+// Running totals while calibrating
+struct Sums {
+    squares: f64,
+    samples: usize,
+}
+
+impl NoiseFloor {
+    pub fn new(config: &NoiseGateConfig, sample_rate: usize) -> Self {
+        let mut noise_floor = Self {
+            config: config.clone(),
+            sample_rate,
+            floor: None,
+            calibrating: None,
+        };
+        noise_floor.calibrate();
+
+        noise_floor
+    }
+
+    // Measure the noise again from now on
+    pub fn calibrate(&mut self) {
+        info!(
+            "Measuring background noise for {} seconds, stay quiet",
+            self.config.calibration
+        );
+        self.calibrating = Some(Sums {
+            squares: 0.0,
+            samples: 0,
+        });
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+    }
+
+    // Gate the VAD's decision on a block, which is never voice while calibrating
+    pub fn process(&mut self, block: &[f32], is_voice: bool) -> bool {
+        let rms = dynamics::rms(block);
+
+        if let Some(sums) = &mut self.calibrating {
+            sums.squares += block.iter().map(|x| (x * x) as f64).sum::<f64>();
+            sums.samples += block.len();
+
+            if sums.samples as f32 >= self.config.calibration * self.sample_rate as f32 {
+                let floor = (sums.squares / sums.samples.max(1) as f64).sqrt() as f32;
+                info!(
+                    "Background noise is at {:.1} dBFS",
+                    20.0 * floor.max(1e-5).log10()
+                );
+                self.floor = Some(floor);
+                self.calibrating = None;
+            }
+            return false;
+        }
+
+        let Some(floor) = &mut self.floor else {
+            return is_voice;
+        };
+
+        if !is_voice && self.config.adapt {
+            *floor += (rms - *floor) * ADAPT_RATE;
+        }
+
+        is_voice && rms > *floor * 10f32.powf(self.config.margin / 20.0)
+    }
+}
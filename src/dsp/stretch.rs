@@ -0,0 +1,73 @@
+// Length of the overlapping frames in ms, about a pitch period of low voices or longer
+const FRAME_MS: usize = 20;
+
+// How far in ms a frame may move to line up with the previous one
+const TOLERANCE_MS: usize = 5;
+
+// Play speech faster or slower without changing its pitch, using WSOLA
+// Frames are taken from the input at the new speed, each moved slightly to where it lines up best
+// with the audio that would naturally have followed the previous frame, then overlapped and added
+pub fn stretch(samples: &[f32], speed: f32, sample_rate: usize) -> Vec<f32> {
+    let frame = (sample_rate * FRAME_MS / 1000).max(4) & !1;
+    let hop = frame / 2;
+    let tolerance = sample_rate * TOLERANCE_MS / 1000;
+
+    if (speed - 1.0).abs() < 0.01 || speed <= 0.0 || samples.len() < frame + tolerance {
+        return samples.to_vec();
+    }
+
+    // Hann windows at half overlap add up to one
+    let window = (0..frame)
+        .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / frame as f32).cos())
+        .collect::<Vec<_>>();
+
+    let last_start = samples.len() - frame;
+    let mut out = vec![0.0; (samples.len() as f32 / speed) as usize + frame];
+    let mut previous = 0;
+    let mut written = 0;
+
+    for k in 0.. {
+        let nominal = (k as f32 * hop as f32 * speed) as usize;
+        if nominal > last_start {
+            break;
+        }
+
+        // Find the start lining up best with what followed the previous frame
+        let start = if k == 0 {
+            0
+        } else {
+            let natural = &samples[(previous + hop).min(last_start)..][..hop];
+            let correlation = |start: usize| {
+                samples[start..start + hop]
+                    .iter()
+                    .zip(natural)
+                    .map(|(x, y)| x * y)
+                    .sum::<f32>()
+            };
+            (nominal.saturating_sub(tolerance)..=(nominal + tolerance).min(last_start))
+                .map(|start| (start, correlation(start)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(nominal, |(start, _)| start)
+        };
+
+        let position = k * hop;
+        if position + frame > out.len() {
+            break;
+        }
+        for (i, (sample, weight)) in samples[start..start + frame]
+            .iter()
+            .zip(&window)
+            .enumerate()
+        {
+            // Nothing overlaps the start of the first frame, so it isn't faded in
+            let weight = if k == 0 && i < hop { 1.0 } else { *weight };
+            out[position + i] += sample * weight;
+        }
+
+        previous = start;
+        written = position + frame;
+    }
+
+    out.truncate(written);
+    out
+}
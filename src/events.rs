@@ -0,0 +1,58 @@
+use std::{
+    sync::{
+        Mutex,
+        mpsc::{Receiver, Sender, channel},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{util::lock, utterance::Utterance};
+
+// Something that happened in a pipeline, mostly to an utterance on its way through it
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // Voice was heard and recording started, the utterance gets its id once transcribed
+    UtteranceStarted { timestamp: u64 }, // Milliseconds since the unix epoch
+    TranscriptionReady(Utterance),       // What whisper heard, before translating
+    TranslationReady(Utterance),         // Ready for the outputs, translated if translating is on
+    TtsQueued { id: u64, duration: f32 }, // Seconds of speech queued to play for an utterance
+    PlaybackFinished { id: u64 },        // The last of an utterance's speech was played
+    SampleRate { rate: usize },          // The audio server's rate changed
+    BufferSize { frames: usize },        // The audio server's period changed
+    Xruns { count: u64 },                // The audio server missed deadlines
+}
+
+impl Event {
+    // Recording of an utterance started now
+    pub fn utterance_started() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self::UtteranceStarted { timestamp }
+    }
+}
+
+// Hands the events of a pipeline to everything subscribed to them
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventBus {
+    // Receive every event from now on
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        lock(&self.subscribers).push(tx);
+        rx
+    }
+
+    // Send an event to every subscriber, forgetting those which went away
+    pub fn publish(&self, event: &Event) {
+        lock(&self.subscribers).retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt::Display;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+// Hardware a local model runs on
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Provider {
+    #[default]
+    Auto, // Whatever the stage normally uses
+    Cpu,
+    Cuda,
+    #[serde(rename = "DirectML")]
+    DirectMl,
+    #[serde(rename = "CoreML")]
+    CoreMl,
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Cpu => write!(f, "CPU"),
+            Self::Cuda => write!(f, "CUDA"),
+            Self::DirectMl => write!(f, "DirectML"),
+            Self::CoreMl => write!(f, "CoreML"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct ExecutionConfig {
+    #[serde(default)]
+    pub provider: Provider,
+    pub threads: Option<usize>, // CPU threads, the stage decides if not set
+}
+
+impl ExecutionConfig {
+    // Pick the provider a stage runs on, using its default if the requested one isn't supported
+    pub fn select(&self, stage: &str, supported: &[Provider], default: Provider) -> Provider {
+        let provider = match self.provider {
+            Provider::Auto => default,
+            provider if supported.contains(&provider) => provider,
+            provider => {
+                warn!(
+                    "{} doesn't support {}, falling back to {}",
+                    stage, provider, default
+                );
+                default
+            }
+        };
+
+        match self.threads {
+            Some(threads) => info!("{} running on {} with {} threads", stage, provider, threads),
+            None => info!("{} running on {}", stage, provider),
+        }
+
+        provider
+    }
+}
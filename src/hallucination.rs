@@ -0,0 +1,115 @@
+use log::error;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::whisper::Transcription;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct HallucinationConfig {
+    #[serde(default = "default_blocklist")]
+    pub blocklist: Vec<String>, // Phrases whisper makes up, matched against the whole utterance
+    #[serde(default = "default_max_repeats")]
+    pub max_repeats: usize, // Most times a phrase may be said in a row
+    #[serde(default = "default_no_speech")]
+    pub no_speech: f32, // Drop utterances whisper thinks are at least this likely to be silence
+}
+
+fn default_blocklist() -> Vec<String> {
+    [
+        r"thanks? (you )?(so much |very much )?for watching",
+        r"(please )?(like and )?subscribe.*",
+        r"subtitles by .*",
+        r".*amara\.org.*",
+        r"untertitel(ung)? (im auftrag|von|der) .*",
+        r"[\[(♪].*[\])♪]",
+    ]
+    .map(str::to_owned)
+    .to_vec()
+}
+
+fn default_max_repeats() -> usize {
+    4
+}
+
+fn default_no_speech() -> f32 {
+    0.8
+}
+
+// Pattern matching an utterance made up of nothing but a blocklisted phrase
+pub fn pattern(phrase: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!(r"(?i)^\s*(?:{})[\s.,!?]*$", phrase))
+}
+
+// Longest run of the same phrase said back to back
+fn longest_repeat(words: &[String]) -> usize {
+    let mut longest = 1;
+    for length in 1..=words.len() / 2 {
+        for start in 0..=words.len() - 2 * length {
+            let phrase = &words[start..start + length];
+            let repeats = words[start..]
+                .chunks_exact(length)
+                .take_while(|chunk| *chunk == phrase)
+                .count();
+            longest = longest.max(repeats);
+        }
+    }
+
+    longest
+}
+
+// Catches text whisper made up from near silence, so it's never spoken
+pub struct HallucinationFilter {
+    blocklist: Vec<Regex>,
+    max_repeats: usize,
+    no_speech: f32,
+}
+
+impl HallucinationFilter {
+    pub fn new(config: &HallucinationConfig) -> Self {
+        // Invalid patterns are reported by validation, so they're only skipped here
+        let blocklist = config
+            .blocklist
+            .iter()
+            .filter_map(|phrase| {
+                pattern(phrase)
+                    .inspect_err(|err| error!("Invalid hallucination pattern!\n{}", err))
+                    .ok()
+            })
+            .collect();
+
+        Self {
+            blocklist,
+            max_repeats: config.max_repeats,
+            no_speech: config.no_speech,
+        }
+    }
+
+    // Why a transcription looks made up, if it does
+    pub fn check(&self, transcription: &Transcription) -> Option<&'static str> {
+        let text = transcription.text.trim();
+
+        if self.blocklist.iter().any(|pattern| pattern.is_match(text)) {
+            return Some("blocklisted phrase");
+        }
+
+        let words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        if longest_repeat(&words) > self.max_repeats {
+            return Some("repeated phrase");
+        }
+
+        if !transcription.segments.is_empty()
+            && transcription
+                .segments
+                .iter()
+                .all(|segment| segment.no_speech_prob >= self.no_speech)
+        {
+            return Some("likely silence");
+        }
+
+        None
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use log::info;
+use serde::Deserialize;
+
+use crate::controls::Controls;
+
+// How often key state is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Change in dB of each press of a gain key
+const GAIN_STEP: f32 = 2.0;
+
+// Media keys which aren't reported by device_query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKey {
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    Mute,
+    MicMute,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl MediaKey {
+    const ALL: [MediaKey; 8] = [
+        MediaKey::PlayPause,
+        MediaKey::Stop,
+        MediaKey::Next,
+        MediaKey::Previous,
+        MediaKey::Mute,
+        MediaKey::MicMute,
+        MediaKey::VolumeUp,
+        MediaKey::VolumeDown,
+    ];
+}
+
+impl FromStr for MediaKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PlayPause" => Ok(Self::PlayPause),
+            "Stop" => Ok(Self::Stop),
+            "Next" => Ok(Self::Next),
+            "Previous" => Ok(Self::Previous),
+            "Mute" => Ok(Self::Mute),
+            "MicMute" => Ok(Self::MicMute),
+            "VolumeUp" => Ok(Self::VolumeUp),
+            "VolumeDown" => Ok(Self::VolumeDown),
+            _ => Err(format!("Unknown media key {}", s)),
+        }
+    }
+}
+
+// A key which can be bound to an action
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hotkey {
+    Key(Keycode),
+    Media(MediaKey),
+}
+
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(key) = MediaKey::from_str(s) {
+            return Ok(Self::Media(key));
+        }
+
+        Keycode::from_str(s)
+            .map(Self::Key)
+            .map_err(|_| format!("Unknown key {}", s))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hotkey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Hotkey::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct HotkeyConfig {
+    pub mute: Option<Hotkey>,      // Toggle input mute
+    pub pause: Option<Hotkey>,     // Toggle output pause
+    pub calibrate: Option<Hotkey>, // Measure the background noise again
+    pub discard: Option<Hotkey>,   // Drop what is being recorded
+    pub finish: Option<Hotkey>,    // Transcribe what is being recorded without waiting for silence
+    pub replay: Option<Hotkey>,    // Play the speech of the last utterance again
+    pub translate: Option<Hotkey>, // Toggle translation
+    pub output_mute: Option<Hotkey>, // Toggle the voice, without holding it back like pause
+    pub input_gain_up: Option<Hotkey>,
+    pub input_gain_down: Option<Hotkey>,
+    pub output_gain_up: Option<Hotkey>,
+    pub output_gain_down: Option<Hotkey>,
+}
+
+#[cfg(target_os = "linux")]
+mod media {
+    use evdev::{Device, Key};
+    use log::warn;
+
+    use super::MediaKey;
+
+    fn evdev_key(key: MediaKey) -> Key {
+        match key {
+            MediaKey::PlayPause => Key::KEY_PLAYPAUSE,
+            MediaKey::Stop => Key::KEY_STOPCD,
+            MediaKey::Next => Key::KEY_NEXTSONG,
+            MediaKey::Previous => Key::KEY_PREVIOUSSONG,
+            MediaKey::Mute => Key::KEY_MUTE,
+            MediaKey::MicMute => Key::KEY_MICMUTE,
+            MediaKey::VolumeUp => Key::KEY_VOLUMEUP,
+            MediaKey::VolumeDown => Key::KEY_VOLUMEDOWN,
+        }
+    }
+
+    // Input devices which have media keys
+    pub struct MediaKeys {
+        devices: Vec<Device>,
+    }
+
+    impl MediaKeys {
+        pub fn new() -> Self {
+            let devices = evdev::enumerate()
+                .map(|(_, device)| device)
+                .filter(|device| {
+                    device.supported_keys().is_some_and(|keys| {
+                        MediaKey::ALL
+                            .iter()
+                            .any(|key| keys.contains(evdev_key(*key)))
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            if devices.is_empty() {
+                warn!(
+                    "No readable input devices with media keys found, make sure you are in the input group"
+                );
+            }
+
+            Self { devices }
+        }
+
+        // Get media keys currently held down
+        pub fn pressed(&self) -> Vec<MediaKey> {
+            let mut pressed = vec![];
+
+            for device in &self.devices {
+                if let Ok(state) = device.get_key_state() {
+                    for key in MediaKey::ALL {
+                        if state.contains(evdev_key(key)) && !pressed.contains(&key) {
+                            pressed.push(key);
+                        }
+                    }
+                }
+            }
+
+            pressed
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod media {
+    use log::warn;
+
+    use super::MediaKey;
+
+    pub struct MediaKeys;
+
+    impl MediaKeys {
+        pub fn new() -> Self {
+            warn!("Media keys are only supported on Linux");
+            Self
+        }
+
+        pub fn pressed(&self) -> Vec<MediaKey> {
+            vec![]
+        }
+    }
+}
+
+// Get every bound key currently held down
+fn pressed_hotkeys(
+    device_state: &DeviceState,
+    media_keys: &Option<media::MediaKeys>,
+) -> Vec<Hotkey> {
+    let mut pressed = device_state
+        .get_keys()
+        .into_iter()
+        .map(Hotkey::Key)
+        .collect::<Vec<_>>();
+
+    if let Some(media_keys) = media_keys {
+        pressed.extend(media_keys.pressed().into_iter().map(Hotkey::Media));
+    }
+
+    pressed
+}
+
+// Poll for hotkeys and apply their actions
+fn hotkey_loop(config: HotkeyConfig, controls: Arc<Controls>, running: Arc<AtomicBool>) {
+    let device_state = DeviceState::new();
+
+    // Only open input devices if a media key is actually bound
+    let uses_media_keys = [
+        &config.mute,
+        &config.pause,
+        &config.calibrate,
+        &config.discard,
+        &config.finish,
+        &config.replay,
+        &config.translate,
+        &config.output_mute,
+        &config.input_gain_up,
+        &config.input_gain_down,
+        &config.output_gain_up,
+        &config.output_gain_down,
+    ]
+    .iter()
+        .any(|key| matches!(key, Some(Hotkey::Media(_))));
+    let media_keys = uses_media_keys.then(media::MediaKeys::new);
+
+    // Keys held down on the previous poll, used to only act on presses
+    let mut previous: Vec<Hotkey> = vec![];
+
+    while running.load(Ordering::SeqCst) {
+        let pressed = pressed_hotkeys(&device_state, &media_keys);
+
+        for key in &pressed {
+            // Ignore keys being held down
+            if previous.contains(key) {
+                continue;
+            }
+
+            if config.mute.as_ref() == Some(key) {
+                if controls.toggle_mute() {
+                    info!("Input muted");
+                } else {
+                    info!("Input unmuted");
+                }
+            }
+
+            if config.pause.as_ref() == Some(key) {
+                if controls.toggle_pause() {
+                    info!("Output paused");
+                } else {
+                    info!("Output resumed");
+                }
+            }
+
+            if config.calibrate.as_ref() == Some(key) {
+                controls.request_calibration();
+            }
+
+            if config.discard.as_ref() == Some(key) {
+                controls.request_discard();
+            }
+
+            if config.finish.as_ref() == Some(key) {
+                controls.request_finish();
+            }
+
+            if config.replay.as_ref() == Some(key) {
+                controls.request_replay();
+            }
+
+            if config.translate.as_ref() == Some(key) {
+                if controls.toggle_translation() {
+                    info!("Translation on");
+                } else {
+                    info!("Translation off");
+                }
+            }
+
+            if config.output_mute.as_ref() == Some(key) {
+                if controls.toggle_output_mute() {
+                    info!("Voice muted");
+                } else {
+                    info!("Voice unmuted");
+                }
+            }
+
+            for (hotkey, step) in [
+                (&config.input_gain_up, GAIN_STEP),
+                (&config.input_gain_down, -GAIN_STEP),
+            ] {
+                if hotkey.as_ref() == Some(key) {
+                    controls.set_input_gain(controls.input_gain() + step);
+                    info!("Input gain {:+.0} dB", controls.input_gain());
+                }
+            }
+
+            for (hotkey, step) in [
+                (&config.output_gain_up, GAIN_STEP),
+                (&config.output_gain_down, -GAIN_STEP),
+            ] {
+                if hotkey.as_ref() == Some(key) {
+                    controls.set_output_gain(controls.output_gain() + step);
+                    info!("Voice gain {:+.0} dB", controls.output_gain());
+                }
+            }
+        }
+
+        previous = pressed;
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Start thread listening for hotkeys
+pub fn spawn_hotkeys(
+    config: HotkeyConfig,
+    controls: Arc<Controls>,
+    running: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    thread::Builder::new()
+        .name("hotkeys".to_owned())
+        .spawn(move || hotkey_loop(config, controls, running))
+}
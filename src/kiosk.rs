@@ -0,0 +1,229 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{error, info, warn};
+
+use live_translate_rs::rundir;
+
+use crate::cli::Cli;
+
+// How often the instance is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Wait before restarting, doubling while the instance keeps failing
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// An instance running this long counts as started, resetting the backoff
+const STABLE_TIME: Duration = Duration::from_secs(60);
+
+// Status older than this means the instance is stuck
+const STALE_STATUS: Duration = Duration::from_secs(5);
+
+// Where the kiosk reports whether the instance is healthy
+struct Indicator {
+    health_file: Option<PathBuf>,
+    gpio_value: Option<PathBuf>,
+    last: Option<bool>,
+}
+
+impl Indicator {
+    fn new(health_file: Option<PathBuf>, gpio_pin: Option<u32>) -> Self {
+        let gpio_value = gpio_pin.and_then(|pin| match export_gpio(pin) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                error!("Could not set up GPIO pin {}!\n{}", pin, err);
+                None
+            }
+        });
+
+        Self {
+            health_file,
+            gpio_value,
+            last: None,
+        }
+    }
+
+    // Report the health, only touching the outputs when it changes
+    fn set(&mut self, healthy: bool, reason: &str) {
+        if self.last == Some(healthy) {
+            return;
+        }
+        self.last = Some(healthy);
+
+        if healthy {
+            info!("Instance is healthy");
+        } else {
+            warn!("Instance is unhealthy, {}", reason);
+        }
+
+        if let Some(path) = &self.health_file {
+            let content = if healthy {
+                "healthy\n".to_owned()
+            } else {
+                format!("unhealthy: {}\n", reason)
+            };
+
+            // Replace in one step so readers never see a partial file
+            let temp_path = path.with_extension("tmp");
+            if let Err(err) =
+                std::fs::write(&temp_path, content).and_then(|_| std::fs::rename(&temp_path, path))
+            {
+                error!("Could not write health file {}!\n{}", path.display(), err);
+            }
+        }
+
+        if let Some(value) = &self.gpio_value
+            && let Err(err) = std::fs::write(value, if healthy { "1" } else { "0" })
+        {
+            error!("Could not set GPIO pin!\n{}", err);
+        }
+    }
+}
+
+// Make a pin an output through sysfs, returning the file which sets its value
+fn export_gpio(pin: u32) -> Result<PathBuf, std::io::Error> {
+    let gpio = Path::new("/sys/class/gpio");
+    let pin_path = gpio.join(format!("gpio{}", pin));
+    if !pin_path.exists() {
+        std::fs::write(gpio.join("export"), pin.to_string())?;
+
+        // udev needs a moment to make the new files writable
+        thread::sleep(Duration::from_millis(100));
+    }
+    std::fs::write(pin_path.join("direction"), "out")?;
+
+    Ok(pin_path.join("value"))
+}
+
+// Whether the instance is publishing fresh status, with the reason if it isn't
+fn check_health(profile: &str) -> Result<(), String> {
+    let path = rundir::status_path(profile);
+    let modified = std::fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|_| "no status yet".to_owned())?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    if age > STALE_STATUS {
+        return Err(format!("status hasn't been updated for {}s", age.as_secs()));
+    }
+
+    let status: serde_json::Value = std::fs::read(&path)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .ok_or_else(|| "status can't be read".to_owned())?;
+    match status["pipelines"].as_array() {
+        Some(pipelines) if !pipelines.is_empty() => Ok(()),
+        _ => Err("no pipelines are running".to_owned()),
+    }
+}
+
+// Run the instance as a child process, with the same settings apart from the interface
+fn spawn(cli: &Cli) -> Result<Child, std::io::Error> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg("--no-tui")
+        .args(["--profile", &cli.profile])
+        .args(["--log-level", &cli.log_level.to_string()]);
+    if let Some(config) = &cli.config {
+        command.arg("--config").arg(config);
+    }
+    if let Some(data_dir) = &cli.data_dir {
+        command.arg("--data-dir").arg(data_dir);
+    }
+
+    command.spawn()
+}
+
+// Sleep unless told to stop, returning whether still running
+fn wait(duration: Duration, running: &AtomicBool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        if !running.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    running.load(Ordering::SeqCst)
+}
+
+// Keep the profile running unattended, restarting it whenever it exits or fails to start
+pub fn run(cli: &Cli) {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    }) {
+        error!("Could not create crtlc handle!\n{}", err);
+        return;
+    };
+
+    let mut indicator = Indicator::new(cli.health_file.clone(), cli.gpio_pin);
+    indicator.set(false, "starting");
+
+    info!("Running {} as a kiosk", cli.profile);
+    let mut backoff = MIN_BACKOFF;
+    while running.load(Ordering::SeqCst) {
+        let started = Instant::now();
+        match spawn(cli) {
+            Ok(mut child) => {
+                // Watch the instance until it exits
+                let exited = loop {
+                    if !wait(CHECK_INTERVAL, &running) {
+                        break None;
+                    }
+
+                    match child.try_wait() {
+                        Ok(Some(status)) => break Some(status),
+                        Ok(None) => {}
+                        Err(err) => {
+                            error!("Could not check on the instance!\n{}", err);
+                        }
+                    }
+
+                    match check_health(&cli.profile) {
+                        Ok(()) => indicator.set(true, ""),
+                        Err(reason) => indicator.set(false, &reason),
+                    }
+                };
+
+                let Some(status) = exited else {
+                    // Stop the instance cleanly, killing it if it doesn't listen
+                    if let Err(err) = rundir::stop(&cli.profile) {
+                        warn!("Could not stop {}, killing it\n{}", cli.profile, err);
+                        let _ = child.kill();
+                    }
+                    let _ = child.wait();
+                    break;
+                };
+                indicator.set(false, &format!("exited with {}", status));
+            }
+            Err(err) => {
+                error!("Could not start {}!\n{}", cli.profile, err);
+                indicator.set(false, "could not be started");
+            }
+        }
+
+        // Back off while failing quickly, but start over once it ran for a while
+        if started.elapsed() >= STABLE_TIME {
+            backoff = MIN_BACKOFF;
+        }
+        info!("Restarting {} in {}s", cli.profile, backoff.as_secs());
+        if !wait(backoff, &running) {
+            break;
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    indicator.set(false, "stopped");
+}
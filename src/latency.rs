@@ -0,0 +1,51 @@
+use std::{collections::VecDeque, time::Duration};
+
+use log::info;
+
+// Utterances the rolling average is taken over
+const AVERAGE_OVER: usize = 20;
+
+// Time spent in each stage from the end of speech until its audio entered the play buffer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stages {
+    pub vad_wait: Duration, // Silence waited for before the recording was finished
+    pub whisper: Duration,
+    pub translate: Duration,
+    pub tts_request: Duration, // Waiting on the piper server
+    pub resample: Duration,    // Converting the speech to the output rate and processing it
+}
+
+impl Stages {
+    pub fn total(&self) -> Duration {
+        self.vad_wait + self.whisper + self.translate + self.tts_request + self.resample
+    }
+}
+
+// Latency of recent utterances, logged with a breakdown of where the time went
+#[derive(Default)]
+pub struct LatencyLog {
+    recent: VecDeque<Stages>,
+}
+
+impl LatencyLog {
+    pub fn record(&mut self, stages: Stages) {
+        self.recent.push_back(stages);
+        while self.recent.len() > AVERAGE_OVER {
+            self.recent.pop_front();
+        }
+
+        let average =
+            self.recent.iter().map(Stages::total).sum::<Duration>() / self.recent.len() as u32;
+        info!(
+            "Latency {}ms (VAD wait {}ms, whisper {}ms, translate {}ms, TTS request {}ms, resample {}ms), average {}ms over the last {}",
+            stages.total().as_millis(),
+            stages.vad_wait.as_millis(),
+            stages.whisper.as_millis(),
+            stages.translate.as_millis(),
+            stages.tts_request.as_millis(),
+            stages.resample.as_millis(),
+            average.as_millis(),
+            self.recent.len()
+        );
+    }
+}
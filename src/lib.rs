@@ -0,0 +1,103 @@
+pub mod backlog;
+pub mod commands;
+pub mod config;
+pub mod control_server;
+pub mod controls;
+pub mod data_dir;
+pub mod diarize;
+pub mod doctor;
+pub mod dsp;
+pub mod events;
+pub mod execution;
+pub mod hallucination;
+pub mod hotkeys;
+pub mod latency;
+pub mod metrics;
+pub mod models;
+pub mod nsm;
+pub mod offline;
+pub mod oneshot;
+pub mod pipeline;
+pub mod piper;
+pub mod postprocess;
+pub mod recording;
+pub mod reload;
+pub mod rundir;
+pub mod sink;
+pub mod sound;
+pub mod status;
+pub mod transcript;
+pub mod translate;
+pub mod tunnel;
+pub mod util;
+pub mod utterance;
+pub mod vad;
+pub mod validate;
+pub mod wake;
+pub mod whisper;
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{
+    commands::CommandConfig,
+    config::GeneralConfig,
+    diarize::DiarizationConfig,
+    hotkeys::HotkeyConfig,
+    pipeline::{PipelineConfig, RoomConfig},
+    piper::PiperConfig,
+    postprocess::PostprocessConfig,
+    recording::RecordingConfig,
+    sink::SinkConfig,
+    sound::{AudioConfig, block_pool::Block},
+    transcript::TranscriptConfig,
+    translate::TranslateConfig,
+    vad::VadConfig,
+    wake::WakeConfig,
+    whisper::WhisperConfig,
+};
+
+pub use crate::pipeline::{Pipeline, PipelineBuilder};
+
+// Configuration struct
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub general: GeneralConfig,
+    #[serde(default)]
+    pub hotkeys: HotkeyConfig,
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub vad: VadConfig, // How voice is told apart from silence
+    pub whisper: WhisperConfig,
+    pub piper: PiperConfig,
+    pub translate: Option<TranslateConfig>,
+    #[serde(default = "sink::default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+    pub transcript: Option<TranscriptConfig>, // Record every utterance of the session
+    pub recording: Option<RecordingConfig>,   // Keep the audio of every utterance for review
+    pub diarization: Option<DiarizationConfig>, // Tell apart the speakers on the input
+    pub postprocess: Option<PostprocessConfig>, // Fix up text for captions and speech
+    pub wake: Option<WakeConfig>, // Only translate after a wake word, for rooms where not everything should be
+    #[serde(default, rename = "room")]
+    pub rooms: Vec<RoomConfig>, // Speak the translation into other rooms, each in its own language
+    #[serde(default, rename = "pipeline")]
+    pub pipelines: Vec<PipelineConfig>, // Run several pipelines, e.g. both directions of a call
+    #[serde(default, rename = "command")]
+    pub commands: Vec<CommandConfig>, // Spoken phrases which switch the target language or voice
+}
+
+pub enum ProcessUnit {
+    // Audio from the realtime thread, returned to its pool when dropped
+    // Comes with what was played at the same time if echo is cancelled
+    Continue(Block, Option<Block>),
+    Reload(Arc<Config>), // Apply a changed config
+    SetTarget(String),   // Change the translation target language
+    SetVoice(String),    // Change the voice speaking the translation
+    SampleRate(usize),   // Rate of the audio that follows
+    BufferSize(usize),   // Frames the audio server processes at a time from now on
+    Xruns(u64),          // Xruns the audio server reported since the last were sent
+    Pause,               // Input stops until resumed, an unfinished recording can't be completed
+    Drain,               // Output what was already heard, then quit
+    Quit,
+}
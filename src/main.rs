@@ -1,256 +1,765 @@
-mod config;
-mod piper;
-mod sound;
-mod util;
-mod whisper;
+mod cli;
+mod daemon;
+mod kiosk;
+mod tui;
+mod wizard;
 
-use device_query::{DeviceQuery, DeviceState};
+use clap::Parser;
 use log::{error, info};
-use serde::Deserialize;
 use std::{
-    collections::VecDeque,
+    collections::HashMap,
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
-        mpsc::Receiver,
     },
-    thread::{self},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "pulse")]
+use live_translate_rs::sound::audio_pulse;
+use live_translate_rs::{
+    Config, Pipeline, control_server, controls::Controls, data_dir, doctor, hotkeys, metrics,
+    models, nsm::NsmSession, offline, oneshot, piper, reload, rundir, sound::jack_snapshot,
+    util::lock, whisper,
 };
-use webrtc_vad::Vad;
-use whisper_rs::WhisperContext;
 
 use crate::{
-    piper::play_tts,
-    sound::{AudioClient, AudioClientType, AudioConfig, audio_jack::JackClient},
+    cli::{Cli, Command, ConfigCommand, ModelsCommand, VoicesCommand},
+    daemon::Daemon,
+    tui::{LogBuffer, Tui},
 };
 
-// TODO: Add tests
+// How often the status file is rewritten
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
 
-// Configuration struct
-#[derive(Deserialize, Clone, Debug)]
-struct Config {
-    general: config::GeneralConfig,
-    audio: AudioConfig,
-    whisper: whisper::WhisperConfig,
-    piper: piper::PiperConfig,
-}
+// Wait before restarting a pipeline whose processing died, doubled each time it dies again
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
 
-enum ProcessUnit {
-    Continue(Vec<f32>),
-    Quit,
-}
+// Restarts in a row before giving up, counted from zero once a pipeline has run this long
+const MAX_RESTARTS: u32 = 5;
+const RESTART_RESET: Duration = Duration::from_secs(300);
 
-fn process_audio(
-    whisper_ctx: WhisperContext,
-    config: Arc<Config>,
-    play_buffer: Arc<Mutex<VecDeque<f32>>>,
-    audio: Receiver<ProcessUnit>,
-) {
-    // Recording state
-    let mut recording: bool = false; // Current recording status
-    let mut silence: u32 = 0; // How many blocks have been silent, used to decide when to stop recording
-    let mut samples: Vec<f32> = vec![];
-
-    // Voice activity detector instance
-    let mut vad = Vad::new_with_rate(webrtc_vad::SampleRate::Rate48kHz);
-
-    for unit in audio {
-        match unit {
-            ProcessUnit::Continue(in_buf) => {
-                // Convert to i16 for VAD
-                let mut samples_int = in_buf
-                    .iter()
-                    .map(|x| (x.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
-                    .collect::<Vec<_>>();
-
-                // Truncate to correct size
-                samples_int.truncate(960);
-
-                let is_voice = if config.general.push_to_talk {
-                    DeviceState::new()
-                        .get_keys()
-                        .contains(&config.general.ptt_key)
-                } else {
-                    // Detect voice activity
-                    match vad.is_voice_segment(&samples_int) {
-                        Ok(is_voice) => is_voice,
-                        Err(_) => {
-                            // No error returned >:(
-                            // https://github.com/kaegi/webrtc-vad/issues/9
-                            error!("VAD could not evaluate if the audio was voice!");
-                            continue;
-                        }
-                    }
-                };
+// Pipeline whose processing died, started again once its backoff has passed
+struct Restart {
+    name: String,
+    index: usize,  // Where it goes back among the pipelines
+    attempts: u32, // Restarts in a row
+    backoff: Duration,
+    due: Instant, // When to restart it, or when it was restarted
+}
 
-                // If recording already started
-                if recording {
-                    // Add samples to recording buffer
-                    samples.append(&mut in_buf.to_vec());
-
-                    // If voice activity detected
-                    if is_voice {
-                        // Reset silence counter
-                        silence = 0;
-                    } else {
-                        // Increment silence counter
-                        silence += 1;
-                    }
+impl Restart {
+    fn new(name: String, index: usize) -> Self {
+        Self {
+            name,
+            index,
+            attempts: 0,
+            backoff: MIN_RESTART_BACKOFF,
+            due: Instant::now(),
+        }
+    }
 
-                    // If there has been enough silence
-                    if silence >= config.whisper.silence_length {
-                        // Finish recording
-                        info!("Recording finished");
-                        recording = false;
-
-                        // Transcribe
-                        match whisper::transcribe(&config.whisper, &whisper_ctx, samples.clone()) {
-                            Ok(result) => {
-                                if let Some(result) = result {
-                                    // Play TTS
-                                    if let Err(err) = play_tts(play_buffer.clone(), result) {
-                                        error!("Could not generate TTS audio!\n{}", err)
-                                    };
-                                }
-                            }
-                            Err(err) => error!("Could not transcribe audio!\n{}", err),
-                        }
-                    }
-                } else {
-                    // If noise level increases
-                    if is_voice {
-                        // Start recording
-                        info!("Recording started...");
-                        recording = true;
-                        samples.clear(); // Clear previous recording
-                        samples.append(&mut in_buf.to_vec());
-                    }
-                }
-            }
-            ProcessUnit::Quit => break,
+    // Wait longer before the next attempt, None once there have been too many
+    fn schedule(&mut self) -> Option<Duration> {
+        if self.attempts >= MAX_RESTARTS {
+            return None;
         }
+
+        let wait = self.backoff;
+        self.attempts += 1;
+        self.due = Instant::now() + wait;
+        self.backoff = (wait * 2).min(MAX_RESTART_BACKOFF);
+        Some(wait)
     }
 }
 
 fn main() {
+    // Parse command line arguments
+    let cli = Cli::parse();
+
     // Initialise logger
-    // Custom format to force newlines, allowing raw mode so keys can be retrieved without pressing enter
+    // Logs go through a buffer so the TUI can show them while it owns the terminal
+    // A daemon logs straight to stderr, which systemd keeps in the journal
+    let logs = LogBuffer::default();
+    let target = if cli.daemon {
+        env_logger::Target::Stderr
+    } else {
+        env_logger::Target::Pipe(Box::new(logs.clone()))
+    };
     env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Info)
+        .filter_level(cli.log_level)
+        .target(target)
         .init();
 
+    // Models and voices live in a per-user directory unless told otherwise
+    data_dir::init(cli.data_dir.clone());
+
+    // Stopping another instance doesn't need a config
+    if let Some(Command::Stop) = cli.command {
+        match rundir::stop(&cli.profile) {
+            Ok(()) => info!("Stopped {}", cli.profile),
+            Err(err) => error!("Could not stop {}!\n{}", cli.profile, err),
+        }
+        return;
+    }
+
+    // Commands for another instance don't need a config either
+    if let Some(command @ (Command::Reload | Command::Status)) = &cli.command {
+        let request = match command {
+            Command::Reload => "reload",
+            _ => "status",
+        };
+        match rundir::send(&cli.profile, request) {
+            Ok(reply) => println!("{}", reply),
+            Err(err) => error!("Could not reach {}!\n{}", cli.profile, err),
+        }
+        return;
+    }
+
+    // Connections are restored from the saved state, not the config
+    if let Some(Command::RestoreConnections) = cli.command {
+        match jack_snapshot::restore_saved() {
+            Ok(0) => info!("No connections to restore"),
+            Ok(restored) => info!("Restored {} connections", restored),
+            Err(err) => error!("Could not connect to the jack server!\n{}", err),
+        }
+        return;
+    }
+
+    // Devices are listed from the server, not the config
+    #[cfg(feature = "pulse")]
+    if let Some(Command::Devices) = cli.command {
+        if let Err(err) = audio_pulse::print_devices() {
+            error!("Could not list pulseaudio devices!\n{}", err);
+        }
+        return;
+    }
+
+    // Models are managed without a config
+    if let Some(Command::Models { command }) = &cli.command {
+        match command {
+            ModelsCommand::List => models::print_list(),
+            ModelsCommand::Download { model } => {
+                if let Err(err) = models::download(model) {
+                    error!("Could not download {}!\n{}", model, err);
+                }
+            }
+        }
+        return;
+    }
+
+    // Voices go where the config says, or the data directory without one
+    if let Some(Command::Voices { command }) = &cli.command {
+        let voice_dir = reload::read_config(&cli.config_path()).map_or_else(
+            |_| data_dir::get().to_owned(),
+            |config| config.piper.voice_dir(),
+        );
+        match command {
+            VoicesCommand::List { language } => {
+                if let Err(err) = piper::print_voices(&voice_dir, language.as_deref()) {
+                    error!("Could not list piper voices!\n{}", err);
+                }
+            }
+            VoicesCommand::Download { voice } => match piper::download_voice(&voice_dir, voice) {
+                Ok(()) => info!("Downloaded {} to {}", voice, voice_dir.display()),
+                Err(err) => error!("Could not download {}!\n{}", voice, err),
+            },
+        }
+        return;
+    }
+
+    // Creating a config doesn't need one to exist
+    if let Some(Command::Config {
+        command: ConfigCommand::Init,
+    }) = cli.command
+    {
+        if let Err(err) = wizard::config_init(&cli.config_path()) {
+            error!("Could not create config!\n{}", err);
+        }
+        return;
+    }
+
+    // The kiosk only watches over an instance running in a child process
+    if cli.kiosk && cli.command.is_none() {
+        kiosk::run(&cli);
+        return;
+    }
+
+    // Under a session manager, the config and connections are kept with the session
+    let nsm = match NsmSession::announce() {
+        Some(Ok(session)) => Some(session),
+        Some(Err(err)) => {
+            error!("Could not join the NSM session!\n{}", err);
+            return;
+        }
+        None => None,
+    };
+    let client_id = nsm.as_ref().map(|session| session.client_id.clone());
+
     // Load configuration file
-    // TODO: Make tool for creating config if one isnt found
     // TODO: Potentially create macro for this pattern
     // TODO: Reconnect ports after disconnection when error occurs, where applicable
     // TODO: Kill piper server when error occurs, where applicable
-    let config = match std::fs::read_to_string("config.toml") {
-        Ok(content) => content,
-        Err(_) => {
-            error!("Could not read config file!");
+    let config_path = match &nsm {
+        Some(session) => session.config_path(&cli.config_path()),
+        None => cli.config_path(),
+    };
+    let mut config = match reload::read_config(&config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            if !config_path.exists() {
+                info!("Run `live-translate-rs config init` to create one");
+            }
             return;
         }
     };
 
-    // Parse TOML
-    let config: Arc<Config> = Arc::new(match toml::from_str(&config) {
-        Ok(parsed) => parsed,
-        Err(err) => {
-            error!("Could not parse config file!\n{}", err);
-            return;
+    apply_session(&mut config, client_id.as_deref());
+
+    // Nothing may be downloaded from here on
+    if cli.offline || config.general.offline {
+        offline::enable();
+    }
+
+    // Run one-shot commands instead of the live pipeline
+    if let Some(command) = cli.command {
+        match command {
+            Command::Transcribe {
+                file,
+                translate,
+                to,
+                output,
+            } => {
+                if let Err(err) =
+                    oneshot::transcribe_file(config, &file, translate, to, output.as_deref())
+                {
+                    error!("Could not transcribe {}!\n{}", file.display(), err);
+                }
+            }
+            Command::Doctor => {
+                if !doctor::run(&config) {
+                    std::process::exit(1);
+                }
+            }
+            Command::Stop
+            | Command::Reload
+            | Command::Status
+            | Command::RestoreConnections
+            | Command::Config { .. }
+            | Command::Models { .. }
+            | Command::Voices { .. } => {}
+            #[cfg(feature = "pulse")]
+            Command::Devices => {}
         }
-    });
+        return;
+    }
 
-    // Load whisper
-    let whisper_ctx = match whisper::setup_whisper(config.whisper.clone()) {
-        Ok(ctx) => ctx,
-        Err(err) => {
-            error!("Could not set up whisper!\n{}", err);
-            return;
+    // Check the pipeline end to end without an audio server
+    if cli.selftest {
+        if !doctor::selftest(&config) {
+            std::process::exit(1);
         }
-    };
+        return;
+    }
 
-    // Start TTS server
-    let mut piper = match piper::setup_piper(&config.piper) {
-        Ok(child) => child,
+    // Lock the profile so a second instance can't fight over the same ports
+    // Each client of a session has its own, so a session can run several
+    let profile = client_id.as_ref().unwrap_or(&cli.profile);
+    let run_dir = match rundir::RunDir::acquire(profile) {
+        Ok(run_dir) => run_dir,
         Err(err) => {
-            error!("Could not start piper server!\n{}", err);
+            error!("Could not start {}!\n{}", profile, err);
             return;
         }
     };
 
-    // Channel for sending audio from jack thread to processing thread
-    let (audio_tx, audio_rx) = std::sync::mpsc::channel::<ProcessUnit>();
+    let mut config = Arc::new(config);
+
+    // Effective config of each pipeline, just the main config if none are defined
+    let pipeline_configs: Vec<(String, Arc<Config>)> = if config.pipelines.is_empty() {
+        vec![("main".to_owned(), config.clone())]
+    } else {
+        config
+            .pipelines
+            .iter()
+            .map(|pipeline| (pipeline.name.clone(), Arc::new(pipeline.apply(&config))))
+            .collect()
+    };
 
-    // Buffer for playing audio
-    let play_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
+    // Every voice the pipelines use, or can be switched to by a spoken command
+    let pipeline_voices = pipeline_configs
+        .iter()
+        .map(|(_, config)| config.piper.model.clone())
+        .chain(
+            config
+                .rooms
+                .iter()
+                .map(|room| room.piper(&config.piper).model),
+        )
+        .chain(
+            config
+                .commands
+                .iter()
+                .filter_map(|command| command.voice.clone()),
+        )
+        .collect::<Vec<_>>();
+
+    // Nothing missing can be fetched, so report all of it before loading anything
+    if offline::enabled() {
+        let missing = offline::preflight(
+            pipeline_configs.iter().map(|(_, config)| config.as_ref()),
+            &config.piper,
+            &pipeline_voices,
+        );
+        if !missing.is_empty() {
+            error!("Can't run offline, missing:\n{}", missing.join("\n"));
+            return;
+        }
+        info!("Running offline, everything needed is there");
+    }
 
-    // Clone arcs for processing thread
-    let play_buffer_cloned = play_buffer.clone();
-    let config_cloned = config.clone();
+    // Load each whisper model once, shared between pipelines
+    let mut whisper_ctxs = HashMap::new();
+    for (_, pipeline_config) in &pipeline_configs {
+        let model = &pipeline_config.whisper.model;
+        if whisper_ctxs.contains_key(model) {
+            continue;
+        }
 
-    // Spawn processing thread
-    let audio_thread = match thread::Builder::new()
-        .name("audio_processor".to_owned())
-        .spawn(move || process_audio(whisper_ctx, config_cloned, play_buffer_cloned, audio_rx))
-    {
-        Ok(thread) => thread,
+        match whisper::setup_whisper(pipeline_config.whisper.clone()) {
+            Ok(ctx) => {
+                whisper_ctxs.insert(model.clone(), Some(Arc::new(ctx)));
+            }
+            // Keep recording to transcribe once it can be loaded
+            Err(err) if pipeline_config.whisper.backlog.is_some() => {
+                error!(
+                    "Could not set up whisper, only recording until it can be loaded!\n{}",
+                    err
+                );
+                whisper_ctxs.insert(model.clone(), None);
+            }
+            Err(err) => {
+                error!("Could not set up whisper!\n{}", err);
+                return;
+            }
+        };
+    }
+
+    // Start TTS server with every voice the pipelines use
+    let mut piper = match piper::setup_piper(&config.piper, &pipeline_voices, cli.force_reinstall) {
+        Ok(child) => child,
         Err(err) => {
-            error!("Could not start audio processing thread!\n{}", err);
+            error!("Could not start piper server!\n{}", err);
             return;
         }
     };
 
-    // Clone for use in closure
-    let audio_tx_cloned = audio_tx.clone();
-    let play_buffer_cloned = play_buffer.clone();
+    // Controls shared with hotkeys
+    let controls = Arc::new(Controls::default());
 
-    let audio_client_type = &config.general.audio_client;
+    // Bool so that program can safely exit
+    let running = Arc::new(AtomicBool::new(true));
 
-    // Create audio client
-    // TODO: Try to fit this into its own function
-    let mut audio_client = match audio_client_type {
-        AudioClientType::Jack => JackClient::new(&config.audio.jack.clone().unwrap()).unwrap(),
-    };
+    // Start every pipeline
+    let mut pipelines = vec![];
+    for (name, pipeline_config) in pipeline_configs {
+        let whisper_ctx = whisper_ctxs[&pipeline_config.whisper.model].clone();
 
-    // Start audio client
-    audio_client
-        .start(audio_tx_cloned, play_buffer_cloned)
-        .unwrap();
+        match Pipeline::start(name.clone(), pipeline_config, whisper_ctx, controls.clone()) {
+            Ok(pipeline) => pipelines.push(pipeline),
+            Err(err) => {
+                error!("Could not start pipeline {}!\n{}", name, err);
+
+                // Release already running pipelines
+                for pipeline in pipelines {
+                    pipeline.stop();
+                }
+                if let Err(err) = piper.kill() {
+                    error!("Could not kill piper server!\n{}", err);
+                };
+                return;
+            }
+        }
+    }
+
+    // Listen for hotkeys
+    let hotkey_thread =
+        match hotkeys::spawn_hotkeys(config.hotkeys.clone(), controls.clone(), running.clone()) {
+            Ok(thread) => Some(thread),
+            Err(err) => {
+                error!("Could not start hotkey thread!\n{}", err);
+                None
+            }
+        };
 
-    // Bool so that program can safely exit
-    let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
 
-    // Handler for exit
+    // Handler for exit, without it the TUI or control socket can still stop the program
     if let Err(err) = ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
+        // A second Ctrl+C doesn't wait for the pipelines to drain
+        if !r.swap(false, Ordering::SeqCst) {
+            eprintln!("Exiting without finishing");
+            std::process::exit(130);
+        }
     }) {
         error!("Could not create crtlc handle!\n{}", err);
-        return;
     };
 
+    // Watch for config changes
+    let config_watcher = match reload::watch_config(config_path.clone(), running.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            error!("Could not watch config file for changes!\n{}", err);
+            None
+        }
+    };
+
+    // Listen for commands from other processes, with the status rendered here
+    let statuses = pipelines
+        .iter()
+        .map(|pipeline| (pipeline.name.clone(), pipeline.status.clone()))
+        .collect::<Vec<_>>();
+    let status = Arc::new(Mutex::new(rundir::status(&pipelines, &controls)));
+    let control_thread = match run_dir.listen(
+        controls.clone(),
+        running.clone(),
+        statuses.clone(),
+        status.clone(),
+    ) {
+        Ok(thread) => Some(thread),
+        Err(err) => {
+            error!("Could not open control socket!\n{}", err);
+            None
+        }
+    };
+
+    // Serve the control API for stream decks and scripts
+    let control_server = config.general.control.as_ref().and_then(|address| {
+        match control_server::serve(
+            address,
+            controls.clone(),
+            running.clone(),
+            statuses,
+            status.clone(),
+            config.piper.voice_dir(),
+            config.general.control_token.clone(),
+        ) {
+            Ok(server) => {
+                info!("Serving control API on http://{}", address);
+                Some(server)
+            }
+            Err(err) => {
+                error!("Could not serve control API on {}!\n{}", address, err);
+                None
+            }
+        }
+    });
+    let mut status_written = Instant::now();
+
+    // Connect as the session was saved, then save with it from now on
+    let nsm_thread = nsm.and_then(|session| {
+        session.restore();
+        let client_name = config
+            .audio
+            .jack
+            .as_ref()
+            .map(|jack| jack.client_name.clone());
+        match session.serve(client_name, running.clone()) {
+            Ok(thread) => Some(thread),
+            Err(err) => {
+                error!("Could not start session manager thread!\n{}", err);
+                None
+            }
+        }
+    });
+
+    // Serve metrics for monitoring, rendered here along with the status
+    let metrics = Arc::new(Mutex::new(metrics::render(&pipelines)));
+    let metrics_thread = config.general.metrics.as_ref().and_then(|address| {
+        match metrics::serve(address, metrics.clone(), running.clone()) {
+            Ok(thread) => {
+                info!("Serving metrics on http://{}/metrics", address);
+                Some(thread)
+            }
+            Err(err) => {
+                error!("Could not serve metrics on {}!\n{}", address, err);
+                None
+            }
+        }
+    });
+
+    // Show the interface unless asked not to
+    let mut tui = if cli.no_tui || cli.daemon {
+        None
+    } else {
+        match Tui::new(logs) {
+            Ok(tui) => Some(tui),
+            Err(err) => {
+                error!("Could not start TUI!\n{}", err);
+                None
+            }
+        }
+    };
+
+    // Tell systemd we're up when running as a service
+    let mut daemon = cli.daemon.then(Daemon::from_env);
+    if let Some(daemon) = &daemon {
+        daemon.ready();
+    }
+    let mut reloads = controls.reloads();
+
+    // Pipelines waiting to be restarted, and when each was last restarted
+    let mut restarts: Vec<Restart> = vec![];
+    let mut restarted: HashMap<String, Restart> = HashMap::new();
+
     // Keep running until exit
     while running.load(Ordering::SeqCst) {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        // Redraw and handle keys, or just wait
+        match &mut tui {
+            Some(tui) => {
+                if let Err(err) = tui.update(&pipelines, &controls, &running) {
+                    error!("Could not update TUI!\n{}", err);
+                }
+            }
+            None => std::thread::sleep(Duration::from_millis(100)),
+        }
+        if let Some(daemon) = &mut daemon {
+            daemon.ping();
+        }
+
+        // Publish live status
+        if status_written.elapsed() >= STATUS_INTERVAL {
+            if let Err(err) = run_dir.write_status(&pipelines, &controls) {
+                error!("Could not write status file!\n{}", err);
+            }
+            if metrics_thread.is_some() {
+                *lock(&metrics) = metrics::render(&pipelines);
+            }
+            *lock(&status) = rundir::status(&pipelines, &controls);
+            status_written = Instant::now();
+        }
+
+        // Input from files ends, quit once all of it was heard
+        if !pipelines.is_empty() && pipelines.iter().all(Pipeline::input_finished) {
+            info!("Reached the end of the input");
+            running.store(false, Ordering::SeqCst);
+            break;
+        }
+
+        // Start pipelines again if their processing died, rather than leaving them silent
+        // Each restart in a row waits longer, until there were too many
+        let mut index = 0;
+        while index < pipelines.len() {
+            if !pipelines[index].processing_stopped() {
+                index += 1;
+                continue;
+            }
+
+            // Closing it restores the connections it changed and frees the ports its sinks use
+            let pipeline = pipelines.remove(index);
+            let name = pipeline.name.clone();
+            pipeline.stop();
+
+            let mut restart = match restarted.remove(&name) {
+                Some(restart) if restart.due.elapsed() < RESTART_RESET => restart,
+                _ => Restart::new(name.clone(), index),
+            };
+            restart.index = index;
+            let Some(wait) = restart.schedule() else {
+                error!(
+                    "Processing of pipeline {} stopped {} times in a row, stopping!",
+                    name, restart.attempts
+                );
+                running.store(false, Ordering::SeqCst);
+                break;
+            };
+            error!(
+                "Processing of pipeline {} stopped unexpectedly, restarting it in {}s",
+                name,
+                wait.as_secs()
+            );
+            restarts.push(restart);
+        }
+
+        let (due, waiting): (Vec<_>, Vec<_>) = restarts
+            .drain(..)
+            .partition(|restart| restart.due <= Instant::now());
+        restarts = waiting;
+        for mut restart in due {
+            let pipeline_config = config
+                .pipelines
+                .iter()
+                .find(|pipeline| pipeline.name == restart.name)
+                .map_or_else(
+                    || config.clone(),
+                    |pipeline| Arc::new(pipeline.apply(&config)),
+                );
+            let whisper_ctx = whisper_ctxs
+                .get(&pipeline_config.whisper.model)
+                .cloned()
+                .flatten();
+            match Pipeline::start(
+                restart.name.clone(),
+                pipeline_config,
+                whisper_ctx,
+                controls.clone(),
+            ) {
+                Ok(pipeline) => {
+                    info!("Restarted pipeline {}", restart.name);
+                    pipelines.insert(restart.index.min(pipelines.len()), pipeline);
+                    restart.due = Instant::now();
+                    restarted.insert(restart.name.clone(), restart);
+                }
+                Err(err) => match restart.schedule() {
+                    Some(wait) => {
+                        error!(
+                            "Could not restart pipeline {}, retrying in {}s\n{}",
+                            restart.name,
+                            wait.as_secs(),
+                            err
+                        );
+                        restarts.push(restart);
+                    }
+                    None => {
+                        error!(
+                            "Could not restart pipeline {}, stopping!\n{}",
+                            restart.name, err
+                        );
+                        running.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                },
+            }
+        }
+
+        // Apply commands for the pipelines from the control socket and API
+        for (command_rx, _) in control_thread.iter().chain(&control_server) {
+            while let Ok(command) = command_rx.try_recv() {
+                command.apply(&pipelines);
+            }
+        }
+
+        // Read the config again when asked to through the control socket
+        let new_config = if controls.reloads() != reloads {
+            reloads = controls.reloads();
+            match reload::read_config(&config_path) {
+                Ok(new_config) => {
+                    info!("Reloading config");
+                    Some(new_config)
+                }
+                Err(err) => {
+                    error!("Could not reload config!\n{}", err);
+                    None
+                }
+            }
+        } else {
+            config_watcher
+                .as_ref()
+                .and_then(|(config_rx, _)| config_rx.try_recv().ok())
+        };
+
+        // Apply config changes to every pipeline
+        if let Some(mut new_config) = new_config {
+            apply_session(&mut new_config, client_id.as_deref());
+            if let Some(daemon) = &daemon {
+                daemon.reloading();
+            }
+            config = Arc::new(reload::merge_config(&config, new_config));
+
+            // Matched by name, a pipeline waiting to restart leaves a gap
+            for pipeline in &pipelines {
+                match config
+                    .pipelines
+                    .iter()
+                    .find(|pipeline_config| pipeline_config.name == pipeline.name)
+                {
+                    Some(pipeline_config) => {
+                        pipeline.reload(Arc::new(pipeline_config.apply(&config)))
+                    }
+                    None => pipeline.reload(config.clone()),
+                }
+            }
+            if let Some(daemon) = &daemon {
+                daemon.ready();
+            }
+        }
     }
 
-    // Stop processing thread
-    if let Err(err) = audio_tx.send(ProcessUnit::Quit) {
-        error!(
-            "Could not send stop signal to audio processing thread!\n{}",
-            err
-        );
-    };
-    if let Err(_) = audio_thread.join() {
-        error!("Could not join audio processing thread!");
-    };
+    if let Some(daemon) = &daemon {
+        daemon.stopping();
+    }
 
-    // Kill audio client
-    audio_client.stop();
+    // Stop capturing, what was already heard is finished while the other threads stop
+    info!("Shutting down, finishing what was already heard");
+    let deadline =
+        Instant::now() + Duration::from_secs_f32(config.general.shutdown_timeout.max(0.0));
+    for pipeline in &pipelines {
+        pipeline.drain();
+    }
 
-    // Kill TTS
+    // Give the terminal back
+    drop(tui);
+
+    // Stop config watcher
+    if let Some((_, config_thread)) = config_watcher
+        && config_thread.join().is_err()
+    {
+        error!("Could not join config watcher thread!");
+    }
+
+    // Stop control socket
+    if let Some((_, control_thread)) = control_thread
+        && control_thread.join().is_err()
+    {
+        error!("Could not join control socket thread!");
+    }
+
+    // Stop control API
+    if let Some((_, control_server)) = control_server
+        && control_server.join().is_err()
+    {
+        error!("Could not join control API thread!");
+    }
+
+    // Stop metrics server
+    if let Some(metrics_thread) = metrics_thread
+        && metrics_thread.join().is_err()
+    {
+        error!("Could not join metrics thread!");
+    }
+
+    // Stop answering the session manager
+    if let Some(nsm_thread) = nsm_thread
+        && nsm_thread.join().is_err()
+    {
+        error!("Could not join session manager thread!");
+    }
+
+    // Stop hotkey thread
+    if let Some(hotkey_thread) = hotkey_thread
+        && hotkey_thread.join().is_err()
+    {
+        error!("Could not join hotkey thread!");
+    }
+
+    // Wait for the pipelines to drain, their audio clients reconnect the ports they changed
+    for pipeline in pipelines {
+        pipeline.shutdown(deadline);
+    }
+
+    // Stop TTS once nothing needs it anymore
     if let Err(err) = piper.kill() {
         error!("Could not kill piper server!\n{}", err);
     };
 }
+
+// Under a session manager the JACK client takes the id it was given, whatever the config says
+fn apply_session(config: &mut Config, client_id: Option<&str>) {
+    if let Some(client_id) = client_id
+        && let Some(jack) = config.audio.jack.as_mut()
+    {
+        jack.client_name = client_id.to_owned();
+    }
+}
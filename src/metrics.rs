@@ -0,0 +1,225 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::error;
+
+use crate::{pipeline::Pipeline, util::lock};
+
+// How often the listener is checked for connections
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Counters of a pipeline, added to by the processing thread
+#[derive(Debug, Default)]
+pub struct Metrics {
+    utterances: AtomicU64,
+    whisper_micros: AtomicU64, // Total time spent in whisper
+    whisper_runs: AtomicU64,
+    tts_micros: AtomicU64, // Total time spent synthesizing speech
+    tts_runs: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_utterance(&self) {
+        self.utterances.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_whisper(&self, duration: Duration) {
+        self.whisper_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.whisper_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_tts(&self, duration: Duration) {
+        self.tts_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.tts_runs.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Add a metric with a value for each pipeline
+fn metric<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    pipelines: &[Pipeline],
+    value: impl Fn(&Pipeline) -> T,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for pipeline in pipelines {
+        let _ = writeln!(
+            out,
+            "{}{{pipeline=\"{}\"}} {}",
+            name,
+            pipeline.name,
+            value(pipeline)
+        );
+    }
+}
+
+// Metrics of every pipeline in the prometheus text format
+pub fn render(pipelines: &[Pipeline]) -> String {
+    let mut out = String::new();
+    let seconds = |micros: &AtomicU64| micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+
+    metric(
+        &mut out,
+        "live_translate_utterances_total",
+        "counter",
+        "Utterances transcribed and sent to the outputs",
+        pipelines,
+        |pipeline| pipeline.status.metrics.utterances.load(Ordering::Relaxed),
+    );
+    metric(
+        &mut out,
+        "live_translate_whisper_seconds_sum",
+        "counter",
+        "Time spent transcribing with whisper",
+        pipelines,
+        |pipeline| seconds(&pipeline.status.metrics.whisper_micros),
+    );
+    metric(
+        &mut out,
+        "live_translate_whisper_seconds_count",
+        "counter",
+        "Recordings transcribed with whisper",
+        pipelines,
+        |pipeline| pipeline.status.metrics.whisper_runs.load(Ordering::Relaxed),
+    );
+    metric(
+        &mut out,
+        "live_translate_tts_seconds_sum",
+        "counter",
+        "Time spent synthesizing speech",
+        pipelines,
+        |pipeline| seconds(&pipeline.status.metrics.tts_micros),
+    );
+    metric(
+        &mut out,
+        "live_translate_tts_seconds_count",
+        "counter",
+        "Utterances synthesized",
+        pipelines,
+        |pipeline| pipeline.status.metrics.tts_runs.load(Ordering::Relaxed),
+    );
+    metric(
+        &mut out,
+        "live_translate_latency_seconds",
+        "gauge",
+        "Time from the end of speech to output of the last utterance",
+        pipelines,
+        |pipeline| pipeline.status.latency().as_secs_f64(),
+    );
+    metric(
+        &mut out,
+        "live_translate_play_buffer_seconds",
+        "gauge",
+        "Speech waiting to be played",
+        pipelines,
+        |pipeline| pipeline.play_buffer.queued(),
+    );
+    metric(
+        &mut out,
+        "live_translate_dropped_blocks_total",
+        "counter",
+        "Audio blocks dropped because processing fell behind",
+        pipelines,
+        |pipeline| pipeline.dropped_blocks(),
+    );
+    metric(
+        &mut out,
+        "live_translate_xruns_total",
+        "counter",
+        "Buffer overruns and underruns reported by the audio server",
+        pipelines,
+        |pipeline| pipeline.xruns(),
+    );
+    metric(
+        &mut out,
+        "live_translate_prefill_seconds",
+        "gauge",
+        "Speech buffered before playing starts, grown after repeated xruns",
+        pipelines,
+        |pipeline| pipeline.play_buffer.prefill(),
+    );
+    metric(
+        &mut out,
+        "live_translate_period_frames",
+        "gauge",
+        "Frames per period of the audio server",
+        pipelines,
+        |pipeline| pipeline.status.period(),
+    );
+    metric(
+        &mut out,
+        "live_translate_sample_rate",
+        "gauge",
+        "Sample rate of the audio server",
+        pipelines,
+        |pipeline| pipeline.play_buffer.sample_rate(),
+    );
+
+    out
+}
+
+// Answer one request with the latest metrics
+fn handle_request(stream: TcpStream, metrics: &Mutex<String>) -> Result<(), std::io::Error> {
+    stream.set_nonblocking(false)?;
+
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, body) = if target == "/metrics" {
+        ("200 OK", lock(metrics).clone())
+    } else {
+        ("404 Not Found", "not found\n".to_owned())
+    };
+
+    write!(
+        &stream,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// Serve the metrics over HTTP until told to stop
+// The main thread renders them into metrics, as it owns the pipelines
+pub fn serve(
+    address: &str,
+    metrics: Arc<Mutex<String>>,
+    running: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+
+    thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(err) = handle_request(stream, &metrics) {
+                            error!("Could not answer metrics request!\n{}", err);
+                        }
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(err) => error!("Could not accept metrics connection!\n{}", err),
+                }
+            }
+        })
+}
@@ -0,0 +1,287 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use reqwest::{StatusCode, header::RANGE};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{data_dir, offline};
+
+// Repository whisper.cpp publishes its models in
+const REPO_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+// Listing of the repository, with the size and SHA256 of every file
+const TREE_URL: &str = "https://huggingface.co/api/models/ggerganov/whisper.cpp/tree/main";
+
+// Models whisper.cpp publishes, used when the repository can't be listed
+// Quantized variants are smaller and faster at a small cost in accuracy
+pub const MODELS: [&str; 33] = [
+    "tiny",
+    "tiny-q5_1",
+    "tiny-q8_0",
+    "tiny.en",
+    "tiny.en-q5_1",
+    "tiny.en-q8_0",
+    "base",
+    "base-q5_1",
+    "base-q8_0",
+    "base.en",
+    "base.en-q5_1",
+    "base.en-q8_0",
+    "small",
+    "small-q5_1",
+    "small-q8_0",
+    "small.en",
+    "small.en-q5_1",
+    "small.en-q8_0",
+    "medium",
+    "medium-q5_0",
+    "medium-q8_0",
+    "medium.en",
+    "medium.en-q5_0",
+    "medium.en-q8_0",
+    "large-v1",
+    "large-v2",
+    "large-v2-q5_0",
+    "large-v2-q8_0",
+    "large-v3",
+    "large-v3-q5_0",
+    "large-v3-turbo",
+    "large-v3-turbo-q5_0",
+    "large-v3-turbo-q8_0",
+];
+
+#[derive(Debug)]
+pub enum ErrModel {
+    IoError(std::io::Error),
+    ReqwestError(reqwest::Error),
+    UnknownModel(String),
+    ChecksumMismatch(String),
+    Offline(String),
+}
+
+impl Display for ErrModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::ReqwestError(reqwest_error) => {
+                write!(f, "Could not download whisper model!\n{}", reqwest_error)
+            }
+            Self::UnknownModel(model) => write!(
+                f,
+                "{} isn't a whisper model, `live-translate-rs models list` shows them all",
+                model
+            ),
+            Self::ChecksumMismatch(model) => write!(
+                f,
+                "Download of {} is corrupt and was removed, try again",
+                model
+            ),
+            Self::Offline(model) => write!(f, "Model {} can't be downloaded offline", model),
+        }
+    }
+}
+
+impl std::error::Error for ErrModel {}
+
+impl From<std::io::Error> for ErrModel {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<reqwest::Error> for ErrModel {
+    fn from(value: reqwest::Error) -> Self {
+        Self::ReqwestError(value)
+    }
+}
+
+// A model that can be downloaded
+pub struct Model {
+    pub name: String,
+    pub size: u64,              // Bytes, 0 if unknown
+    pub sha256: Option<String>, // Checksum of the file, if known
+}
+
+// File in the repository listing
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    size: u64,
+    lfs: Option<Lfs>,
+}
+
+// Large files are stored with their SHA256 as the object id
+#[derive(Deserialize)]
+struct Lfs {
+    oid: String,
+}
+
+// Where a model is kept once downloaded
+pub fn model_path(model: &str) -> PathBuf {
+    data_dir::path(format!("whisper/ggml-{}.bin", model))
+}
+
+// Every model in the repository
+pub fn available() -> Result<Vec<Model>, reqwest::Error> {
+    let entries: Vec<TreeEntry> = reqwest::blocking::get(TREE_URL)?
+        .error_for_status()?
+        .json()?;
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| {
+            let name = entry.path.strip_prefix("ggml-")?.strip_suffix(".bin")?;
+            Some(Model {
+                name: name.to_owned(),
+                size: entry.size,
+                sha256: entry.lfs.map(|lfs| lfs.oid),
+            })
+        })
+        .collect())
+}
+
+// Print every model that can be downloaded, marking the ones already downloaded
+pub fn print_list() {
+    let models = available().unwrap_or_else(|err| {
+        warn!(
+            "Could not list whisper models, showing the known ones\n{}",
+            err
+        );
+        MODELS
+            .iter()
+            .map(|model| Model {
+                name: model.to_string(),
+                size: 0,
+                sha256: None,
+            })
+            .collect()
+    });
+
+    for model in models {
+        let size = match model.size {
+            0 => String::new(),
+            size => format!("{} MB", size / 1_000_000),
+        };
+        let downloaded = if model_path(&model.name).exists() {
+            "downloaded"
+        } else {
+            ""
+        };
+        println!("{:<24}{:>10}  {}", model.name, size, downloaded);
+    }
+}
+
+// SHA256 of a file as lowercase hex
+fn file_sha256(path: &Path) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn progress_bar(model: &str, total: u64) -> ProgressBar {
+    let progress = ProgressBar::new(total);
+    if let Ok(style) = ProgressStyle::with_template(
+        "{msg} [{bar:40}] {bytes}/{total_bytes} at {bytes_per_sec}, {eta} left",
+    ) {
+        progress.set_style(style.progress_chars("=> "));
+    }
+    progress.set_message(format!("Downloading {}", model));
+
+    progress
+}
+
+// Download a model, carrying on from an earlier partial download and checking the result
+pub fn download(model: &str) -> Result<PathBuf, ErrModel> {
+    std::fs::create_dir_all(data_dir::path("whisper"))?;
+    let path = model_path(model);
+    let partial = path.with_extension("bin.part");
+
+    // Checksum to verify against, if the repository can be listed
+    let sha256 = match available() {
+        Ok(models) => {
+            models
+                .into_iter()
+                .find(|known| known.name == model)
+                .ok_or_else(|| ErrModel::UnknownModel(model.to_owned()))?
+                .sha256
+        }
+        Err(err) => {
+            warn!(
+                "Could not list whisper models, downloading {} without checking it\n{}",
+                model, err
+            );
+            None
+        }
+    };
+
+    // Ask for the rest of a partial download
+    let mut offset = std::fs::metadata(&partial).map_or(0, |metadata| metadata.len());
+    // Large models take longer than the default timeout
+    let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+    let mut request = client.get(format!("{}/ggml-{}.bin", REPO_URL, model));
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let response = request.send()?;
+
+    // Nothing is left if the partial download is already complete
+    if response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+        let mut response = response.error_for_status()?;
+
+        // Servers which ignore the range send everything again
+        let mut options = OpenOptions::new();
+        options.create(true);
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            info!(
+                "Resuming download of {} at {} MB",
+                model,
+                offset / 1_000_000
+            );
+            options.append(true);
+        } else {
+            offset = 0;
+            options.write(true).truncate(true);
+        }
+        let file = options.open(&partial)?;
+
+        let progress = progress_bar(model, offset + response.content_length().unwrap_or(0));
+        progress.set_position(offset);
+        std::io::copy(&mut response, &mut progress.wrap_write(file))?;
+        progress.finish_and_clear();
+    }
+
+    // Throw away a corrupt download, so the next try starts over
+    if let Some(sha256) = sha256 {
+        info!("Checking download of {}", model);
+        if file_sha256(&partial)? != sha256 {
+            std::fs::remove_file(&partial)?;
+            return Err(ErrModel::ChecksumMismatch(model.to_owned()));
+        }
+    }
+    std::fs::rename(&partial, &path)?;
+    info!("Model {} downloaded", model);
+
+    Ok(path)
+}
+
+// Path of a model, downloading it first if it's missing
+pub fn ensure(model: &str) -> Result<PathBuf, ErrModel> {
+    let path = model_path(model);
+    if path.exists() {
+        return Ok(path);
+    }
+    if offline::enabled() {
+        return Err(ErrModel::Offline(model.to_owned()));
+    }
+
+    warn!("Model {} not found, attempting to download", path.display());
+    download(model)
+}
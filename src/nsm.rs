@@ -0,0 +1,391 @@
+use std::{
+    fmt::Display,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+
+use crate::{sink::osc::push_osc_string, sound::audio_jack};
+
+// Name shown in the session manager
+const APPLICATION_NAME: &str = "Live Translate";
+
+// NSM API version spoken
+const API_MAJOR: i32 = 1;
+const API_MINOR: i32 = 2;
+
+// How long the session manager has to tell the client to open its session
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often the socket is checked for messages while running
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Largest message read, NSM only sends a few short strings
+const MAX_PACKET: usize = 4096;
+
+// Files kept in the session's directory
+const CONFIG_FILE: &str = "config.toml";
+const CONNECTIONS_FILE: &str = "connections.json";
+
+#[derive(Debug)]
+pub enum ErrNsm {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+    JackError(jack::Error),
+    InvalidUrl(String),
+    Refused(String), // Message the session manager gave
+    Timeout,
+}
+
+impl Display for ErrNsm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(error) => write!(f, "{}", error),
+            Self::JsonError(error) => write!(f, "{}", error),
+            Self::JackError(error) => write!(f, "{}", error),
+            Self::InvalidUrl(url) => write!(f, "Invalid NSM_URL {}", url),
+            Self::Refused(message) => write!(f, "Session manager refused the client: {}", message),
+            Self::Timeout => write!(f, "Session manager didn't open a session in time"),
+        }
+    }
+}
+
+impl std::error::Error for ErrNsm {}
+
+impl From<std::io::Error> for ErrNsm {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for ErrNsm {
+    fn from(value: serde_json::Error) -> Self {
+        Self::JsonError(value)
+    }
+}
+
+impl From<jack::Error> for ErrNsm {
+    fn from(value: jack::Error) -> Self {
+        Self::JackError(value)
+    }
+}
+
+// An argument of an OSC message, only the types NSM uses
+enum Arg {
+    Str(String),
+    Int(i32),
+}
+
+// Build an OSC message from its address and arguments
+fn message(address: &str, args: &[Arg]) -> Vec<u8> {
+    let mut packet = vec![];
+    push_osc_string(&mut packet, address);
+
+    let tags = args
+        .iter()
+        .map(|arg| match arg {
+            Arg::Str(_) => 's',
+            Arg::Int(_) => 'i',
+        })
+        .collect::<String>();
+    push_osc_string(&mut packet, &format!(",{}", tags));
+
+    for arg in args {
+        match arg {
+            Arg::Str(s) => push_osc_string(&mut packet, s),
+            Arg::Int(i) => packet.extend_from_slice(&i.to_be_bytes()),
+        }
+    }
+
+    packet
+}
+
+// Read a null terminated OSC string, moving past its padding
+fn read_string(packet: &[u8], position: &mut usize) -> Option<String> {
+    let rest = packet.get(*position..)?;
+    let length = rest.iter().position(|byte| *byte == 0)?;
+    let s = String::from_utf8_lossy(&rest[..length]).into_owned();
+    *position += (length + 4) & !3;
+    Some(s)
+}
+
+// Address and arguments of an OSC message, None if it has arguments NSM doesn't use
+fn parse(packet: &[u8]) -> Option<(String, Vec<Arg>)> {
+    let mut position = 0;
+    let address = read_string(packet, &mut position)?;
+    let tags = read_string(packet, &mut position).unwrap_or_default();
+
+    let mut args = vec![];
+    for tag in tags.chars().skip(1) {
+        match tag {
+            's' => args.push(Arg::Str(read_string(packet, &mut position)?)),
+            'i' => {
+                let bytes = packet.get(position..position + 4)?;
+                args.push(Arg::Int(i32::from_be_bytes(bytes.try_into().ok()?)));
+                position += 4;
+            }
+            _ => return None,
+        }
+    }
+
+    Some((address, args))
+}
+
+// String arguments of a message, the rest left out
+fn strings(args: &[Arg]) -> Vec<&str> {
+    args.iter()
+        .filter_map(|arg| match arg {
+            Arg::Str(s) => Some(s.as_str()),
+            Arg::Int(_) => None,
+        })
+        .collect()
+}
+
+// Address of the session manager from an NSM_URL like "osc.udp://host:port/"
+fn server_address(url: &str) -> Result<SocketAddr, ErrNsm> {
+    url.strip_prefix("osc.udp://")
+        .map(|address| address.trim_end_matches('/'))
+        .and_then(|address| address.to_socket_addrs().ok()?.next())
+        .ok_or_else(|| ErrNsm::InvalidUrl(url.to_owned()))
+}
+
+// A session of the Non/New Session Manager the client was opened in
+// The config and connections are kept in the session's directory, and the JACK client
+// takes the id the session manager gave it, so the session can be saved and restored as a whole
+pub struct NsmSession {
+    pub path: PathBuf, // Directory for the client's files in this session
+    pub display_name: String,
+    pub client_id: String, // Unique in the session, used as the JACK client name
+    socket: UdpSocket,
+    server: SocketAddr,
+}
+
+impl NsmSession {
+    // Announce to the session manager in NSM_URL and wait to be told where the session is,
+    // None if not started by a session manager
+    pub fn announce() -> Option<Result<Self, ErrNsm>> {
+        let url = std::env::var("NSM_URL").ok()?;
+        Some(Self::join(&url))
+    }
+
+    fn join(url: &str) -> Result<Self, ErrNsm> {
+        let server = server_address(url)?;
+        let socket = UdpSocket::bind(if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+
+        let executable = std::env::args()
+            .next()
+            .and_then(|path| {
+                Path::new(&path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "live-translate-rs".to_owned());
+        socket.send_to(
+            &message(
+                "/nsm/server/announce",
+                &[
+                    Arg::Str(APPLICATION_NAME.to_owned()),
+                    Arg::Str(":".to_owned()),
+                    Arg::Str(executable),
+                    Arg::Int(API_MAJOR),
+                    Arg::Int(API_MINOR),
+                    Arg::Int(std::process::id() as i32),
+                ],
+            ),
+            server,
+        )?;
+
+        // The announce is answered, then the session manager says which session to open
+        let deadline = Instant::now() + OPEN_TIMEOUT;
+        let mut buffer = [0; MAX_PACKET];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ErrNsm::Timeout);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(ErrNsm::Timeout);
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let Some((address, args)) = parse(&buffer[..length]) else {
+                continue;
+            };
+            let args = strings(&args);
+
+            match (address.as_str(), args.as_slice()) {
+                ("/reply", ["/nsm/server/announce", message, server_name, ..]) => {
+                    info!("Announced to {}: {}", server_name, message);
+                }
+                ("/error", ["/nsm/server/announce", message, ..]) => {
+                    return Err(ErrNsm::Refused((*message).to_owned()));
+                }
+                ("/nsm/client/open", [path, display_name, client_id, ..]) => {
+                    let session = Self {
+                        path: PathBuf::from(path),
+                        display_name: (*display_name).to_owned(),
+                        client_id: (*client_id).to_owned(),
+                        socket,
+                        server,
+                    };
+                    std::fs::create_dir_all(&session.path)?;
+                    session.reply("/nsm/client/open", "Opened");
+                    info!(
+                        "Opened NSM session in {} as {}",
+                        session.path.display(),
+                        session.client_id
+                    );
+                    return Ok(session);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn reply(&self, address: &str, text: &str) {
+        let packet = message(
+            "/reply",
+            &[Arg::Str(address.to_owned()), Arg::Str(text.to_owned())],
+        );
+        if let Err(err) = self.socket.send_to(&packet, self.server) {
+            error!("Could not reply to the session manager!\n{}", err);
+        }
+    }
+
+    fn error(&self, address: &str, text: &str) {
+        // General error, the API has no closer code for a failed save
+        let packet = message(
+            "/error",
+            &[
+                Arg::Str(address.to_owned()),
+                Arg::Int(-1),
+                Arg::Str(text.to_owned()),
+            ],
+        );
+        if let Err(err) = self.socket.send_to(&packet, self.server) {
+            error!("Could not reply to the session manager!\n{}", err);
+        }
+    }
+
+    // Config of the session, started from the usual one the first time the client is added
+    pub fn config_path(&self, default: &Path) -> PathBuf {
+        let path = self.path.join(CONFIG_FILE);
+        if !path.exists()
+            && default.exists()
+            && let Err(err) = std::fs::copy(default, &path)
+        {
+            error!(
+                "Could not copy {} into the session!\n{}",
+                default.display(),
+                err
+            );
+        }
+        path
+    }
+
+    // Save the connections of the JACK client with the session
+    fn save(&self, client_name: Option<&str>) -> Result<usize, ErrNsm> {
+        let connections = match client_name {
+            Some(client_name) => audio_jack::client_connections(client_name)?,
+            None => vec![],
+        };
+        std::fs::write(
+            self.path.join(CONNECTIONS_FILE),
+            serde_json::to_vec_pretty(&connections)?,
+        )?;
+
+        Ok(connections.len())
+    }
+
+    // Make the connections saved with the session, once the client's ports exist
+    pub fn restore(&self) {
+        let connections: Vec<(String, String)> =
+            match std::fs::read(self.path.join(CONNECTIONS_FILE)) {
+                Ok(content) => match serde_json::from_slice(&content) {
+                    Ok(connections) => connections,
+                    Err(err) => {
+                        error!("Could not parse the session's connections!\n{}", err);
+                        return;
+                    }
+                },
+                // Nothing saved yet
+                Err(_) => return,
+            };
+
+        match audio_jack::connect_all(&connections) {
+            Ok(made) => info!("Restored {} connections of the session", made),
+            Err(err) => error!("Could not restore the session's connections!\n{}", err),
+        }
+    }
+
+    // Answer the session manager until told to stop, saving the connections of the
+    // JACK client when the session is saved. The session manager stops the client with SIGTERM
+    pub fn serve(
+        self,
+        client_name: Option<String>, // None without a JACK client
+        running: Arc<AtomicBool>,
+    ) -> Result<JoinHandle<()>, std::io::Error> {
+        self.socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        thread::Builder::new()
+            .name("nsm".to_owned())
+            .spawn(move || {
+                let mut buffer = [0; MAX_PACKET];
+                while running.load(Ordering::SeqCst) {
+                    let length = match self.socket.recv(&mut buffer) {
+                        Ok(length) => length,
+                        Err(err)
+                            if matches!(
+                                err.kind(),
+                                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            continue;
+                        }
+                        Err(err) => {
+                            error!("Could not receive from the session manager!\n{}", err);
+                            return;
+                        }
+                    };
+
+                    match parse(&buffer[..length]) {
+                        Some((address, _)) if address == "/nsm/client/save" => {
+                            match self.save(client_name.as_deref()) {
+                                Ok(saved) => {
+                                    info!("Saved {} connections with the session", saved);
+                                    self.reply("/nsm/client/save", "Saved");
+                                }
+                                Err(err) => {
+                                    error!("Could not save the session!\n{}", err);
+                                    self.error("/nsm/client/save", &err.to_string());
+                                }
+                            }
+                        }
+                        Some((address, _)) if address == "/nsm/client/open" => {
+                            warn!("Switching sessions isn't supported, restart the client instead");
+                        }
+                        _ => {}
+                    }
+                }
+            })
+    }
+}
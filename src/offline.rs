@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    Config, models,
+    piper::{self, PiperConfig},
+    translate::EngineConfig,
+};
+
+// Set at startup on machines without network access, nothing is downloaded or installed after
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+// Everything the pipelines need which isn't there, as it can't be fetched later
+pub fn preflight<'a>(
+    configs: impl IntoIterator<Item = &'a Config>,
+    piper: &PiperConfig,
+    voices: &[String],
+) -> Vec<String> {
+    let mut missing = vec![];
+    for config in configs {
+        let model = &config.whisper.model;
+        let path = models::model_path(model);
+        if !path.exists() {
+            missing.push(format!("Whisper model {} at {}", model, path.display()));
+        }
+
+        match config.translate.as_ref().map(|translate| &translate.engine) {
+            Some(EngineConfig::DeepL(_)) => {
+                missing.push("Access to DeepL, which is an online service".to_owned())
+            }
+            #[cfg(feature = "nllb")]
+            Some(EngineConfig::Nllb(nllb)) if !std::path::Path::new(&nllb.model_path).exists() => {
+                missing.push(format!("NLLB model at {}", nllb.model_path))
+            }
+            _ => {}
+        }
+    }
+    missing.extend(piper::missing(piper, voices));
+
+    // Pipelines often share a model
+    missing.sort();
+    missing.dedup();
+    missing
+}
@@ -0,0 +1,196 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use hound::Error as HoundError;
+use log::{error, info};
+
+use crate::{
+    Config,
+    controls::Controls,
+    pipeline::Processor,
+    piper::{self, ErrSetupPiper},
+    sink::{OutputSink, stdout::StdoutSink, tts::TtsSink},
+    sound::play_buffer::{LIVE_CAPACITY, PlayBuffer},
+    status::Status,
+    translate::{self, ErrTranslate, TranslateConfig},
+    util::read_wav,
+    whisper::{self, ErrSetupWhisper},
+};
+
+// How long to wait for the piper server to come up
+const PIPER_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum ErrTranscribeFile {
+    IoError(std::io::Error),
+    HoundError(HoundError),
+    SetupWhisperError(ErrSetupWhisper),
+    SetupPiperError(ErrSetupPiper),
+    TranslateError(ErrTranslate),
+    NoTranslationEngine,
+}
+
+impl Display for ErrTranscribeFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(error) => write!(f, "{}", error),
+            Self::HoundError(error) => write!(f, "{}", error),
+            Self::SetupWhisperError(error) => write!(f, "{}", error),
+            Self::SetupPiperError(error) => write!(f, "{}", error),
+            Self::TranslateError(error) => write!(f, "{}", error),
+            Self::NoTranslationEngine => write!(
+                f,
+                "No translation engine configured, add a [translate] section to the config"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ErrTranscribeFile {}
+
+impl From<std::io::Error> for ErrTranscribeFile {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<HoundError> for ErrTranscribeFile {
+    fn from(value: HoundError) -> Self {
+        Self::HoundError(value)
+    }
+}
+
+impl From<ErrSetupWhisper> for ErrTranscribeFile {
+    fn from(value: ErrSetupWhisper) -> Self {
+        Self::SetupWhisperError(value)
+    }
+}
+
+impl From<ErrSetupPiper> for ErrTranscribeFile {
+    fn from(value: ErrSetupPiper) -> Self {
+        Self::SetupPiperError(value)
+    }
+}
+
+impl From<ErrTranslate> for ErrTranscribeFile {
+    fn from(value: ErrTranslate) -> Self {
+        Self::TranslateError(value)
+    }
+}
+
+// Transcribe a wav file and print the result to stdout, optionally writing the speech to a wav file
+pub fn transcribe_file(
+    mut config: Config,
+    file: &Path,
+    translate: bool,
+    to: Option<String>,
+    output: Option<&Path>,
+) -> Result<(), ErrTranscribeFile> {
+    // Apply command line options over the config
+    config.whisper.translate = translate;
+    config.translate = match to {
+        Some(target) => {
+            let translate_config = config
+                .translate
+                .ok_or(ErrTranscribeFile::NoTranslationEngine)?;
+
+            Some(TranslateConfig {
+                target,
+                ..translate_config
+            })
+        }
+        None => None,
+    };
+
+    // Read audio, it's processed at its own rate
+    let (samples, samplerate) = read_wav(BufReader::new(File::open(file)?))?;
+
+    // Start TTS first so it can load while whisper does
+    let mut piper = match output {
+        Some(_) => Some(piper::setup_piper(&config.piper, &[], false)?),
+        None => None,
+    };
+
+    let result = run_file(config, samples, samplerate, output);
+
+    // Kill TTS
+    if let Some(piper) = &mut piper
+        && let Err(err) = piper.kill()
+    {
+        error!("Could not kill piper server!\n{}", err);
+    }
+
+    result
+}
+
+fn run_file(
+    config: Config,
+    samples: Vec<f32>,
+    samplerate: usize,
+    output: Option<&Path>,
+) -> Result<(), ErrTranscribeFile> {
+    // Set up the same stages as the live pipeline
+    let whisper_ctx = whisper::setup_whisper(config.whisper.clone())?;
+    let translator = match &config.translate {
+        Some(translate_config) => Some(translate::create_translator(translate_config)?),
+        None => None,
+    };
+    // Room for speech a few times longer than the recording
+    let seconds = samples.len() / samplerate.max(1);
+    let (play_buffer, mut play_consumer) = PlayBuffer::new((seconds * 4).max(LIVE_CAPACITY));
+    let mut sinks: Vec<Box<dyn OutputSink>> = vec![Box::new(StdoutSink)];
+    if output.is_some() {
+        piper::wait_for_piper(config.piper.port, PIPER_TIMEOUT)?;
+        sinks.push(Box::new(TtsSink::new(
+            play_buffer.clone(),
+            config.piper.clone(),
+            config.postprocess.as_ref(),
+        )));
+    }
+
+    let mut processor = Processor::new(
+        Arc::new(config),
+        Some(Arc::new(whisper_ctx)),
+        Arc::new(Controls::default()),
+        Arc::new(AtomicBool::new(false)),
+        translator,
+        sinks,
+        play_buffer.clone(),
+        Arc::new(Status::default()),
+        None,
+        None,
+        None,
+        None,
+        vec![],
+    );
+    processor.set_sample_rate(samplerate);
+    processor.transcribe(samples);
+
+    // Write out everything that would have been played
+    if let Some(output) = output {
+        let mut speech = vec![0.0; play_buffer.len()];
+        play_consumer.fill(&mut speech);
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: play_buffer.sample_rate() as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(output, spec)?;
+        for sample in speech {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+
+        info!("Speech written to {}", output.display());
+    }
+
+    Ok(())
+}
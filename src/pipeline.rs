@@ -0,0 +1,1924 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TryRecvError, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use device_query::{DeviceQuery, DeviceState};
+use log::{error, info, warn};
+use serde::Deserialize;
+use webrtc_vad::Vad;
+use whisper_rs::WhisperContext;
+
+#[cfg(feature = "alsa")]
+use crate::sound::audio_alsa::AlsaClient;
+#[cfg(feature = "pulse")]
+use crate::sound::audio_pulse::PulseClient;
+use crate::{
+    Config, ProcessUnit,
+    backlog::Backlog,
+    commands::{Action, Commands},
+    controls::Controls,
+    diarize::Diarizer,
+    dsp::{AudioStage, Chain, StageConfig, dynamics, echo::EchoCanceller, noise_floor::NoiseFloor},
+    events::Event,
+    hallucination::HallucinationFilter,
+    latency::{LatencyLog, Stages},
+    piper::PiperConfig,
+    postprocess::{PostprocessConfig, Postprocessor},
+    recording::Recorder,
+    sink::{
+        self, ErrSink, OutputSink, SinkConfig,
+        callback::{Callback, CallbackSink},
+        caption_sync::CaptionSync,
+        tts::TtsSink,
+    },
+    sound::{
+        AudioClient, AudioClientType, DynAudioClient,
+        audio_file::FileClient,
+        audio_jack::{InputMix, JackClient},
+        audio_net::NetClient,
+        audio_queue::{self, AudioReceiver, AudioSender},
+        cue::ErrorCue,
+        play_buffer::{DEFAULT_SAMPLE_RATE, LIVE_CAPACITY, PlayBuffer},
+        reblock::{self, Reblocker},
+    },
+    status::Status,
+    transcript::Transcript,
+    translate::{self, ErrTranslate, Translator},
+    util::Resampler,
+    utterance::{Task, Utterance},
+    vad::EnergyDetector,
+    wake::WakeGate,
+    whisper::{self, ErrSetupWhisper, ErrTranscribe, Transcriber, Transcription},
+};
+
+#[derive(Debug)]
+pub enum ErrStartPipeline {
+    IoError(std::io::Error),
+    JackError(jack::Error),
+    SinkError(ErrSink),
+    TranslateError(ErrTranslate),
+    WhisperError(ErrSetupWhisper),
+    NoAudioConfig,
+    AudioClientError(Box<dyn std::error::Error + Send>),
+}
+
+impl Display for ErrStartPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(error) => write!(f, "{}", error),
+            Self::JackError(error) => write!(f, "{}", error),
+            Self::SinkError(error) => write!(f, "{}", error),
+            Self::TranslateError(error) => write!(f, "{}", error),
+            Self::WhisperError(error) => write!(f, "{}", error),
+            Self::NoAudioConfig => write!(f, "No config for the selected audio client"),
+            Self::AudioClientError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ErrStartPipeline {}
+
+impl From<std::io::Error> for ErrStartPipeline {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<jack::Error> for ErrStartPipeline {
+    fn from(value: jack::Error) -> Self {
+        Self::JackError(value)
+    }
+}
+
+impl From<ErrSink> for ErrStartPipeline {
+    fn from(value: ErrSink) -> Self {
+        Self::SinkError(value)
+    }
+}
+
+impl From<ErrTranslate> for ErrStartPipeline {
+    fn from(value: ErrTranslate) -> Self {
+        Self::TranslateError(value)
+    }
+}
+
+impl From<ErrSetupWhisper> for ErrStartPipeline {
+    fn from(value: ErrSetupWhisper) -> Self {
+        Self::WhisperError(value)
+    }
+}
+
+// A pipeline overriding parts of the main config
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PipelineConfig {
+    pub name: String,
+    pub input_port: String,
+    #[serde(default)]
+    pub extra_input_ports: Vec<String>,
+    pub input_mix: Option<InputMix>,
+    pub output_ports: Vec<String>,
+    #[serde(default)]
+    pub right_output_ports: Vec<String>,
+    pub pan: Option<f32>,
+    pub pre: Option<Vec<StageConfig>>, // Input processing
+    pub silence_length: Option<u32>,   // Silence before an utterance ends
+    pub model: Option<String>,         // Whisper model
+    pub language: Option<String>,      // Language spoken on the input
+    pub translate: Option<bool>,       // Whether whisper translates to english
+    pub no_context: Option<bool>,
+    pub target: Option<String>, // Target language for the translation engine
+    pub voice: Option<String>,  // Piper voice to speak with
+    pub sinks: Option<Vec<SinkConfig>>,
+}
+
+impl PipelineConfig {
+    // Create the config for this pipeline from the main config
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+
+        if let Some(jack) = config.audio.jack.as_mut() {
+            jack.input_port = self.input_port.clone();
+            jack.extra_input_ports = self.extra_input_ports.clone();
+            jack.output_ports = self.output_ports.clone();
+            jack.right_output_ports = self.right_output_ports.clone();
+            if let Some(input_mix) = self.input_mix {
+                jack.input_mix = input_mix;
+            }
+            if let Some(pan) = self.pan {
+                jack.pan = pan;
+            }
+        }
+        if let Some(pre) = &self.pre {
+            config.audio.pre = pre.clone();
+        }
+        if let Some(silence_length) = self.silence_length {
+            config.whisper.silence_length = silence_length;
+        }
+        if let Some(model) = &self.model {
+            config.whisper.model = model.clone();
+        }
+        if let Some(language) = &self.language {
+            config.whisper.language = Some(language.clone());
+        }
+        if let Some(translate) = self.translate {
+            config.whisper.translate = translate;
+        }
+        if let Some(no_context) = self.no_context {
+            config.whisper.no_context = no_context;
+        }
+        if let (Some(target), Some(translate)) = (&self.target, config.translate.as_mut()) {
+            translate.target = target.clone();
+        }
+        if let Some(voice) = &self.voice {
+            config.piper.model = voice.clone();
+        }
+        if let Some(sinks) = &self.sinks {
+            config.sinks = sinks.clone();
+        }
+
+        config
+    }
+}
+
+// Listeners in another room, hearing the input in their own language on their own ports
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomConfig {
+    pub name: String,
+    pub target: String,        // Language spoken into the room
+    pub voice: Option<String>, // Piper voice, by default the one piper.voices has for the target
+    pub output_ports: Vec<String>,
+}
+
+impl RoomConfig {
+    // Piper settings which speak with the room's voice
+    pub fn piper(&self, piper: &PiperConfig) -> PiperConfig {
+        let voice = match &self.voice {
+            Some(voice) => voice.clone(),
+            None => piper.voice_for(Some(&self.target)).to_owned(),
+        };
+
+        PiperConfig {
+            model: voice,
+            voices: HashMap::new(),
+            ..piper.clone()
+        }
+    }
+}
+
+// A room with the speech queued for it
+struct Room {
+    config: RoomConfig,
+    tts: TtsSink,
+}
+
+impl Room {
+    fn new(
+        config: &RoomConfig,
+        piper: &PiperConfig,
+        postprocess: Option<&PostprocessConfig>,
+        play_buffer: Arc<PlayBuffer>,
+    ) -> Self {
+        Self {
+            tts: TtsSink::new(play_buffer, config.piper(piper), postprocess),
+            config: config.clone(),
+        }
+    }
+}
+
+// Create the whisper state for a loaded model, leaving whisper unavailable if that fails
+fn start_transcriber(ctx: Arc<WhisperContext>, cancel: Arc<AtomicBool>) -> Option<Transcriber> {
+    match Transcriber::new(ctx, cancel) {
+        Ok(transcriber) => Some(transcriber),
+        Err(err) => {
+            error!("Could not create whisper state!\n{}", err);
+            None
+        }
+    }
+}
+
+// Length of the VAD frames in ms, falling back to the default for lengths the VAD can't take
+fn frame_length(config: &Config) -> u32 {
+    if reblock::FRAME_LENGTHS.contains(&config.audio.frame) {
+        config.audio.frame
+    } else {
+        reblock::DEFAULT_FRAME_LENGTH
+    }
+}
+
+// Collect input into frames of the config's length, publishing their size
+fn new_reblocker(config: &Config, sample_rate: usize, status: &Status) -> Reblocker {
+    let reblocker = Reblocker::new(frame_length(config), sample_rate);
+    status.set_frame(reblocker.size());
+    reblocker
+}
+
+// How far past whisper.max_length a recording may run while waiting for a pause
+const MAX_LENGTH_OVERRUN: f32 = 1.25;
+
+// How often shutdown checks whether the pipeline has finished
+const SHUTDOWN_POLL: Duration = Duration::from_millis(50);
+
+// Apply the caption replacements to text, if any are configured
+fn fix_captions(postprocess: Option<&Postprocessor>, text: String) -> String {
+    match postprocess {
+        Some(postprocess) => postprocess.captions(&text),
+        None => text,
+    }
+}
+
+// Turns incoming audio into utterances and sends them to the outputs
+pub struct Processor {
+    config: Arc<Config>,
+    transcriber: Option<Transcriber>, // Not set while whisper is unavailable
+    controls: Arc<Controls>,
+    cancel: Arc<AtomicBool>, // Set when the pipeline stops, aborting whisper
+    translator: Option<Box<dyn Translator>>,
+    sinks: Vec<Box<dyn OutputSink>>,
+    captions: Option<CaptionSync>, // Caption sinks synced to TTS playback
+    play_buffer: Arc<PlayBuffer>,
+    status: Arc<Status>,
+    cue: Option<ErrorCue>, // Played to the operator when an utterance is dropped
+    transcript: Option<Transcript>,
+    recorder: Option<Recorder>,
+    backlog: Option<Backlog>, // Recordings waiting for whisper to be available
+    whisper_retry: Instant,   // Last attempt at loading whisper
+    whisper_loading: Option<Receiver<Result<WhisperContext, ErrSetupWhisper>>>, // Off this thread
+    hallucination: Option<HallucinationFilter>,
+    wake: Option<WakeGate>, // Only lets utterances through after a wake word
+    commands: Commands,     // Spoken phrases which change the target or voice
+    diarizer: Option<Diarizer>,
+    postprocess: Option<Postprocessor>,
+    recording_chain: Chain,          // Processing of finished recordings
+    noise_floor: Option<NoiseFloor>, // Gates the VAD if set
+    calibrations: u64,               // Calibration requests handled so far
+    discards: u64,                   // Requests to drop the recording handled so far
+    finishes: u64,                   // Requests to end the recording handled so far
+    replays: u64,                    // Requests to replay speech handled so far
+    last_clip: Option<Vec<f32>>,     // Speech of the last utterance, to play again
+    rooms: Vec<Room>,
+    echo: Option<EchoCanceller>,
+    target: Option<String>, // Translation target set at runtime, overriding the config
+    voice: Option<String>,  // Voice set at runtime, overriding the config
+    sample_rate: usize,     // Rate of the input audio
+    pre_chain: Chain,       // Input processing chain
+    vad: Vad,               // Voice activity detector instance
+    vad_rate: usize,        // Rate the VAD works at, the input is resampled if it differs
+    reblocker: Reblocker,   // Collects the input into frames for the VAD
+    vad_resampler: Option<Resampler>, // Brings a copy of the input to vad_rate if it differs
+    vad_frames: Reblocker,  // Frames of that copy, which the VAD decides on
+    last_voice: bool,       // WebRTC VAD decision, kept for frames it couldn't evaluate
+    energy: EnergyDetector, // Decides on voice by level, used depending on vad.mode
+
+    // Recording state
+    recording: bool, // Current recording status
+    silence: u32,    // How many blocks have been silent, used to decide when to stop recording
+    samples: Vec<f32>,
+    pre_roll: VecDeque<Vec<f32>>, // Latest blocks before recording started, so the first syllable isn't cut off
+    pre_rolled: usize,            // Samples at the start of the recording from the pre-roll
+    voice_ended: Option<Instant>, // When voice was last heard in the recording
+    latency: LatencyLog,
+    utterance_count: u64, // Number of utterances so far, used as their id
+    history: VecDeque<Utterance>, // Recent utterances, kept for translating again
+    summary_batch: Vec<Utterance>, // Utterances not yet spoken while speech is backed up
+    playing: VecDeque<(u64, u64)>, // Utterances with speech queued, and the play buffer position it ends at
+}
+
+impl Processor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: Arc<Config>,
+        whisper_ctx: Option<Arc<WhisperContext>>,
+        controls: Arc<Controls>,
+        cancel: Arc<AtomicBool>,
+        translator: Option<Box<dyn Translator>>,
+        sinks: Vec<Box<dyn OutputSink>>,
+        play_buffer: Arc<PlayBuffer>,
+        status: Arc<Status>,
+        cue: Option<ErrorCue>,
+        transcript: Option<Transcript>,
+        recorder: Option<Recorder>,
+        backlog: Option<Backlog>,
+        room_buffers: Vec<Arc<PlayBuffer>>, // One per room in the config
+    ) -> Self {
+        let rooms = config
+            .rooms
+            .iter()
+            .zip(room_buffers)
+            .map(|(room, buffer)| {
+                Room::new(room, &config.piper, config.postprocess.as_ref(), buffer)
+            })
+            .collect();
+
+        // Move caption sinks to their own thread if they should wait for TTS
+        let (sinks, captions) = if config.general.sync_captions_to_tts {
+            let (caption_sinks, sinks): (Vec<_>, Vec<_>) =
+                sinks.into_iter().partition(|sink| sink.is_caption());
+
+            match CaptionSync::new(caption_sinks, play_buffer.clone()) {
+                Ok(captions) => (sinks, Some(captions)),
+                Err(err) => {
+                    error!("Could not start caption sync thread!\n{}", err);
+                    (sinks, None)
+                }
+            }
+        } else {
+            (sinks, None)
+        };
+
+        let hallucination = config
+            .whisper
+            .hallucination
+            .as_ref()
+            .map(HallucinationFilter::new);
+        let diarizer = config.diarization.as_ref().map(Diarizer::new);
+        let postprocess = config.postprocess.as_ref().map(Postprocessor::new);
+
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            pre_chain: Chain::new(&config.audio.pre, DEFAULT_SAMPLE_RATE),
+            recording_chain: Chain::new(&config.audio.recording, DEFAULT_SAMPLE_RATE),
+            echo: config
+                .audio
+                .echo
+                .as_ref()
+                .map(|echo| EchoCanceller::new(echo, DEFAULT_SAMPLE_RATE)),
+            noise_floor: config
+                .audio
+                .noise_gate
+                .as_ref()
+                .map(|noise_gate| NoiseFloor::new(noise_gate, DEFAULT_SAMPLE_RATE)),
+            calibrations: controls.calibrations(),
+            discards: controls.discards(),
+            finishes: controls.finishes(),
+            replays: controls.replays(),
+            last_clip: None,
+            vad: Vad::new_with_rate(webrtc_vad::SampleRate::Rate48kHz),
+            vad_rate: DEFAULT_SAMPLE_RATE,
+            reblocker: new_reblocker(&config, DEFAULT_SAMPLE_RATE, &status),
+            vad_resampler: None,
+            vad_frames: Reblocker::new(frame_length(&config), DEFAULT_SAMPLE_RATE),
+            last_voice: false,
+            energy: EnergyDetector::new(&config.vad.energy, DEFAULT_SAMPLE_RATE),
+            config,
+            transcriber: whisper_ctx.and_then(|ctx| start_transcriber(ctx, cancel.clone())),
+            controls,
+            cancel,
+            translator,
+            sinks,
+            captions,
+            play_buffer,
+            status,
+            cue,
+            transcript,
+            recorder,
+            backlog,
+            hallucination,
+            wake: config.wake.as_ref().map(WakeGate::new),
+            commands: Commands::new(&config.commands),
+            diarizer,
+            postprocess,
+            whisper_retry: Instant::now(),
+            whisper_loading: None,
+            rooms,
+            target: None,
+            voice: None,
+            recording: false,
+            silence: 0,
+            samples: vec![],
+            pre_roll: VecDeque::new(),
+            pre_rolled: 0,
+            voice_ended: None,
+            latency: LatencyLog::default(),
+            utterance_count: 0,
+            history: VecDeque::new(),
+            summary_batch: vec![],
+            playing: VecDeque::new(),
+        }
+    }
+
+    // Process audio until told to quit
+    pub fn run(mut self, mut audio: AudioReceiver) {
+        loop {
+            match audio.recv() {
+                ProcessUnit::Continue(in_buf, reference) => {
+                    self.process_audio(&in_buf, reference.as_deref())
+                }
+                ProcessUnit::Reload(config) => self.reload(config),
+                ProcessUnit::SetTarget(target) => self.set_target(target),
+                ProcessUnit::SetVoice(voice) => self.set_voice(voice),
+                ProcessUnit::SampleRate(sample_rate) => self.set_sample_rate(sample_rate),
+                ProcessUnit::BufferSize(frames) => self.set_buffer_size(frames),
+                ProcessUnit::Xruns(count) => self.publish(Event::Xruns { count }),
+                ProcessUnit::Pause => self.discard_recording(),
+                ProcessUnit::Drain => {
+                    self.drain();
+                    break;
+                }
+                ProcessUnit::Quit => break,
+            }
+        }
+    }
+
+    // Translate into another language from now on, and show the recent utterances in it
+    fn set_target(&mut self, target: String) {
+        info!("Translating into {}", target);
+        self.target = Some(target);
+        self.retranslate_history();
+    }
+
+    // Speak with another voice from now on
+    fn set_voice(&mut self, voice: String) {
+        info!("Speaking with {}", voice);
+        self.voice = Some(voice);
+        self.reload(self.config.clone());
+    }
+
+    // Process audio at a different rate from now on
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+        info!("Input sample rate is {} Hz", sample_rate);
+        self.publish(Event::SampleRate { rate: sample_rate });
+
+        // The VAD only supports a few rates, anything else is resampled for it
+        let (vad_rate, rate) = match sample_rate {
+            8000 => (8000, webrtc_vad::SampleRate::Rate8kHz),
+            16000 => (16000, webrtc_vad::SampleRate::Rate16kHz),
+            32000 => (32000, webrtc_vad::SampleRate::Rate32kHz),
+            48000 => (48000, webrtc_vad::SampleRate::Rate48kHz),
+            _ => (16000, webrtc_vad::SampleRate::Rate16kHz),
+        };
+
+        self.sample_rate = sample_rate;
+        self.pre_chain = Chain::new(&self.config.audio.pre, sample_rate);
+        self.recording_chain = Chain::new(&self.config.audio.recording, sample_rate);
+        self.echo = self
+            .config
+            .audio
+            .echo
+            .as_ref()
+            .map(|echo| EchoCanceller::new(echo, sample_rate));
+        if let Some(noise_floor) = &mut self.noise_floor {
+            noise_floor.set_sample_rate(sample_rate);
+        }
+        self.energy.set_sample_rate(sample_rate);
+        self.vad = Vad::new_with_rate(rate);
+        self.vad_rate = vad_rate;
+        self.reblocker = new_reblocker(&self.config, sample_rate, &self.status);
+        self.reset_vad_frames(frame_length(&self.config));
+
+        // A recording at the old rate can't be continued
+        self.pre_roll.clear();
+        if self.recording {
+            self.recording = false;
+            self.status.set_recording(false);
+            self.samples.clear();
+        }
+    }
+
+    // Note the audio server's period, processing is framed independently of it
+    fn set_buffer_size(&mut self, frames: usize) {
+        if frames as u32 == self.status.period() {
+            return;
+        }
+        info!("Audio server processes {} frames at a time", frames);
+        self.status.set_period(frames);
+        self.publish(Event::BufferSize { frames });
+    }
+
+    // Switch to a changed config
+    fn reload(&mut self, mut config: Arc<Config>) {
+        // Keep the voice chosen at runtime when the config file changes
+        if let Some(voice) = &self.voice
+            && *voice != config.piper.model
+        {
+            Arc::make_mut(&mut config).piper.model = voice.clone();
+        }
+
+        if config.audio.pre != self.config.audio.pre {
+            self.pre_chain = Chain::new(&config.audio.pre, self.sample_rate);
+        }
+        if config.audio.recording != self.config.audio.recording {
+            self.recording_chain = Chain::new(&config.audio.recording, self.sample_rate);
+        }
+        if config.audio.frame != self.config.audio.frame {
+            self.reblocker = new_reblocker(&config, self.sample_rate, &self.status);
+            self.reset_vad_frames(frame_length(&config));
+        }
+        if config.vad.energy != self.config.vad.energy {
+            self.energy = EnergyDetector::new(&config.vad.energy, self.sample_rate);
+        }
+        if config.audio.noise_gate != self.config.audio.noise_gate {
+            self.noise_floor = config
+                .audio
+                .noise_gate
+                .as_ref()
+                .map(|noise_gate| NoiseFloor::new(noise_gate, self.sample_rate));
+        }
+        if config.whisper.hallucination != self.config.whisper.hallucination {
+            self.hallucination = config
+                .whisper
+                .hallucination
+                .as_ref()
+                .map(HallucinationFilter::new);
+        }
+        if config.commands != self.config.commands {
+            self.commands = Commands::new(&config.commands);
+        }
+        if config.wake != self.config.wake {
+            self.wake = config.wake.as_ref().map(WakeGate::new);
+        }
+        if config.diarization != self.config.diarization {
+            self.diarizer = config.diarization.as_ref().map(Diarizer::new);
+        }
+        if config.postprocess != self.config.postprocess {
+            self.postprocess = config.postprocess.as_ref().map(Postprocessor::new);
+        }
+
+        for sink in self.sinks.iter_mut() {
+            sink.reload(&config);
+        }
+        for room in self.rooms.iter_mut() {
+            let mut room_config = (*config).clone();
+            room_config.piper = room.config.piper(&config.piper);
+            room.tts.reload(&room_config);
+        }
+        if let Some(captions) = &self.captions {
+            captions.reload(config.clone());
+        }
+
+        self.config = config;
+
+        // Nothing more will be batched
+        if self.summary_backlog().is_none() {
+            self.flush_summary(true);
+        }
+    }
+
+    // Start the copy of the input the VAD decides on again, for frames of length ms at the current rates
+    fn reset_vad_frames(&mut self, length: u32) {
+        self.vad_frames = Reblocker::new(length, self.vad_rate);
+        self.last_voice = false;
+        self.vad_resampler = if self.vad_rate == self.sample_rate {
+            None
+        } else {
+            match Resampler::new(self.sample_rate, self.vad_rate) {
+                Ok(resampler) => Some(resampler),
+                Err(err) => {
+                    error!("Could not create resampler for VAD!\n{:?}", err);
+                    None
+                }
+            }
+        };
+    }
+
+    // Check a block for voice with the detectors vad.mode asks for
+    // The whole block is recorded either way, the WebRTC VAD only sees a copy in frames at its own rate
+    fn is_voice(&mut self, block: &[f32]) -> bool {
+        if self.config.general.push_to_talk {
+            return DeviceState::new()
+                .get_keys()
+                .contains(&self.config.general.ptt_key);
+        }
+
+        // Both always run, so either is up to date when the mode is changed
+        if let Some(is_voice) = self.vad_frame(block) {
+            self.last_voice = is_voice;
+        }
+        let energy = self.energy.process(block);
+
+        self.config.vad.mode.combine(self.last_voice, energy)
+    }
+
+    // Run the VAD on the next frame of the copy, None if there was none or it couldn't be evaluated
+    fn vad_frame(&mut self, block: &[f32]) -> Option<bool> {
+        // Bring the block to a rate the VAD supports
+        // The resampler keeps its state, so frames are continuous and the copy keeps pace on average
+        match &mut self.vad_resampler {
+            Some(resampler) => match resampler.process(block, false) {
+                Ok(resampled) => self.vad_frames.push(&resampled, None),
+                Err(err) => {
+                    error!("Could not resample audio for VAD!\n{:?}", err);
+                    return None;
+                }
+            },
+            None if self.vad_rate == self.sample_rate => self.vad_frames.push(block, None),
+            None => return None,
+        }
+
+        // Resampling delays the copy a little, so the first block may not have a frame yet
+        let (frame, _) = self.vad_frames.next_frame()?;
+
+        // Convert to i16 for VAD
+        let samples_int = frame
+            .iter()
+            .map(|x| (x.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+            .collect::<Vec<_>>();
+
+        // Detect voice activity
+        match self.vad.is_voice_segment(&samples_int) {
+            Ok(is_voice) => Some(is_voice),
+            Err(_) => {
+                // No error returned >:(
+                // https://github.com/kaegi/webrtc-vad/issues/9
+                error!("VAD could not evaluate if the audio was voice!");
+                None
+            }
+        }
+    }
+
+    // Process audio as it arrives, in frames of the configured length
+    fn process_audio(&mut self, in_buf: &[f32], reference: Option<&[f32]>) {
+        self.check_playback();
+
+        self.reblocker.push(in_buf, reference);
+        while let Some((mut frame, reference)) = self.reblocker.next_frame() {
+            self.process_block(&mut frame, reference.as_deref());
+        }
+    }
+
+    // Reference is what was played while the block was captured, if echo is cancelled
+    fn process_block(&mut self, in_buf: &mut [f32], reference: Option<&[f32]>) {
+        // Speak what was held back once the backlog has mostly played
+        self.flush_summary(false);
+
+        // Catch up on recordings saved while whisper was unavailable
+        self.retry_whisper();
+        self.process_backlog();
+
+        // Play the last speech again when asked to
+        let replays = self.controls.replays();
+        if replays != self.replays {
+            self.replays = replays;
+            match &self.last_clip {
+                Some(clip) => {
+                    info!("Playing the last utterance again");
+                    self.play_buffer.push(clip.clone());
+                }
+                None => info!("Nothing spoken yet to play again"),
+            }
+        }
+
+        // Drop the unfinished recording when asked to
+        let discards = self.controls.discards();
+        if discards != self.discards {
+            self.discards = discards;
+            self.discard_recording();
+        }
+
+        // Drop input and any unfinished recording while muted
+        if self.controls.muted() {
+            self.discard_recording();
+            return;
+        }
+
+        // Remove our own output before anything else changes the input
+        if let Some(echo) = &mut self.echo
+            && let Some(reference) = reference
+        {
+            echo.process(in_buf, reference);
+        }
+
+        // Apply input processing
+        self.pre_chain.process(in_buf);
+
+        self.status
+            .set_level(20.0 * dynamics::rms(in_buf).max(1e-5).log10());
+
+        let mut is_voice = self.is_voice(in_buf);
+
+        // Measure the background noise again when asked to
+        let calibrations = self.controls.calibrations();
+        if calibrations != self.calibrations {
+            self.calibrations = calibrations;
+            if let Some(noise_floor) = &mut self.noise_floor {
+                noise_floor.calibrate();
+            }
+        }
+
+        // Ignore voice that isn't clearly louder than the background noise
+        if let Some(noise_floor) = &mut self.noise_floor
+            && !self.config.general.push_to_talk
+        {
+            is_voice = noise_floor.process(in_buf, is_voice);
+        }
+        self.status.set_voice(is_voice);
+
+        // End the recording without waiting for silence when asked to
+        let finishes = self.controls.finishes();
+        let finish = finishes != self.finishes;
+        self.finishes = finishes;
+
+        // If recording already started
+        if self.recording {
+            // Add samples to recording buffer
+            self.samples.extend_from_slice(in_buf);
+
+            // If voice activity detected
+            if is_voice {
+                // Reset silence counter
+                self.silence = 0;
+                self.voice_ended = Some(Instant::now());
+            } else {
+                // Increment silence counter
+                self.silence += 1;
+            }
+
+            // Cut long monologues at the next pause, or anyway once they run well over
+            let length = self.samples.len() as f32 / self.sample_rate as f32;
+            let too_long = self.config.whisper.max_length.is_some_and(|max_length| {
+                length >= max_length && (!is_voice || length >= max_length * MAX_LENGTH_OVERRUN)
+            });
+            if too_long {
+                info!("Recording reached {:.1} seconds", length);
+            }
+
+            // If there has been enough silence
+            if self.silence >= self.config.whisper.silence_length || finish || too_long {
+                // Finish recording
+                info!("Recording finished");
+                self.recording = false;
+                self.status.set_recording(false);
+
+                let samples = std::mem::take(&mut self.samples);
+
+                // Very short speech is usually a cough or a click, which whisper makes things up for
+                let speech = samples
+                    .len()
+                    .saturating_sub(self.silence as usize * in_buf.len() + self.pre_rolled);
+                if (speech as f32) < self.config.whisper.min_length * self.sample_rate as f32 {
+                    info!("Recording too short, discarded");
+                    self.voice_ended = None;
+                } else {
+                    self.transcribe(samples);
+                }
+            }
+        } else {
+            // If noise level increases
+            if is_voice {
+                // Start recording
+                info!("Recording started...");
+                self.publish(Event::utterance_started());
+                self.recording = true;
+                self.status.set_recording(true);
+                self.silence = 0;
+                self.voice_ended = Some(Instant::now());
+                self.samples.clear(); // Clear previous recording
+                self.samples.extend(self.pre_roll.drain(..).flatten());
+                self.pre_rolled = self.samples.len();
+                self.samples.extend_from_slice(in_buf);
+            } else {
+                // Keep the latest blocks to start the next recording with
+                self.pre_roll.push_back(in_buf.to_vec());
+                while self.pre_roll.len() > self.config.whisper.pre_roll as usize {
+                    self.pre_roll.pop_front();
+                }
+            }
+        }
+    }
+
+    // Drop a recording which won't be finished
+    fn discard_recording(&mut self) {
+        self.pre_roll.clear();
+        self.reblocker.clear();
+        self.vad_frames.clear();
+        if self.recording {
+            info!("Recording discarded");
+            self.recording = false;
+            self.status.set_recording(false);
+        }
+    }
+
+    // Finish what was being said and speak what was held back, as nothing more is coming
+    fn drain(&mut self) {
+        if self.recording {
+            info!("Recording finished for shutdown");
+            self.recording = false;
+            self.status.set_recording(false);
+
+            // Including the last samples, which didn't fill a frame
+            let mut partial = self.reblocker.take_partial();
+            self.pre_chain.process(&mut partial);
+            let mut samples = std::mem::take(&mut self.samples);
+            samples.extend(partial);
+            let speech = samples.len().saturating_sub(self.pre_rolled);
+            if (speech as f32) < self.config.whisper.min_length * self.sample_rate as f32 {
+                info!("Recording too short, discarded");
+            } else {
+                self.transcribe(samples);
+            }
+        }
+
+        self.flush_summary(true);
+    }
+
+    // Transcribe a finished recording and output the result
+    pub fn transcribe(&mut self, mut samples: Vec<f32>) {
+        self.recording_chain.process(&mut samples);
+
+        let finished = Instant::now();
+        let mut stages = Stages {
+            vad_wait: self
+                .voice_ended
+                .take()
+                .map(|voice_ended| finished - voice_ended)
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        // Keep the recording for later while whisper is unavailable
+        if self.transcriber.is_none() {
+            self.save_recording(&samples);
+            return;
+        }
+        let kept = (self.backlog.is_some() || self.recorder.is_some()).then(|| samples.clone());
+
+        let result = self.run_whisper(samples, self.sample_rate);
+        stages.whisper = finished.elapsed();
+        self.status.metrics.record_whisper(stages.whisper);
+
+        match result {
+            Ok(Some(transcription)) => {
+                let input = kept.as_deref().map(|samples| (samples, self.sample_rate));
+                self.output_transcription(transcription, finished, stages, None, input)
+            }
+            Ok(None) => {}
+            Err(ErrTranscribe::Cancelled) => info!("Transcription cancelled"),
+            Err(ErrTranscribe::WhisperError(err)) if self.backlog.is_some() => {
+                error!(
+                    "Whisper failed, only recording until it can be loaded again!\n{}",
+                    err
+                );
+                self.transcriber = None;
+                self.whisper_retry = Instant::now();
+                if let Some(samples) = kept {
+                    self.save_recording(&samples);
+                }
+            }
+            Err(err) => {
+                error!("Could not transcribe audio!\n{}", err);
+                self.error_cue();
+            }
+        }
+    }
+
+    fn run_whisper(
+        &mut self,
+        samples: Vec<f32>,
+        sample_rate: usize,
+    ) -> Result<Option<Transcription>, ErrTranscribe> {
+        let Some(transcriber) = &mut self.transcriber else {
+            return Ok(None);
+        };
+
+        // A preset picked at runtime replaces the configured one
+        let mut whisper_config = self.config.whisper.clone();
+        if let Some(preset) = self.controls.preset() {
+            whisper_config.preset = Some(preset);
+        }
+
+        // Whisper takes the samples, so the voice is compared first
+        let speaker = self
+            .diarizer
+            .as_mut()
+            .map(|diarizer| diarizer.speaker(&samples, sample_rate));
+
+        let mut transcription = transcriber.transcribe(&whisper_config, samples, sample_rate)?;
+        if let Some(transcription) = &mut transcription {
+            transcription.speaker = speaker;
+        }
+
+        // Drop what whisper made up before it reaches any output
+        if let Some(transcription) = &transcription
+            && let Some(hallucination) = &self.hallucination
+            && let Some(reason) = hallucination.check(transcription)
+        {
+            info!("Dropped \"{}\", {}", transcription.text.trim(), reason);
+            return Ok(None);
+        }
+
+        // Commands are carried out rather than translated, whether awake or not
+        if let Some(transcription) = &transcription
+            && let Some(actions) = self.commands.actions(&transcription.text)
+        {
+            info!("Heard command \"{}\"", transcription.text.trim());
+            for action in actions {
+                match action {
+                    Action::SetTarget(target) => self.set_target(target),
+                    Action::SetVoice(voice) => self.set_voice(voice),
+                }
+            }
+            return Ok(None);
+        }
+
+        // Only what follows a wake word is translated
+        if let Some(transcription) = &mut transcription
+            && let Some(wake) = &mut self.wake
+            && !wake.check(transcription)
+        {
+            return Ok(None);
+        }
+
+        Ok(transcription)
+    }
+
+    // Save a recording to be transcribed once whisper is back, or drop it if there's no backlog
+    fn save_recording(&mut self, samples: &[f32]) {
+        let Some(backlog) = &mut self.backlog else {
+            error!("Whisper isn't loaded, dropping recording!");
+            self.error_cue();
+            return;
+        };
+
+        if let Err(err) = backlog.save(samples, self.sample_rate) {
+            error!("Could not save recording!\n{}", err);
+            self.error_cue();
+        }
+    }
+
+    // Try loading whisper again once in a while if it's unavailable
+    // It loads on its own thread, so audio keeps being recorded in the meantime
+    fn retry_whisper(&mut self) {
+        if let Some(loading) = &self.whisper_loading {
+            match loading.try_recv() {
+                Ok(result) => {
+                    self.whisper_loading = None;
+                    self.whisper_loaded(result);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    error!("Whisper loading thread stopped, still only recording!");
+                    self.whisper_loading = None;
+                }
+            }
+            return;
+        }
+
+        let Some(backlog_config) = &self.config.whisper.backlog else {
+            return;
+        };
+        if self.transcriber.is_some()
+            || self.whisper_retry.elapsed() < Duration::from_secs(backlog_config.retry)
+        {
+            return;
+        }
+        self.whisper_retry = Instant::now();
+
+        info!("Trying to load whisper again");
+        let (loaded_tx, loaded_rx) = channel();
+        let whisper_config = self.config.whisper.clone();
+        match thread::Builder::new()
+            .name("whisper_loader".to_owned())
+            .spawn(move || {
+                // Nothing to do if the pipeline stopped in the meantime
+                let _ = loaded_tx.send(whisper::setup_whisper(whisper_config));
+            }) {
+            Ok(_) => self.whisper_loading = Some(loaded_rx),
+            Err(err) => error!("Could not start whisper loading thread!\n{}", err),
+        }
+    }
+
+    // Start transcribing with whisper loaded in the background
+    fn whisper_loaded(&mut self, result: Result<WhisperContext, ErrSetupWhisper>) {
+        match result {
+            Ok(ctx) => {
+                self.transcriber = start_transcriber(Arc::new(ctx), self.cancel.clone());
+                if self.transcriber.is_some() {
+                    info!(
+                        "Whisper is back, transcribing {} saved recordings",
+                        self.backlog.as_ref().map_or(0, Backlog::len)
+                    );
+                }
+            }
+            Err(err) => error!("Could not set up whisper, still only recording!\n{}", err),
+        }
+    }
+
+    // Transcribe the oldest saved recording, one at a time between live recordings
+    fn process_backlog(&mut self) {
+        if self.recording || self.transcriber.is_none() {
+            return;
+        }
+        let Some(saved) = self.backlog.as_mut().and_then(Backlog::pop) else {
+            return;
+        };
+
+        let (samples, sample_rate) = match Backlog::load(&saved) {
+            Ok(recording) => recording,
+            Err(err) => {
+                error!(
+                    "Could not read saved recording {}, skipping it!\n{}",
+                    saved.path.display(),
+                    err
+                );
+                return;
+            }
+        };
+
+        let finished = Instant::now();
+        let kept = self.recorder.is_some().then(|| samples.clone());
+        match self.run_whisper(samples, sample_rate) {
+            Ok(transcription) => {
+                if let Some(transcription) = transcription {
+                    self.output_transcription(
+                        transcription,
+                        finished,
+                        Stages::default(),
+                        Some(saved.timestamp),
+                        kept.as_deref().map(|samples| (samples, sample_rate)),
+                    );
+                }
+                if let Err(err) = std::fs::remove_file(&saved.path) {
+                    error!(
+                        "Could not remove saved recording {}!\n{}",
+                        saved.path.display(),
+                        err
+                    );
+                }
+            }
+            Err(ErrTranscribe::Cancelled) => {
+                if let Some(backlog) = &mut self.backlog {
+                    backlog.push_front(saved);
+                }
+            }
+            Err(err @ ErrTranscribe::Timeout(_)) => {
+                error!(
+                    "Skipping saved recording {}, it stays on disk!\n{}",
+                    saved.path.display(),
+                    err
+                );
+            }
+            Err(err) => {
+                error!(
+                    "Whisper failed on a saved recording, only recording until it can be loaded again!\n{}",
+                    err
+                );
+                self.transcriber = None;
+                self.whisper_retry = Instant::now();
+                if let Some(backlog) = &mut self.backlog {
+                    backlog.push_front(saved);
+                }
+            }
+        }
+    }
+
+    // Let the operator hear that something was dropped, if enabled
+    fn error_cue(&self) {
+        if let Some(cue) = &self.cue
+            && self.config.general.error_cues
+        {
+            cue.play();
+        }
+    }
+
+    // Language the translation engine is given an utterance in
+    fn source_language(&self, utterance: &Utterance) -> Option<String> {
+        let configured = self
+            .config
+            .translate
+            .as_ref()
+            .and_then(|translate_config| translate_config.source.clone());
+
+        // Whisper output is english if it translated already
+        configured.or(match utterance.task {
+            Task::Translate => Some("en".to_owned()),
+            Task::Transcribe => utterance.language.clone(),
+        })
+    }
+
+    // Translate an utterance's text into the current target language
+    fn translate_utterance(&mut self, utterance: &mut Utterance) {
+        let source = self.source_language(utterance);
+        let (Some(translator), Some(translate_config)) =
+            (self.translator.as_mut(), self.config.translate.as_ref())
+        else {
+            return;
+        };
+        let target = self.target.as_ref().unwrap_or(&translate_config.target);
+
+        match translator.translate(utterance.text.trim(), source.as_deref(), target) {
+            Ok(translation) => {
+                utterance.translation = Some(fix_captions(self.postprocess.as_ref(), translation));
+                utterance.output_language = Some(target.clone());
+            }
+            Err(err) => error!("Could not translate text!\n{}", err),
+        }
+    }
+
+    // Speak an utterance into every room in the room's language
+    fn speak_in_rooms(&mut self, utterance: &Utterance) {
+        let source = self.source_language(utterance);
+        let translating = self.controls.translating();
+
+        for room in self.rooms.iter_mut() {
+            let mut room_utterance = utterance.clone();
+
+            // Reuse the translation if the room shares the main target
+            let target = &room.config.target;
+            if translating
+                && utterance.output_language.as_ref() != Some(target)
+                && let Some(translator) = self.translator.as_mut()
+            {
+                match translator.translate(utterance.text.trim(), source.as_deref(), target) {
+                    Ok(translation) => {
+                        room_utterance.translation =
+                            Some(fix_captions(self.postprocess.as_ref(), translation));
+                        room_utterance.output_language = Some(target.clone());
+                    }
+                    Err(err) => {
+                        error!(
+                            "Could not translate text for room {}!\n{}",
+                            room.config.name, err
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            if let Err(err) = room.tts.handle(&room_utterance) {
+                error!("Could not speak in room {}!\n{}", room.config.name, err);
+            }
+        }
+    }
+
+    // Send recent utterances to the caption outputs again in the new target language
+    fn retranslate_history(&mut self) {
+        if self.history.is_empty() || !self.controls.translating() {
+            return;
+        }
+        info!("Translating last {} utterances again", self.history.len());
+
+        let mut history = std::mem::take(&mut self.history);
+        for utterance in history.iter_mut() {
+            self.translate_utterance(utterance);
+
+            let event = Event::TranslationReady(utterance.clone());
+            self.status.events.publish(&event);
+            for sink in self.sinks.iter_mut().filter(|sink| sink.is_caption()) {
+                if let Err(err) = sink.event(&event) {
+                    error!("Could not output to {} sink!\n{}", sink.name(), err);
+                }
+            }
+            if let Some(captions) = &self.captions {
+                captions.show(event, None);
+            }
+            self.status.update_history(utterance);
+        }
+        self.history = history;
+    }
+
+    // Seconds of speech waiting to be played
+    fn queued(&self) -> f32 {
+        self.play_buffer.queued()
+    }
+
+    // Backlog which starts summary mode, if it is enabled
+    fn summary_backlog(&self) -> Option<f32> {
+        self.config
+            .translate
+            .as_ref()
+            .and_then(|translate_config| translate_config.summary.as_ref())
+            .map(|summary| summary.backlog)
+    }
+
+    // Speak one summary of the held back utterances once the backlog has halved,
+    // or straight away if forced
+    fn flush_summary(&mut self, force: bool) {
+        if self.summary_batch.is_empty() {
+            return;
+        }
+        let backlog = self.summary_backlog().unwrap_or(0.0);
+        if !force && self.queued() > backlog / 2.0 {
+            return;
+        }
+
+        let batch = std::mem::take(&mut self.summary_batch);
+        let text = batch
+            .iter()
+            .map(|utterance| utterance.text.trim())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let source = self.source_language(&batch[0]);
+
+        // Start from the latest utterance, keeping its voice and language
+        let mut summary = batch[batch.len() - 1].clone();
+        summary.id = self.utterance_count;
+        self.utterance_count += 1;
+        summary.duration = batch.iter().map(|utterance| utterance.duration).sum();
+        summary.segments.clear();
+        summary.text = text.clone();
+        if batch
+            .iter()
+            .any(|utterance| utterance.speaker != summary.speaker)
+        {
+            summary.speaker = None;
+        }
+
+        // Have the engine condense it, falling back to everything that was said
+        let condensed = match (
+            self.translator.as_mut(),
+            self.config.translate.as_ref(),
+            self.controls.translating(),
+        ) {
+            (Some(translator), Some(translate_config), true)
+                if translate_config.summary.is_some() =>
+            {
+                let target = self.target.as_ref().unwrap_or(&translate_config.target);
+                let prompt = translate_config
+                    .summary
+                    .as_ref()
+                    .map_or("", |summary| summary.prompt.as_str());
+                match translator.summarize(&text, source.as_deref(), target, prompt) {
+                    Ok(Some(condensed)) => Some((condensed, target.clone())),
+                    Ok(None) => {
+                        warn!("Translation engine can't summarize, speaking everything held back");
+                        None
+                    }
+                    Err(err) => {
+                        error!("Could not summarize text!\n{}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        match condensed {
+            Some((condensed, target)) => {
+                summary.translation = Some(condensed);
+                summary.output_language = Some(target);
+            }
+            None => {
+                let output = batch
+                    .iter()
+                    .map(|utterance| utterance.output_text().trim())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                summary.translation = Some(output);
+            }
+        }
+        info!("Speaking summary of {} utterances", batch.len());
+
+        let start = self.play_buffer.pushed();
+        let event = Event::TranslationReady(summary);
+        self.status.events.publish(&event);
+        let mut dropped = false;
+        for sink in self.sinks.iter_mut().filter(|sink| sink.is_speech()) {
+            if let Err(err) = sink.event(&event) {
+                error!("Could not output to {} sink!\n{}", sink.name(), err);
+                dropped = true;
+            }
+        }
+        if dropped {
+            self.error_cue();
+        }
+        if let Event::TranslationReady(summary) = event {
+            self.speech_queued(summary.id, start);
+        }
+    }
+
+    // Hand an event to every sink and subscriber
+    // Utterances ready for output are sent separately, as not every sink gets every one
+    fn publish(&mut self, event: Event) {
+        self.status.events.publish(&event);
+        for sink in self.sinks.iter_mut() {
+            if let Err(err) = sink.event(&event) {
+                error!("Could not output to {} sink!\n{}", sink.name(), err);
+            }
+        }
+        if let Some(captions) = &self.captions {
+            captions.show(event, None);
+        }
+    }
+
+    // Announce speech queued for an utterance since the play buffer was at start, if there was any
+    fn speech_queued(&mut self, id: u64, start: u64) {
+        let end = self.play_buffer.pushed();
+        if end > start {
+            let duration = (end - start) as f32 / self.play_buffer.sample_rate() as f32;
+            self.playing.push_back((id, end));
+            self.publish(Event::TtsQueued { id, duration });
+        }
+    }
+
+    // Announce the utterances whose speech has been played by now
+    fn check_playback(&mut self) {
+        let played = self.play_buffer.played();
+        while let Some(&(id, end)) = self.playing.front() {
+            if end > played {
+                break;
+            }
+            self.playing.pop_front();
+            self.publish(Event::PlaybackFinished { id });
+        }
+    }
+
+    // Translate a finished transcription and send it to every output
+    // Stages holds the time taken so far, the rest is added as the utterance goes out
+    // Recorded is when a saved recording was made, it's too late to speak so it's only shown and written out
+    // Input is the audio it was transcribed from with its rate, if it's kept for the recorder
+    fn output_transcription(
+        &mut self,
+        transcription: Transcription,
+        finished: Instant,
+        mut stages: Stages,
+        recorded: Option<u64>,
+        input: Option<(&[f32], usize)>,
+    ) {
+        let config = &self.config;
+
+        let task = if config.whisper.translate {
+            Task::Translate
+        } else {
+            Task::Transcribe
+        };
+        let mut utterance = Utterance::new(self.utterance_count, task, transcription);
+        utterance.text = fix_captions(self.postprocess.as_ref(), utterance.text);
+        // Subtitles and verbose JSON are written from the segments
+        for segment in &mut utterance.segments {
+            let text = std::mem::take(&mut segment.text);
+            segment.text = fix_captions(self.postprocess.as_ref(), text);
+        }
+        match recorded {
+            Some(recorded) => utterance.timestamp = recorded,
+            None => utterance.tags = self.controls.tags(),
+        }
+        self.utterance_count += 1;
+        self.status.metrics.record_utterance();
+
+        // Whisper output is english if it translated already
+        utterance.output_language = if config.whisper.translate {
+            Some("en".to_owned())
+        } else {
+            utterance.language.clone()
+        };
+        self.publish(Event::TranscriptionReady(utterance.clone()));
+
+        // Translate into the target language
+        if self.controls.translating() {
+            let started = Instant::now();
+            self.translate_utterance(&mut utterance);
+            stages.translate = started.elapsed();
+        }
+
+        // Position in the play buffer any speech for this utterance will start at
+        let start = self.play_buffer.pushed();
+
+        // Hold speech back to be summarized while too much is queued
+        let summarize = match self.summary_backlog() {
+            Some(backlog) => !self.summary_batch.is_empty() || self.queued() > backlog,
+            None => false,
+        };
+        if summarize {
+            if self.summary_batch.is_empty() {
+                info!("Speech is backed up, summarizing until it catches up");
+            }
+            self.summary_batch.push(utterance.clone());
+        }
+
+        // Send to every output
+        let event = Event::TranslationReady(utterance.clone());
+        self.status.events.publish(&event);
+        let mut dropped = false;
+        let speak = !summarize && recorded.is_none();
+        for sink in self
+            .sinks
+            .iter_mut()
+            .filter(|sink| speak || !sink.is_speech())
+        {
+            let started = Instant::now();
+            if let Err(err) = sink.event(&event) {
+                error!("Could not output to {} sink!\n{}", sink.name(), err);
+                dropped = true;
+            } else if sink.is_speech() {
+                self.status.metrics.record_tts(started.elapsed());
+                if let Some(timing) = sink.timing() {
+                    stages.tts_request += timing.request;
+                    stages.resample += timing.resample;
+                }
+                if let Some(clip) = sink.take_clip() {
+                    if let Some(recorder) = &mut self.recorder
+                        && let Err(err) =
+                            recorder.tts(&utterance, &clip, self.play_buffer.sample_rate())
+                    {
+                        error!("Could not record speech!\n{}", err);
+                    }
+                    self.last_clip = Some(clip);
+                }
+            }
+        }
+        if dropped {
+            self.error_cue();
+        }
+
+        if recorded.is_none() {
+            self.latency.record(stages);
+            self.speak_in_rooms(&utterance);
+        }
+
+        self.status.set_last(&utterance, finished.elapsed());
+
+        // Keep a record of the session
+        if let Some(transcript) = &mut self.transcript {
+            let tts_duration =
+                (self.play_buffer.pushed() - start) as f32 / self.play_buffer.sample_rate() as f32;
+            if let Err(err) = transcript.write(
+                &utterance,
+                tts_duration,
+                finished.elapsed().as_millis() as u64,
+            ) {
+                error!("Could not write transcript!\n{}", err);
+            }
+        }
+
+        // Keep what was said for review
+        if let Some(recorder) = &mut self.recorder
+            && let Some((samples, sample_rate)) = input
+            && let Err(err) = recorder.input(&utterance, samples, sample_rate)
+        {
+            error!("Could not record input!\n{}", err);
+        }
+
+        // Remember for translating again if the target changes
+        let retranslate = self
+            .config
+            .translate
+            .as_ref()
+            .map_or(0, |translate_config| translate_config.retranslate);
+        if retranslate > 0 {
+            self.history.push_back(utterance.clone());
+            while self.history.len() > retranslate {
+                self.history.pop_front();
+            }
+        }
+
+        // Show captions once speech starts, or straight away if nothing was spoken
+        if let Some(captions) = &self.captions {
+            let spoken = self.play_buffer.pushed() > start;
+            captions.show(event, spoken.then_some(start));
+        }
+
+        self.speech_queued(utterance.id, start);
+    }
+}
+
+// Sets up a pipeline, for apps embedding it rather than running the binary
+pub struct PipelineBuilder {
+    name: String,
+    config: Arc<Config>,
+    whisper_ctx: Option<Option<Arc<WhisperContext>>>, // Loaded from the config if not given
+    controls: Arc<Controls>,
+    sinks: Vec<Box<dyn OutputSink>>, // Outputs besides those in the config
+    audio_client: Option<Box<dyn DynAudioClient>>, // Created from the config if not given
+}
+
+impl PipelineBuilder {
+    // Name used in logs, thread names and the files the pipeline writes
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    // Use an already loaded model, e.g. to share it between pipelines
+    // None starts without whisper, only recording if a backlog is configured
+    pub fn whisper_ctx(mut self, whisper_ctx: Option<Arc<WhisperContext>>) -> Self {
+        self.whisper_ctx = Some(whisper_ctx);
+        self
+    }
+
+    // Controls to share, e.g. with hotkeys or other pipelines
+    pub fn controls(mut self, controls: Arc<Controls>) -> Self {
+        self.controls = controls;
+        self
+    }
+
+    // Output every finished utterance to a sink of the app
+    pub fn sink(mut self, sink: Box<dyn OutputSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    // Call a function with every finished utterance, from the processing thread
+    pub fn on_transcription(self, callback: impl FnMut(&Utterance) + Send + 'static) -> Self {
+        let callback: Callback = Box::new(callback);
+        self.sink(Box::new(CallbackSink::new(callback)))
+    }
+
+    // Use an audio client instead of the one in the config, e.g. a mock client for testing
+    pub fn audio_client(mut self, audio_client: impl AudioClient + 'static) -> Self {
+        self.audio_client = Some(Box::new(audio_client));
+        self
+    }
+
+    // Start capturing, processing and playing audio
+    pub fn start(self) -> Result<Pipeline, ErrStartPipeline> {
+        let Self {
+            name,
+            config,
+            whisper_ctx,
+            controls,
+            sinks: app_sinks,
+            audio_client,
+        } = self;
+
+        let whisper_ctx = match whisper_ctx {
+            Some(whisper_ctx) => whisper_ctx,
+            None => Some(Arc::new(whisper::setup_whisper(config.whisper.clone())?)),
+        };
+
+        // Queue for sending audio from jack thread to processing thread
+        let (audio_tx, audio_rx) = audio_queue::bounded(config.audio.queue.clone());
+
+        // Buffer for playing audio
+        let (play_buffer, play_consumer) = PlayBuffer::new(LIVE_CAPACITY);
+
+        // Buffers for speech in each room
+        let (room_buffers, room_consumers): (Vec<_>, Vec<_>) = config
+            .rooms
+            .iter()
+            .map(|_| PlayBuffer::new(LIVE_CAPACITY))
+            .unzip();
+        let room_buffers_cloned = room_buffers.clone();
+
+        // Buffer for cues only the operator hears
+        let (monitor_buffer, monitor_consumer) = PlayBuffer::new(LIVE_CAPACITY);
+        let cue = ErrorCue::new(monitor_buffer);
+
+        // State shown in the TUI
+        let status = Arc::new(Status::with_history(config.general.history));
+
+        // Create translation engine
+        let translator = match &config.translate {
+            Some(translate_config) => Some(translate::create_translator(translate_config)?),
+            None => None,
+        };
+
+        // Record of the session
+        let transcript = match &config.transcript {
+            Some(transcript_config) => Some(Transcript::new(transcript_config, &name)?),
+            None => None,
+        };
+
+        // Audio of the session
+        let recorder = match &config.recording {
+            Some(recording_config) => Some(Recorder::new(recording_config, &name)?),
+            None => None,
+        };
+
+        // Recordings kept while whisper is unavailable
+        let backlog = match &config.whisper.backlog {
+            Some(backlog_config) => Some(Backlog::new(backlog_config, &name)?),
+            None => None,
+        };
+
+        // Create outputs
+        let mut sinks = sink::create_sinks(
+            &config.sinks,
+            &config.piper,
+            config.postprocess.as_ref(),
+            play_buffer.clone(),
+        )?;
+        sinks.extend(app_sinks);
+
+        // Create audio client
+        let mut audio_client: Box<dyn DynAudioClient> =
+            match (audio_client, &config.general.audio_client) {
+                (Some(audio_client), _) => audio_client,
+                (None, AudioClientType::Jack) => {
+                    let mut jack_config = config
+                        .audio
+                        .jack
+                        .clone()
+                        .ok_or(ErrStartPipeline::NoAudioConfig)?;
+                    jack_config.room_ports = config
+                        .rooms
+                        .iter()
+                        .map(|room| (room.name.clone(), room.output_ports.clone()))
+                        .collect();
+                    jack_config.echo_reference = config.audio.echo.is_some();
+                    Box::new(JackClient::new(&jack_config)?)
+                }
+                (None, AudioClientType::File) => {
+                    let file_config = config
+                        .audio
+                        .file
+                        .as_ref()
+                        .ok_or(ErrStartPipeline::NoAudioConfig)?;
+                    Box::new(
+                        FileClient::new(file_config)
+                            .map_err(|err| ErrStartPipeline::AudioClientError(Box::new(err)))?,
+                    )
+                }
+                (None, AudioClientType::Net) => {
+                    let net_config = config
+                        .audio
+                        .net
+                        .as_ref()
+                        .ok_or(ErrStartPipeline::NoAudioConfig)?;
+                    Box::new(
+                        NetClient::new(net_config)
+                            .map_err(|err| ErrStartPipeline::AudioClientError(Box::new(err)))?,
+                    )
+                }
+                #[cfg(feature = "alsa")]
+                (None, AudioClientType::Alsa) => {
+                    let alsa_config = config
+                        .audio
+                        .alsa
+                        .as_ref()
+                        .ok_or(ErrStartPipeline::NoAudioConfig)?;
+                    Box::new(
+                        AlsaClient::new(alsa_config)
+                            .map_err(|err| ErrStartPipeline::AudioClientError(Box::new(err)))?,
+                    )
+                }
+                #[cfg(feature = "pulse")]
+                (None, AudioClientType::Pulse) => {
+                    let pulse_config = config.audio.pulse.clone().unwrap_or_default();
+                    Box::new(
+                        PulseClient::new(&pulse_config)
+                            .map_err(|err| ErrStartPipeline::AudioClientError(Box::new(err)))?,
+                    )
+                }
+            };
+
+        // Spawn processing thread
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_cloned = cancel.clone();
+        let config_cloned = config.clone();
+        let controls_cloned = controls.clone();
+        let play_buffer_cloned = play_buffer.clone();
+        let status_cloned = status.clone();
+        let audio_thread = thread::Builder::new()
+            .name(format!("audio_processor_{}", name))
+            .spawn(move || {
+                Processor::new(
+                    config_cloned,
+                    whisper_ctx,
+                    controls_cloned,
+                    cancel_cloned,
+                    translator,
+                    sinks,
+                    play_buffer_cloned,
+                    status_cloned,
+                    Some(cue),
+                    transcript,
+                    recorder,
+                    backlog,
+                    room_buffers_cloned,
+                )
+                .run(audio_rx)
+            })?;
+
+        // Start audio client
+        audio_client
+            .start(
+                audio_tx.clone(),
+                play_consumer,
+                monitor_consumer,
+                room_consumers,
+                controls.clone(),
+            )
+            .map_err(ErrStartPipeline::AudioClientError)?;
+
+        Ok(Pipeline {
+            name,
+            status,
+            play_buffer,
+            room_buffers,
+            controls,
+            audio_tx,
+            audio_thread,
+            audio_client,
+            cancel,
+        })
+    }
+}
+
+// A running pipeline, from audio input through to its outputs
+pub struct Pipeline {
+    pub name: String,
+    pub status: Arc<Status>,
+    pub play_buffer: Arc<PlayBuffer>,
+    room_buffers: Vec<Arc<PlayBuffer>>,
+    controls: Arc<Controls>,
+    audio_tx: AudioSender,
+    audio_thread: JoinHandle<()>,
+    audio_client: Box<dyn DynAudioClient>,
+    cancel: Arc<AtomicBool>, // Aborts whisper when stopping
+}
+
+impl Pipeline {
+    // Set up a pipeline running with the given config
+    pub fn builder(config: Arc<Config>) -> PipelineBuilder {
+        PipelineBuilder {
+            name: "main".to_owned(),
+            config,
+            whisper_ctx: None,
+            controls: Arc::new(Controls::default()),
+            sinks: vec![],
+            audio_client: None,
+        }
+    }
+
+    // Start capturing, processing and playing audio
+    pub fn start(
+        name: String,
+        config: Arc<Config>,
+        whisper_ctx: Option<Arc<WhisperContext>>, // Not set if whisper couldn't be loaded
+        controls: Arc<Controls>,
+    ) -> Result<Self, ErrStartPipeline> {
+        Self::builder(config)
+            .name(name)
+            .whisper_ctx(whisper_ctx)
+            .controls(controls)
+            .start()
+    }
+
+    // Apply a changed config to the running pipeline
+    pub fn reload(&self, config: Arc<Config>) {
+        if let Err(err) = self.audio_tx.send(ProcessUnit::Reload(config)) {
+            error!("Could not reload pipeline {}!\n{}", self.name, err);
+        }
+    }
+
+    // Translate into a different language from now on
+    pub fn set_target(&self, target: String) {
+        if let Err(err) = self.audio_tx.send(ProcessUnit::SetTarget(target)) {
+            error!("Could not set target language of {}!\n{}", self.name, err);
+        }
+    }
+
+    // Speak with a different voice from now on, it has to be downloaded already
+    pub fn set_voice(&self, voice: String) {
+        if let Err(err) = self.audio_tx.send(ProcessUnit::SetVoice(voice)) {
+            error!("Could not set voice of {}!\n{}", self.name, err);
+        }
+    }
+
+    // Stop transcribing and silence the output, keeping the audio client and its connections
+    pub fn pause(&self) {
+        if self.audio_client.paused() {
+            return;
+        }
+        self.audio_client.pause();
+        if let Err(err) = self.audio_tx.send(ProcessUnit::Pause) {
+            error!("Could not pause pipeline {}!\n{}", self.name, err);
+        }
+        info!("Pipeline {} paused", self.name);
+    }
+
+    // Carry on after pause, speaking anything that was held back
+    pub fn resume(&self) {
+        if !self.audio_client.paused() {
+            return;
+        }
+        self.audio_client.resume();
+        info!("Pipeline {} resumed", self.name);
+    }
+
+    pub fn paused(&self) -> bool {
+        self.audio_client.paused()
+    }
+
+    // Receive every event of the pipeline from now on, e.g. to follow utterances through it
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.status.events.subscribe()
+    }
+
+    // Audio blocks dropped since starting because processing fell behind
+    pub fn dropped_blocks(&self) -> u64 {
+        self.audio_tx.total_dropped()
+    }
+
+    // Xruns reported by the audio server since starting
+    pub fn xruns(&self) -> u64 {
+        self.audio_client.xruns()
+    }
+
+    // Whether the audio client's input has ended and was processed, e.g. at the end of a file
+    pub fn input_finished(&self) -> bool {
+        self.audio_client.finished()
+    }
+
+    // Whether the processing thread ended without being stopped, e.g. after a panic
+    pub fn processing_stopped(&self) -> bool {
+        self.audio_thread.is_finished()
+    }
+
+    // Stop processing and release the audio client
+    pub fn stop(self) {
+        // Stop processing thread, without waiting for a transcription to finish
+        self.cancel.store(true, Ordering::Relaxed);
+        if let Err(err) = self.audio_tx.send(ProcessUnit::Quit) {
+            error!(
+                "Could not send stop signal to audio processing thread of {}!\n{}",
+                self.name, err
+            );
+        };
+        self.release();
+    }
+
+    // Stop capturing, and have the processing thread output what was already heard before quitting
+    // Followed by shutdown, which waits for it
+    pub fn drain(&self) {
+        self.audio_tx.stop_input();
+        if let Err(err) = self.audio_tx.send(ProcessUnit::Drain) {
+            error!("Could not drain pipeline {}!\n{}", self.name, err);
+        }
+    }
+
+    // Wait for a drained pipeline to finish and its speech to play, then release the audio client
+    // Whatever is left at the deadline is dropped
+    pub fn shutdown(self, deadline: Instant) {
+        while !self.audio_thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(SHUTDOWN_POLL);
+        }
+        if !self.audio_thread.is_finished() {
+            warn!("Pipeline {} didn't finish in time, cancelling", self.name);
+            self.cancel.store(true, Ordering::Relaxed);
+        }
+
+        // Speech held back by pausing won't play, so isn't waited for
+        let queued = || {
+            std::iter::once(&self.play_buffer)
+                .chain(&self.room_buffers)
+                .map(|buffer| buffer.queued())
+                .fold(0.0, f32::max)
+        };
+        while queued() > 0.0
+            && !self.paused()
+            && !self.controls.paused()
+            && Instant::now() < deadline
+        {
+            thread::sleep(SHUTDOWN_POLL);
+        }
+        if queued() > 0.0 {
+            warn!(
+                "Dropping {:.1} seconds of speech from pipeline {}",
+                queued(),
+                self.name
+            );
+        }
+
+        self.release();
+    }
+
+    // Join the processing thread and close the audio client, which reconnects its ports
+    fn release(mut self) {
+        if self.audio_thread.join().is_err() {
+            error!("Could not join audio processing thread of {}!", self.name);
+        };
+
+        // Kill audio client
+        self.audio_client.stop();
+    }
+}
@@ -1,24 +1,64 @@
 use std::{
-    collections::VecDeque,
+    collections::HashMap,
     fmt::Display,
+    fs::File,
     io::{BufRead, BufReader},
-    path::Path,
+    net::TcpStream,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
-    sync::{Arc, Mutex},
-    thread,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use log::{error, info, warn};
 use serde::Deserialize;
 
-use crate::util::resample;
+use crate::{
+    data_dir,
+    dsp::{AudioStage, Chain, StageConfig, stretch::stretch},
+    execution::{ExecutionConfig, Provider},
+    offline,
+    sound::play_buffer::PlayBuffer,
+    tunnel::{RemoteConfig, Tunnel},
+    util::{Resampler, read_wav_frames},
+};
+
+// Python virtual environment piper is installed into, inside the data directory
+const ENV_PATH: &str = "env";
+
+// Python packages the server needs, pinning a version here installs it again on the next start
+const PACKAGES: [&str; 3] = ["pip", "piper-tts", "flask"];
+
+// Packages last installed into the environment, inside it
+const PACKAGES_STAMP: &str = "live-translate-packages";
+
+// Repository piper voices are downloaded from
+const VOICES_URL: &str = "https://huggingface.co/rhasspy/piper-voices/resolve/main";
+
+// Time the server gets to exit on its own when stopping
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often the local server is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Longest wait between restarts of a server which keeps crashing
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub enum ErrSetupPiper {
     IoError(std::io::Error),
     CouldNotCreateEnv,
     CouldNotInstallDeps,
-    CouldNotDownloadModel,
+    CouldNotDownloadModel(reqwest::Error),
+    UnknownVoice(String),
+    MissingVoiceFile(PathBuf),
+    PiperNotInstalled(PathBuf),
+    Offline(String),
+    ServerTimeout,
 }
 
 impl Display for ErrSetupPiper {
@@ -29,7 +69,26 @@ impl Display for ErrSetupPiper {
                 write!(f, "Could not create python virtual environment for piper")
             }
             Self::CouldNotInstallDeps => write!(f, "Could not install python dependencies"),
-            Self::CouldNotDownloadModel => write!(f, "Could not download piper model!"),
+            Self::CouldNotDownloadModel(error) => {
+                write!(f, "Could not download piper model!\n{}", error)
+            }
+            Self::UnknownVoice(voice) => write!(
+                f,
+                "{} isn't a piper voice name like \"de_DE-thorsten-medium\"",
+                voice
+            ),
+            Self::MissingVoiceFile(path) => write!(
+                f,
+                "Voice file {} is missing after downloading the voice",
+                path.display()
+            ),
+            Self::PiperNotInstalled(python) => write!(
+                f,
+                "Piper isn't installed for {}, install piper-tts and flask with it",
+                python.display()
+            ),
+            Self::Offline(voice) => write!(f, "Piper voice {} can't be downloaded offline", voice),
+            Self::ServerTimeout => write!(f, "Piper server did not start in time"),
         }
     }
 }
@@ -42,6 +101,12 @@ impl From<std::io::Error> for ErrSetupPiper {
     }
 }
 
+impl From<reqwest::Error> for ErrSetupPiper {
+    fn from(value: reqwest::Error) -> Self {
+        Self::CouldNotDownloadModel(value)
+    }
+}
+
 #[derive(Debug)]
 pub enum ErrPlayTTS {
     ReqwestError(reqwest::Error),
@@ -79,13 +144,260 @@ impl From<speexdsp_resampler::Error> for ErrPlayTTS {
     }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct PiperConfig {
-    pub model: String,
+    pub model: String, // Default voice
+    #[serde(default)]
+    pub voices: HashMap<String, String>, // Voice to use for each language code
+    #[serde(default)]
+    pub post: Vec<StageConfig>, // Processing applied to TTS audio before playback
+    pub remote: Option<RemoteConfig>, // Use a piper server on another machine through ssh
+    pub server: Option<PathBuf>, // Standalone piper server to run instead of installing piper with python
+    #[serde(default)]
+    pub install: PiperInstall, // Python piper is run with, without a standalone server
+    pub voice_dir: Option<PathBuf>, // Where voices are downloaded to, the data directory by default
+    #[serde(default = "default_port")]
+    pub port: u16, // Local port the server listens on, or the tunnel to a remote one
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    #[serde(default = "default_speed")]
+    pub speed: f32, // Playback speed without changing pitch, e.g. 1.2 so longer translations keep up
+    pub max_backlog: Option<f32>, // Seconds of speech that may be queued before the backlog policy kicks in
+    #[serde(default)]
+    pub backlog_policy: BacklogPolicy,
+    #[serde(default = "default_catch_up_speed")]
+    pub catch_up_speed: f32, // Extra speed while speeding up to catch up
+    #[serde(default = "default_gap")]
+    pub gap: f32, // Silence in ms between utterances queued back to back
+    #[serde(default = "default_fade")]
+    pub fade: f32, // Fade in ms at the start and end of each utterance, avoiding clicks
+    #[serde(default = "default_timeout")]
+    pub timeout: f32, // Seconds to wait for the speech of an utterance
+    #[serde(default = "default_cache_size")]
+    pub cache_size: usize, // Short utterances whose speech is kept to be played again, 0 to turn off
+    #[serde(default)]
+    pub split_sentences: bool, // Speak long utterances a sentence at a time, so the first plays sooner
+    #[serde(default)]
+    pub announce_speaker: bool, // Say who is speaking when diarization hears a different speaker
+    #[serde(default = "default_restart_wait")]
+    pub restart_wait: f32, // Seconds speech is held while the server is restarted after a crash
+}
+
+fn default_port() -> u16 {
+    5000
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_catch_up_speed() -> f32 {
+    1.5
+}
+
+fn default_gap() -> f32 {
+    200.0
+}
+
+fn default_fade() -> f32 {
+    5.0
+}
+
+fn default_timeout() -> f32 {
+    30.0
+}
+
+fn default_cache_size() -> usize {
+    50
+}
+
+fn default_restart_wait() -> f32 {
+    15.0
+}
+
+// What to do with new speech while more than the max backlog is queued
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BacklogPolicy {
+    #[default]
+    DropOldest, // Skip ahead past the oldest queued utterances, cutting off the one playing
+    CaptionOnly, // Only show the new utterance without speaking it
+    SpeedUp,     // Speak the new utterance faster
+}
+
+// Where the python piper server comes from
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PiperInstall {
+    #[default]
+    Managed, // Virtual environment in the data directory, created and updated with pip
+    Venv(PathBuf), // Existing virtual environment with piper installed, used as it is
+    System,        // Python on the PATH with piper installed, e.g. by the distro
+}
+
+impl PiperConfig {
+    // Python the server is run with, unless a standalone server is used
+    fn python(&self) -> PathBuf {
+        match &self.install {
+            PiperInstall::Managed => env_bin("python"),
+            PiperInstall::Venv(env) => venv_bin(env, "python"),
+            PiperInstall::System if cfg!(target_os = "windows") => PathBuf::from("python"),
+            PiperInstall::System => PathBuf::from("python3"),
+        }
+    }
+
+    pub fn voice_dir(&self) -> PathBuf {
+        self.voice_dir
+            .clone()
+            .unwrap_or_else(|| data_dir::get().to_owned())
+    }
+
+    // Address speech is requested from
+    pub fn url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    // Get the voice to speak a language with
+    pub fn voice_for(&self, language: Option<&str>) -> &str {
+        language
+            .and_then(|language| self.voices.get(language))
+            .unwrap_or(&self.model)
+    }
+}
+
+// Running piper server, either local or reached through a tunnel
+pub enum PiperServer {
+    Local(Supervisor),
+    Remote(Tunnel),
+}
+
+impl PiperServer {
+    // Stop the server or close the tunnel
+    pub fn kill(&mut self) -> Result<(), std::io::Error> {
+        match self {
+            Self::Local(supervisor) => supervisor.stop(),
+            Self::Remote(tunnel) => tunnel.close(),
+        }
+
+        Ok(())
+    }
+}
+
+// Local server, started again whenever it exits
+pub struct Supervisor {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    // Watch a started server, restarting it with the same command
+    fn spawn(
+        child: Child,
+        command: impl FnMut() -> Command + Send + 'static,
+    ) -> Result<Self, std::io::Error> {
+        let running = Arc::new(AtomicBool::new(true));
+
+        let running_cloned = running.clone();
+        let thread = thread::Builder::new()
+            .name("piper_supervisor".to_owned())
+            .spawn(move || supervise(child, command, running_cloned))?;
+
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    // Stop the server
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            error!("Could not join piper supervisor thread!");
+        }
+    }
+}
+
+// Keep the server running until stopped, backing off while it keeps crashing
+// Speech waits for it in the meantime, see PiperConfig::restart_wait
+fn supervise(mut child: Child, mut command: impl FnMut() -> Command, running: Arc<AtomicBool>) {
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        let started = Instant::now();
+
+        // Watch for the server exiting
+        let status = loop {
+            if !running.load(Ordering::SeqCst) {
+                if let Err(err) = terminate(&mut child, TERMINATE_TIMEOUT) {
+                    error!("Could not stop piper server!\n{}", err);
+                }
+                return;
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(err) => {
+                    error!("Could not check piper server!\n{}", err);
+                    return;
+                }
+            }
+        };
+        error!("Piper server exited ({})", status);
+
+        // Back off while it keeps crashing
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = Duration::from_secs(1);
+        }
+        child = loop {
+            warn!("Restarting piper server in {}s", backoff.as_secs());
+            let retry = Instant::now();
+            while retry.elapsed() < backoff {
+                if !running.load(Ordering::SeqCst) {
+                    return;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+
+            match run_command_with_log(&mut command()) {
+                Ok(child) => break child,
+                Err(err) => error!("Could not restart piper server!\n{}", err),
+            }
+        };
+        info!("Piper server restarted");
+    }
+}
+
+// Ask a child to exit with SIGTERM, killing it if it's still running after the timeout
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn terminate(child: &mut Child, timeout: Duration) -> Result<(), std::io::Error> {
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+
+    // The child hasn't been waited on, so its pid can't belong to another process yet
+    #[cfg(unix)]
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } == 0 {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        warn!("Piper server didn't exit in time, killing it");
+    }
+
+    child.kill()?;
+    child.wait()?;
+    Ok(())
 }
 
 // Pipe output to log and run
-fn run_command_with_log(command: &mut Command) -> Result<Child, std::io::Error> {
+pub fn run_command_with_log(command: &mut Command) -> Result<Child, std::io::Error> {
     let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -118,92 +430,523 @@ fn run_command_with_log(command: &mut Command) -> Result<Child, std::io::Error>
     Ok(child)
 }
 
+// Files a voice needs, the model and its config
+fn voice_files(voice_dir: &Path, voice: &str) -> [PathBuf; 2] {
+    ["onnx", "onnx.json"].map(|extension| voice_dir.join(format!("{}.{}", voice, extension)))
+}
+
+pub fn voice_downloaded(voice_dir: &Path, voice: &str) -> bool {
+    voice_files(voice_dir, voice)
+        .iter()
+        .all(|path| path.exists())
+}
+
+// Executable inside a python virtual environment, which is laid out differently on windows
+fn venv_bin(env: &Path, name: &str) -> PathBuf {
+    if cfg!(target_os = "windows") {
+        env.join("Scripts").join(format!("{}.exe", name))
+    } else {
+        env.join("bin").join(name)
+    }
+}
+
+// Executable inside the virtual environment managed in the data directory
+fn env_bin(name: &str) -> PathBuf {
+    venv_bin(&data_dir::path(ENV_PATH), name)
+}
+
+// Whether a python can run the piper server, for installs which aren't managed here
+fn has_piper(python: &Path) -> bool {
+    Command::new(python)
+        .args(["-c", "import piper.http_server"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Server shipped next to the executable by an installer
+fn bundled_server() -> Option<PathBuf> {
+    let name = if cfg!(target_os = "windows") {
+        "piper-server.exe"
+    } else {
+        "piper-server"
+    };
+    let path = std::env::current_exe().ok()?.parent()?.join(name);
+    path.exists().then_some(path)
+}
+
+// What a local server needs which isn't there, checked up front when offline
+pub fn missing(config: &PiperConfig, extra_voices: &[String]) -> Vec<String> {
+    if config.remote.is_some() {
+        return vec![];
+    }
+
+    let voice_dir = config.voice_dir();
+    let mut missing: Vec<String> = std::iter::once(&config.model)
+        .chain(config.voices.values())
+        .chain(extra_voices)
+        .flat_map(|voice| voice_files(&voice_dir, voice))
+        .filter(|path| !path.exists())
+        .map(|path| format!("Piper voice file {}", path.display()))
+        .collect();
+
+    // A standalone server is taken as it is
+    if config.server.is_none() && bundled_server().is_none() {
+        let python = config.python();
+        if !has_piper(&python) {
+            missing.push(format!("Piper installed for {}", python.display()));
+        }
+    }
+
+    missing
+}
+
+// Download a voice and its config from the piper voices repository
+pub fn download_voice(voice_dir: &Path, voice: &str) -> Result<(), ErrSetupPiper> {
+    // Names are locale-speaker-quality, e.g. de_DE-thorsten-medium
+    let unknown = || ErrSetupPiper::UnknownVoice(voice.to_owned());
+    let (locale, rest) = voice.split_once('-').ok_or_else(unknown)?;
+    let (speaker, quality) = rest.rsplit_once('-').ok_or_else(unknown)?;
+    let language = locale.split('_').next().ok_or_else(unknown)?;
+
+    std::fs::create_dir_all(voice_dir)?;
+
+    // High quality voices take longer than the default timeout
+    let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+    for path in voice_files(voice_dir, voice) {
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Err(unknown());
+        };
+        let url = format!(
+            "{}/{}/{}/{}/{}/{}",
+            VOICES_URL, language, locale, speaker, quality, file_name
+        );
+        let mut download = client.get(url).send()?.error_for_status()?;
+
+        // Write to a temporary file first so a broken download isn't mistaken for a voice
+        let partial = path.with_extension("part");
+        std::io::copy(&mut download, &mut File::create(&partial)?)?;
+        std::fs::rename(&partial, &path)?;
+    }
+
+    Ok(())
+}
+
+// Voice in the piper catalog
+#[derive(Deserialize)]
+pub struct CatalogVoice {
+    pub key: String, // Name used to download it, e.g. de_DE-thorsten-medium
+    pub quality: String,
+    pub language: CatalogLanguage,
+    #[serde(default)]
+    pub num_speakers: u32,
+}
+
+#[derive(Deserialize)]
+pub struct CatalogLanguage {
+    pub code: String, // e.g. de_DE
+    pub name_english: String,
+}
+
+// Every voice that can be downloaded, sorted by name
+pub fn voice_catalog() -> Result<Vec<CatalogVoice>, reqwest::Error> {
+    let voices: HashMap<String, CatalogVoice> =
+        reqwest::blocking::get(format!("{}/voices.json", VOICES_URL))?
+            .error_for_status()?
+            .json()?;
+
+    let mut voices = voices.into_values().collect::<Vec<_>>();
+    voices.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(voices)
+}
+
+// Names of every voice that can be downloaded
+pub fn available_voices() -> Result<Vec<String>, reqwest::Error> {
+    Ok(voice_catalog()?
+        .into_iter()
+        .map(|voice| voice.key)
+        .collect())
+}
+
+// Print the catalog, optionally only voices for a language like "de" or "de_DE"
+pub fn print_voices(voice_dir: &Path, language: Option<&str>) -> Result<(), reqwest::Error> {
+    for voice in voice_catalog()? {
+        if let Some(language) = language
+            && !voice.language.code.starts_with(language)
+        {
+            continue;
+        }
+
+        let speakers = match voice.num_speakers {
+            0 | 1 => String::new(),
+            speakers => format!("{} speakers", speakers),
+        };
+        let downloaded = if voice_downloaded(voice_dir, &voice.key) {
+            "downloaded"
+        } else {
+            ""
+        };
+        println!(
+            "{:<40}{:<24}{:<8}{:<14}{}",
+            voice.key, voice.language.name_english, voice.quality, speakers, downloaded
+        );
+    }
+
+    Ok(())
+}
+
 // Make sure dependencies are installed and start piper
-pub fn setup_piper(config: &PiperConfig) -> Result<Child, ErrSetupPiper> {
-    // Virtual environment
-    const ENV_PATH: &str = "./env";
+// Extra voices are downloaded so they can be requested from the server
+pub fn setup_piper(
+    config: &PiperConfig,
+    extra_voices: &[String],
+    reinstall: bool, // Install the python packages even if they're there already
+) -> Result<PiperServer, ErrSetupPiper> {
+    // Voices are managed on the remote machine
+    if let Some(remote) = &config.remote {
+        return Ok(PiperServer::Remote(Tunnel::open(remote, config.port)?));
+    }
+
+    // Download missing models
+    let voice_dir = config.voice_dir();
+    for model in std::iter::once(&config.model)
+        .chain(config.voices.values())
+        .chain(extra_voices)
+    {
+        if !voice_downloaded(&voice_dir, model) {
+            if offline::enabled() {
+                return Err(ErrSetupPiper::Offline(model.clone()));
+            }
+            warn!("Piper model {} not found, downloading now", model);
+            download_voice(&voice_dir, model)?;
+            info!("Piper model {} downloaded", model);
+        };
+
+        // The server fails on the first request for a voice if its config is missing
+        if let Some(missing) = voice_files(&voice_dir, model)
+            .into_iter()
+            .find(|path| !path.exists())
+        {
+            return Err(ErrSetupPiper::MissingVoiceFile(missing));
+        }
+    }
 
+    // A standalone server needs no python
+    let server = config.server.clone().or_else(bundled_server);
+    let python = config.python();
+    match (&server, &config.install) {
+        (Some(server), _) => info!("Using piper server {}", server.display()),
+        (None, PiperInstall::Managed) => setup_env(reinstall)?,
+        (None, _) if !has_piper(&python) => return Err(ErrSetupPiper::PiperNotInstalled(python)),
+        (None, _) => info!("Using piper installed for {}", python.display()),
+    }
+
+    // Piper only has a CUDA switch, threads are left to onnxruntime
+    let provider =
+        config
+            .execution
+            .select("Piper", &[Provider::Cpu, Provider::Cuda], Provider::Cpu);
+    if config.execution.threads.is_some() {
+        warn!("Piper doesn't support setting threads, ignoring");
+    }
+
+    // Run server, voices are looked up relative to the voice directory
+    let model = config.model.clone();
+    let port = config.port.to_string();
+    let command = move || {
+        let mut command = match &server {
+            Some(server) => Command::new(server),
+            None => {
+                let mut command = Command::new(&python);
+                command.args(["-m", "piper.http_server"]);
+                command
+            }
+        };
+        command.args(["-m", model.as_str(), "--port", port.as_str()]);
+        command.current_dir(&voice_dir);
+        if provider == Provider::Cuda {
+            command.arg("--cuda");
+        }
+        command
+    };
+    let piper = run_command_with_log(&mut command())?;
+
+    Ok(PiperServer::Local(Supervisor::spawn(piper, command)?))
+}
+
+// Install piper into a python virtual environment
+fn setup_env(reinstall: bool) -> Result<(), ErrSetupPiper> {
     // Create virtual environment of it doesn't already exist
-    if !Path::new(ENV_PATH).exists() {
+    let env_path = data_dir::path(ENV_PATH);
+    if !env_path.exists() {
         warn!("Python virtual environment does not exist, creating now");
 
-        let status =
-            run_command_with_log(Command::new("python3.11").args(["-m", "venv", ENV_PATH]))?
-                .wait()?;
+        // Windows has the python launcher rather than versioned executables
+        let mut command = if cfg!(target_os = "windows") {
+            let mut command = Command::new("py");
+            command.arg("-3.11");
+            command
+        } else {
+            Command::new("python3.11")
+        };
+        let status = run_command_with_log(command.args(["-m", "venv"]).arg(&env_path))?.wait()?;
         if !status.success() {
             return Err(ErrSetupPiper::CouldNotCreateEnv);
         }
     }
 
+    // Nothing can be installed offline, what's there has to do
+    if offline::enabled() {
+        let python = env_bin("python");
+        return match has_piper(&python) {
+            true => Ok(()),
+            false => Err(ErrSetupPiper::PiperNotInstalled(python)),
+        };
+    }
+
+    // Skip installing, which needs the network, while the same packages are there
+    let stamp = env_path.join(PACKAGES_STAMP);
+    let packages = PACKAGES.join("\n");
+    if !reinstall
+        && std::fs::read_to_string(&stamp).is_ok_and(|installed| installed == packages)
+        && has_piper(&env_bin("python"))
+    {
+        info!("Python dependencies are already installed");
+        return Ok(());
+    }
+
     // Install depencencies
-    let status = run_command_with_log(Command::new(format!("{}/bin/pip", ENV_PATH)).args([
-        "install",
-        "--upgrade",
-        "pip",
-        "piper-tts",
-        "flask",
-    ]))?
-    .wait()?;
+    let mut command = Command::new(env_bin("python"));
+    command.args(["-m", "pip", "install", "--upgrade"]);
+    if reinstall {
+        command.arg("--force-reinstall");
+    }
+    let status = run_command_with_log(command.args(PACKAGES))?.wait()?;
     if !status.success() {
         return Err(ErrSetupPiper::CouldNotInstallDeps);
     }
+    std::fs::write(&stamp, packages)?;
 
-    // Download missing model
-    if !std::fs::exists(format!("./{}.onnx", config.model))? {
-        warn!("Piper model not found, downloading now");
+    Ok(())
+}
 
-        let status =
-            run_command_with_log(Command::new(format!("{}/bin/python", ENV_PATH)).args([
-                "-m",
-                "piper.download_voices",
-                &config.model,
-            ]))?
-            .wait()?;
-        if !status.success() {
-            return Err(ErrSetupPiper::CouldNotDownloadModel);
+// Wait until the piper server accepts connections
+pub fn wait_for_piper(port: u16, timeout: Duration) -> Result<(), ErrSetupPiper> {
+    let start = Instant::now();
+    while TcpStream::connect(("localhost", port)).is_err() {
+        if start.elapsed() > timeout {
+            return Err(ErrSetupPiper::ServerTimeout);
         }
-    };
+        thread::sleep(Duration::from_millis(100));
+    }
 
-    // Run server
-    let piper = run_command_with_log(Command::new(format!("{}/bin/python", ENV_PATH)).args([
-        "-m",
-        "piper.http_server",
-        "-m",
-        config.model.as_str(),
-    ]))?;
+    Ok(())
+}
 
-    Ok(piper)
+// Time spent speaking an utterance
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TtsTiming {
+    pub request: Duration,  // Waiting on the server
+    pub resample: Duration, // Bringing the speech to the output rate and processing it
 }
 
-pub fn play_tts(play_buffer: Arc<Mutex<VecDeque<f32>>>, message: String) -> Result<(), ErrPlayTTS> {
-    // Get TTS from server
-    let http_client = reqwest::blocking::Client::new();
-    let voice = http_client
-        .post("http://localhost:5000")
-        .body(format!("{{ \"text\": \"{}\" }}", message))
-        .send()?
-        .bytes()?;
+// Frames of speech decoded at a time, about 100ms at piper's rates
+const STREAM_FRAMES: usize = 2048;
+
+// Most characters sent to be spoken at once, longer text is cut at a word
+const MAX_TEXT: usize = 2000;
 
-    // Create reader to parse TTS outout
-    let mut reader = hound::WavReader::new(std::io::Cursor::new(voice))?;
-    // Create buffer for TTS samples
-    let mut samples: Vec<f32> = vec![];
+// Make transcribed text safe to send, without control characters and not too long
+fn sanitize(text: &str) -> String {
+    let text = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
 
-    // Loop through samples
-    for sample in reader.samples::<i16>() {
-        // Convert sample to floats and scale accordingly
-        samples.push(sample? as f32 / i16::MAX as f32);
+    if text.chars().count() <= MAX_TEXT {
+        return text.trim().to_owned();
     }
 
-    // Get sample rate
-    let samplerate = reader.spec().sample_rate as usize;
+    warn!(
+        "Text is too long to speak, cutting it to {} characters",
+        MAX_TEXT
+    );
+    let end = text
+        .char_indices()
+        .nth(MAX_TEXT)
+        .map_or(text.len(), |(end, _)| end);
+    let cut = &text[..end];
+    cut.rsplit_once(' ')
+        .map_or(cut, |(words, _)| words)
+        .trim()
+        .to_owned()
+}
 
-    let resampled = resample(samples, samplerate, 48000)?;
+// Fewest characters in a sentence spoken on its own, shorter ones are joined to the next
+const MIN_SENTENCE: usize = 20;
+
+// Split text into sentences at the punctuation ending them, so each can be spoken as soon as it's ready
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let end = i + c.len_utf8();
+        // CJK punctuation isn't followed by a space
+        let boundary = match c {
+            '.' | '!' | '?' => chars.peek().is_none_or(|(_, next)| next.is_whitespace()),
+            '。' | '！' | '？' => true,
+            _ => false,
+        };
+        if boundary && text[start..end].trim().chars().count() >= MIN_SENTENCE {
+            sentences.push(text[start..end].trim());
+            start = end;
+        }
+    }
+    if !text[start..].trim().is_empty() {
+        sentences.push(text[start..].trim());
+    }
 
-    // Lock play buffer
-    let mut play_buffer = play_buffer.lock().unwrap();
-    // Add resulting TTS audio to the play buffer
-    play_buffer.append(&mut Into::<VecDeque<_>>::into(resampled));
+    sentences
+}
 
-    Ok(())
+// Fade the start of a clip in over a number of samples
+fn fade_in(samples: &mut [f32], length: usize) {
+    let length = length.min(samples.len());
+    for (i, sample) in samples[..length].iter_mut().enumerate() {
+        *sample *= i as f32 / length as f32;
+    }
+}
+
+// Fade the end of a clip out over a number of samples
+fn fade_out(samples: &mut [f32], length: usize) {
+    let length = length.min(samples.len());
+    for (i, sample) in samples.iter_mut().rev().take(length).enumerate() {
+        *sample *= i as f32 / length as f32;
+    }
+}
+
+// Longest wait for a connection to the piper server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Client for the piper server, kept so the connection is reused between utterances
+pub fn tts_client(config: &PiperConfig) -> Result<reqwest::blocking::Client, ErrPlayTTS> {
+    Ok(reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(Duration::from_secs_f32(config.timeout.max(0.0)))
+        .tcp_nodelay(true)
+        .build()?)
+}
+
+// Speak a message, queueing the speech as it arrives from the server
+// Returns the speech queued, without the pause before it
+#[allow(clippy::too_many_arguments)]
+pub fn play_tts(
+    http_client: &reqwest::blocking::Client,
+    url: &str,
+    play_buffer: Arc<PlayBuffer>,
+    message: String,
+    voice: &str,
+    speed: f32,
+    gap: f32,
+    fade: f32,
+    post_chain: &mut Chain,
+) -> Result<(TtsTiming, Vec<f32>), ErrPlayTTS> {
+    let text = sanitize(&message);
+    if text.is_empty() {
+        return Ok((TtsTiming::default(), vec![]));
+    }
+
+    // Get TTS from server
+    let start = Instant::now();
+    let response = http_client
+        .post(url)
+        .body(serde_json::json!({ "text": text, "voice": voice }).to_string())
+        .send()?;
+
+    // Parse TTS output as it comes in, whatever sample format and channels piper was set up with
+    let mut reader = hound::WavReader::new(response)?;
+    let sample_rate = play_buffer.sample_rate();
+    let mut resampler = Resampler::new(reader.spec().sample_rate as usize, sample_rate)?;
+    let fade = (fade / 1000.0 * sample_rate as f32) as usize;
+    let gap = (gap / 1000.0 * sample_rate as f32) as usize;
+
+    // Stretching needs the whole clip, so changed speeds wait for all of it
+    let streaming = (speed - 1.0).abs() < 0.01;
+
+    let mut clip = vec![];
+    let mut pushed = 0; // Samples of the clip already queued
+    let mut processing = Duration::ZERO;
+    loop {
+        let samples = read_wav_frames(&mut reader, STREAM_FRAMES)?;
+        let started = Instant::now();
+        let last = samples.len() < STREAM_FRAMES;
+        let mut resampled = resampler.process(&samples, last)?;
+
+        if streaming {
+            // Apply output processing
+            post_chain.process(&mut resampled);
+            clip.extend(resampled);
+
+            // The end is held back until it's known where to fade out
+            if last {
+                fade_out(&mut clip[pushed..], fade);
+            }
+            let end = if last {
+                clip.len()
+            } else {
+                clip.len().saturating_sub(fade)
+            };
+
+            if end > pushed {
+                if pushed == 0 {
+                    fade_in(&mut clip, fade);
+                    leave_gap(&play_buffer, gap);
+                }
+                play_buffer.push(clip[pushed..end].to_vec());
+                pushed = end;
+            }
+        } else {
+            clip.extend(resampled);
+        }
+
+        processing += started.elapsed();
+        if last {
+            break;
+        }
+    }
+
+    if !streaming {
+        let started = Instant::now();
+
+        // Change the speed of the speech, keeping its pitch
+        clip = stretch(&clip, speed, sample_rate);
+
+        // Apply output processing
+        post_chain.process(&mut clip);
+
+        fade_in(&mut clip, fade);
+        fade_out(&mut clip, fade);
+        leave_gap(&play_buffer, gap);
+        play_buffer.push(clip.clone());
+
+        processing += started.elapsed();
+    }
+
+    Ok((
+        TtsTiming {
+            request: start.elapsed() - processing,
+            resample: processing,
+        },
+        clip,
+    ))
+}
+
+// Leave a pause after speech which is still playing, rather than running into it
+pub fn leave_gap(play_buffer: &PlayBuffer, gap: usize) {
+    if !play_buffer.is_empty() {
+        play_buffer.push(vec![0.0; gap]);
+    }
 }
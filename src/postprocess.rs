@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use log::error;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Replacement {
+    pub pattern: String,     // Regular expression, e.g. "(?i)m c t thirty two"
+    pub replacement: String, // Text put in its place, $1 refers to the first group
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct PostprocessConfig {
+    #[serde(default)]
+    pub captions: Vec<Replacement>, // Fixes to the transcription and translation, shown and spoken
+    #[serde(default)]
+    pub speech: Vec<Replacement>, // Only applied to the text piper speaks
+    #[serde(default)]
+    pub phonemes: BTreeMap<String, String>, // Words piper says with these espeak phonemes instead
+}
+
+// Pattern of a replacement, reported by validation if it's invalid
+pub fn pattern(replacement: &Replacement) -> Result<Regex, regex::Error> {
+    Regex::new(&replacement.pattern)
+}
+
+// Compile a table, skipping invalid patterns as validation reports them
+fn compile(replacements: &[Replacement]) -> Vec<(Regex, String)> {
+    replacements
+        .iter()
+        .filter_map(|replacement| {
+            pattern(replacement)
+                .inspect_err(|err| error!("Invalid replacement pattern!\n{}", err))
+                .ok()
+                .map(|regex| (regex, replacement.replacement.clone()))
+        })
+        .collect()
+}
+
+fn apply(table: &[(Regex, String)], text: &str) -> String {
+    table
+        .iter()
+        .fold(text.to_owned(), |text, (regex, replacement)| {
+            regex.replace_all(&text, replacement.as_str()).into_owned()
+        })
+}
+
+// Replaces what whisper gets wrong in captions, and what piper says wrong in speech
+pub struct Postprocessor {
+    captions: Vec<(Regex, String)>,
+    speech: Vec<(Regex, String)>,
+}
+
+impl Postprocessor {
+    pub fn new(config: &PostprocessConfig) -> Self {
+        let mut speech = compile(&config.speech);
+
+        // Piper reads phonemes between double brackets as they are
+        for (word, phonemes) in &config.phonemes {
+            match Regex::new(&format!(r"(?i)\b{}\b", regex::escape(word))) {
+                Ok(regex) => speech.push((regex, format!("[[ {} ]]", phonemes.replace('$', "$$")))),
+                Err(err) => error!("Invalid word {} for phonemes!\n{}", word, err),
+            }
+        }
+
+        Self {
+            captions: compile(&config.captions),
+            speech,
+        }
+    }
+
+    // Text as it should be shown
+    pub fn captions(&self, text: &str) -> String {
+        apply(&self.captions, text)
+    }
+
+    // Text as it should be handed to piper
+    pub fn speech(&self, text: &str) -> String {
+        apply(&self.speech, text)
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::info;
+use serde::Deserialize;
+
+use crate::{transcript::utc_date, utterance::Utterance};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordingConfig {
+    #[serde(default = "default_directory")]
+    pub directory: String, // Directory recordings are written into
+    #[serde(default)]
+    pub mode: RecordingMode,
+}
+
+fn default_directory() -> String {
+    "recordings".to_owned()
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RecordingMode {
+    #[default]
+    Utterance, // A pair of files for each utterance, named by its id
+    Session, // One file each for what was said and spoken, with a label track marking the utterances
+}
+
+// Audio going into the pipeline or coming out of it
+#[derive(Clone, Copy)]
+enum Track {
+    Input,
+    Tts,
+}
+
+impl Track {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Tts => "tts",
+        }
+    }
+}
+
+// Session file of one track, with an Audacity label file marking where each utterance is
+struct SessionFile {
+    writer: WavWriter<BufWriter<File>>,
+    labels: File,
+    sample_rate: usize,
+    written: u64, // Samples in the file so far
+}
+
+// Write what was said and what was spoken for it as WAV files, to review the translation afterwards
+pub struct Recorder {
+    config: RecordingConfig,
+    pipeline: String,
+    session: u64,      // Seconds since the unix epoch when the session started
+    date: String,      // Date the session started on
+    parts: [usize; 2], // Files started per track because the sample rate changed
+    files: [Option<SessionFile>; 2],
+}
+
+fn spec(sample_rate: usize) -> WavSpec {
+    WavSpec {
+        channels: 1,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    }
+}
+
+impl Recorder {
+    pub fn new(config: &RecordingConfig, pipeline: &str) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&config.directory)?;
+
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Ok(Self {
+            config: config.clone(),
+            pipeline: pipeline.to_owned(),
+            session,
+            date: utc_date(session * 1000),
+            parts: [0; 2],
+            files: [None, None],
+        })
+    }
+
+    fn path(&self, name: &str, extension: &str) -> PathBuf {
+        PathBuf::from(&self.config.directory).join(format!(
+            "{}_{}_{}_{}.{}",
+            self.date, self.session, self.pipeline, name, extension
+        ))
+    }
+
+    // Record the audio an utterance was transcribed from
+    pub fn input(
+        &mut self,
+        utterance: &Utterance,
+        samples: &[f32],
+        sample_rate: usize,
+    ) -> Result<(), hound::Error> {
+        self.write(Track::Input, utterance, samples, sample_rate)
+    }
+
+    // Record the speech queued for an utterance
+    pub fn tts(
+        &mut self,
+        utterance: &Utterance,
+        samples: &[f32],
+        sample_rate: usize,
+    ) -> Result<(), hound::Error> {
+        self.write(Track::Tts, utterance, samples, sample_rate)
+    }
+
+    fn write(
+        &mut self,
+        track: Track,
+        utterance: &Utterance,
+        samples: &[f32],
+        sample_rate: usize,
+    ) -> Result<(), hound::Error> {
+        match self.config.mode {
+            RecordingMode::Utterance => {
+                let name = format!("{}_{}", utterance.id, track.name());
+                let mut writer = WavWriter::create(self.path(&name, "wav"), spec(sample_rate))?;
+                for sample in samples {
+                    writer.write_sample(*sample)?;
+                }
+                writer.finalize()
+            }
+            RecordingMode::Session => self.append(track, utterance, samples, sample_rate),
+        }
+    }
+
+    // Add to the session file of a track, starting a new one if the rate changed
+    fn append(
+        &mut self,
+        track: Track,
+        utterance: &Utterance,
+        samples: &[f32],
+        sample_rate: usize,
+    ) -> Result<(), hound::Error> {
+        let index = track as usize;
+        if let Some(file) = &self.files[index]
+            && file.sample_rate != sample_rate
+        {
+            self.parts[index] += 1;
+            self.files[index] = None;
+        }
+
+        if self.files[index].is_none() {
+            let name = match self.parts[index] {
+                0 => track.name().to_owned(),
+                part => format!("{}_{}", track.name(), part),
+            };
+            let path = self.path(&name, "wav");
+            info!("Recording {} to {}", track.name(), path.display());
+            self.files[index] = Some(SessionFile {
+                writer: WavWriter::create(path, spec(sample_rate))?,
+                labels: File::create(self.path(&name, "txt"))?,
+                sample_rate,
+                written: 0,
+            });
+        }
+        let Some(file) = &mut self.files[index] else {
+            return Ok(());
+        };
+
+        // Label from the start to the end of the utterance, in seconds
+        let start = file.written as f64 / sample_rate as f64;
+        let end = (file.written + samples.len() as u64) as f64 / sample_rate as f64;
+        let text = match track {
+            Track::Input => utterance.text.trim(),
+            Track::Tts => utterance.output_text().trim(),
+        };
+        writeln!(
+            file.labels,
+            "{:.3}\t{:.3}\t{}: {}",
+            start, end, utterance.id, text
+        )?;
+
+        for sample in samples {
+            file.writer.write_sample(*sample)?;
+        }
+        file.written += samples.len() as u64;
+
+        // Keep the header up to date, so the file can be played if the program dies
+        file.writer.flush()
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{Config, piper, validate};
+
+// Time to wait for an editor to finish writing before reading the file
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// One problem per line
+fn format_problems(problems: &[validate::Problem]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n  {}", problem))
+        .collect()
+}
+
+// Read and parse a config file
+pub fn read_config(path: &Path) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read config file!\n{}", err))?;
+
+    let table: toml::Table =
+        toml::from_str(&content).map_err(|err| format!("Could not parse config file!\n{}", err))?;
+    let mut problems = validate::unknown_sections(&table);
+
+    // A misspelled section often shows up as a missing field, so list both
+    let config: Config = match toml::from_str(&content) {
+        Ok(config) => config,
+        Err(err) => {
+            return Err(format!(
+                "Could not parse config file!\n{}{}",
+                err,
+                format_problems(&problems)
+            ));
+        }
+    };
+    problems.extend(config.validate());
+
+    // Report everything wrong at once
+    if !problems.is_empty() {
+        return Err(format!(
+            "Config file has problems!{}",
+            format_problems(&problems)
+        ));
+    }
+
+    Ok(config)
+}
+
+// Take the settings from a new config which can be changed while running
+// Anything else keeps its old value, with a warning if it was changed
+pub fn merge_config(old: &Config, new: Config) -> Config {
+    let mut merged = old.clone();
+
+    // Settings which need the pipelines to be rebuilt
+    let restart_required = [
+        (
+            "general.audio_client",
+            old.general.audio_client != new.general.audio_client,
+        ),
+        (
+            "general.sync_captions_to_tts",
+            old.general.sync_captions_to_tts != new.general.sync_captions_to_tts,
+        ),
+        (
+            "general.metrics",
+            old.general.metrics != new.general.metrics,
+        ),
+        (
+            "general.history",
+            old.general.history != new.general.history,
+        ),
+        (
+            "general.control_token",
+            old.general.control_token != new.general.control_token,
+        ),
+        ("hotkeys", old.hotkeys != new.hotkeys),
+        ("audio.jack", old.audio.jack != new.audio.jack),
+        ("audio.file", old.audio.file != new.audio.file),
+        ("audio.net", old.audio.net != new.audio.net),
+        #[cfg(feature = "alsa")]
+        ("audio.alsa", old.audio.alsa != new.audio.alsa),
+        #[cfg(feature = "pulse")]
+        ("audio.pulse", old.audio.pulse != new.audio.pulse),
+        ("audio.queue", old.audio.queue != new.audio.queue),
+        ("audio.echo", old.audio.echo != new.audio.echo),
+        ("whisper.model", old.whisper.model != new.whisper.model),
+        (
+            "whisper.execution",
+            old.whisper.execution != new.whisper.execution,
+        ),
+        (
+            "whisper.backlog",
+            old.whisper.backlog != new.whisper.backlog,
+        ),
+        (
+            "whisper.gpu_device",
+            old.whisper.gpu_device != new.whisper.gpu_device,
+        ),
+        (
+            "whisper.flash_attn",
+            old.whisper.flash_attn != new.whisper.flash_attn,
+        ),
+        ("translate", old.translate != new.translate),
+        ("piper.remote", old.piper.remote != new.piper.remote),
+        ("piper.port", old.piper.port != new.piper.port),
+        (
+            "piper.voice_dir",
+            old.piper.voice_dir != new.piper.voice_dir,
+        ),
+        (
+            "piper.execution",
+            old.piper.execution != new.piper.execution,
+        ),
+        ("sinks", old.sinks != new.sinks),
+        ("transcript", old.transcript != new.transcript),
+        ("recording", old.recording != new.recording),
+        ("room", old.rooms != new.rooms),
+        ("pipeline", old.pipelines != new.pipelines),
+    ];
+    for (name, changed) in restart_required {
+        if changed {
+            warn!("Changing {} requires a restart, ignoring", name);
+        }
+    }
+
+    // VAD settings
+    merged.general.push_to_talk = new.general.push_to_talk;
+    merged.general.ptt_key = new.general.ptt_key;
+    merged.audio.pre = new.audio.pre;
+    merged.audio.frame = new.audio.frame;
+    merged.vad = new.vad;
+
+    // Wake words and commands are checked on each utterance
+    merged.wake = new.wake;
+    merged.commands = new.commands;
+
+    // Cues are checked before each is played
+    merged.general.error_cues = new.general.error_cues;
+
+    // Whisper parameters
+    merged.whisper = crate::whisper::WhisperConfig {
+        model: old.whisper.model.clone(),
+        execution: old.whisper.execution.clone(),
+        backlog: old.whisper.backlog.clone(),
+        gpu_device: old.whisper.gpu_device,
+        flash_attn: old.whisper.flash_attn,
+        ..new.whisper
+    };
+
+    // TTS voice, voices are only downloaded at startup so ones that aren't keep the old voice
+    let downloaded = |voice: &str| {
+        let available =
+            old.piper.remote.is_some() || piper::voice_downloaded(&old.piper.voice_dir(), voice);
+        if !available {
+            warn!(
+                "Piper voice {} isn't downloaded, restart to download it",
+                voice
+            );
+        }
+        available
+    };
+    let model = if downloaded(&new.piper.model) {
+        new.piper.model.clone()
+    } else {
+        old.piper.model.clone()
+    };
+    let voices = new
+        .piper
+        .voices
+        .iter()
+        .filter_map(|(language, voice)| {
+            if downloaded(voice) {
+                Some((language.clone(), voice.clone()))
+            } else {
+                old.piper
+                    .voices
+                    .get(language)
+                    .map(|voice| (language.clone(), voice.clone()))
+            }
+        })
+        .collect();
+    merged.piper = crate::piper::PiperConfig {
+        model,
+        voices,
+        remote: old.piper.remote.clone(),
+        execution: old.piper.execution.clone(),
+        voice_dir: old.piper.voice_dir.clone(),
+        port: old.piper.port,
+        install: old.piper.install.clone(),
+        ..new.piper
+    };
+
+    merged
+}
+
+// Watch a config file, sending the new config whenever it changes
+pub fn watch_config(
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+) -> Result<(Receiver<Config>, JoinHandle<()>), notify::Error> {
+    let (event_tx, event_rx) = channel();
+    let (config_tx, config_rx) = channel();
+
+    // Watch the directory, editors often replace the file rather than writing to it
+    let mut watcher = notify::recommended_watcher(event_tx)?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    let thread = thread::Builder::new()
+        .name("config_watcher".to_owned())
+        .spawn(move || watch_loop(watcher, path, event_rx, config_tx, running))?;
+
+    Ok((config_rx, thread))
+}
+
+fn watch_loop(
+    _watcher: RecommendedWatcher, // Kept alive for as long as the thread runs
+    path: PathBuf,
+    events: Receiver<notify::Result<notify::Event>>,
+    config_tx: Sender<Config>,
+    running: Arc<AtomicBool>,
+) {
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    while running.load(Ordering::SeqCst) {
+        let event = match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => event,
+            Ok(Err(err)) => {
+                error!("Error watching config file!\n{}", err);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        // Ignore other files in the directory
+        if !event
+            .paths
+            .iter()
+            .any(|changed| changed.file_name().map(|name| name.to_owned()) == file_name)
+        {
+            continue;
+        }
+
+        // Let the write finish, and skip the other events it caused
+        thread::sleep(SETTLE_TIME);
+        while events.try_recv().is_ok() {}
+
+        match read_config(&path) {
+            Ok(config) => {
+                info!("Config file changed, reloading");
+                if config_tx.send(config).is_err() {
+                    break;
+                }
+            }
+            Err(err) => error!("{}\nKeeping the current config", err),
+        }
+    }
+}
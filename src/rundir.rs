@@ -0,0 +1,434 @@
+use std::{
+    fmt::Display,
+    fs::{self, File, TryLockError},
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use serde_json::json;
+
+use crate::{controls::Controls, pipeline::Pipeline, status::Status, util::lock, whisper::Preset};
+
+// How often the control socket is checked for connections
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Utterances returned by a history request without a limit
+pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+// How long `stop` waits for the running instance to exit
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ErrRunDir {
+    IoError(std::io::Error),
+    AlreadyRunning(Option<u32>),
+    NotRunning,
+    StopTimeout,
+}
+
+impl Display for ErrRunDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::AlreadyRunning(Some(pid)) => {
+                write!(f, "Already running with this profile (pid {})", pid)
+            }
+            Self::AlreadyRunning(None) => write!(f, "Already running with this profile"),
+            Self::NotRunning => write!(f, "Not running with this profile"),
+            Self::StopTimeout => write!(f, "Timed out waiting for the running instance to stop"),
+        }
+    }
+}
+
+impl std::error::Error for ErrRunDir {}
+
+impl From<std::io::Error> for ErrRunDir {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+// Directory holding the runtime files of a profile
+fn run_path(profile: &str) -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("live-translate-rs")
+        .join(profile)
+}
+
+// Commands for the pipelines, which are owned by the main thread
+pub enum PipelineCommand {
+    Pause(Option<String>), // Pause one pipeline by name, or all of them
+    Resume(Option<String>),
+    SetTarget(Option<String>, String), // Translate into another language
+    SetVoice(Option<String>, String),  // Speak with another voice
+}
+
+impl PipelineCommand {
+    // Apply to the pipelines it names
+    pub fn apply(&self, pipelines: &[Pipeline]) {
+        let (Self::Pause(name)
+        | Self::Resume(name)
+        | Self::SetTarget(name, _)
+        | Self::SetVoice(name, _)) = self;
+        for pipeline in pipelines {
+            if name.as_ref().is_some_and(|name| *name != pipeline.name) {
+                continue;
+            }
+            match self {
+                Self::Pause(_) => pipeline.pause(),
+                Self::Resume(_) => pipeline.resume(),
+                Self::SetTarget(_, target) => pipeline.set_target(target.clone()),
+                Self::SetVoice(_, voice) => pipeline.set_voice(voice.clone()),
+            }
+        }
+    }
+}
+
+// Status file written by the instance running a profile
+pub fn status_path(profile: &str) -> PathBuf {
+    run_path(profile).join("status.json")
+}
+
+// Runtime directory of the running instance, holding its lock, pid, control socket and status
+pub struct RunDir {
+    path: PathBuf,
+    _lock: File, // Held for as long as the instance runs
+}
+
+impl RunDir {
+    // Take the lock for a profile, failing if another instance holds it
+    pub fn acquire(profile: &str) -> Result<Self, ErrRunDir> {
+        let path = run_path(profile);
+        fs::create_dir_all(&path)?;
+
+        let lock = File::create(path.join("lock"))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(path.join("pid"))
+                    .ok()
+                    .and_then(|pid| pid.trim().parse().ok());
+                return Err(ErrRunDir::AlreadyRunning(pid));
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        fs::write(path.join("pid"), format!("{}\n", std::process::id()))?;
+
+        // Clear a socket left behind by an instance which didn't exit cleanly
+        let _ = fs::remove_file(path.join("control.sock"));
+
+        Ok(Self { path, _lock: lock })
+    }
+
+    // Listen for commands on the control socket
+    // Status is rendered by the main thread, which owns the pipelines
+    pub fn listen(
+        &self,
+        controls: Arc<Controls>,
+        running: Arc<AtomicBool>,
+        statuses: Vec<(String, Arc<Status>)>,
+        status: Arc<Mutex<serde_json::Value>>,
+    ) -> Result<(Receiver<PipelineCommand>, JoinHandle<()>), std::io::Error> {
+        let listener = UnixListener::bind(self.path.join("control.sock"))?;
+        listener.set_nonblocking(true)?;
+        let (command_tx, command_rx) = channel();
+
+        let thread = thread::Builder::new()
+            .name("control_socket".to_owned())
+            .spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(err) = handle_command(
+                                stream,
+                                &controls,
+                                &running,
+                                &statuses,
+                                &status,
+                                &command_tx,
+                            ) {
+                                error!("Could not handle control command!\n{}", err);
+                            }
+                        }
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                        Err(err) => error!("Could not accept control connection!\n{}", err),
+                    }
+                }
+            })?;
+
+        Ok((command_rx, thread))
+    }
+
+    // Write the live state of every pipeline for other programs to read
+    pub fn write_status(
+        &self,
+        pipelines: &[Pipeline],
+        controls: &Controls,
+    ) -> Result<(), std::io::Error> {
+        let status = status(pipelines, controls);
+
+        // Replace in one step so readers never see a partial file
+        let temp_path = self.path.join("status.json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(&status)?)?;
+        fs::rename(temp_path, self.path.join("status.json"))
+    }
+}
+
+impl Drop for RunDir {
+    fn drop(&mut self) {
+        for file in ["pid", "control.sock", "status.json"] {
+            let _ = fs::remove_file(self.path.join(file));
+        }
+    }
+}
+
+// Live state of every pipeline, as written to the status file
+pub fn status(pipelines: &[Pipeline], controls: &Controls) -> serde_json::Value {
+    let pipelines = pipelines
+        .iter()
+        .map(|pipeline| {
+            json!({
+                "name": pipeline.name,
+                "level": pipeline.status.level(),
+                "voice": pipeline.status.voice(),
+                "recording": pipeline.status.recording(),
+                "frame": pipeline.status.frame(),
+                "period": pipeline.status.period(),
+                "queued": pipeline.play_buffer.queued(),
+                "latency": pipeline.status.latency().as_millis() as u64,
+                "paused": pipeline.paused(),
+                "last": pipeline.status.last(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "pid": std::process::id(),
+        "muted": controls.muted(),
+        "paused": controls.paused(),
+        "translating": controls.translating(),
+        "input_gain": controls.input_gain(),
+        "output_gain": controls.output_gain(),
+        "output_muted": controls.output_muted(),
+        "preset": controls.preset(),
+        "tags": controls.tags(),
+        "pipelines": pipelines,
+    })
+}
+
+// Most recent utterances of every pipeline, oldest first
+pub fn history(statuses: &[(String, Arc<Status>)], limit: usize) -> Vec<serde_json::Value> {
+    let mut entries = statuses
+        .iter()
+        .flat_map(|(name, status)| {
+            status.history().into_iter().map(move |entry| {
+                let mut entry = json!(entry);
+                entry["pipeline"] = json!(name);
+                entry
+            })
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry["timestamp"].as_u64());
+
+    let skip = entries.len().saturating_sub(limit);
+    entries.split_off(skip)
+}
+
+// Answer a HTTP request, so the socket can be queried with e.g. curl --unix-socket
+fn handle_request(
+    stream: &UnixStream,
+    target: &str,
+    statuses: &[(String, Arc<Status>)],
+) -> Result<(), std::io::Error> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let (status, body) = match path {
+        "/history" => {
+            let limit = query
+                .split('&')
+                .find_map(|param| param.strip_prefix("limit="))
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(DEFAULT_HISTORY_LIMIT);
+            ("200 OK", json!(history(statuses, limit)))
+        }
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    };
+
+    let body = body.to_string();
+    write!(
+        &*stream,
+        "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// Apply one command from a control connection and reply
+fn handle_command(
+    stream: UnixStream,
+    controls: &Controls,
+    running: &AtomicBool,
+    statuses: &[(String, Arc<Status>)],
+    status: &Mutex<serde_json::Value>,
+    command_tx: &Sender<PipelineCommand>,
+) -> Result<(), std::io::Error> {
+    stream.set_nonblocking(false)?;
+
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+
+    if let Some(request) = command.strip_prefix("GET ") {
+        let target = request.split_whitespace().next().unwrap_or("/");
+        return handle_request(&stream, target, statuses);
+    }
+
+    // Status is a line of JSON rather than a plain reply
+    if command.trim() == "status" {
+        let status = lock(status).to_string();
+        return writeln!(&stream, "{}", status);
+    }
+
+    let reply = match command.trim() {
+        "stop" => {
+            info!("Stop requested through control socket");
+            running.store(false, Ordering::SeqCst);
+            "ok"
+        }
+        "reload" => {
+            info!("Reload requested through control socket");
+            controls.request_reload();
+            "ok"
+        }
+        "mute" => {
+            controls.toggle_mute();
+            "ok"
+        }
+        "pause" => {
+            controls.toggle_pause();
+            "ok"
+        }
+        "translate" => {
+            controls.toggle_translation();
+            "ok"
+        }
+        "calibrate" => {
+            controls.request_calibration();
+            "ok"
+        }
+        "discard" => {
+            controls.request_discard();
+            "ok"
+        }
+        "finish" => {
+            controls.request_finish();
+            "ok"
+        }
+        "replay" => {
+            controls.request_replay();
+            "ok"
+        }
+        command if command.starts_with("pause ") || command.starts_with("resume ") => {
+            let (action, name) = command.split_once(' ').unwrap_or_default();
+            let name = name.trim();
+            if name != "all" && !statuses.iter().any(|(pipeline, _)| pipeline == name) {
+                "unknown pipeline"
+            } else {
+                let target = (name != "all").then(|| name.to_owned());
+                let command = match action {
+                    "pause" => PipelineCommand::Pause(target),
+                    _ => PipelineCommand::Resume(target),
+                };
+                match command_tx.send(command) {
+                    Ok(()) => "ok",
+                    Err(_) => "not running",
+                }
+            }
+        }
+        command if command.starts_with("preset ") => match command["preset ".len()..].trim() {
+            "none" => {
+                info!("Using the configured decoding preset");
+                controls.set_preset(None);
+                "ok"
+            }
+            name => match Preset::from_name(name) {
+                Some(preset) => {
+                    info!("Using decoding preset {}", name);
+                    controls.set_preset(Some(preset));
+                    "ok"
+                }
+                None => "unknown preset",
+            },
+        },
+        command if command.starts_with("tag ") => {
+            match command["tag ".len()..].trim().split_once(' ') {
+                Some((key, value)) => {
+                    info!("Tagging utterances with {} = {}", key, value.trim());
+                    controls.set_tag(key, Some(value.trim()));
+                    "ok"
+                }
+                None => "usage: tag <key> <value>",
+            }
+        }
+        command if command.starts_with("untag ") => match command["untag ".len()..].trim() {
+            "all" => {
+                controls.clear_tags();
+                "ok"
+            }
+            key => {
+                controls.set_tag(key, None);
+                "ok"
+            }
+        },
+        _ => "unknown command",
+    };
+
+    writeln!(&stream, "{}", reply)
+}
+
+// Send a command to the instance running a profile and return its reply
+pub fn send(profile: &str, command: &str) -> Result<String, ErrRunDir> {
+    let stream = UnixStream::connect(run_path(profile).join("control.sock"))
+        .map_err(|_| ErrRunDir::NotRunning)?;
+    writeln!(&stream, "{}", command)?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+
+    Ok(reply.trim().to_owned())
+}
+
+// Ask the instance running a profile to stop and wait for it to exit
+pub fn stop(profile: &str) -> Result<(), ErrRunDir> {
+    let path = run_path(profile);
+
+    let mut stream =
+        UnixStream::connect(path.join("control.sock")).map_err(|_| ErrRunDir::NotRunning)?;
+    writeln!(stream, "stop")?;
+
+    // The lock is released once the instance has exited
+    let lock = File::open(path.join("lock"))?;
+    let start = Instant::now();
+    while start.elapsed() < STOP_TIMEOUT {
+        if lock.try_lock().is_ok() {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    Err(ErrRunDir::StopTimeout)
+}
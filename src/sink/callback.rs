@@ -0,0 +1,30 @@
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::Utterance,
+};
+
+// Called with every finished utterance, for apps embedding a pipeline
+pub type Callback = Box<dyn FnMut(&Utterance) + Send>;
+
+// Hand utterances to a function of the app embedding the pipeline
+pub struct CallbackSink {
+    callback: Callback,
+}
+
+impl CallbackSink {
+    pub fn new(callback: Callback) -> Self {
+        Self { callback }
+    }
+}
+
+impl OutputSink for CallbackSink {
+    fn name(&self) -> &'static str {
+        "callback"
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        (self.callback)(utterance);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::error;
+
+use crate::{Config, events::Event, sink::OutputSink, sound::play_buffer::PlayBuffer};
+
+// How often playback position is checked while holding a caption
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+enum CaptionUnit {
+    Show(Event, Option<u64>), // Event and the play buffer position its speech starts at
+    Reload(Arc<Config>),
+}
+
+// Holds captions back until their speech starts playing
+pub struct CaptionSync {
+    caption_tx: Option<Sender<CaptionUnit>>,
+    caption_thread: Option<JoinHandle<()>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl CaptionSync {
+    pub fn new(
+        sinks: Vec<Box<dyn OutputSink>>,
+        play_buffer: Arc<PlayBuffer>,
+    ) -> Result<Self, std::io::Error> {
+        let (caption_tx, caption_rx) = channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let stopped_cloned = stopped.clone();
+        let caption_thread = thread::Builder::new()
+            .name("caption_sync".to_owned())
+            .spawn(move || caption_loop(sinks, play_buffer, caption_rx, stopped_cloned))?;
+
+        Ok(Self {
+            caption_tx: Some(caption_tx),
+            caption_thread: Some(caption_thread),
+            stopped,
+        })
+    }
+
+    // Queue an event for the caption sinks to be handled once playback reaches start,
+    // None handles it straight away after those queued before
+    pub fn show(&self, event: Event, start: Option<u64>) {
+        self.send(CaptionUnit::Show(event, start));
+    }
+
+    // Apply a changed config to the caption sinks
+    pub fn reload(&self, config: Arc<Config>) {
+        self.send(CaptionUnit::Reload(config));
+    }
+
+    fn send(&self, unit: CaptionUnit) {
+        if let Some(caption_tx) = &self.caption_tx
+            && let Err(err) = caption_tx.send(unit)
+        {
+            error!("Could not send caption to sync thread!\n{}", err);
+        }
+    }
+}
+
+impl Drop for CaptionSync {
+    fn drop(&mut self) {
+        // Flush remaining captions without waiting for playback
+        self.stopped.store(true, Ordering::SeqCst);
+        self.caption_tx.take();
+
+        if let Some(caption_thread) = self.caption_thread.take()
+            && caption_thread.join().is_err()
+        {
+            error!("Could not join caption sync thread!");
+        }
+    }
+}
+
+fn caption_loop(
+    mut sinks: Vec<Box<dyn OutputSink>>,
+    play_buffer: Arc<PlayBuffer>,
+    caption_rx: Receiver<CaptionUnit>,
+    stopped: Arc<AtomicBool>,
+) {
+    for unit in caption_rx {
+        match unit {
+            CaptionUnit::Show(event, start) => {
+                // Wait until the first sample of the speech has been played
+                if let Some(start) = start {
+                    while play_buffer.played() <= start && !stopped.load(Ordering::SeqCst) {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                }
+
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.event(&event) {
+                        error!("Could not output to {} sink!\n{}", sink.name(), err);
+                    }
+                }
+            }
+            CaptionUnit::Reload(config) => {
+                for sink in sinks.iter_mut() {
+                    sink.reload(&config);
+                }
+            }
+        }
+    }
+}
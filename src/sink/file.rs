@@ -0,0 +1,45 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::Utterance,
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct FileSinkConfig {
+    pub path: String,
+}
+
+// Append utterances to a text file, one per line
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn new(config: &FileSinkConfig) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+
+        Ok(Self { file })
+    }
+}
+
+impl OutputSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        writeln!(self.file, "{}", utterance.caption_text())?;
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::BTreeMap, fs::File, io::Write, path::PathBuf};
+
+use flate2::{Compression, write::ZlibEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::{Task, Utterance},
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct JsonSinkConfig {
+    pub directory: String, // Directory to write one file per utterance into
+}
+
+// Segment in the OpenAI verbose_json format
+// seek and temperature are left out, whisper.cpp doesn't report the window or fallback used
+#[derive(Serialize)]
+struct VerboseSegment<'a> {
+    id: i32,
+    start: f32,
+    end: f32,
+    text: &'a str,
+    tokens: &'a [i32],
+    avg_logprob: f32,
+    compression_ratio: f32,
+    no_speech_prob: f32,
+}
+
+// Ratio of the text's length to its zlib compressed length, high when whisper repeats itself
+fn compression_ratio(text: &str) -> f32 {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    if encoder.write_all(text.as_bytes()).is_err() {
+        return 0.0;
+    }
+
+    match encoder.finish() {
+        Ok(compressed) if !compressed.is_empty() => text.len() as f32 / compressed.len() as f32,
+        _ => 0.0,
+    }
+}
+
+// Word in the OpenAI verbose_json format, with its time from the start of the utterance
+#[derive(Serialize)]
+struct VerboseWord<'a> {
+    word: &'a str,
+    start: f32,
+    end: f32,
+}
+
+// Utterance in the OpenAI verbose_json format
+#[derive(Serialize)]
+struct VerboseJson<'a> {
+    task: Task,
+    language: Option<&'a str>,
+    duration: f32,
+    text: &'a str,
+    segments: Vec<VerboseSegment<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    words: Vec<VerboseWord<'a>>, // Only written with word timestamps
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: &'a BTreeMap<String, String>, // Not part of the format, only written when set
+}
+
+impl<'a> From<&'a Utterance> for VerboseJson<'a> {
+    fn from(utterance: &'a Utterance) -> Self {
+        Self {
+            task: utterance.task,
+            language: utterance.language.as_deref(),
+            duration: utterance.duration,
+            text: utterance.text.trim(),
+            segments: utterance
+                .segments
+                .iter()
+                .map(|segment| VerboseSegment {
+                    id: segment.id,
+                    start: segment.start,
+                    end: segment.end,
+                    text: &segment.text,
+                    tokens: &segment.tokens,
+                    avg_logprob: segment.avg_logprob,
+                    compression_ratio: compression_ratio(&segment.text),
+                    no_speech_prob: segment.no_speech_prob,
+                })
+                .collect(),
+            words: utterance
+                .segments
+                .iter()
+                .flat_map(|segment| &segment.words)
+                .map(|word| VerboseWord {
+                    word: &word.text,
+                    start: word.start,
+                    end: word.end,
+                })
+                .collect(),
+            tags: &utterance.tags,
+        }
+    }
+}
+
+// Write each utterance to its own whisper compatible json file
+pub struct JsonSink {
+    directory: PathBuf,
+}
+
+impl JsonSink {
+    pub fn new(config: &JsonSinkConfig) -> Result<Self, std::io::Error> {
+        let directory = PathBuf::from(&config.directory);
+
+        // Ensure output directory exists
+        std::fs::create_dir_all(&directory)?;
+
+        Ok(Self { directory })
+    }
+}
+
+impl OutputSink for JsonSink {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        let path = self
+            .directory
+            .join(format!("{}-{}.json", utterance.timestamp, utterance.id));
+        let file = File::create(path)?;
+
+        serde_json::to_writer_pretty(file, &VerboseJson::from(utterance))?;
+
+        Ok(())
+    }
+}
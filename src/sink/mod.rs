@@ -0,0 +1,154 @@
+use std::{fmt::Display, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    Config,
+    events::Event,
+    piper::{ErrPlayTTS, PiperConfig, TtsTiming},
+    postprocess::PostprocessConfig,
+    sink::{
+        file::{FileSink, FileSinkConfig},
+        json::{JsonSink, JsonSinkConfig},
+        osc::{OscSink, OscSinkConfig},
+        stdout::StdoutSink,
+        subtitle::{SubtitleSink, SubtitleSinkConfig},
+        tts::TtsSink,
+        websocket::{WebSocketSink, WebSocketSinkConfig},
+    },
+    sound::play_buffer::PlayBuffer,
+    utterance::Utterance,
+};
+
+pub mod callback;
+pub mod caption_sync;
+pub mod file;
+pub mod json;
+pub mod osc;
+pub mod stdout;
+pub mod subtitle;
+pub mod tts;
+pub mod websocket;
+
+#[derive(Debug)]
+pub enum ErrSink {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+    TtsError(ErrPlayTTS),
+}
+
+impl Display for ErrSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::JsonError(json_error) => write!(f, "{}", json_error),
+            Self::TtsError(tts_error) => write!(f, "{}", tts_error),
+        }
+    }
+}
+
+impl std::error::Error for ErrSink {}
+
+impl From<std::io::Error> for ErrSink {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for ErrSink {
+    fn from(value: serde_json::Error) -> Self {
+        Self::JsonError(value)
+    }
+}
+
+impl From<ErrPlayTTS> for ErrSink {
+    fn from(value: ErrPlayTTS) -> Self {
+        Self::TtsError(value)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum SinkConfig {
+    Tts,
+    File(FileSinkConfig),
+    Json(JsonSinkConfig),
+    WebSocket(WebSocketSinkConfig),
+    Stdout,
+    Osc(OscSinkConfig),
+    Subtitle(SubtitleSinkConfig),
+}
+
+// Only speak utterances if no sinks are configured
+pub fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::Tts]
+}
+
+pub trait OutputSink: Send {
+    // Name used in logs
+    fn name(&self) -> &'static str;
+
+    // Output a finished utterance
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink>;
+
+    // React to an event of the pipeline, by default outputting utterances once they're ready
+    fn event(&mut self, event: &Event) -> Result<(), ErrSink> {
+        match event {
+            Event::TranslationReady(utterance) => self.handle(utterance),
+            _ => Ok(()),
+        }
+    }
+
+    // Apply a changed config
+    fn reload(&mut self, _config: &Config) {}
+
+    // Whether the sink displays captions, which can be held back until their speech plays
+    fn is_caption(&self) -> bool {
+        false
+    }
+
+    // Whether the sink speaks, which summary mode holds back while speech is backed up
+    fn is_speech(&self) -> bool {
+        false
+    }
+
+    // Time spent speaking the last utterance, for sinks which speak
+    fn timing(&self) -> Option<TtsTiming> {
+        None
+    }
+
+    // Speech queued for the last utterance at the play buffer's rate, for sinks which speak
+    fn take_clip(&mut self) -> Option<Vec<f32>> {
+        None
+    }
+}
+
+// Create every configured sink
+pub fn create_sinks(
+    configs: &[SinkConfig],
+    piper_config: &PiperConfig,
+    postprocess: Option<&PostprocessConfig>,
+    play_buffer: Arc<PlayBuffer>,
+) -> Result<Vec<Box<dyn OutputSink>>, ErrSink> {
+    let mut sinks: Vec<Box<dyn OutputSink>> = vec![];
+
+    for config in configs {
+        let sink: Box<dyn OutputSink> = match config {
+            SinkConfig::Tts => Box::new(TtsSink::new(
+                play_buffer.clone(),
+                piper_config.clone(),
+                postprocess,
+            )),
+            SinkConfig::File(config) => Box::new(FileSink::new(config)?),
+            SinkConfig::Json(config) => Box::new(JsonSink::new(config)?),
+            SinkConfig::WebSocket(config) => Box::new(WebSocketSink::new(config)?),
+            SinkConfig::Stdout => Box::new(StdoutSink),
+            SinkConfig::Osc(config) => Box::new(OscSink::new(config)?),
+            SinkConfig::Subtitle(config) => Box::new(SubtitleSink::new(config)?),
+        };
+
+        sinks.push(sink);
+    }
+
+    Ok(sinks)
+}
@@ -0,0 +1,98 @@
+use std::net::UdpSocket;
+
+use serde::Deserialize;
+
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::Utterance,
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct OscSinkConfig {
+    pub address: String, // Address to send to, e.g. "127.0.0.1:9000"
+    #[serde(default = "default_osc_path")]
+    pub path: String,
+    #[serde(default)]
+    pub timing: bool, // Add the duration, and send every segment with its times to <path>/segment
+}
+
+fn default_osc_path() -> String {
+    "/live-translate/text".to_owned()
+}
+
+// Append an OSC string, null terminated and padded to 4 bytes
+pub fn push_osc_string(packet: &mut Vec<u8>, s: &str) {
+    packet.extend_from_slice(s.as_bytes());
+    packet.push(0);
+    while !packet.len().is_multiple_of(4) {
+        packet.push(0);
+    }
+}
+
+// Append an OSC float, big endian
+pub fn push_osc_float(packet: &mut Vec<u8>, f: f32) {
+    packet.extend_from_slice(&f.to_be_bytes());
+}
+
+// Send utterances as OSC messages with a single string argument
+pub struct OscSink {
+    socket: UdpSocket,
+    address: String,
+    path: String,
+    timing: bool,
+}
+
+impl OscSink {
+    pub fn new(config: &OscSinkConfig) -> Result<Self, std::io::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+
+        Ok(Self {
+            socket,
+            address: config.address.clone(),
+            path: config.path.clone(),
+            timing: config.timing,
+        })
+    }
+}
+
+impl OutputSink for OscSink {
+    fn name(&self) -> &'static str {
+        "osc"
+    }
+
+    fn is_caption(&self) -> bool {
+        true
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        let mut packet = vec![];
+        push_osc_string(&mut packet, &self.path);
+        if self.timing {
+            push_osc_string(&mut packet, ",sf");
+            push_osc_string(&mut packet, &utterance.caption_text());
+            push_osc_float(&mut packet, utterance.duration);
+        } else {
+            push_osc_string(&mut packet, ",s");
+            push_osc_string(&mut packet, &utterance.caption_text());
+        }
+
+        self.socket.send_to(&packet, &self.address)?;
+
+        // Segments are in the spoken language, so they only match untranslated text
+        if self.timing && utterance.translation.is_none() {
+            let path = format!("{}/segment", self.path);
+            for segment in &utterance.segments {
+                let mut packet = vec![];
+                push_osc_string(&mut packet, &path);
+                push_osc_string(&mut packet, ",ffs");
+                push_osc_float(&mut packet, segment.start);
+                push_osc_float(&mut packet, segment.end);
+                push_osc_string(&mut packet, segment.text.trim());
+
+                self.socket.send_to(&packet, &self.address)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::Utterance,
+};
+
+// Print utterances to stdout
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn is_caption(&self) -> bool {
+        true
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        println!("{}", utterance.caption_text());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    sink::{ErrSink, OutputSink},
+    utterance::Utterance,
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SubtitleSinkConfig {
+    pub path: String,
+    #[serde(default)]
+    pub format: SubtitleFormat,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SubtitleFormat {
+    #[default]
+    Srt,
+    Vtt,
+}
+
+// Timestamp of a cue, with a comma before the milliseconds in SRT and a dot in VTT
+fn cue_time(time: Duration, format: SubtitleFormat) -> String {
+    let millis = time.as_millis();
+    let separator = match format {
+        SubtitleFormat::Srt => ',',
+        SubtitleFormat::Vtt => '.',
+    };
+
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+// Write captions to a subtitle file timed from when the sink was created, e.g. for a stream recording
+// Each segment gets its own cue, translations are one cue as they don't line up with the segments
+pub struct SubtitleSink {
+    file: File,
+    format: SubtitleFormat,
+    started: Instant,
+    cues: usize,
+}
+
+impl SubtitleSink {
+    pub fn new(config: &SubtitleSinkConfig) -> Result<Self, std::io::Error> {
+        let mut file = File::create(&config.path)?;
+        if config.format == SubtitleFormat::Vtt {
+            writeln!(file, "WEBVTT\n")?;
+        }
+
+        Ok(Self {
+            file,
+            format: config.format,
+            started: Instant::now(),
+            cues: 0,
+        })
+    }
+
+    fn write_cue(&mut self, start: Duration, end: Duration, text: &str) -> Result<(), ErrSink> {
+        self.cues += 1;
+        if self.format == SubtitleFormat::Srt {
+            writeln!(self.file, "{}", self.cues)?;
+        }
+        writeln!(
+            self.file,
+            "{} --> {}\n{}\n",
+            cue_time(start, self.format),
+            cue_time(end, self.format),
+            text
+        )?;
+
+        Ok(())
+    }
+}
+
+impl OutputSink for SubtitleSink {
+    fn name(&self) -> &'static str {
+        "subtitle"
+    }
+
+    fn is_caption(&self) -> bool {
+        true
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        // Captions are shown from when they're output, for as long as the speech lasted
+        let shown = self.started.elapsed();
+        let offset = |seconds: f32| shown + Duration::from_secs_f32(seconds.max(0.0));
+
+        if utterance.translation.is_some() || utterance.segments.is_empty() {
+            self.write_cue(shown, offset(utterance.duration), &utterance.caption_text())?;
+        } else {
+            for segment in &utterance.segments {
+                let text = match utterance.speaker {
+                    Some(speaker) => format!("Speaker {}: {}", speaker, segment.text.trim()),
+                    None => segment.text.trim().to_owned(),
+                };
+                self.write_cue(offset(segment.start), offset(segment.end), &text)?;
+            }
+        }
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
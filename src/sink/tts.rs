@@ -0,0 +1,299 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use log::{debug, warn};
+
+use crate::{
+    Config,
+    dsp::Chain,
+    piper::{
+        BacklogPolicy, ErrPlayTTS, PiperConfig, TtsTiming, leave_gap, play_tts, split_sentences,
+        tts_client, wait_for_piper,
+    },
+    postprocess::{PostprocessConfig, Postprocessor},
+    sink::{ErrSink, OutputSink},
+    sound::play_buffer::PlayBuffer,
+    utterance::Utterance,
+};
+
+// Longest text in characters whose speech is cached, longer utterances are rarely repeated
+const MAX_CACHED_TEXT: usize = 100;
+
+// Text, voice and language speech was made for
+type CacheKey = (String, String, Option<String>);
+
+// Speech of recent short utterances, so repeated phrases like "one moment" skip the server
+// Few entries are kept, so they are searched in order, most recently used last
+struct TtsCache {
+    size: usize,
+    clips: VecDeque<(CacheKey, Vec<f32>)>,
+}
+
+impl TtsCache {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            clips: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<&Vec<f32>> {
+        let index = self.clips.iter().position(|(cached, _)| cached == key)?;
+        let entry = self.clips.remove(index)?;
+        self.clips.push_back(entry);
+        self.clips.back().map(|(_, clip)| clip)
+    }
+
+    fn insert(&mut self, key: CacheKey, clip: Vec<f32>) {
+        if self.size == 0 {
+            return;
+        }
+        self.clips.push_back((key, clip));
+        while self.clips.len() > self.size {
+            self.clips.pop_front();
+        }
+    }
+
+    // Forget everything, e.g. once the speech would sound different
+    fn clear(&mut self, size: usize) {
+        self.size = size;
+        self.clips.clear();
+    }
+}
+
+// Speak utterances through piper
+pub struct TtsSink {
+    play_buffer: Arc<PlayBuffer>,
+    config: PiperConfig,
+    post_chain: Chain,
+    sample_rate: usize, // Rate the post chain was built for
+    timing: Option<TtsTiming>,
+    starts: VecDeque<u64>, // Positions in the play buffer the queued utterances start at
+    clip: Option<Vec<f32>>, // Speech of the last utterance
+    http_client: Option<reqwest::blocking::Client>, // Made on first use, and again when the timeout changes
+    cache: TtsCache,
+    speaker: Option<usize>, // Speaker of the last utterance, announced again when it changes
+    postprocess: Option<Postprocessor>, // Fixes the pronunciation of the text spoken
+}
+
+impl TtsSink {
+    pub fn new(
+        play_buffer: Arc<PlayBuffer>,
+        config: PiperConfig,
+        postprocess: Option<&PostprocessConfig>,
+    ) -> Self {
+        let sample_rate = play_buffer.sample_rate();
+        Self {
+            play_buffer,
+            post_chain: Chain::new(&config.post, sample_rate),
+            sample_rate,
+            cache: TtsCache::new(config.cache_size),
+            config,
+            timing: None,
+            starts: VecDeque::new(),
+            clip: None,
+            http_client: None,
+            speaker: None,
+            postprocess: postprocess.map(Postprocessor::new),
+        }
+    }
+
+    // Skip ahead to later utterances until the backlog fits, keeping at least the newest
+    // Returns how many utterances were dropped
+    fn drop_oldest(&mut self, max_backlog: f32) -> usize {
+        let pushed = self.play_buffer.pushed();
+        let limit = (max_backlog * self.sample_rate as f32) as u64;
+        let mut dropped = 0;
+        while let Some(start) = self.starts.pop_front() {
+            // Each skip drops the utterance before the one skipped to
+            self.play_buffer.skip_to(start);
+            dropped += 1;
+            if pushed - start <= limit {
+                break;
+            }
+        }
+
+        dropped
+    }
+}
+
+impl OutputSink for TtsSink {
+    fn name(&self) -> &'static str {
+        "tts"
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        // Follow the output if its rate changed
+        let sample_rate = self.play_buffer.sample_rate();
+        if sample_rate != self.sample_rate {
+            self.post_chain = Chain::new(&self.config.post, sample_rate);
+            self.sample_rate = sample_rate;
+            self.cache.clear(self.config.cache_size);
+        }
+
+        // Forget utterances which started playing
+        let played = self.play_buffer.played();
+        self.starts.retain(|start| *start > played);
+
+        // Keep the speech from drifting too far behind
+        let queued = self.play_buffer.queued();
+        debug!("{:.1} seconds of speech queued", queued);
+        let mut speed = self.config.speed;
+        if let Some(max_backlog) = self.config.max_backlog
+            && queued > max_backlog
+        {
+            match self.config.backlog_policy {
+                BacklogPolicy::DropOldest => {
+                    let dropped = self.drop_oldest(max_backlog);
+                    warn!(
+                        "Speech is {:.1} seconds behind, dropped {} queued utterances",
+                        queued, dropped
+                    );
+                }
+                BacklogPolicy::CaptionOnly => {
+                    warn!(
+                        "Speech is {:.1} seconds behind, not speaking \"{}\"",
+                        queued,
+                        utterance.output_text()
+                    );
+                    self.timing = None;
+                    self.clip = None;
+                    return Ok(());
+                }
+                BacklogPolicy::SpeedUp => {
+                    warn!(
+                        "Speech is {:.1} seconds behind, speeding up to catch up",
+                        queued
+                    );
+                    speed *= self.config.catch_up_speed;
+                }
+            }
+        }
+
+        // Pick a voice matching the language being spoken
+        let voice = self.config.voice_for(utterance.output_language.as_deref());
+
+        // Say who is speaking when it changes
+        let speaker = utterance
+            .speaker
+            .filter(|speaker| self.config.announce_speaker && self.speaker != Some(*speaker));
+        if utterance.speaker.is_some() {
+            self.speaker = utterance.speaker;
+        }
+        let mut text = match speaker {
+            Some(speaker) => format!("Speaker {}. {}", speaker, utterance.output_text().trim()),
+            None => utterance.output_text().trim().to_owned(),
+        };
+        if let Some(postprocess) = &self.postprocess {
+            text = postprocess.speech(&text);
+        }
+
+        // Play repeated phrases from the cache, unless they have to be sped up
+        let key =
+            (speed == self.config.speed && text.chars().count() <= MAX_CACHED_TEXT).then(|| {
+                (
+                    text.clone(),
+                    voice.to_owned(),
+                    utterance.output_language.clone(),
+                )
+            });
+        if let Some(key) = &key
+            && let Some(clip) = self.cache.get(key)
+        {
+            debug!("Speaking \"{}\" from the cache", text);
+            self.starts.push_back(self.play_buffer.pushed());
+            leave_gap(
+                &self.play_buffer,
+                (self.config.gap / 1000.0 * self.sample_rate as f32) as usize,
+            );
+            self.play_buffer.push(clip.clone());
+            self.timing = Some(TtsTiming::default());
+            self.clip = Some(clip.clone());
+            return Ok(());
+        }
+
+        self.starts.push_back(self.play_buffer.pushed());
+        let http_client = match &self.http_client {
+            Some(http_client) => http_client,
+            None => self.http_client.insert(tts_client(&self.config)?),
+        };
+
+        // Each sentence is queued as soon as it's spoken, while the next is still being made
+        let sentences = if self.config.split_sentences {
+            split_sentences(&text)
+        } else {
+            vec![text.as_str()]
+        };
+        let mut timing = TtsTiming::default();
+        let mut clip = vec![];
+        for (i, sentence) in sentences.into_iter().enumerate() {
+            let gap = if i == 0 { self.config.gap } else { 0.0 };
+            let mut waited = false;
+            let (sentence_timing, sentence_clip) = loop {
+                match play_tts(
+                    http_client,
+                    &self.config.url(),
+                    self.play_buffer.clone(),
+                    sentence.to_owned(),
+                    voice,
+                    speed,
+                    gap,
+                    self.config.fade,
+                    &mut self.post_chain,
+                ) {
+                    // The server may be restarting after a crash, hold the speech until it's back
+                    Err(ErrPlayTTS::ReqwestError(err)) if err.is_connect() && !waited => {
+                        warn!("Piper server is unavailable, waiting for it");
+                        waited = true;
+                        let restart_wait =
+                            Duration::from_secs_f32(self.config.restart_wait.max(0.0));
+                        if wait_for_piper(self.config.port, restart_wait).is_err() {
+                            return Err(ErrPlayTTS::ReqwestError(err).into());
+                        }
+                    }
+                    result => break result?,
+                }
+            };
+            timing.request += sentence_timing.request;
+            timing.resample += sentence_timing.resample;
+            clip.extend(sentence_clip);
+        }
+        self.timing = Some(timing);
+        if let Some(key) = key
+            && !clip.is_empty()
+        {
+            self.cache.insert(key, clip.clone());
+        }
+        self.clip = Some(clip);
+
+        Ok(())
+    }
+
+    fn is_speech(&self) -> bool {
+        true
+    }
+
+    fn timing(&self) -> Option<TtsTiming> {
+        self.timing
+    }
+
+    fn take_clip(&mut self) -> Option<Vec<f32>> {
+        self.clip.take()
+    }
+
+    fn reload(&mut self, config: &Config) {
+        if config.piper.post != self.config.post {
+            self.post_chain = Chain::new(&config.piper.post, self.sample_rate);
+        }
+        if config.piper.timeout != self.config.timeout {
+            self.http_client = None;
+        }
+
+        // Cached speech may have been made with a different voice, speed or processing
+        if config.piper != self.config {
+            self.cache.clear(config.piper.cache_size);
+        }
+
+        self.config = config.piper.clone();
+        self.postprocess = config.postprocess.as_ref().map(Postprocessor::new);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use tungstenite::Message;
+
+use crate::{
+    sink::{ErrSink, OutputSink},
+    util::lock,
+    utterance::Utterance,
+};
+
+// How often the listener checks whether the sink was dropped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a client has to finish the handshake, and to take each message
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Messages waiting for a slow client before it misses some
+const CLIENT_QUEUE: usize = 64;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct WebSocketSinkConfig {
+    pub address: String, // Address to listen on, e.g. "127.0.0.1:9001"
+}
+
+// Broadcast utterances as JSON to every connected websocket client
+// Each client has its own thread, so one which stalls never holds up the pipeline or the others
+pub struct WebSocketSink {
+    clients: Arc<Mutex<Vec<SyncSender<String>>>>,
+    running: Arc<AtomicBool>,
+    listener_thread: Option<JoinHandle<()>>,
+}
+
+impl WebSocketSink {
+    pub fn new(config: &WebSocketSinkConfig) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(&config.address)?;
+        listener.set_nonblocking(true)?;
+        let clients: Arc<Mutex<Vec<SyncSender<String>>>> = Arc::new(Mutex::new(vec![]));
+        let running = Arc::new(AtomicBool::new(true));
+
+        info!("Websocket sink listening on {}", config.address);
+
+        // Accept clients in the background until the sink is dropped
+        let clients_cloned = clients.clone();
+        let running_cloned = running.clone();
+        let listener_thread = thread::Builder::new()
+            .name("websocket_sink".to_owned())
+            .spawn(move || {
+                while running_cloned.load(Ordering::SeqCst) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL);
+                            continue;
+                        }
+                        Err(err) => {
+                            error!("Could not accept websocket connection!\n{}", err);
+                            continue;
+                        }
+                    };
+
+                    let (message_tx, message_rx) = sync_channel(CLIENT_QUEUE);
+                    let spawned = thread::Builder::new()
+                        .name("websocket_client".to_owned())
+                        .spawn(move || {
+                            if let Err(err) = serve_client(stream, message_rx) {
+                                error!("Websocket handshake failed!\n{}", err);
+                            }
+                        });
+                    match spawned {
+                        Ok(_) => lock(&clients_cloned).push(message_tx),
+                        Err(err) => error!("Could not start websocket client thread!\n{}", err),
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            clients,
+            running,
+            listener_thread: Some(listener_thread),
+        })
+    }
+}
+
+// Handshake with a client, then send it messages until either side goes away
+fn serve_client(stream: TcpStream, message_rx: Receiver<String>) -> Result<(), ErrSink> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(err) => return Err(std::io::Error::other(err.to_string()).into()),
+    };
+    info!("Websocket client connected");
+
+    // Ends once the sink is dropped and the sender with it
+    for message in message_rx {
+        if let Err(err) = socket.send(Message::Text(message.into())) {
+            info!("Websocket client disconnected: {}", err);
+            return Ok(());
+        }
+    }
+
+    if let Err(err) = socket.close(None) {
+        info!("Could not close websocket connection: {}", err);
+    }
+    Ok(())
+}
+
+impl Drop for WebSocketSink {
+    fn drop(&mut self) {
+        // Stop listening, so the address is free again, e.g. for a restarted pipeline
+        self.running.store(false, Ordering::SeqCst);
+        lock(&self.clients).clear();
+
+        if let Some(listener_thread) = self.listener_thread.take()
+            && listener_thread.join().is_err()
+        {
+            error!("Could not join websocket listener thread!");
+        }
+    }
+}
+
+impl OutputSink for WebSocketSink {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    fn is_caption(&self) -> bool {
+        true
+    }
+
+    fn handle(&mut self, utterance: &Utterance) -> Result<(), ErrSink> {
+        let message = serde_json::to_string(utterance)?;
+
+        // Queue for every client without waiting, forgetting any that have disconnected
+        lock(&self.clients).retain(|client| match client.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Websocket client is too slow, it misses an utterance");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+
+        Ok(())
+    }
+}
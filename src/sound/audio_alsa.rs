@@ -0,0 +1,377 @@
+use std::{
+    fmt::Display,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use alsa::{
+    Direction, ValueOr,
+    pcm::{Access, Format, HwParams, PCM},
+};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    ProcessUnit,
+    controls::Controls,
+    sound::{AudioClient, audio_queue::AudioSender, fill_output, play_buffer::PlayConsumer},
+    util::Resampler,
+};
+
+// Periods the device buffers, more survive a busy system at the cost of latency
+const BUFFER_PERIODS: usize = 4;
+
+#[derive(Debug)]
+pub enum ErrAlsaClient {
+    AlsaError(alsa::Error),
+    ResampleError(speexdsp_resampler::Error),
+    IoError(std::io::Error),
+}
+
+impl Display for ErrAlsaClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlsaError(error) => write!(f, "Could not open alsa device!\n{}", error),
+            Self::ResampleError(error) => write!(f, "Could not create resampler!\n{:?}", error),
+            Self::IoError(error) => write!(f, "Could not start alsa thread!\n{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ErrAlsaClient {}
+
+impl From<alsa::Error> for ErrAlsaClient {
+    fn from(value: alsa::Error) -> Self {
+        Self::AlsaError(value)
+    }
+}
+
+impl From<speexdsp_resampler::Error> for ErrAlsaClient {
+    fn from(value: speexdsp_resampler::Error) -> Self {
+        Self::ResampleError(value)
+    }
+}
+
+impl From<std::io::Error> for ErrAlsaClient {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct AlsaConfig {
+    #[serde(default = "default_device")]
+    pub capture_device: String, // e.g. "default", "hw:1,0" or "plughw:CARD=Device"
+    #[serde(default = "default_device")]
+    pub playback_device: String,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: usize, // Rate the pipeline runs at, the devices are resampled if they differ
+    #[serde(default = "default_period")]
+    pub period: usize, // Frames the devices are read and written in, like a JACK buffer size
+}
+
+fn default_device() -> String {
+    "default".to_owned()
+}
+
+fn default_sample_rate() -> usize {
+    16000
+}
+
+fn default_period() -> usize {
+    1024
+}
+
+// A device opened for mono audio at the pipeline's rate, or as close as it gets
+struct Device {
+    pcm: PCM,
+    rate: usize,     // Rate the device runs at
+    channels: usize, // Mono is mixed down from or copied to every channel
+}
+
+// Resampler from one rate to the other, None if they're the same
+fn resampler(from: usize, to: usize) -> Result<Option<Resampler>, ErrAlsaClient> {
+    if from == to {
+        return Ok(None);
+    }
+    info!("Resampling alsa audio from {} Hz to {} Hz", from, to);
+
+    Ok(Some(Resampler::new(from, to)?))
+}
+
+impl Device {
+    fn open(name: &str, direction: Direction, config: &AlsaConfig) -> Result<Self, alsa::Error> {
+        let pcm = PCM::new(name, direction, false)?;
+        {
+            let params = HwParams::any(&pcm)?;
+            params.set_access(Access::RWInterleaved)?;
+            params.set_format(Format::s16())?;
+            params.set_channels_near(1)?;
+            params.set_rate_near(config.sample_rate as u32, ValueOr::Nearest)?;
+            params.set_period_size_near(config.period as i64, ValueOr::Nearest)?;
+            params.set_buffer_size_near((config.period * BUFFER_PERIODS) as i64)?;
+            pcm.hw_params(&params)?;
+        }
+
+        let params = pcm.hw_params_current()?;
+        let device = Self {
+            rate: params.get_rate()? as usize,
+            channels: params.get_channels()? as usize,
+            pcm,
+        };
+        info!(
+            "Opened alsa device {} at {} Hz with {} channels and {} frame periods",
+            name,
+            device.rate,
+            device.channels,
+            params.get_period_size()?
+        );
+
+        Ok(device)
+    }
+
+    // Recover from an xrun or suspend, true if the device can carry on
+    fn recover(&self, err: alsa::Error, xruns: &AtomicU64, audio_tx: &AudioSender) -> bool {
+        if let Err(err) = self.pcm.try_recover(err, true) {
+            error!("Could not recover alsa device!\n{}", err);
+            return false;
+        }
+
+        xruns.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = audio_tx.send(ProcessUnit::Xruns(1)) {
+            error!("Could not send xruns for processing!\n{}", err);
+        }
+        true
+    }
+}
+
+// Audio client using ALSA devices directly, for boxes with one sound card and no audio server
+// Input is read from the capture device and speech written to the playback device, each on its
+// own thread. Monitor and room speech have nowhere to go, so they're only drained
+pub struct AlsaClient {
+    config: AlsaConfig,
+    capture: Option<Device>,  // Taken by the capture thread when starting
+    playback: Option<Device>, // Taken by the playback thread when starting
+    paused: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    xruns: Arc<AtomicU64>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl AudioClient for AlsaClient {
+    type Config = AlsaConfig;
+    type Error = ErrAlsaClient;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error> {
+        let capture = Device::open(&config.capture_device, Direction::Capture, config)?;
+        let playback = Device::open(&config.playback_device, Direction::Playback, config)?;
+
+        Ok(Self {
+            config: config.clone(),
+            capture: Some(capture),
+            playback: Some(playback),
+            paused: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+            xruns: Arc::new(AtomicU64::new(0)),
+            threads: vec![],
+        })
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        mut play: PlayConsumer,
+        mut monitor: PlayConsumer,
+        mut rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        let (Some(capture), Some(playback)) = (self.capture.take(), self.playback.take()) else {
+            error!("Alsa client was already started!");
+            return Ok(());
+        };
+        let Some(mut block_tx) = audio_tx.blocks(self.config.period, false) else {
+            error!("Audio queue already has a sender!");
+            return Ok(());
+        };
+        let sample_rate = self.config.sample_rate;
+        let period = self.config.period;
+        let mut capture_resampler = resampler(capture.rate, sample_rate)?;
+        let mut playback_resampler = resampler(sample_rate, playback.rate)?;
+        self.running.store(true, Ordering::SeqCst);
+
+        // Everything past the devices runs at the pipeline's rate
+        for consumer in std::iter::once(&play)
+            .chain(std::iter::once(&monitor))
+            .chain(&rooms)
+        {
+            consumer.buffer().set_sample_rate(sample_rate);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::SampleRate(sample_rate)) {
+            error!("Could not send sample rate for processing!\n{}", err);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::BufferSize(period)) {
+            error!("Could not send buffer size for processing!\n{}", err);
+        }
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let xruns = self.xruns.clone();
+        let capture_tx = audio_tx.clone();
+        let capture_thread = thread::Builder::new()
+            .name("alsa_capture".to_owned())
+            .spawn(move || {
+                let io = match capture.pcm.io_i16() {
+                    Ok(io) => io,
+                    Err(err) => {
+                        error!("Could not read from alsa device!\n{}", err);
+                        return;
+                    }
+                };
+                let mut interleaved = vec![0; period * capture.channels];
+                let mut mono = Vec::with_capacity(period);
+
+                while running.load(Ordering::SeqCst) {
+                    let frames = match io.readi(&mut interleaved) {
+                        Ok(frames) => frames,
+                        Err(err) => {
+                            if !capture.recover(err, &xruns, &capture_tx) {
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    if paused.load(Ordering::Relaxed) {
+                        continue;
+                    }
+
+                    // Mix every channel down to mono
+                    mono.clear();
+                    mono.extend(
+                        interleaved[..frames * capture.channels]
+                            .chunks(capture.channels)
+                            .map(|frame| {
+                                frame.iter().map(|sample| *sample as f32).sum::<f32>()
+                                    / (capture.channels as f32 * i16::MAX as f32)
+                            }),
+                    );
+                    let block = match capture_resampler.as_mut() {
+                        Some(resampler) => match resampler.process(&mono, false) {
+                            Ok(resampled) => block_tx.pool().take(resampled.into_iter()),
+                            Err(err) => {
+                                error!("Could not resample alsa input!\n{:?}", err);
+                                continue;
+                            }
+                        },
+                        None => block_tx.pool().take(mono.iter().copied()),
+                    };
+                    if let Err(err) = block_tx.send(block, None) {
+                        error!("Could not send audio for processing!\n{}", err);
+                        return;
+                    }
+                }
+            })?;
+        self.threads.push(capture_thread);
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let xruns = self.xruns.clone();
+        let playback_thread = thread::Builder::new()
+            .name("alsa_playback".to_owned())
+            .spawn(move || {
+                let io = match playback.pcm.io_i16() {
+                    Ok(io) => io,
+                    Err(err) => {
+                        error!("Could not write to alsa device!\n{}", err);
+                        return;
+                    }
+                };
+                let mut out_buf = vec![0.0; period];
+                let mut discard = vec![0.0; period];
+                let mut interleaved = Vec::with_capacity(period * 2 * playback.channels);
+
+                while running.load(Ordering::SeqCst) {
+                    let held = paused.load(Ordering::Relaxed) || controls.paused();
+                    fill_output(
+                        &mut out_buf,
+                        &mut discard,
+                        &mut play,
+                        &mut monitor,
+                        &mut rooms,
+                        held,
+                    );
+
+                    let resampled;
+                    let samples = match playback_resampler.as_mut() {
+                        Some(resampler) => match resampler.process(&out_buf, false) {
+                            Ok(samples) => {
+                                resampled = samples;
+                                &resampled
+                            }
+                            Err(err) => {
+                                error!("Could not resample alsa output!\n{:?}", err);
+                                continue;
+                            }
+                        },
+                        None => &out_buf,
+                    };
+
+                    // Copy mono to every channel
+                    interleaved.clear();
+                    for sample in samples {
+                        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                        interleaved.extend(std::iter::repeat_n(sample, playback.channels));
+                    }
+
+                    // Blocks until the device has room, which keeps playback in time
+                    let mut written = 0;
+                    while written < samples.len() && running.load(Ordering::SeqCst) {
+                        match io.writei(&interleaved[written * playback.channels..]) {
+                            Ok(frames) => written += frames,
+                            Err(err) => {
+                                if !playback.recover(err, &xruns, &audio_tx) {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Err(err) = playback.pcm.drop() {
+                    error!("Could not stop alsa device!\n{}", err);
+                }
+            })?;
+        self.threads.push(playback_thread);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn xruns(&self) -> u64 {
+        self.xruns.load(Ordering::Relaxed)
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Reads and writes return within a period
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("Could not join alsa thread!");
+            }
+        }
+    }
+}
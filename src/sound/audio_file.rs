@@ -0,0 +1,217 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    controls::Controls,
+    sound::{
+        AudioClient,
+        audio_mock::{ErrMockClient, MockClient, MockConfig},
+        audio_queue::AudioSender,
+        play_buffer::PlayConsumer,
+    },
+    util::{lock, read_flac, read_wav},
+};
+
+#[derive(Debug)]
+pub enum ErrFileClient {
+    IoError(std::io::Error),
+    HoundError(hound::Error),
+    FlacError(claxon::Error),
+    MockError(ErrMockClient),
+}
+
+impl Display for ErrFileClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(error) => write!(f, "Could not open audio file!\n{}", error),
+            Self::HoundError(error) => write!(f, "Could not read or write wav file!\n{}", error),
+            Self::FlacError(error) => write!(f, "Could not read flac file!\n{}", error),
+            Self::MockError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ErrFileClient {}
+
+impl From<std::io::Error> for ErrFileClient {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<hound::Error> for ErrFileClient {
+    fn from(value: hound::Error) -> Self {
+        Self::HoundError(value)
+    }
+}
+
+impl From<claxon::Error> for ErrFileClient {
+    fn from(value: claxon::Error) -> Self {
+        Self::FlacError(value)
+    }
+}
+
+impl From<ErrMockClient> for ErrFileClient {
+    fn from(value: ErrMockClient) -> Self {
+        Self::MockError(value)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct FileConfig {
+    pub input: PathBuf, // Wav or flac file heard as the input, mixed down to mono
+    pub output: Option<PathBuf>, // Wav file everything played is written to, in time with the input
+    #[serde(default = "default_speed")]
+    pub speed: f32, // Times faster than real time the input is read, 0 as fast as processing takes it
+    #[serde(default = "default_block_size")]
+    pub block_size: usize, // Samples per period, like a JACK buffer size
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_block_size() -> usize {
+    1024
+}
+
+type WavFile = hound::WavWriter<BufWriter<File>>;
+
+// Audio client reading its input from a file and writing its output to one
+// For batch translation of recordings and for testing without an audio server
+pub struct FileClient {
+    config: FileConfig,
+    mock: MockClient,
+    writer: Arc<Mutex<Option<WavFile>>>, // Taken when finishing the file, or after a failed write
+}
+
+// Read a wav or flac file, by its extension
+fn read_input(path: &Path) -> Result<(Vec<f32>, usize), ErrFileClient> {
+    let reader = BufReader::new(File::open(path)?);
+    let flac = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"));
+
+    Ok(if flac {
+        read_flac(reader)?
+    } else {
+        read_wav(reader)?
+    })
+}
+
+impl AudioClient for FileClient {
+    type Config = FileConfig;
+    type Error = ErrFileClient;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error> {
+        // Everything runs at the input's rate, so the output has it too
+        let (input, sample_rate) = read_input(&config.input)?;
+        info!(
+            "Reading {:.1}s of audio from {}",
+            input.len() as f32 / sample_rate.max(1) as f32,
+            config.input.display()
+        );
+
+        let writer = match &config.output {
+            Some(output) => Some(hound::WavWriter::create(
+                output,
+                hound::WavSpec {
+                    channels: 1,
+                    sample_rate: sample_rate as u32,
+                    bits_per_sample: 32,
+                    sample_format: hound::SampleFormat::Float,
+                },
+            )?),
+            None => None,
+        };
+        let writer = Arc::new(Mutex::new(writer));
+        let writer_cloned = writer.clone();
+
+        let mock_config = MockConfig {
+            sample_rate,
+            block_size: config.block_size,
+            speed: config.speed,
+        };
+        let mock = MockClient::with_output(
+            &mock_config,
+            input,
+            Box::new(move |samples| {
+                let mut writer = lock(&writer_cloned);
+                if let Some(file) = writer.as_mut()
+                    && let Err(err) = samples
+                        .iter()
+                        .try_for_each(|sample| file.write_sample(*sample))
+                {
+                    error!(
+                        "Could not write output file, not writing any more!\n{}",
+                        err
+                    );
+                    *writer = None;
+                }
+            }),
+        );
+
+        Ok(Self {
+            config: config.clone(),
+            mock,
+            writer,
+        })
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        play: PlayConsumer,
+        monitor: PlayConsumer,
+        rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        self.mock.start(audio_tx, play, monitor, rooms, controls)?;
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.mock.pause();
+    }
+
+    fn resume(&self) {
+        self.mock.resume();
+    }
+
+    fn paused(&self) -> bool {
+        self.mock.paused()
+    }
+
+    fn xruns(&self) -> u64 {
+        0
+    }
+
+    fn finished(&self) -> bool {
+        self.mock.finished()
+    }
+
+    fn stop(&mut self) {
+        self.mock.stop();
+
+        // Write the header now the length is known
+        if let Some(writer) = lock(&self.writer).take() {
+            match writer.finalize() {
+                Ok(()) => {
+                    if let Some(output) = &self.config.output {
+                        info!("Output written to {}", output.display());
+                    }
+                }
+                Err(err) => error!("Could not finish output file!\n{}", err),
+            }
+        }
+    }
+}
@@ -1,151 +1,742 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, Mutex, mpsc::Sender},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use jack::{
-    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, Port, ProcessScope,
-    contrib::ClosureProcessHandler,
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, ClientStatus, Control,
+    NotificationHandler, Port, PortFlags, ProcessHandler, ProcessScope, TransportState,
 };
 use log::{error, info, warn};
+use regex::Regex;
 use serde::Deserialize;
 
-use crate::{ProcessUnit, sound::AudioClient};
+use crate::{
+    ProcessUnit,
+    controls::Controls,
+    dsp::dynamics::GainRamp,
+    sound::{
+        AudioClient,
+        audio_queue::AudioSender,
+        block_pool::{Block, BlockPool},
+        jack_snapshot,
+        passthrough::{Ducker, PassthroughConfig},
+        play_buffer::{PlayBuffer, PlayConsumer},
+        virtual_device::{VirtualMic, VirtualMicConfig},
+    },
+};
+
+// Type name of jack audio ports
+const AUDIO_TYPE: &str = "32 bit float mono audio";
+
+// How often the connection to the server is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Wait before the first attempt to reconnect, doubled after each failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Xruns within a check interval after which speech waits longer before playing
+const XRUNS_TO_ADAPT: usize = 3;
 
-#[derive(Deserialize, Clone, Debug)]
+type ProcessCallback = Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct JackConfig {
-    pub input_port: String,
-    pub output_ports: Vec<String>,
+    #[serde(default = "default_client_name")]
+    pub client_name: String, // Name in the JACK graph, set by the session manager when run under NSM
+    pub input_port: String, // Port names can also be globs like "bluez_input.*" or regexes starting with ~
+    #[serde(default)]
+    pub extra_input_ports: Vec<String>, // Further input channels, e.g. the right side of a stereo source
+    #[serde(default)]
+    pub input_mix: InputMix,
+    pub output_ports: Vec<String>, // Mono output, or the left side if right_output_ports is set
+    #[serde(default)]
+    pub right_output_ports: Vec<String>, // Makes the output stereo so the voice can be panned
+    #[serde(default)]
+    pub pan: f32, // From -1.0 (left) to 1.0 (right)
+    #[serde(default)]
+    pub monitor_ports: Vec<String>, // Heard only by the operator, e.g. headphones
+    #[serde(default)]
+    pub start_server: bool, // Start the server if it isn't running
+    #[serde(default = "default_auto_connect")]
+    pub auto_connect: bool, // Connect the ports named here, also as they appear, off to leave it to a patchbay
+    #[serde(default)]
+    pub follow_transport: bool, // Only listen and speak while the JACK transport rolls, e.g. with a DAW
+    #[serde(default)]
+    pub prefill: f32, // ms speech waits before playing, giving the audio thread headroom
+    #[serde(default = "default_max_prefill")]
+    pub max_prefill: f32, // ms prefill grows to by a period each time xruns keep happening
+    pub passthrough: Option<PassthroughConfig>, // Mix the input into the output, ducked under the voice
+    pub virtual_mic: Option<VirtualMicConfig>, // Also play the voice into a microphone other apps can use
+    #[serde(skip)]
+    pub room_ports: Vec<(String, Vec<String>)>, // Output ports of each room by its name, set from the rooms
+    #[serde(skip)]
+    pub echo_reference: bool, // Send what was played along with the input, set if echo is cancelled
 }
 
-pub struct JackClient {
-    client: Option<Client>,
-    async_client: Option<
-        AsyncClient<
-            (),
-            ClosureProcessHandler<(), Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>>,
-        >,
-    >,
-    temp_disconnected: Vec<String>,
-    input_name: String,
-    in_port: Option<Port<AudioIn>>,
-    out_port: Option<Port<AudioOut>>,
+fn default_client_name() -> String {
+    "rust_jack_client".to_owned()
 }
 
-impl AudioClient for JackClient {
-    type Config = JackConfig;
-    type Error = jack::Error;
+fn default_auto_connect() -> bool {
+    true
+}
 
-    fn new(config: &Self::Config) -> Result<Self, Self::Error>
-    where
-        Self: Sized,
-    {
-        // Initialise jack client
-        let (client, _status) = Client::new("rust_jack_client", ClientOptions::NO_START_SERVER)?;
+fn default_max_prefill() -> f32 {
+    200.0
+}
 
-        // Register input port
-        let in_port = client.register_port("input_MONO", AudioIn::default())?;
+impl JackConfig {
+    // Every input port, one per channel
+    pub fn input_ports(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.input_port).chain(&self.extra_input_ports)
+    }
+}
+
+// How input channels become the mono audio VAD and whisper work on
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum InputMix {
+    #[default]
+    Downmix, // Average of every channel
+    Channel(usize), // Only one channel, counting from 1
+}
+
+impl InputMix {
+    // Combine a period from each input port into one block, without allocating
+    fn mix(&self, ports: &[Port<AudioIn>], ps: &ProcessScope, pool: &mut BlockPool) -> Block {
+        match self {
+            Self::Channel(channel) => {
+                let port = ports.get(channel.saturating_sub(1)).unwrap_or(&ports[0]);
+                pool.take(port.as_slice(ps).iter().copied())
+            }
+            Self::Downmix if ports.len() == 1 => pool.take(ports[0].as_slice(ps).iter().copied()),
+            Self::Downmix => {
+                let scale = 1.0 / ports.len() as f32;
+                pool.take(
+                    (0..ps.n_frames() as usize).map(|i| {
+                        ports.iter().map(|port| port.as_slice(ps)[i]).sum::<f32>() * scale
+                    }),
+                )
+            }
+        }
+    }
+}
 
-        // Regsiter output port
-        let out_port = client.register_port("output_MONO", AudioOut::default())?;
+// Left and right gain for a pan position, keeping the same loudness across the field
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
 
-        // Connect input
-        let input_name = config.input_port.clone();
-        client.connect_ports_by_name(&input_name, in_port.name()?.as_str())?;
+// Ends of the play buffers the process callback reads from
+struct Consumers {
+    play: PlayConsumer,
+    monitor: PlayConsumer,    // Heard only by the operator
+    rooms: Vec<PlayConsumer>, // One per room, in the order of room_ports
+}
 
-        // List of connections before program
-        let mut temp_disconnected: Vec<String> = vec![];
+type ConsumerSlot = Arc<Mutex<Option<Consumers>>>;
 
-        // Connect output
-        for port in config.output_ports.clone() {
-            if let Some(port) = client.port_by_name(&port) {
-                // Connect output to port
-                client.connect_ports(&out_port, &port)?;
+// Consumers lent to one session's process callback, returned once the callback is dropped
+// so the session replacing it after a server restart can take them
+struct Lease {
+    consumers: Option<Consumers>,
+    slot: ConsumerSlot,
+}
 
-                // Check for microphone connection
-                if port.is_connected_to(&config.input_port)? {
+impl Lease {
+    fn take(slot: &ConsumerSlot) -> Option<Self> {
+        let consumers = slot.lock().ok()?.take()?;
+        Some(Self {
+            consumers: Some(consumers),
+            slot: slot.clone(),
+        })
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(consumers) = self.consumers.take()
+            && let Ok(mut slot) = self.slot.lock()
+        {
+            *slot = Some(consumers);
+        }
+    }
+}
+
+// Regex a configured port name stands for, None if it's an exact name
+// Names starting with ~ are regexes, names with * or ? are globs matching the whole name
+pub fn port_regex(name: &str) -> Option<Result<Regex, regex::Error>> {
+    if let Some(regex) = name.strip_prefix('~') {
+        return Some(Regex::new(regex));
+    }
+    if !name.contains(['*', '?']) {
+        return None;
+    }
+
+    let glob = name
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_owned(),
+            '?' => ".".to_owned(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect::<String>();
+    Some(Regex::new(&format!("^{}$", glob)))
+}
+
+// Existing ports a configured name stands for, sources with IS_OUTPUT and destinations with IS_INPUT
+fn matching_ports(client: &Client, name: &str, flags: PortFlags) -> Vec<String> {
+    match port_regex(name) {
+        None => client
+            .port_by_name(name)
+            .map(|_| vec![name.to_owned()])
+            .unwrap_or_default(),
+        Some(Ok(regex)) => client
+            .ports(None, Some(AUDIO_TYPE), flags)
+            .into_iter()
+            .filter(|port| regex.is_match(port))
+            .collect(),
+        Some(Err(err)) => {
+            error!("Invalid port pattern {}!\n{}", name, err);
+            vec![]
+        }
+    }
+}
+
+// Whether a port is connected to another
+fn connected(client: &Client, port: &str, other: &str) -> Result<bool, jack::Error> {
+    match client.port_by_name(port) {
+        Some(port) => port.is_connected_to(other),
+        None => Ok(false),
+    }
+}
+
+// Names of the client's own ports, to connect them again as other ports appear
+struct OwnPorts {
+    inputs: Vec<String>, // One per input channel
+    output: String,
+    right: Option<String>, // Set when the output is stereo
+    monitor: String,
+    rooms: Vec<String>, // One per room, in the order of room_ports
+}
+
+// Connect the client's ports to every port the config names, leaving existing connections alone
+// so it can run again whenever ports appear, outputs connected straight to an input are disconnected
+// Names which match nothing are only reported if report is set, as their ports may appear later
+fn connect_ports(
+    client: &Client,
+    config: &JackConfig,
+    own: &OwnPorts,
+    temp_disconnected: &mut Vec<(String, String)>,
+    report: bool,
+) -> Result<(), jack::Error> {
+    let matching = |name: &String, flags| {
+        let ports = matching_ports(client, name, flags);
+        if ports.is_empty() && report {
+            warn!("No port matches {}, connecting it once one appears", name);
+        }
+        ports
+    };
+
+    // Connect inputs
+    let mut sources = vec![];
+    for (name, in_port) in config.input_ports().zip(&own.inputs) {
+        for source in matching(name, PortFlags::IS_OUTPUT) {
+            if !connected(client, in_port, &source)? {
+                client.connect_ports_by_name(&source, in_port)?;
+            }
+            sources.push(source);
+        }
+    }
+
+    // Connect outputs
+    let outputs = config
+        .output_ports
+        .iter()
+        .map(|name| (name, &own.output))
+        .chain(own.right.iter().flat_map(|right| {
+            config
+                .right_output_ports
+                .iter()
+                .map(move |name| (name, right))
+        }))
+        .chain(
+            config
+                .room_ports
+                .iter()
+                .zip(&own.rooms)
+                .flat_map(|((_, names), room)| names.iter().map(move |name| (name, room))),
+        );
+    for (name, own_port) in outputs {
+        for port in matching(name, PortFlags::IS_INPUT) {
+            // Connect output to port
+            if !connected(client, own_port, &port)? {
+                client.connect_ports_by_name(own_port, &port)?;
+            }
+
+            // Check for microphone connection
+            for input in &sources {
+                if connected(client, &port, input)? {
                     info!(
                         "Port {} connected to input, temporarily disconnecting",
-                        port.name()?
+                        port
                     );
 
-                    // Add to list
-                    temp_disconnected.push(port.name()?);
+                    // Add to list, and save it in case this run doesn't get to restore it
+                    temp_disconnected.push((input.clone(), port.clone()));
+                    jack_snapshot::save(input, &port);
 
                     // Disconnect ports
-                    client.disconnect_ports_by_name(&config.input_port, &port.name()?)?;
+                    client.disconnect_ports_by_name(input, &port)?;
                 }
-            } else {
-                warn!("Port {} doesn't exist!", port);
+            }
+        }
+    }
+
+    // Connect monitor
+    for name in &config.monitor_ports {
+        for port in matching(name, PortFlags::IS_INPUT) {
+            if !connected(client, &own.monitor, &port)? {
+                client.connect_ports_by_name(&own.monitor, &port)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Runs the process callback, and tells processing when the server's period changes
+struct Process {
+    callback: ProcessCallback,
+    period: Arc<AtomicUsize>, // Frames per period, which prefill grows by
+    audio_tx: AudioSender,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
+        (self.callback)(client, ps)
+    }
+
+    fn buffer_size(&mut self, _: &Client, size: jack::Frames) -> Control {
+        self.period.store(size as usize, Ordering::SeqCst);
+        if let Err(err) = self.audio_tx.send(ProcessUnit::BufferSize(size as usize)) {
+            error!("Could not send buffer size for processing!\n{}", err);
+        }
+        Control::Continue
+    }
+}
+
+// Server events, only flagged here as the client can't be used from these callbacks
+struct Notifications {
+    lost: Arc<AtomicBool>,
+    xruns: Arc<AtomicUsize>,
+    ports_changed: Arc<AtomicBool>, // Set when a port appears, which may have to be connected
+    audio_tx: AudioSender,
+    buffers: Vec<Arc<PlayBuffer>>, // Output buffers which follow the server's rate
+}
+
+impl Notifications {
+    // Tell everything working with the audio what rate it's at
+    fn set_sample_rate(&self, sample_rate: usize) {
+        for buffer in &self.buffers {
+            buffer.set_sample_rate(sample_rate);
+        }
+        if let Err(err) = self.audio_tx.send(ProcessUnit::SampleRate(sample_rate)) {
+            error!("Could not send sample rate for processing!\n{}", err);
+        }
+    }
+}
+
+impl NotificationHandler for Notifications {
+    unsafe fn shutdown(&mut self, _status: ClientStatus, _reason: &str) {
+        self.lost.store(true, Ordering::SeqCst);
+    }
+
+    fn sample_rate(&mut self, _: &Client, sample_rate: jack::Frames) -> Control {
+        self.set_sample_rate(sample_rate as usize);
+        Control::Continue
+    }
+
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.xruns.fetch_add(1, Ordering::SeqCst);
+        Control::Continue
+    }
+
+    fn port_registration(&mut self, _: &Client, _port_id: jack::PortId, is_registered: bool) {
+        if is_registered {
+            self.ports_changed.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+// Client with its ports registered and connected, ready to be activated
+struct Connection {
+    client: Client,
+    in_ports: Vec<Port<AudioIn>>,
+    out_port: Port<AudioOut>,
+    right_port: Option<Port<AudioOut>>, // Set when the output is stereo
+    monitor_port: Port<AudioOut>,
+    room_ports: Vec<Port<AudioOut>>,
+    input_mix: InputMix,
+    pan: f32,
+    echo_reference: bool,
+    follow_transport: bool,
+    passthrough: Option<PassthroughConfig>,
+    own_ports: OwnPorts,
+    temp_disconnected: Vec<(String, String)>, // Connections from an input to an output
+}
+
+impl Connection {
+    fn open(config: &JackConfig) -> Result<Self, jack::Error> {
+        // Initialise jack client
+        let options = if config.start_server {
+            ClientOptions::empty()
+        } else {
+            ClientOptions::NO_START_SERVER
+        };
+        let (client, status) = Client::new(&config.client_name, options)?;
+        if status.contains(ClientStatus::SERVER_STARTED) {
+            info!("Started the jack server");
+        }
+
+        // Undo what an earlier run which crashed left disconnected, before checking the connections
+        jack_snapshot::restore(&client);
+
+        // Register input ports, one per channel
+        let inputs = config.input_ports().collect::<Vec<_>>();
+        let in_ports = if inputs.len() == 1 {
+            vec![client.register_port("input_MONO", AudioIn::default())?]
+        } else {
+            (1..=inputs.len())
+                .map(|channel| {
+                    client.register_port(&format!("input_{}", channel), AudioIn::default())
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // Regsiter output ports
+        let stereo = !config.right_output_ports.is_empty();
+        let out_port = client.register_port(
+            if stereo { "output_L" } else { "output_MONO" },
+            AudioOut::default(),
+        )?;
+        let right_port = if stereo {
+            Some(client.register_port("output_R", AudioOut::default())?)
+        } else {
+            None
+        };
+
+        // Register monitor port
+        let monitor_port = client.register_port("monitor_MONO", AudioOut::default())?;
+
+        // Register a port for each room
+        let room_ports = config
+            .room_ports
+            .iter()
+            .map(|(room, _)| client.register_port(&format!("room_{}", room), AudioOut::default()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Names of the ports, to connect them as matching ports come and go
+        let own_ports = OwnPorts {
+            inputs: in_ports
+                .iter()
+                .map(|port| port.name())
+                .collect::<Result<_, _>>()?,
+            output: out_port.name()?,
+            right: right_port.as_ref().map(|port| port.name()).transpose()?,
+            monitor: monitor_port.name()?,
+            rooms: room_ports
+                .iter()
+                .map(|port| port.name())
+                .collect::<Result<_, _>>()?,
+        };
+
+        // List of connections before program
+        let mut temp_disconnected = vec![];
+        if config.auto_connect {
+            connect_ports(&client, config, &own_ports, &mut temp_disconnected, true)?;
+        } else {
+            info!("Not connecting ports, audio.jack.auto_connect is off");
+        }
+
+        // Connect the virtual microphone, a channel to each of its ports
+        if let Some(virtual_mic) = &config.virtual_mic {
+            let ports = client.ports(
+                Some(&format!("^{}:", virtual_mic.name)),
+                Some(AUDIO_TYPE),
+                PortFlags::IS_INPUT,
+            );
+            if ports.is_empty() {
+                warn!("Virtual microphone {} has no ports!", virtual_mic.name);
+            }
+
+            let own_ports = [Some(&out_port), right_port.as_ref()]
+                .into_iter()
+                .flatten()
+                .cycle();
+            for (port, own_port) in ports.iter().zip(own_ports) {
+                client.connect_ports_by_name(&own_port.name()?, port)?;
             }
         }
 
         Ok(Self {
-            client: Some(client),
+            client,
+            in_ports,
+            out_port,
+            right_port,
+            monitor_port,
+            room_ports,
+            input_mix: config.input_mix,
+            pan: config.pan,
+            echo_reference: config.echo_reference,
+            follow_transport: config.follow_transport,
+            passthrough: config.passthrough.clone(),
+            own_ports,
             temp_disconnected,
-            input_name,
-            in_port: Some(in_port),
-            out_port: Some(out_port),
-            async_client: None,
         })
     }
 
-    fn start(
-        &mut self,
-        audio_tx: Sender<ProcessUnit>,
-        play_buffer: Arc<Mutex<VecDeque<f32>>>,
-    ) -> Result<(), Self::Error> {
-        let in_port = self.in_port.take().unwrap();
-        let mut out_port = self.out_port.take().unwrap();
+    // Start processing audio
+    fn activate(
+        self,
+        audio_tx: AudioSender,
+        consumers: &ConsumerSlot,
+        controls: Arc<Controls>,
+        paused: Arc<AtomicBool>,
+    ) -> Result<Session, jack::Error> {
+        let Some(mut lease) = Lease::take(consumers) else {
+            error!("Play buffers are still held by the previous jack client!");
+            return Err(jack::Error::ClientActivationError);
+        };
+        let buffers = match &lease.consumers {
+            Some(consumers) => [&consumers.play, &consumers.monitor]
+                .into_iter()
+                .chain(&consumers.rooms)
+                .map(|consumer| consumer.buffer().clone())
+                .collect(),
+            None => return Err(jack::Error::ClientActivationError),
+        };
+        // Buffers of speech, which wait longer before playing after repeated xruns
+        let speech_buffers = match &lease.consumers {
+            Some(consumers) => std::iter::once(&consumers.play)
+                .chain(&consumers.rooms)
+                .map(|consumer| consumer.buffer().clone())
+                .collect(),
+            None => vec![],
+        };
 
-        let handler: Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send> =
-            Box::new(move |_: &Client, ps: &ProcessScope| -> Control {
-                // Get audio from input
-                let in_buf = in_port.as_slice(ps);
+        let in_ports = self.in_ports;
+        let mut out_port = self.out_port;
+        let mut right_port = self.right_port;
+        let mut monitor_port = self.monitor_port;
+        let mut room_ports = self.room_ports;
+        let input_mix = self.input_mix;
+        let (left_gain, right_gain) = pan_gains(self.pan);
+        let echo_reference = self.echo_reference;
+        let follow_transport = self.follow_transport;
+        let sample_rate = self.client.sample_rate();
+        let mut ducker = self
+            .passthrough
+            .as_ref()
+            .map(|passthrough| Ducker::new(passthrough, sample_rate));
+        let mut input_gain = GainRamp::default();
+        let mut output_gains = (0..=room_ports.len())
+            .map(|_| GainRamp::default())
+            .collect::<Vec<_>>();
 
-                if let Err(err) = audio_tx.send(ProcessUnit::Continue(in_buf.to_vec())) {
-                    error!("Could not send audio for processing!\n{}", err);
-                    return jack::Control::Continue;
-                };
-
-                // Create buffer to write sound output
-                let out_buf = out_port.as_mut_slice(ps);
-
-                {
-                    // Lock the play buffer
-                    let mut play_buffer = match play_buffer.lock() {
-                        Ok(buffer) => buffer,
-                        Err(err) => {
-                            error!("Could not lock play buffer!\n{}", err);
-                            return jack::Control::Continue;
-                        }
-                    };
-
-                    // Iterate through samples
-                    for frame in out_buf.iter_mut() {
-                        // Pop sample from buffer if its available, otherwise output silence
-                        *frame = play_buffer.pop_front().unwrap_or(0.0);
-                    }
+        // Each period takes a second block for the reference when cancelling echo
+        let block_size = self.client.buffer_size() as usize;
+        let Some(mut block_tx) = audio_tx.blocks(block_size, echo_reference) else {
+            error!("Audio queue is still held by the previous jack client!");
+            return Err(jack::Error::ClientActivationError);
+        };
+        let allocated = block_tx.pool().allocated();
+
+        // Jack client callbacks
+        let lost = Arc::new(AtomicBool::new(false));
+        let xruns = Arc::new(AtomicUsize::new(0));
+        let ports_changed = Arc::new(AtomicBool::new(false));
+        let notifications = Notifications {
+            lost: lost.clone(),
+            xruns: xruns.clone(),
+            ports_changed: ports_changed.clone(),
+            audio_tx: audio_tx.clone(),
+            buffers,
+        };
+        notifications.set_sample_rate(sample_rate);
+
+        // Processing learns the period before the first audio, and whenever it changes
+        let buffer_size = self.client.buffer_size() as usize;
+        let period = Arc::new(AtomicUsize::new(buffer_size));
+        if let Err(err) = audio_tx.send(ProcessUnit::BufferSize(buffer_size)) {
+            error!("Could not send buffer size for processing!\n{}", err);
+        }
+
+        let handler: ProcessCallback = Box::new(move |client: &Client, ps: &ProcessScope| {
+            // A stopped transport pauses like the hotkey does
+            let stopped = follow_transport
+                && matches!(
+                    client.transport().query_state(),
+                    Ok(TransportState::Stopped)
+                );
+            let paused = paused.load(Ordering::Relaxed) || stopped;
+
+            let Some(consumers) = lease.consumers.as_mut() else {
+                return jack::Control::Continue;
+            };
+
+            // Cues for the operator play even while paused
+            consumers.monitor.fill(monitor_port.as_mut_slice(ps));
+
+            // Create buffer to write sound output
+            let out_buf = out_port.as_mut_slice(ps);
+
+            // Hold queued audio while paused
+            let held = paused || controls.paused();
+            let playing =
+                !held && !controls.output_muted() && !consumers.play.buffer().is_empty();
+            if held {
+                out_buf.fill(0.0);
+                for room_port in room_ports.iter_mut() {
+                    room_port.as_mut_slice(ps).fill(0.0);
+                }
+            } else {
+                // Pop samples from buffer if they are available, otherwise output silence
+                consumers.play.fill(out_buf);
+                for (room_port, room) in room_ports.iter_mut().zip(consumers.rooms.iter_mut()) {
+                    room.fill(room_port.as_mut_slice(ps));
                 }
+            }
 
-                // Tell jack to continue
-                jack::Control::Continue
+            // Set the level of the voice in every output
+            let voice_gain = controls.voice_gain();
+            output_gains[0].process(out_buf, voice_gain);
+            for (room_port, gain) in room_ports.iter_mut().zip(&mut output_gains[1..]) {
+                gain.process(room_port.as_mut_slice(ps), voice_gain);
+            }
+
+            // Get audio from input, mixed down to mono
+            // The output is filled first so it can go along as the echo reference
+            let in_buf = (!paused || ducker.is_some()).then(|| {
+                let mut in_buf = input_mix.mix(&in_ports, ps, block_tx.pool());
+                input_gain.process(&mut in_buf, controls.input_gain());
+                in_buf
             });
+            let reference =
+                (!paused && echo_reference).then(|| block_tx.pool().take(out_buf.iter().copied()));
 
-        // Jack client callback
-        let process = ClosureProcessHandler::new(handler);
+            // Pan the voice between both sides
+            let mut right_buf = right_port
+                .as_mut()
+                .map(|right_port| right_port.as_mut_slice(ps));
+            if let Some(right_buf) = right_buf.as_deref_mut() {
+                for (left, right) in out_buf.iter_mut().zip(right_buf.iter_mut()) {
+                    *right = *left * right_gain;
+                    *left *= left_gain;
+                }
+            }
 
-        let client = self.client.take().unwrap();
+            // Let the input through, turned down while the voice plays
+            if let Some(ducker) = ducker.as_mut()
+                && let Some(in_buf) = &in_buf
+            {
+                ducker.mix(in_buf, out_buf, right_buf, playing);
+            }
+
+            // Input only let through while paused goes back to the pool without a lock
+            if let Some(in_buf) = in_buf {
+                if paused {
+                    block_tx.pool().recycle(in_buf);
+                } else if let Err(err) = block_tx.send(in_buf, reference) {
+                    error!("Could not send audio for processing!\n{}", err);
+                }
+            }
+
+            // Tell jack to continue
+            jack::Control::Continue
+        });
+
+        let process = Process {
+            callback: handler,
+            period: period.clone(),
+            audio_tx,
+        };
 
         // Start jack client
-        self.async_client = Some(client.activate_async((), process)?);
+        let async_client = self.client.activate_async(notifications, process)?;
 
-        Ok(())
+        Ok(Session {
+            async_client,
+            speech_buffers,
+            period,
+            own_ports: self.own_ports,
+            temp_disconnected: self.temp_disconnected,
+            lost,
+            xruns,
+            ports_changed,
+            allocated,
+        })
     }
+}
 
-    fn stop(&mut self) {
+// Active client, with what's needed to undo its changes to the connections
+struct Session {
+    async_client: AsyncClient<Notifications, Process>,
+    speech_buffers: Vec<Arc<PlayBuffer>>,
+    period: Arc<AtomicUsize>,
+    own_ports: OwnPorts,
+    temp_disconnected: Vec<(String, String)>,
+    lost: Arc<AtomicBool>,
+    xruns: Arc<AtomicUsize>,
+    ports_changed: Arc<AtomicBool>,
+    allocated: Arc<AtomicUsize>, // Blocks the realtime thread allocated since last checked
+}
+
+impl Session {
+    // Connect ports which appeared since the last time, e.g. a headset that was switched on
+    fn connect_new(&mut self, config: &JackConfig) {
+        let client = self.async_client.as_client();
+        if let Err(err) = connect_ports(
+            client,
+            config,
+            &self.own_ports,
+            &mut self.temp_disconnected,
+            false,
+        ) {
+            error!("Could not connect new ports!\n{}", err);
+        }
+    }
+
+    // Have speech wait a period longer before playing, up to the config's limit
+    fn grow_prefill(&self, config: &JackConfig) {
+        let Some(play) = self.speech_buffers.first() else {
+            return;
+        };
+        let step = self.period.load(Ordering::SeqCst) as f32 / play.sample_rate() as f32;
+        let prefill = (play.prefill() + step).min(config.max_prefill / 1000.0);
+        if prefill <= play.prefill() {
+            return;
+        }
+
+        warn!(
+            "Xruns keep happening, speech waits {:.0} ms before playing",
+            prefill * 1000.0
+        );
+        for buffer in &self.speech_buffers {
+            buffer.set_prefill(prefill);
+        }
+    }
+
+    fn close(self) {
         // Stop jack client
-        let (client, _, _) = match self.async_client.take().unwrap().deactivate() {
+        let (client, _, _) = match self.async_client.deactivate() {
             Ok(client) => client,
             Err(err) => {
                 error!("Could not deactivate jack client!\n{}", err);
@@ -153,14 +744,308 @@ impl AudioClient for JackClient {
             }
         };
 
-        // Reconnect disconnected ports
-        for port in &self.temp_disconnected {
-            if let Err(err) = client.connect_ports_by_name(&self.input_name, &port) {
+        // Reconnect disconnected ports, those which failed stay saved for the next run
+        let mut restored = vec![];
+        for (input, port) in &self.temp_disconnected {
+            match client.connect_ports_by_name(input, port) {
+                Ok(()) => restored.push((input.clone(), port.clone())),
+                Err(err) => error!("Could not reconnect port {} to {}!\n{}", input, port, err),
+            }
+        }
+        jack_snapshot::forget(&restored);
+    }
+}
+
+pub struct JackClient {
+    config: JackConfig,
+    connection: Option<Connection>,
+    virtual_mic: Option<VirtualMic>, // Removed when the client stops
+    stop_tx: Option<Sender<()>>,
+    supervisor: Option<JoinHandle<()>>,
+    paused: Arc<AtomicBool>, // Shared with the process callback, kept across reconnects
+    xruns: Arc<AtomicU64>,   // Total across every session
+}
+
+impl AudioClient for JackClient {
+    type Config = JackConfig;
+    type Error = jack::Error;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        if config.start_server {
+            info!("Connecting to the jack server, starting it if it isn't running");
+        } else {
+            info!(
+                "Connecting to the jack server, not starting it as audio.jack.start_server is off"
+            );
+        }
+
+        // Create the virtual microphone before connecting to it
+        let virtual_mic = config.virtual_mic.as_ref().and_then(|virtual_mic| {
+            let channels = if config.right_output_ports.is_empty() {
+                1
+            } else {
+                2
+            };
+            VirtualMic::create(virtual_mic, channels)
+                .inspect_err(|err| error!("Could not create virtual microphone!\n{}", err))
+                .ok()
+        });
+
+        let connection = Connection::open(config).inspect_err(|_| {
+            if config.start_server {
+                error!("Could not connect to or start the jack server!");
+            } else {
                 error!(
-                    "Could not reconnect port {} to {}!\n{}",
-                    &self.input_name, &port, err
+                    "Could not connect to the jack server, is it running? \
+                     Set audio.jack.start_server = true to start it automatically"
                 );
             }
+        })?;
+
+        Ok(Self {
+            config: config.clone(),
+            connection: Some(connection),
+            virtual_mic,
+            stop_tx: None,
+            supervisor: None,
+            paused: Arc::new(AtomicBool::new(false)),
+            xruns: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        play: PlayConsumer,
+        monitor: PlayConsumer,
+        rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        for consumer in std::iter::once(&play).chain(&rooms) {
+            consumer.buffer().set_prefill(self.config.prefill / 1000.0);
+        }
+        let consumers = Arc::new(Mutex::new(Some(Consumers {
+            play,
+            monitor,
+            rooms,
+        })));
+        let connection = self.connection.take().unwrap();
+        let session = connection.activate(
+            audio_tx.clone(),
+            &consumers,
+            controls.clone(),
+            self.paused.clone(),
+        )?;
+
+        // Watch the connection, reconnecting if the server goes away
+        let (stop_tx, stop_rx) = channel();
+        let config = self.config.clone();
+        let paused = self.paused.clone();
+        let xruns = self.xruns.clone();
+        let supervisor = thread::Builder::new()
+            .name("jack_supervisor".to_owned())
+            .spawn(move || {
+                supervise(
+                    config, session, stop_rx, audio_tx, consumers, controls, paused, xruns,
+                )
+            })
+            .map_err(|err| {
+                error!("Could not start jack supervisor thread!\n{}", err);
+                jack::Error::ClientActivationError
+            })?;
+
+        self.stop_tx = Some(stop_tx);
+        self.supervisor = Some(supervisor);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn xruns(&self) -> u64 {
+        self.xruns.load(Ordering::Relaxed)
+    }
+
+    fn stop(&mut self) {
+        // Closing the channel stops the supervisor, which closes the client
+        drop(self.stop_tx.take());
+        if let Some(supervisor) = self.supervisor.take()
+            && supervisor.join().is_err()
+        {
+            error!("Could not join jack supervisor thread!");
+        }
+
+        if let Some(mut virtual_mic) = self.virtual_mic.take() {
+            virtual_mic.remove();
+        }
+    }
+}
+
+// Also on unwinding after a panic, so the connections changed for the session are restored
+impl Drop for JackClient {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Keep a session running, replacing it whenever the server restarts
+#[allow(clippy::too_many_arguments)]
+fn supervise(
+    config: JackConfig,
+    mut session: Session,
+    stop_rx: Receiver<()>,
+    audio_tx: AudioSender,
+    consumers: ConsumerSlot,
+    controls: Arc<Controls>,
+    paused: Arc<AtomicBool>,
+    total_xruns: Arc<AtomicU64>,
+) {
+    loop {
+        match stop_rx.recv_timeout(CHECK_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => {
+                session.close();
+                return;
+            }
+        }
+
+        let xruns = session.xruns.swap(0, Ordering::SeqCst);
+        total_xruns.fetch_add(xruns as u64, Ordering::Relaxed);
+        if xruns > 0 {
+            warn!(
+                "{} xruns in the last second, audio may have dropped out",
+                xruns
+            );
+            if let Err(err) = audio_tx.send(ProcessUnit::Xruns(xruns as u64)) {
+                error!("Could not send xruns for processing!\n{}", err);
+            }
+        }
+        if xruns >= XRUNS_TO_ADAPT {
+            session.grow_prefill(&config);
+        }
+
+        let allocated = session.allocated.swap(0, Ordering::Relaxed);
+        if allocated > 0 {
+            warn!(
+                "Processing fell behind, {} audio blocks were allocated in the realtime thread",
+                allocated
+            );
+        }
+
+        if session.ports_changed.swap(false, Ordering::SeqCst) && config.auto_connect {
+            session.connect_new(&config);
+        }
+
+        if !session.lost.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        // The old client is dead, its connections went with the server
+        error!("Lost connection to the jack server, reconnecting");
+        drop(session);
+
+        let mut backoff = MIN_BACKOFF;
+        session = loop {
+            match Connection::open(&config).and_then(|connection| {
+                connection.activate(
+                    audio_tx.clone(),
+                    &consumers,
+                    controls.clone(),
+                    paused.clone(),
+                )
+            }) {
+                Ok(session) => {
+                    info!("Reconnected to the jack server");
+                    break session;
+                }
+                Err(err) => warn!(
+                    "Could not reconnect to the jack server, retrying in {}s\n{}",
+                    backoff.as_secs(),
+                    err
+                ),
+            }
+
+            match stop_rx.recv_timeout(backoff) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        };
+    }
+}
+
+// Connections of every port of a client, each from a source to a destination
+// Clients which had to take another name, e.g. "name-01", count as the same client
+pub fn client_connections(client_name: &str) -> Result<Vec<(String, String)>, jack::Error> {
+    let (client, _status) = Client::new("live_translate_session", ClientOptions::NO_START_SERVER)?;
+
+    let ours = |port: &str| {
+        port.strip_prefix(client_name)
+            .is_some_and(|rest| rest.starts_with([':', '-']))
+    };
+    let ports = client.ports(None, Some(AUDIO_TYPE), PortFlags::empty());
+
+    let mut connections = vec![];
+    for own in ports.iter().filter(|port| ours(port)) {
+        let Some(port) = client.port_by_name(own) else {
+            continue;
+        };
+        let source = port.flags().contains(PortFlags::IS_OUTPUT);
+        for other in ports.iter().filter(|other| !ours(other)) {
+            if port.is_connected_to(other)? {
+                connections.push(if source {
+                    (own.clone(), other.clone())
+                } else {
+                    (other.clone(), own.clone())
+                });
+            }
+        }
+    }
+
+    Ok(connections)
+}
+
+// Make connections from a source to a destination, returning how many were made
+// Ports which don't exist are skipped, as they may have gone since the connections were saved
+pub fn connect_all(connections: &[(String, String)]) -> Result<usize, jack::Error> {
+    let (client, _status) = Client::new("live_translate_session", ClientOptions::NO_START_SERVER)?;
+
+    let mut made = 0;
+    for (source, destination) in connections {
+        if connected(&client, source, destination)? {
+            continue;
+        }
+        match client.connect_ports_by_name(source, destination) {
+            Ok(()) => made += 1,
+            Err(err) => warn!(
+                "Could not connect port {} to {}!\n{}",
+                source, destination, err
+            ),
         }
     }
+
+    Ok(made)
+}
+
+// List audio ports which can be recorded from and played to
+pub fn list_ports() -> Result<(Vec<String>, Vec<String>), jack::Error> {
+    let (client, _status) = Client::new("rust_jack_client", ClientOptions::NO_START_SERVER)?;
+
+    let sources = client.ports(None, Some(AUDIO_TYPE), PortFlags::IS_OUTPUT);
+    let sinks = client.ports(None, Some(AUDIO_TYPE), PortFlags::IS_INPUT);
+
+    Ok((sources, sinks))
 }
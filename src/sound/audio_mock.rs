@@ -0,0 +1,262 @@
+use std::{
+    fmt::Display,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::error;
+use serde::Deserialize;
+
+use crate::{
+    ProcessUnit,
+    controls::Controls,
+    sound::{
+        AudioClient,
+        audio_queue::AudioSender,
+        fill_output,
+        play_buffer::{DEFAULT_SAMPLE_RATE, PlayConsumer},
+    },
+    util::lock,
+};
+
+#[derive(Debug)]
+pub struct ErrMockClient(std::io::Error);
+
+impl Display for ErrMockClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not start mock audio thread!\n{}", self.0)
+    }
+}
+
+impl std::error::Error for ErrMockClient {}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct MockConfig {
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: usize,
+    #[serde(default = "default_block_size")]
+    pub block_size: usize, // Samples per period, like a JACK buffer size
+    #[serde(default = "default_speed")]
+    pub speed: f32, // Times faster than real time audio is sent, 0 as fast as processing takes it
+}
+
+fn default_sample_rate() -> usize {
+    DEFAULT_SAMPLE_RATE
+}
+
+fn default_block_size() -> usize {
+    1024
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: default_sample_rate(),
+            block_size: default_block_size(),
+            speed: default_speed(),
+        }
+    }
+}
+
+// Called with every period played, from the mock audio thread
+pub type OutputCallback = Box<dyn FnMut(&[f32]) + Send>;
+
+// Audio client without an audio server, for testing the pipeline
+// Plays the given input followed by silence, and keeps everything output
+pub struct MockClient {
+    config: MockConfig,
+    input: Arc<Vec<f32>>,
+    output: Arc<Mutex<Vec<f32>>>,
+    on_output: Option<OutputCallback>, // Taken by the thread when starting
+    position: Arc<AtomicUsize>,        // Input samples sent so far
+    audio_tx: Option<AudioSender>,
+    paused: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockClient {
+    // Input to send, at the config's sample rate
+    pub fn with_input(config: &MockConfig, input: Vec<f32>) -> Self {
+        let output = Arc::new(Mutex::new(vec![]));
+        let output_cloned = output.clone();
+        let mut client = Self::with_output(
+            config,
+            input,
+            Box::new(move |samples| lock(&output_cloned).extend_from_slice(samples)),
+        );
+        client.output = output;
+        client
+    }
+
+    // Hand what is played to a callback instead of keeping it, e.g. to write it to a file
+    pub fn with_output(config: &MockConfig, input: Vec<f32>, on_output: OutputCallback) -> Self {
+        Self {
+            config: config.clone(),
+            input: Arc::new(input),
+            output: Arc::new(Mutex::new(vec![])),
+            on_output: Some(on_output),
+            position: Arc::new(AtomicUsize::new(0)),
+            audio_tx: None,
+            paused: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    // Everything played so far, shared so it can be read while the pipeline owns the client
+    // Stays empty with an output callback
+    pub fn output(&self) -> Arc<Mutex<Vec<f32>>> {
+        self.output.clone()
+    }
+}
+
+impl AudioClient for MockClient {
+    type Config = MockConfig;
+    type Error = ErrMockClient;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error> {
+        Ok(Self::with_input(config, vec![]))
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        mut play: PlayConsumer,
+        mut monitor: PlayConsumer,
+        mut rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        let Some(mut block_tx) = audio_tx.blocks(self.config.block_size, false) else {
+            error!("Audio queue already has a sender!");
+            return Ok(());
+        };
+        let config = self.config.clone();
+        let input = self.input.clone();
+        let mut on_output = self.on_output.take().unwrap_or_else(|| Box::new(|_| {}));
+        let position = self.position.clone();
+        let paused = self.paused.clone();
+        let running = self.running.clone();
+        running.store(true, Ordering::SeqCst);
+
+        for consumer in std::iter::once(&play)
+            .chain(std::iter::once(&monitor))
+            .chain(&rooms)
+        {
+            consumer.buffer().set_sample_rate(config.sample_rate);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::SampleRate(config.sample_rate)) {
+            error!("Could not send sample rate for processing!\n{}", err);
+        }
+        self.audio_tx = Some(audio_tx.clone());
+
+        let thread = thread::Builder::new()
+            .name("mock_audio".to_owned())
+            .spawn(move || {
+                let period =
+                    Duration::from_secs_f64(config.block_size as f64 / config.sample_rate as f64);
+                let mut out_buf = vec![0.0; config.block_size];
+                let mut discard = vec![0.0; config.block_size];
+                let mut next = Instant::now();
+
+                while running.load(Ordering::SeqCst) {
+                    // Keep to real time like a sound card would, or the given speed while input is left
+                    // Silence after it is real time, so processing catches up
+                    let speed = if position.load(Ordering::Relaxed) < input.len() {
+                        config.speed
+                    } else {
+                        1.0
+                    };
+                    if speed > 0.0 {
+                        next += period.div_f32(speed);
+                        thread::sleep(next.saturating_duration_since(Instant::now()));
+                    } else {
+                        next = Instant::now();
+                    }
+
+                    // Faster than real time waits for processing instead of dropping audio
+                    while speed != 1.0 && audio_tx.full() && running.load(Ordering::SeqCst) {
+                        thread::sleep(period);
+                        next = Instant::now();
+                    }
+
+                    let held = paused.load(Ordering::Relaxed) || controls.paused();
+                    fill_output(
+                        &mut out_buf,
+                        &mut discard,
+                        &mut play,
+                        &mut monitor,
+                        &mut rooms,
+                        held,
+                    );
+                    on_output(&out_buf);
+
+                    if paused.load(Ordering::Relaxed) {
+                        continue;
+                    }
+
+                    // Input, then silence once it has all been sent
+                    let start = position.fetch_add(config.block_size, Ordering::Relaxed);
+                    let rest = input.get(start.min(input.len())..).unwrap_or_default();
+                    let samples = rest
+                        .iter()
+                        .copied()
+                        .chain(std::iter::repeat(0.0))
+                        .take(config.block_size);
+                    let block = block_tx.pool().take(samples);
+                    if let Err(err) = block_tx.send(block, None) {
+                        error!("Could not send audio for processing!\n{}", err);
+                        return;
+                    }
+                }
+            })
+            .map_err(ErrMockClient)?;
+
+        self.thread = Some(thread);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn xruns(&self) -> u64 {
+        0
+    }
+
+    // Once all the input was sent and processing took it
+    fn finished(&self) -> bool {
+        self.position.load(Ordering::Relaxed) >= self.input.len()
+            && self
+                .audio_tx
+                .as_ref()
+                .is_some_and(|audio_tx| audio_tx.queued() == 0)
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            error!("Could not join mock audio thread!");
+        }
+    }
+}
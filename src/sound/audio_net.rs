@@ -0,0 +1,541 @@
+use std::{
+    fmt::Display,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, error, info};
+use serde::Deserialize;
+
+use crate::{
+    ProcessUnit,
+    controls::Controls,
+    sound::{
+        AudioClient,
+        audio_queue::AudioSender,
+        fill_output,
+        play_buffer::{DEFAULT_SAMPLE_RATE, PlayConsumer},
+    },
+};
+
+// How often the receiving thread checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Largest packet received, more than any UDP payload on a usual network
+const MAX_PACKET: usize = 65536;
+
+// Lost packets concealed at most at once, a longer gap is left out
+const MAX_CONCEALED: u16 = 10;
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER: usize = 12;
+
+#[derive(Debug)]
+pub enum ErrNetClient {
+    IoError(std::io::Error),
+    InvalidAddress(String),
+    #[cfg(feature = "opus")]
+    OpusError(opus::Error),
+}
+
+impl Display for ErrNetClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(error) => write!(f, "{}", error),
+            Self::InvalidAddress(address) => write!(f, "Invalid address {}", address),
+            #[cfg(feature = "opus")]
+            Self::OpusError(error) => write!(f, "Could not set up opus!\n{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ErrNetClient {}
+
+impl From<std::io::Error> for ErrNetClient {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+#[cfg(feature = "opus")]
+impl From<opus::Error> for ErrNetClient {
+    fn from(value: opus::Error) -> Self {
+        Self::OpusError(value)
+    }
+}
+
+// How the audio in the RTP packets is encoded
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    L16, // Uncompressed 16 bit big endian PCM, as RFC 3551 has it
+    #[cfg(feature = "opus")]
+    Opus, // Needs building with --features opus
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NetConfig {
+    pub listen: String, // Address RTP is received on, e.g. "0.0.0.0:5004"
+    #[serde(default = "default_codec")]
+    pub codec: Codec,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: usize, // Rate of the received audio, which the pipeline runs at
+    #[serde(default = "default_channels")]
+    pub channels: usize, // Channels of the received audio, mixed down to mono
+    pub send_to: Option<String>, // Address speech is sent back to as RTP, in the same codec
+    #[serde(default = "default_payload_type")]
+    pub payload_type: u8, // RTP payload type of the speech sent
+    #[serde(default = "default_packet")]
+    pub packet: f32, // ms of speech in each packet sent, 2.5 to 60 in steps opus allows with Opus
+}
+
+fn default_codec() -> Codec {
+    Codec::L16
+}
+
+fn default_sample_rate() -> usize {
+    DEFAULT_SAMPLE_RATE
+}
+
+fn default_channels() -> usize {
+    1
+}
+
+fn default_payload_type() -> u8 {
+    96
+}
+
+fn default_packet() -> f32 {
+    20.0
+}
+
+// Payload of an RTP packet with its SSRC and sequence number, None if it isn't RTP
+fn rtp_payload(packet: &[u8]) -> Option<(u32, u16, &[u8])> {
+    let header = packet.get(..RTP_HEADER)?;
+    if header[0] >> 6 != RTP_VERSION {
+        return None;
+    }
+    let padded = header[0] & 0x20 != 0;
+    let extended = header[0] & 0x10 != 0;
+    let csrcs = (header[0] & 0x0f) as usize;
+    let sequence = u16::from_be_bytes([header[2], header[3]]);
+    let ssrc = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+
+    let mut start = RTP_HEADER + csrcs * 4;
+    if extended {
+        let extension = packet.get(start..start + 4)?;
+        start += 4 + u16::from_be_bytes([extension[2], extension[3]]) as usize * 4;
+    }
+    let mut end = packet.len();
+    if padded {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    Some((ssrc, sequence, packet.get(start..end)?))
+}
+
+// Turns received payloads into mono samples
+enum Decoder {
+    L16 {
+        channels: usize,
+    },
+    #[cfg(feature = "opus")]
+    Opus {
+        decoder: opus::Decoder,
+        channels: usize,
+        decoded: Vec<f32>, // Room for the longest opus frame
+    },
+}
+
+impl Decoder {
+    fn new(config: &NetConfig) -> Result<Self, ErrNetClient> {
+        Ok(match config.codec {
+            Codec::L16 => Self::L16 {
+                channels: config.channels.max(1),
+            },
+            #[cfg(feature = "opus")]
+            Codec::Opus => Self::Opus {
+                decoder: opus::Decoder::new(config.sample_rate as u32, opus_channels(config))?,
+                channels: config.channels.clamp(1, 2),
+                // 120 ms is the longest frame
+                decoded: vec![0.0; config.sample_rate * 2 * 120 / 1000],
+            },
+        })
+    }
+
+    // Decode a payload, appending it to out as mono
+    fn decode(&mut self, payload: &[u8], out: &mut Vec<f32>) {
+        match self {
+            Self::L16 { channels } => {
+                let frame_bytes = *channels * 2;
+                out.extend(payload.chunks_exact(frame_bytes).map(|frame| {
+                    frame
+                        .chunks_exact(2)
+                        .map(|sample| i16::from_be_bytes([sample[0], sample[1]]) as f32)
+                        .sum::<f32>()
+                        / (*channels as f32 * i16::MAX as f32)
+                }));
+            }
+            #[cfg(feature = "opus")]
+            Self::Opus {
+                decoder,
+                channels,
+                decoded,
+            } => match decoder.decode_float(payload, decoded, false) {
+                Ok(frames) => mix_down(&decoded[..frames * *channels], *channels, out),
+                Err(err) => debug!("Could not decode opus packet: {}", err),
+            },
+        }
+    }
+
+    // Forget the stream decoded so far, for when another one starts
+    fn reset(&mut self) {
+        match self {
+            Self::L16 { .. } => {}
+            #[cfg(feature = "opus")]
+            Self::Opus { decoder, .. } => {
+                if let Err(err) = decoder.reset_state() {
+                    debug!("Could not reset opus decoder: {}", err);
+                }
+            }
+        }
+    }
+
+    // Fill in for a lost packet, after last decoded length samples
+    fn conceal(&mut self, length: usize, out: &mut Vec<f32>) {
+        match self {
+            Self::L16 { .. } => out.extend(std::iter::repeat_n(0.0, length)),
+            // Opus guesses what the lost packet held from the ones before it
+            #[cfg(feature = "opus")]
+            Self::Opus {
+                decoder,
+                channels,
+                decoded,
+            } => {
+                let length = (length * *channels).min(decoded.len());
+                match decoder.decode_float(&[], &mut decoded[..length], false) {
+                    Ok(frames) => mix_down(&decoded[..frames * *channels], *channels, out),
+                    Err(err) => debug!("Could not conceal lost opus packet: {}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "opus")]
+fn opus_channels(config: &NetConfig) -> opus::Channels {
+    if config.channels > 1 {
+        opus::Channels::Stereo
+    } else {
+        opus::Channels::Mono
+    }
+}
+
+#[cfg(feature = "opus")]
+fn mix_down(interleaved: &[f32], channels: usize, out: &mut Vec<f32>) {
+    out.extend(
+        interleaved
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+    );
+}
+
+// Turns mono speech into payloads
+enum Encoder {
+    L16,
+    #[cfg(feature = "opus")]
+    Opus(opus::Encoder),
+}
+
+impl Encoder {
+    fn new(config: &NetConfig) -> Result<Self, ErrNetClient> {
+        Ok(match config.codec {
+            Codec::L16 => Self::L16,
+            #[cfg(feature = "opus")]
+            Codec::Opus => Self::Opus(opus::Encoder::new(
+                config.sample_rate as u32,
+                opus::Channels::Mono,
+                opus::Application::Voip,
+            )?),
+        })
+    }
+
+    // Encode a packet of samples, appending the payload to out
+    fn encode(&mut self, samples: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Self::L16 => out.extend(samples.iter().flat_map(|sample| {
+                ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_be_bytes()
+            })),
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => {
+                let start = out.len();
+                out.resize(start + MAX_PACKET, 0);
+                match encoder.encode_float(samples, &mut out[start..]) {
+                    Ok(length) => out.truncate(start + length),
+                    Err(err) => {
+                        error!("Could not encode speech as opus!\n{}", err);
+                        out.truncate(start);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Sends speech as an RTP stream
+struct RtpSender {
+    socket: UdpSocket,
+    address: SocketAddr,
+    encoder: Encoder,
+    payload_type: u8,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    packet: Vec<u8>,
+}
+
+impl RtpSender {
+    fn send(&mut self, samples: &[f32]) {
+        self.packet.clear();
+        self.packet
+            .extend_from_slice(&[RTP_VERSION << 6, self.payload_type & 0x7f]);
+        self.packet.extend_from_slice(&self.sequence.to_be_bytes());
+        self.packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        self.packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        self.encoder.encode(samples, &mut self.packet);
+
+        if let Err(err) = self.socket.send_to(&self.packet, self.address) {
+            debug!("Could not send speech to {}: {}", self.address, err);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(samples.len() as u32);
+    }
+}
+
+// Audio client receiving its input as RTP over UDP, e.g. from another machine with ffmpeg
+// or GStreamer, and optionally sending speech back as a second stream
+// The input is timed by the sender, speech by the clock here. Monitor and room speech have
+// nowhere to go, so they're only drained
+pub struct NetClient {
+    config: NetConfig,
+    socket: UdpSocket,
+    send_to: Option<SocketAddr>,
+    paused: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl AudioClient for NetClient {
+    type Config = NetConfig;
+    type Error = ErrNetClient;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error> {
+        let socket = UdpSocket::bind(&config.listen)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let send_to = match &config.send_to {
+            Some(address) => Some(
+                address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| ErrNetClient::InvalidAddress(address.clone()))?,
+            ),
+            None => None,
+        };
+        info!("Receiving {:?} over RTP on {}", config.codec, config.listen);
+
+        Ok(Self {
+            config: config.clone(),
+            socket,
+            send_to,
+            paused: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+            threads: vec![],
+        })
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        mut play: PlayConsumer,
+        mut monitor: PlayConsumer,
+        mut rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        let sample_rate = self.config.sample_rate;
+        let packet_length = (self.config.packet / 1000.0 * sample_rate as f32) as usize;
+        let Some(mut block_tx) = audio_tx.blocks(packet_length, false) else {
+            error!("Audio queue already has a sender!");
+            return Ok(());
+        };
+        let mut decoder = Decoder::new(&self.config)?;
+        let mut sender = match self.send_to {
+            Some(address) => {
+                info!("Sending speech over RTP to {}", address);
+                // Random enough to tell streams apart
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.subsec_nanos())
+                    .unwrap_or(0);
+                Some(RtpSender {
+                    socket: self.socket.try_clone()?,
+                    address,
+                    encoder: Encoder::new(&self.config)?,
+                    payload_type: self.config.payload_type,
+                    sequence: seed as u16,
+                    timestamp: seed.rotate_left(16),
+                    ssrc: seed ^ std::process::id(),
+                    packet: vec![],
+                })
+            }
+            None => None,
+        };
+        let socket = self.socket.try_clone()?;
+        self.running.store(true, Ordering::SeqCst);
+
+        for consumer in std::iter::once(&play)
+            .chain(std::iter::once(&monitor))
+            .chain(&rooms)
+        {
+            consumer.buffer().set_sample_rate(sample_rate);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::SampleRate(sample_rate)) {
+            error!("Could not send sample rate for processing!\n{}", err);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::BufferSize(packet_length)) {
+            error!("Could not send buffer size for processing!\n{}", err);
+        }
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let receive_thread = thread::Builder::new()
+            .name("net_receive".to_owned())
+            .spawn(move || {
+                let mut buffer = vec![0; MAX_PACKET];
+                let mut samples = vec![];
+                let mut last: Option<(u16, usize)> = None; // Sequence and length of the last packet
+                let mut stream = None; // Sender address and SSRC of the packets received
+
+                while running.load(Ordering::SeqCst) {
+                    let (length, address) = match socket.recv_from(&mut buffer) {
+                        Ok(received) => received,
+                        Err(err)
+                            if matches!(
+                                err.kind(),
+                                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            continue;
+                        }
+                        Err(err) => {
+                            error!("Could not receive audio!\n{}", err);
+                            return;
+                        }
+                    };
+                    let Some((ssrc, sequence, payload)) = rtp_payload(&buffer[..length]) else {
+                        debug!("Ignoring a packet from {} which isn't RTP", address);
+                        continue;
+                    };
+
+                    // A new sender, or the same one restarted, numbers its packets afresh
+                    if stream.replace((address, ssrc)) != Some((address, ssrc)) {
+                        info!("Receiving audio from {} (SSRC {:08x})", address, ssrc);
+                        last = None;
+                        decoder.reset();
+                    }
+
+                    // Late packets were concealed already, lost ones are filled in
+                    samples.clear();
+                    if let Some((last_sequence, last_length)) = last {
+                        let gap = sequence.wrapping_sub(last_sequence);
+                        if gap == 0 || gap > u16::MAX / 2 {
+                            continue;
+                        }
+                        if gap > 1 {
+                            debug!("Lost {} packets from {}", gap - 1, address);
+                            for _ in 0..(gap - 1).min(MAX_CONCEALED) {
+                                decoder.conceal(last_length, &mut samples);
+                            }
+                        }
+                    }
+                    let concealed = samples.len();
+                    decoder.decode(payload, &mut samples);
+                    last = Some((sequence, samples.len() - concealed));
+
+                    if paused.load(Ordering::Relaxed) || samples.is_empty() {
+                        continue;
+                    }
+                    let block = block_tx.pool().take(samples.iter().copied());
+                    if let Err(err) = block_tx.send(block, None) {
+                        error!("Could not send audio for processing!\n{}", err);
+                        return;
+                    }
+                }
+            })?;
+        self.threads.push(receive_thread);
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let send_thread = thread::Builder::new()
+            .name("net_send".to_owned())
+            .spawn(move || {
+                let period = Duration::from_secs_f64(packet_length as f64 / sample_rate as f64);
+                let mut out_buf = vec![0.0; packet_length];
+                let mut discard = vec![0.0; packet_length];
+                let mut next = Instant::now();
+
+                while running.load(Ordering::SeqCst) {
+                    // Keep to real time like a sound card would
+                    next += period;
+                    thread::sleep(next.saturating_duration_since(Instant::now()));
+
+                    let held = paused.load(Ordering::Relaxed) || controls.paused();
+                    fill_output(
+                        &mut out_buf,
+                        &mut discard,
+                        &mut play,
+                        &mut monitor,
+                        &mut rooms,
+                        held,
+                    );
+
+                    if let Some(sender) = sender.as_mut() {
+                        sender.send(&out_buf);
+                    }
+                }
+            })?;
+        self.threads.push(send_thread);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // The network has no xruns, lost packets are only logged
+    fn xruns(&self) -> u64 {
+        0
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("Could not join network audio thread!");
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    fmt::Display,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use libpulse_binding::{
+    def::BufferAttr,
+    error::PAErr,
+    sample::{Format, Spec},
+    stream::Direction,
+};
+use libpulse_simple_binding::Simple;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    ProcessUnit,
+    controls::Controls,
+    sound::{
+        AudioClient,
+        audio_queue::AudioSender,
+        fill_output,
+        play_buffer::{DEFAULT_SAMPLE_RATE, PlayConsumer},
+        virtual_device::{ErrPactl, pactl},
+    },
+};
+
+// Name the streams are shown under, e.g. in pavucontrol
+const APPLICATION_NAME: &str = "Live Translate";
+
+// Periods the server buffers for playback, more survive a busy system at the cost of latency
+const BUFFER_PERIODS: usize = 4;
+
+const SAMPLE_BYTES: usize = size_of::<f32>();
+
+#[derive(Debug)]
+pub enum ErrPulseClient {
+    PulseError(PAErr),
+    IoError(std::io::Error),
+    CommandFailed(String), // What pactl printed
+}
+
+impl Display for ErrPulseClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PulseError(error) => write!(f, "Could not open pulseaudio stream!\n{}", error),
+            Self::IoError(error) => write!(f, "{}", error),
+            Self::CommandFailed(message) => write!(f, "pactl failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ErrPulseClient {}
+
+impl From<PAErr> for ErrPulseClient {
+    fn from(value: PAErr) -> Self {
+        Self::PulseError(value)
+    }
+}
+
+impl From<std::io::Error> for ErrPulseClient {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<ErrPactl> for ErrPulseClient {
+    fn from(value: ErrPactl) -> Self {
+        match value {
+            ErrPactl::IoError(error) => Self::IoError(error),
+            ErrPactl::Failed(message) => Self::CommandFailed(message),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PulseConfig {
+    pub source: Option<String>, // Where the input is recorded from, the default source if not set
+    pub sink: Option<String>,   // Where speech is played, the default sink if not set
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: usize, // Rate the pipeline runs at, the server converts the devices to it
+    #[serde(default = "default_period")]
+    pub period: usize, // Frames read and written at a time, like a JACK buffer size
+}
+
+fn default_sample_rate() -> usize {
+    DEFAULT_SAMPLE_RATE
+}
+
+fn default_period() -> usize {
+    1024
+}
+
+impl Default for PulseConfig {
+    fn default() -> Self {
+        Self {
+            source: None,
+            sink: None,
+            sample_rate: default_sample_rate(),
+            period: default_period(),
+        }
+    }
+}
+
+// Open a mono float stream, the server converts it from or to the device
+fn open(
+    direction: Direction,
+    device: Option<&str>,
+    config: &PulseConfig,
+) -> Result<Simple, ErrPulseClient> {
+    let spec = Spec {
+        format: Format::FLOAT32NE,
+        channels: 1,
+        rate: config.sample_rate as u32,
+    };
+    let period_bytes = (config.period * SAMPLE_BYTES) as u32;
+    let (stream_name, attr) = match direction {
+        Direction::Record => (
+            "Input",
+            BufferAttr {
+                maxlength: u32::MAX,
+                tlength: u32::MAX,
+                prebuf: u32::MAX,
+                minreq: u32::MAX,
+                fragsize: period_bytes,
+            },
+        ),
+        _ => (
+            "Speech",
+            BufferAttr {
+                maxlength: u32::MAX,
+                tlength: period_bytes * BUFFER_PERIODS as u32,
+                prebuf: u32::MAX,
+                minreq: period_bytes,
+                fragsize: u32::MAX,
+            },
+        ),
+    };
+
+    let stream = Simple::new(
+        None,
+        APPLICATION_NAME,
+        direction,
+        device,
+        stream_name,
+        &spec,
+        None,
+        Some(&attr),
+    )?;
+    info!(
+        "Opened pulseaudio {} stream on {}",
+        stream_name.to_lowercase(),
+        device.unwrap_or("the default device")
+    );
+
+    Ok(stream)
+}
+
+// Audio client using PulseAudio, or PipeWire through its pulse server, with no setup needed
+// Input is recorded from the default source and speech played on the default sink unless the
+// config names others. Monitor and room speech have nowhere to go, so they're only drained
+pub struct PulseClient {
+    config: PulseConfig,
+    record: Option<Simple>,   // Taken by the record thread when starting
+    playback: Option<Simple>, // Taken by the playback thread when starting
+    paused: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl AudioClient for PulseClient {
+    type Config = PulseConfig;
+    type Error = ErrPulseClient;
+
+    fn new(config: &Self::Config) -> Result<Self, Self::Error> {
+        let record = open(Direction::Record, config.source.as_deref(), config)?;
+        let playback = open(Direction::Playback, config.sink.as_deref(), config)?;
+
+        Ok(Self {
+            config: config.clone(),
+            record: Some(record),
+            playback: Some(playback),
+            paused: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+            threads: vec![],
+        })
+    }
+
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        mut play: PlayConsumer,
+        mut monitor: PlayConsumer,
+        mut rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Self::Error> {
+        let (Some(record), Some(playback)) = (self.record.take(), self.playback.take()) else {
+            error!("Pulseaudio client was already started!");
+            return Ok(());
+        };
+        let Some(mut block_tx) = audio_tx.blocks(self.config.period, false) else {
+            error!("Audio queue already has a sender!");
+            return Ok(());
+        };
+        let sample_rate = self.config.sample_rate;
+        let period = self.config.period;
+        self.running.store(true, Ordering::SeqCst);
+
+        for consumer in std::iter::once(&play)
+            .chain(std::iter::once(&monitor))
+            .chain(&rooms)
+        {
+            consumer.buffer().set_sample_rate(sample_rate);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::SampleRate(sample_rate)) {
+            error!("Could not send sample rate for processing!\n{}", err);
+        }
+        if let Err(err) = audio_tx.send(ProcessUnit::BufferSize(period)) {
+            error!("Could not send buffer size for processing!\n{}", err);
+        }
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let record_thread = thread::Builder::new()
+            .name("pulse_record".to_owned())
+            .spawn(move || {
+                let mut bytes = vec![0; period * SAMPLE_BYTES];
+
+                while running.load(Ordering::SeqCst) {
+                    if let Err(err) = record.read(&mut bytes) {
+                        error!("Could not record from pulseaudio!\n{}", err);
+                        return;
+                    }
+                    if paused.load(Ordering::Relaxed) {
+                        continue;
+                    }
+
+                    let samples = bytes
+                        .chunks_exact(SAMPLE_BYTES)
+                        .map(|sample| f32::from_ne_bytes(sample.try_into().unwrap_or([0; 4])));
+                    let block = block_tx.pool().take(samples);
+                    if let Err(err) = block_tx.send(block, None) {
+                        error!("Could not send audio for processing!\n{}", err);
+                        return;
+                    }
+                }
+            })?;
+        self.threads.push(record_thread);
+
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let playback_thread = thread::Builder::new()
+            .name("pulse_playback".to_owned())
+            .spawn(move || {
+                let mut out_buf = vec![0.0; period];
+                let mut discard = vec![0.0; period];
+                let mut bytes = Vec::with_capacity(period * SAMPLE_BYTES);
+
+                while running.load(Ordering::SeqCst) {
+                    let held = paused.load(Ordering::Relaxed) || controls.paused();
+                    fill_output(
+                        &mut out_buf,
+                        &mut discard,
+                        &mut play,
+                        &mut monitor,
+                        &mut rooms,
+                        held,
+                    );
+
+                    // Blocks until the server has room, which keeps playback in time
+                    bytes.clear();
+                    bytes.extend(out_buf.iter().flat_map(|sample| sample.to_ne_bytes()));
+                    if let Err(err) = playback.write(&bytes) {
+                        error!("Could not play on pulseaudio!\n{}", err);
+                        return;
+                    }
+                }
+
+                if let Err(err) = playback.flush() {
+                    error!("Could not stop pulseaudio stream!\n{}", err);
+                }
+            })?;
+        self.threads.push(playback_thread);
+
+        Ok(())
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // The simple API doesn't report them
+    fn xruns(&self) -> u64 {
+        0
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Reads and writes return within a period
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("Could not join pulseaudio thread!");
+            }
+        }
+    }
+}
+
+// Names of the sources or sinks, as audio.pulse takes them
+fn device_names(kind: &str) -> Result<Vec<String>, ErrPulseClient> {
+    Ok(pactl(&["list", "short", kind])?
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(str::to_owned)
+        .collect())
+}
+
+// Print the sources and sinks the config can name, marking the defaults
+pub fn print_devices() -> Result<(), ErrPulseClient> {
+    let info = pactl(&["info"])?;
+    let default = |key: &str| {
+        info.lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|name| name.trim().to_owned())
+    };
+    let default_source = default("Default Source:");
+    let default_sink = default("Default Sink:");
+
+    for (title, kind, default) in [
+        ("Sources", "sources", default_source),
+        ("Sinks", "sinks", default_sink),
+    ] {
+        println!("{}:", title);
+        for name in device_names(kind)? {
+            let marker = if Some(&name) == default.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  {}{}", name, marker);
+        }
+    }
+
+    Ok(())
+}
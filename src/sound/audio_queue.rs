@@ -0,0 +1,325 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        Arc, Condvar, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+use serde::Deserialize;
+
+use crate::{
+    ProcessUnit,
+    sound::block_pool::{Block, BlockPool, POOL_SPARE},
+    util::lock,
+};
+
+// How long the receiver sleeps while nothing is queued
+// The realtime thread never wakes it, so this is the most audio waits before processing
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// How often dropped blocks are reported while audio is being dropped
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct QueueConfig {
+    #[serde(default = "default_capacity")]
+    pub capacity: usize, // Audio blocks held while processing falls behind, one per period
+    #[serde(default)]
+    pub overflow: Overflow,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_capacity(),
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+fn default_capacity() -> usize {
+    4096
+}
+
+// Which audio block is given up when the queue is full
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    #[default]
+    DropOldest, // Keep up with the speaker, losing what was said while stalled
+    DropNewest, // Finish what was already queued, losing what is said until it catches up
+}
+
+impl QueueConfig {
+    // Audio blocks the ring holds
+    // The sender can't take blocks back out, so with DropOldest there is room for as many again
+    // and the receiver skips the oldest down to capacity once it catches up
+    pub fn slots(&self) -> usize {
+        match self.overflow {
+            Overflow::DropOldest => self.capacity.max(1) * 2,
+            Overflow::DropNewest => self.capacity.max(1),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ErrQueueClosed;
+
+impl Display for ErrQueueClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Audio queue is closed")
+    }
+}
+
+impl std::error::Error for ErrQueueClosed {}
+
+// Audio from the realtime thread and what was played at the same time, see ProcessUnit::Continue
+type Audio = (Block, Option<Block>);
+
+struct Shared {
+    control: Mutex<VecDeque<(u64, ProcessUnit)>>, // Units other than audio, after how many blocks
+    ready: Condvar,
+    producer: Mutex<Option<Producer<Audio>>>, // Until taken by the thread sending audio
+    sent: AtomicU64,                          // Audio blocks queued since starting
+    received: AtomicU64,                      // Audio blocks taken off the queue, skipped or not
+    dropped: AtomicU64,                       // Audio blocks dropped since the last report
+    total: AtomicU64,                         // Audio blocks dropped since starting
+    closed: AtomicBool,                       // Receiver is gone
+    stopped: AtomicBool,                      // Audio is no longer wanted, e.g. while shutting down
+    config: QueueConfig,
+}
+
+impl Shared {
+    fn drop_block(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Create a queue which holds at most config.capacity audio blocks
+// Audio goes through a lock free ring, other units are never dropped and go through a lock
+pub fn bounded(config: QueueConfig) -> (AudioSender, AudioReceiver) {
+    let (producer, consumer) = RingBuffer::new(config.slots());
+    let shared = Arc::new(Shared {
+        control: Mutex::new(VecDeque::new()),
+        ready: Condvar::new(),
+        producer: Mutex::new(Some(producer)),
+        sent: AtomicU64::new(0),
+        received: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        total: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        stopped: AtomicBool::new(false),
+        config,
+    });
+
+    (
+        AudioSender {
+            shared: shared.clone(),
+        },
+        AudioReceiver {
+            shared,
+            consumer,
+            reported: Instant::now(),
+        },
+    )
+}
+
+#[derive(Clone)]
+pub struct AudioSender {
+    shared: Arc<Shared>,
+}
+
+impl AudioSender {
+    // Queue a unit other than audio, after the audio already sent
+    pub fn send(&self, unit: ProcessUnit) -> Result<(), ErrQueueClosed> {
+        debug_assert!(
+            !matches!(unit, ProcessUnit::Continue(..)),
+            "Audio is sent through a BlockSender"
+        );
+
+        let mut control = lock(&self.shared.control);
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(ErrQueueClosed);
+        }
+
+        control.push_back((self.shared.sent.load(Ordering::Acquire), unit));
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+
+    // Take the end audio is sent from, None while another thread holds it
+    // It comes back when dropped, e.g. for the client replacing a lost jack connection
+    // Its pool has a block for every slot of the queue, and another for each reference if the
+    // sender adds them, so audio is only allocated once the queue is dropping it anyway
+    pub fn blocks(&self, block_size: usize, references: bool) -> Option<BlockSender> {
+        let producer = lock(&self.shared.producer).take()?;
+        let per_slot = if references { 2 } else { 1 };
+        let blocks = self.shared.config.slots() * per_slot + POOL_SPARE;
+
+        Some(BlockSender {
+            producer: Some(producer),
+            pool: BlockPool::new(blocks, block_size),
+            shared: self.shared.clone(),
+        })
+    }
+
+    // Drop audio from now on, other units are still queued
+    pub fn stop_input(&self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+    }
+
+    // Audio blocks waiting to be processed
+    pub fn queued(&self) -> usize {
+        let sent = self.shared.sent.load(Ordering::Acquire);
+        sent.saturating_sub(self.shared.received.load(Ordering::Acquire)) as usize
+    }
+
+    // Whether the next audio block would drop one
+    pub fn full(&self) -> bool {
+        self.queued() >= self.shared.config.capacity
+    }
+
+    // Audio blocks dropped since starting
+    pub fn total_dropped(&self) -> u64 {
+        self.shared.total.load(Ordering::Relaxed)
+    }
+}
+
+// End of the queue audio is sent from, never locking or waiting so a realtime thread can hold it
+pub struct BlockSender {
+    producer: Option<Producer<Audio>>, // Only taken when dropped
+    pool: BlockPool,
+    shared: Arc<Shared>,
+}
+
+impl BlockSender {
+    // Pool the blocks sent are taken from
+    pub fn pool(&mut self) -> &mut BlockPool {
+        &mut self.pool
+    }
+
+    // Queue a block of audio, and what was played at the same time if echo is cancelled
+    // The newest block is dropped if the ring is full, going back to the pool without a lock
+    pub fn send(&mut self, block: Block, reference: Option<Block>) -> Result<(), ErrQueueClosed> {
+        let stopped = self.shared.stopped.load(Ordering::Relaxed);
+        let closed = self.shared.closed.load(Ordering::Relaxed);
+        let rejected = match self.producer.as_mut() {
+            Some(producer) if !stopped && !closed => match producer.push((block, reference)) {
+                Ok(()) => {
+                    self.shared.sent.fetch_add(1, Ordering::Release);
+                    None
+                }
+                Err(PushError::Full(audio)) => {
+                    self.shared.drop_block();
+                    Some(audio)
+                }
+            },
+            _ => Some((block, reference)),
+        };
+        if let Some((block, reference)) = rejected {
+            self.pool.recycle(block);
+            if let Some(reference) = reference {
+                self.pool.recycle(reference);
+            }
+        }
+
+        // Quietly when stopped, the sender may be a realtime thread which keeps capturing
+        if closed && !stopped {
+            return Err(ErrQueueClosed);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BlockSender {
+    fn drop(&mut self) {
+        *lock(&self.shared.producer) = self.producer.take();
+    }
+}
+
+pub struct AudioReceiver {
+    shared: Arc<Shared>,
+    consumer: Consumer<Audio>,
+    reported: Instant,
+}
+
+impl AudioReceiver {
+    // Wait for the next unit
+    pub fn recv(&mut self) -> ProcessUnit {
+        let unit = self.next();
+
+        // Report drops here, the sender may be a realtime thread
+        let dropped = self.shared.dropped.load(Ordering::Relaxed);
+        if dropped > 0 && self.reported.elapsed() >= REPORT_INTERVAL {
+            warn!(
+                "Processing fell behind, dropped {} audio blocks ({} in total, {:?})",
+                self.shared.dropped.swap(0, Ordering::Relaxed),
+                self.shared.total.load(Ordering::Relaxed),
+                self.shared.config.overflow
+            );
+            self.reported = Instant::now();
+        }
+
+        unit
+    }
+
+    fn next(&mut self) -> ProcessUnit {
+        // Held while taking audio, so a unit sent after a block can't be passed by it
+        let mut control = lock(&self.shared.control);
+        loop {
+            let received = self.shared.received.load(Ordering::Relaxed);
+            let next_control = control.front().map(|(after, _)| *after);
+            if next_control.is_some_and(|after| after <= received)
+                && let Some((_, unit)) = control.pop_front()
+            {
+                return unit;
+            }
+
+            // Blocks may be taken up to the next unit
+            let mut taken: u64 = 0;
+            let mut take = |consumer: &mut Consumer<Audio>| {
+                if next_control.is_some_and(|after| received + taken >= after) {
+                    return None;
+                }
+                let audio = consumer.pop().ok()?;
+                taken += 1;
+                Some(audio)
+            };
+
+            // Catch up to the speaker, skipping what was said while stalled
+            if self.shared.config.overflow == Overflow::DropOldest {
+                while self.consumer.slots() > self.shared.config.capacity
+                    && take(&mut self.consumer).is_some()
+                {
+                    self.shared.drop_block();
+                }
+            }
+
+            let audio = take(&mut self.consumer);
+            self.shared.received.fetch_add(taken, Ordering::Release);
+            if let Some((block, reference)) = audio {
+                return ProcessUnit::Continue(block, reference);
+            }
+
+            control = self
+                .shared
+                .ready
+                .wait_timeout(control, POLL_INTERVAL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+impl Drop for AudioReceiver {
+    fn drop(&mut self) {
+        let mut control = lock(&self.shared.control);
+        self.shared.closed.store(true, Ordering::SeqCst);
+        control.clear();
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::util::lock;
+
+// Blocks allocated beyond what the audio queue holds, for the ones being filled and processed
+pub const POOL_SPARE: usize = 16;
+
+// Blocks recycled by the thread taking them, which happens each period while paused
+const RECYCLED: usize = 4;
+
+// Way back into the pool, locked only by threads other than the one taking blocks
+type Returns = Arc<Mutex<Producer<Vec<f32>>>>;
+
+// Reusable audio blocks, so the realtime thread doesn't have to allocate
+// Taken without a lock, blocks come back through a ring when dropped
+pub struct BlockPool {
+    free: Consumer<Vec<f32>>,
+    recycled: Vec<Vec<f32>>, // Given back by the taking thread itself, which can't use the ring
+    returns: Returns,
+    allocated: Arc<AtomicUsize>, // Blocks allocated because the pool ran dry
+}
+
+impl BlockPool {
+    // Allocate blocks holding a period of samples each
+    pub fn new(blocks: usize, block_size: usize) -> Self {
+        let (mut producer, free) = RingBuffer::new(blocks);
+        for _ in 0..blocks {
+            if producer.push(Vec::with_capacity(block_size)).is_err() {
+                break;
+            }
+        }
+
+        Self {
+            free,
+            recycled: Vec::with_capacity(RECYCLED),
+            returns: Arc::new(Mutex::new(producer)),
+            allocated: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Take a block holding a copy of samples
+    // Only allocates if every block is still waiting to be processed
+    pub fn take(&mut self, samples: impl Iterator<Item = f32>) -> Block {
+        let mut block = self
+            .recycled
+            .pop()
+            .or_else(|| self.free.pop().ok())
+            .unwrap_or_else(|| {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::new()
+            });
+        block.clear();
+        block.extend(samples);
+
+        Block {
+            samples: block,
+            returns: self.returns.clone(),
+        }
+    }
+
+    // Give a block back from the taking thread, without the lock dropping it would take
+    pub fn recycle(&mut self, mut block: Block) {
+        if self.recycled.len() < RECYCLED {
+            self.recycled.push(std::mem::take(&mut block.samples));
+        }
+    }
+
+    // Count of blocks allocated, to be taken and logged outside the realtime thread
+    pub fn allocated(&self) -> Arc<AtomicUsize> {
+        self.allocated.clone()
+    }
+}
+
+// Audio block which goes back to its pool when dropped
+pub struct Block {
+    samples: Vec<f32>,
+    returns: Returns,
+}
+
+impl Deref for Block {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+impl DerefMut for Block {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        &mut self.samples
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        // Already recycled
+        if self.samples.capacity() == 0 {
+            return;
+        }
+
+        // Freed instead if the pool is gone or full of blocks allocated when it ran dry
+        let samples = std::mem::take(&mut self.samples);
+        let _ = lock(&self.returns).push(samples);
+    }
+}
@@ -0,0 +1,50 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use crate::sound::play_buffer::PlayBuffer;
+
+// Pitches of the two falling beeps in Hz
+const CUE_PITCHES: [f32; 2] = [880.0, 440.0];
+
+// Length of each beep and the gap after it in seconds
+const BEEP_LENGTH: f32 = 0.12;
+const GAP_LENGTH: f32 = 0.04;
+
+// Peak level of the cue, kept below speech
+const CUE_LEVEL: f32 = 0.3;
+
+// Fade in and out of each beep in seconds, avoiding clicks
+const FADE_LENGTH: f32 = 0.01;
+
+// Tone played to the operator's monitor when an utterance is dropped
+pub struct ErrorCue {
+    buffer: Arc<PlayBuffer>,
+}
+
+impl ErrorCue {
+    pub fn new(buffer: Arc<PlayBuffer>) -> Self {
+        Self { buffer }
+    }
+
+    // Generate the tone at the rate of the output
+    fn tone(&self) -> Vec<f32> {
+        let rate = self.buffer.sample_rate() as f32;
+        let beep_samples = (BEEP_LENGTH * rate) as usize;
+        let gap_samples = (GAP_LENGTH * rate) as usize;
+        let fade_samples = (FADE_LENGTH * rate).max(1.0);
+
+        let mut tone = vec![];
+        for pitch in CUE_PITCHES {
+            tone.extend((0..beep_samples).map(|i| {
+                let fade = (i.min(beep_samples - i) as f32 / fade_samples).min(1.0);
+                (TAU * pitch * i as f32 / rate).sin() * CUE_LEVEL * fade
+            }));
+            tone.extend(std::iter::repeat_n(0.0, gap_samples));
+        }
+
+        tone
+    }
+
+    pub fn play(&self) {
+        self.buffer.push(self.tone());
+    }
+}
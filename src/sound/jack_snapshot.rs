@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use jack::{Client, ClientOptions};
+use log::{error, info, warn};
+
+use crate::{data_dir, util::lock};
+
+// Connections from an input to an output, broken while running so the input isn't heard twice
+// Kept in the data directory until they're restored, so a crash doesn't leave them broken
+const SNAPSHOT_FILE: &str = "jack_connections.json";
+
+// Read, change and write the file one pipeline at a time
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+// Whether connections left by an earlier run were restored already, later clients leave them alone
+static RESTORED: AtomicBool = AtomicBool::new(false);
+
+fn path() -> PathBuf {
+    data_dir::path(SNAPSHOT_FILE)
+}
+
+// Connections waiting to be restored
+fn read() -> Vec<(String, String)> {
+    let content = match fs::read(path()) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return vec![],
+        Err(err) => {
+            error!("Could not read saved jack connections!\n{}", err);
+            return vec![];
+        }
+    };
+
+    serde_json::from_slice(&content).unwrap_or_else(|err| {
+        error!("Could not parse saved jack connections!\n{}", err);
+        vec![]
+    })
+}
+
+// Replace the saved connections, removing the file once there are none
+fn write(connections: &[(String, String)]) -> Result<(), std::io::Error> {
+    let path = path();
+    if connections.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+
+    // Replace in one step so a crash never leaves a partial file
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec_pretty(connections)?)?;
+    fs::rename(temp_path, path)
+}
+
+// Remember a connection before breaking it
+pub fn save(input: &str, port: &str) {
+    let _guard = lock(&FILE_LOCK);
+
+    let mut connections = read();
+    let connection = (input.to_owned(), port.to_owned());
+    if !connections.contains(&connection) {
+        connections.push(connection);
+    }
+    if let Err(err) = write(&connections) {
+        error!(
+            "Could not save jack connections, a crash would leave them broken!\n{}",
+            err
+        );
+    }
+}
+
+// Forget connections which were restored
+pub fn forget(restored: &[(String, String)]) {
+    let _guard = lock(&FILE_LOCK);
+
+    let mut connections = read();
+    connections.retain(|connection| !restored.contains(connection));
+    if let Err(err) = write(&connections) {
+        error!("Could not update saved jack connections!\n{}", err);
+    }
+}
+
+// Make the connections an earlier run broke and didn't restore, e.g. as it crashed
+// Returns how many were made, only the first client of a run does anything
+pub fn restore(client: &Client) -> usize {
+    if RESTORED.swap(true, Ordering::SeqCst) {
+        return 0;
+    }
+
+    let _guard = lock(&FILE_LOCK);
+
+    let connections = read();
+    let mut restored = 0;
+    for (input, port) in &connections {
+        let connected = client
+            .port_by_name(port)
+            .is_some_and(|port| port.is_connected_to(input).unwrap_or(false));
+        if connected {
+            continue;
+        }
+
+        match client.connect_ports_by_name(input, port) {
+            Ok(()) => {
+                info!(
+                    "Reconnected port {} to {}, left by an earlier run",
+                    input, port
+                );
+                restored += 1;
+            }
+            // The ports may be gone by now, so there is nothing to restore
+            Err(err) => warn!("Could not reconnect port {} to {}!\n{}", input, port, err),
+        }
+    }
+
+    if let Err(err) = write(&[]) {
+        error!("Could not remove saved jack connections!\n{}", err);
+    }
+
+    restored
+}
+
+// Restore saved connections without starting a pipeline, for the restore-connections command
+pub fn restore_saved() -> Result<usize, jack::Error> {
+    let (client, _status) = Client::new("rust_jack_client", ClientOptions::NO_START_SERVER)?;
+
+    Ok(restore(&client))
+}
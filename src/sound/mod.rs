@@ -1,22 +1,75 @@
-use std::{
-    collections::VecDeque,
-    sync::{Arc, Mutex, mpsc::Sender},
-};
+use std::sync::Arc;
 
 use serde::Deserialize;
 
-use crate::{ProcessUnit, sound::audio_jack::JackConfig};
+#[cfg(feature = "alsa")]
+use crate::sound::audio_alsa::AlsaConfig;
+#[cfg(feature = "pulse")]
+use crate::sound::audio_pulse::PulseConfig;
+use crate::{
+    controls::Controls,
+    dsp::{StageConfig, echo::EchoConfig, noise_floor::NoiseGateConfig},
+    sound::{
+        audio_file::FileConfig,
+        audio_jack::JackConfig,
+        audio_net::NetConfig,
+        audio_queue::{AudioSender, QueueConfig},
+        play_buffer::PlayConsumer,
+        reblock::DEFAULT_FRAME_LENGTH,
+    },
+};
 
+#[cfg(feature = "alsa")]
+pub mod audio_alsa;
+pub mod audio_file;
 pub mod audio_jack;
+pub mod audio_mock;
+pub mod audio_net;
+#[cfg(feature = "pulse")]
+pub mod audio_pulse;
+pub mod audio_queue;
+pub mod block_pool;
+pub mod cue;
+pub mod jack_snapshot;
+pub mod passthrough;
+pub mod play_buffer;
+pub mod reblock;
+pub mod virtual_device;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub enum AudioClientType {
     Jack,
+    File, // Read the input from a file, for batch translation
+    Net,  // Receive the input as RTP over UDP, e.g. from another machine
+    #[cfg(feature = "alsa")]
+    Alsa, // Use ALSA devices directly, without an audio server
+    #[cfg(feature = "pulse")]
+    Pulse, // Record the default source and play on the default sink, without setup
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct AudioConfig {
     pub jack: Option<JackConfig>,
+    pub file: Option<FileConfig>,
+    pub net: Option<NetConfig>,
+    #[cfg(feature = "alsa")]
+    pub alsa: Option<AlsaConfig>,
+    #[cfg(feature = "pulse")]
+    pub pulse: Option<PulseConfig>, // Defaults used if not set
+    #[serde(default)]
+    pub pre: Vec<StageConfig>, // Processing applied to input before VAD and whisper
+    #[serde(default)]
+    pub recording: Vec<StageConfig>, // Processing applied to each finished recording before whisper
+    #[serde(default)]
+    pub queue: QueueConfig, // Audio waiting to be processed
+    #[serde(default = "default_frame")]
+    pub frame: u32, // Length in ms of the frames the VAD decides on: 10, 20 or 30
+    pub echo: Option<EchoConfig>, // Remove the output from the input when the mic can hear it
+    pub noise_gate: Option<NoiseGateConfig>, // Only record input clearly louder than the background noise
+}
+
+fn default_frame() -> u32 {
+    DEFAULT_FRAME_LENGTH
 }
 
 pub trait AudioClient: Send {
@@ -31,10 +84,112 @@ pub trait AudioClient: Send {
     // Start processing audio
     fn start(
         &mut self,
-        audio_tx: Sender<ProcessUnit>,
-        play_buffer: Arc<Mutex<VecDeque<f32>>>,
+        audio_tx: AudioSender,
+        play: PlayConsumer,
+        monitor: PlayConsumer,    // Heard only by the operator
+        rooms: Vec<PlayConsumer>, // Speech for each room, in the order of the audio config's rooms
+        controls: Arc<Controls>,
     ) -> Result<(), Self::Error>;
 
+    // Stop sending input and silence the output while staying connected
+    fn pause(&self);
+
+    // Carry on after pause
+    fn resume(&self);
+
+    fn paused(&self) -> bool;
+
+    // Xruns since starting
+    fn xruns(&self) -> u64;
+
+    // Whether the input has ended and was processed, e.g. at the end of a file
+    fn finished(&self) -> bool {
+        false
+    }
+
     // Stop the client
     fn stop(&mut self);
 }
+
+// Audio client as a pipeline holds it, whatever its type
+// Implemented for every AudioClient, with its error boxed
+pub trait DynAudioClient: Send {
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        play: PlayConsumer,
+        monitor: PlayConsumer,
+        rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Box<dyn std::error::Error + Send>>;
+
+    fn pause(&self);
+
+    fn resume(&self);
+
+    fn paused(&self) -> bool;
+
+    fn xruns(&self) -> u64;
+
+    fn finished(&self) -> bool;
+
+    fn stop(&mut self);
+}
+
+impl<T: AudioClient> DynAudioClient for T {
+    fn start(
+        &mut self,
+        audio_tx: AudioSender,
+        play: PlayConsumer,
+        monitor: PlayConsumer,
+        rooms: Vec<PlayConsumer>,
+        controls: Arc<Controls>,
+    ) -> Result<(), Box<dyn std::error::Error + Send>> {
+        AudioClient::start(self, audio_tx, play, monitor, rooms, controls)
+            .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send>)
+    }
+
+    fn pause(&self) {
+        AudioClient::pause(self)
+    }
+
+    fn resume(&self) {
+        AudioClient::resume(self)
+    }
+
+    fn paused(&self) -> bool {
+        AudioClient::paused(self)
+    }
+
+    fn xruns(&self) -> u64 {
+        AudioClient::xruns(self)
+    }
+
+    fn finished(&self) -> bool {
+        AudioClient::finished(self)
+    }
+
+    fn stop(&mut self) {
+        AudioClient::stop(self)
+    }
+}
+
+// Fill a period of output for clients with only one place to play speech
+// Output is held while paused, the monitor and rooms are only drained into discard
+pub fn fill_output(
+    out_buf: &mut [f32],
+    discard: &mut [f32],
+    play: &mut PlayConsumer,
+    monitor: &mut PlayConsumer,
+    rooms: &mut [PlayConsumer],
+    paused: bool,
+) {
+    out_buf.fill(0.0);
+    if !paused {
+        play.fill(out_buf);
+    }
+    monitor.fill(discard);
+    for room in rooms.iter_mut() {
+        room.fill(discard);
+    }
+}
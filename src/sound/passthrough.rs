@@ -0,0 +1,79 @@
+use serde::Deserialize;
+
+use crate::dsp::dynamics::db_to_gain;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PassthroughConfig {
+    #[serde(default = "default_duck")]
+    pub duck: f32, // Change in dB of the input while the voice plays
+    #[serde(default = "default_attack")]
+    pub attack: f32, // Time in ms to fade the input down once the voice starts
+    #[serde(default = "default_release")]
+    pub release: f32, // Time in ms to fade the input back up after the voice stops
+}
+
+fn default_duck() -> f32 {
+    -20.0
+}
+
+fn default_attack() -> f32 {
+    50.0
+}
+
+fn default_release() -> f32 {
+    500.0
+}
+
+// Mixes the input into the output, turning it down while the voice plays so they don't talk over each other
+pub struct Ducker {
+    ducked: f32, // Gain while the voice plays
+    attack: f32, // Smoothing coefficients per sample
+    release: f32,
+    gain: f32,
+}
+
+// Smoothing coefficient reaching about two thirds of a change in the given time
+fn coefficient(ms: f32, sample_rate: usize) -> f32 {
+    let samples = ms / 1000.0 * sample_rate as f32;
+    if samples < 1.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp()
+    }
+}
+
+impl Ducker {
+    pub fn new(config: &PassthroughConfig, sample_rate: usize) -> Self {
+        Self {
+            ducked: db_to_gain(config.duck),
+            attack: coefficient(config.attack, sample_rate),
+            release: coefficient(config.release, sample_rate),
+            gain: 1.0,
+        }
+    }
+
+    // Add the input to the output, and its right side if stereo, ducked if the voice is playing
+    pub fn mix(
+        &mut self,
+        input: &[f32],
+        left: &mut [f32],
+        mut right: Option<&mut [f32]>,
+        playing: bool,
+    ) {
+        let (target, coefficient) = if playing {
+            (self.ducked, self.attack)
+        } else {
+            (1.0, self.release)
+        };
+
+        for (i, (out, sample)) in left.iter_mut().zip(input).enumerate() {
+            self.gain = target + (self.gain - target) * coefficient;
+            let ducked = sample * self.gain;
+
+            *out += ducked;
+            if let Some(right) = right.as_deref_mut() {
+                right[i] += ducked;
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use log::{error, warn};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+// Rate assumed until the audio client reports its own
+pub const DEFAULT_SAMPLE_RATE: usize = 48000;
+
+// Longest queue of speech a live pipeline holds, in seconds at the default rate
+pub const LIVE_CAPACITY: usize = 120;
+
+// Queue of samples waiting to be played, counting samples in and out
+// so positions in the output stream can be tracked
+// Samples are pushed through a lock, the audio thread takes them without one
+#[derive(Debug)]
+pub struct PlayBuffer {
+    producer: Mutex<Producer<f32>>,
+    pushed: AtomicU64,        // Total samples ever queued
+    played: AtomicU64,        // Total samples ever played
+    skip_to: AtomicU64,       // Position queued samples before are dropped instead of played
+    sample_rate: AtomicUsize, // Rate of the output the samples are played on
+    prefill: AtomicU32,       // Seconds speech waits before playing, stored as f32 bits
+}
+
+// Audio thread's end of a play buffer, never blocking
+pub struct PlayConsumer {
+    consumer: Consumer<f32>,
+    buffer: Arc<PlayBuffer>,
+    started: bool, // Whether queued samples are playing, having waited for the prefill
+    waited: usize, // Samples of silence played since samples were queued
+}
+
+impl PlayBuffer {
+    // Create a buffer holding up to a number of seconds, and the end it's played from
+    pub fn new(seconds: usize) -> (Arc<Self>, PlayConsumer) {
+        let (producer, consumer) = RingBuffer::new(seconds * DEFAULT_SAMPLE_RATE);
+
+        let buffer = Arc::new(Self {
+            producer: Mutex::new(producer),
+            pushed: AtomicU64::default(),
+            played: AtomicU64::default(),
+            skip_to: AtomicU64::default(),
+            sample_rate: AtomicUsize::new(DEFAULT_SAMPLE_RATE),
+            prefill: AtomicU32::default(),
+        });
+
+        (
+            buffer.clone(),
+            PlayConsumer {
+                consumer,
+                buffer,
+                started: false,
+                waited: 0,
+            },
+        )
+    }
+
+    // Queue samples for playback, dropping any which don't fit
+    pub fn push(&self, samples: Vec<f32>) {
+        let mut producer = match self.producer.lock() {
+            Ok(producer) => producer,
+            Err(err) => {
+                error!("Could not lock play buffer!\n{}", err);
+                return;
+            }
+        };
+
+        let fits = producer.slots().min(samples.len());
+        if fits < samples.len() {
+            warn!(
+                "Play buffer is full, dropping {} samples",
+                samples.len() - fits
+            );
+        }
+
+        if let Ok(chunk) = producer.write_chunk_uninit(fits) {
+            let written = chunk.fill_from_iter(samples);
+            self.pushed.fetch_add(written as u64, Ordering::SeqCst);
+        }
+    }
+
+    // Number of samples waiting to be played
+    pub fn len(&self) -> usize {
+        (self.pushed() - self.played()) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Position the next queued sample will play at
+    pub fn pushed(&self) -> u64 {
+        self.pushed.load(Ordering::SeqCst)
+    }
+
+    // Position of the next sample to be played
+    pub fn played(&self) -> u64 {
+        self.played.load(Ordering::SeqCst)
+    }
+
+    // Drop everything queued before a position, played from there on instead
+    pub fn skip_to(&self, position: u64) {
+        self.skip_to.fetch_max(position, Ordering::SeqCst);
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate.load(Ordering::SeqCst)
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: usize) {
+        self.sample_rate.store(sample_rate, Ordering::SeqCst);
+    }
+
+    // Seconds of audio waiting to be played
+    pub fn queued(&self) -> f32 {
+        self.len() as f32 / self.sample_rate() as f32
+    }
+
+    // Seconds samples wait after being queued before they start playing
+    pub fn prefill(&self) -> f32 {
+        f32::from_bits(self.prefill.load(Ordering::Relaxed))
+    }
+
+    // Give the audio thread headroom before playing, e.g. as the audio server keeps missing deadlines
+    pub fn set_prefill(&self, seconds: f32) {
+        self.prefill
+            .store(seconds.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl PlayConsumer {
+    // Shared state of the buffer this plays from
+    pub fn buffer(&self) -> &Arc<PlayBuffer> {
+        &self.buffer
+    }
+
+    // Fill an output buffer with queued samples, padding with silence
+    pub fn fill(&mut self, out_buf: &mut [f32]) {
+        // Throw away samples which were skipped
+        let skip = self
+            .buffer
+            .skip_to
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.buffer.played()) as usize;
+        if skip > 0 {
+            let skipped = skip.min(self.consumer.slots());
+            if let Ok(chunk) = self.consumer.read_chunk(skipped) {
+                chunk.commit_all();
+                self.buffer
+                    .played
+                    .fetch_add(skipped as u64, Ordering::SeqCst);
+            }
+        }
+
+        // Wait for the prefill before starting, rather than as soon as anything is queued
+        if !self.started {
+            let prefill = (self.buffer.prefill() * self.buffer.sample_rate() as f32) as usize;
+            if self.consumer.slots() > 0 {
+                self.started = self.waited >= prefill;
+                self.waited += out_buf.len();
+            }
+            if !self.started {
+                out_buf.fill(0.0);
+                return;
+            }
+        }
+
+        let available = self.consumer.slots().min(out_buf.len());
+
+        let played = match self.consumer.read_chunk(available) {
+            Ok(chunk) => {
+                let (first, second) = chunk.as_slices();
+                out_buf[..first.len()].copy_from_slice(first);
+                out_buf[first.len()..available].copy_from_slice(second);
+                chunk.commit_all();
+                available
+            }
+            Err(_) => 0,
+        };
+        out_buf[played..].fill(0.0);
+
+        self.buffer
+            .played
+            .fetch_add(played as u64, Ordering::SeqCst);
+
+        // Wait again for whatever is queued next
+        if self.consumer.slots() == 0 {
+            self.started = false;
+            self.waited = 0;
+        }
+    }
+}
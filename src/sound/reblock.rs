@@ -0,0 +1,214 @@
+// Frame lengths in ms the VAD accepts
+pub const FRAME_LENGTHS: [u32; 3] = [10, 20, 30];
+
+// Frame length used if the config's isn't one the VAD accepts
+pub const DEFAULT_FRAME_LENGTH: u32 = 20;
+
+// Collects audio arriving in blocks of any size, e.g. the audio server's period, into frames of a fixed size
+// The reference played at the same time is collected alongside, so the two stay aligned
+pub struct Reblocker {
+    size: usize,
+    input: Vec<f32>,
+    reference: Vec<f32>,
+    has_reference: bool, // Whether the blocks in the current frame came with a reference
+}
+
+impl Reblocker {
+    // Frames of length ms at the given rate
+    pub fn new(length: u32, sample_rate: usize) -> Self {
+        let size = (sample_rate * length as usize / 1000).max(1);
+
+        Self {
+            size,
+            input: Vec::with_capacity(size * 2),
+            reference: Vec::with_capacity(size * 2),
+            has_reference: false,
+        }
+    }
+
+    // Samples per frame
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Add a block, taking whatever frames it completes with next_frame
+    pub fn push(&mut self, input: &[f32], reference: Option<&[f32]>) {
+        // A frame can't be partly aligned, so a reference only counts if every block had one
+        match reference {
+            Some(reference) if self.input.is_empty() || self.has_reference => {
+                self.has_reference = true;
+                self.reference.extend_from_slice(reference);
+                self.reference.resize(self.input.len() + input.len(), 0.0);
+            }
+            _ => {
+                self.has_reference = false;
+                self.reference.clear();
+            }
+        }
+        self.input.extend_from_slice(input);
+    }
+
+    // Take the next full frame and its reference, None until enough has been pushed
+    pub fn next_frame(&mut self) -> Option<(Vec<f32>, Option<Vec<f32>>)> {
+        if self.input.len() < self.size {
+            return None;
+        }
+
+        let frame = self.input.drain(..self.size).collect();
+        let reference = self
+            .has_reference
+            .then(|| self.reference.drain(..self.size).collect());
+        if self.input.is_empty() {
+            self.has_reference = false;
+        }
+
+        Some((frame, reference))
+    }
+
+    // Take the samples which don't fill a frame yet, e.g. as no more are coming
+    pub fn take_partial(&mut self) -> Vec<f32> {
+        self.reference.clear();
+        self.has_reference = false;
+        std::mem::take(&mut self.input)
+    }
+
+    // Drop a partial frame, e.g. when the input is interrupted
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.reference.clear();
+        self.has_reference = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Resampler;
+
+    const BLOCK_SIZES: [usize; 6] = [64, 128, 256, 512, 1024, 2048];
+    const SAMPLE_RATES: [usize; 2] = [44100, 48000];
+
+    // Push a second and a bit of numbered samples in blocks, the reference being their negation
+    // Returns the frames and their references, then what was left over
+    fn reblock(
+        length: u32,
+        sample_rate: usize,
+        block_size: usize,
+    ) -> (Vec<(Vec<f32>, Option<Vec<f32>>)>, Vec<f32>, usize) {
+        let total = sample_rate + block_size / 2 + 7;
+        let samples = (0..total).map(|i| i as f32).collect::<Vec<_>>();
+        let reference = samples.iter().map(|sample| -sample).collect::<Vec<_>>();
+
+        let mut reblocker = Reblocker::new(length, sample_rate);
+        let mut frames = vec![];
+        for (block, reference) in samples.chunks(block_size).zip(reference.chunks(block_size)) {
+            reblocker.push(block, Some(reference));
+            while let Some(frame) = reblocker.next_frame() {
+                frames.push(frame);
+            }
+        }
+
+        (frames, reblocker.take_partial(), total)
+    }
+
+    #[test]
+    fn every_sample_comes_out_once() {
+        for sample_rate in SAMPLE_RATES {
+            for length in FRAME_LENGTHS {
+                for block_size in BLOCK_SIZES {
+                    let (frames, partial, total) = reblock(length, sample_rate, block_size);
+                    let size = sample_rate * length as usize / 1000;
+
+                    let out = frames
+                        .iter()
+                        .flat_map(|(frame, _)| frame)
+                        .chain(&partial)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let expected = (0..total).map(|i| i as f32).collect::<Vec<_>>();
+                    assert_eq!(
+                        out, expected,
+                        "{} ms frames of {} sample blocks at {} Hz",
+                        length, block_size, sample_rate
+                    );
+
+                    assert!(frames.iter().all(|(frame, _)| frame.len() == size));
+                    assert!(partial.len() < size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reference_stays_aligned() {
+        for sample_rate in SAMPLE_RATES {
+            for length in FRAME_LENGTHS {
+                for block_size in BLOCK_SIZES {
+                    let (frames, _, _) = reblock(length, sample_rate, block_size);
+
+                    for (frame, reference) in frames {
+                        let reference = reference.expect("every block had a reference");
+                        let negated = frame.iter().map(|sample| -sample).collect::<Vec<_>>();
+                        assert_eq!(
+                            reference, negated,
+                            "{} ms frames of {} sample blocks at {} Hz",
+                            length, block_size, sample_rate
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frame_missing_a_reference_has_none() {
+        let mut reblocker = Reblocker::new(20, 48000);
+        let block = vec![0.5; 512];
+        reblocker.push(&block, Some(&block));
+        reblocker.push(&block, None);
+
+        let (frame, reference) = reblocker.next_frame().expect("a full frame was pushed");
+        assert_eq!(frame.len(), 960);
+        assert!(reference.is_none());
+    }
+
+    // Frames are resampled for the VAD and taken one for each frame of the input, as the
+    // pipeline does, so the copy mustn't fall further behind over time
+    #[test]
+    fn vad_copy_keeps_pace() {
+        let vad_rate = 16000;
+        for sample_rate in [22050, 44100] {
+            for block_size in BLOCK_SIZES {
+                let mut reblocker = Reblocker::new(DEFAULT_FRAME_LENGTH, sample_rate);
+                let mut vad_frames = Reblocker::new(DEFAULT_FRAME_LENGTH, vad_rate);
+                let mut resampler =
+                    Resampler::new(sample_rate, vad_rate).expect("resampler for the VAD");
+
+                let block = vec![0.0; block_size];
+                let mut frames = 0;
+                let mut vad_taken = 0;
+                // A minute of audio
+                for _ in 0..sample_rate * 60 / block_size {
+                    reblocker.push(&block, None);
+                    while let Some((frame, _)) = reblocker.next_frame() {
+                        frames += 1;
+                        let resampled = resampler.process(&frame, false).expect("resampled");
+                        vad_frames.push(&resampled, None);
+                        if vad_frames.next_frame().is_some() {
+                            vad_taken += 1;
+                        }
+                    }
+                }
+
+                assert!(
+                    frames - vad_taken <= 2,
+                    "VAD is {} frames behind at {} Hz in {} sample blocks",
+                    frames - vad_taken,
+                    sample_rate,
+                    block_size
+                );
+                assert!(vad_frames.input.len() < vad_frames.size() * 2);
+            }
+        }
+    }
+}
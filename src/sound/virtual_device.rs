@@ -0,0 +1,169 @@
+use std::{fmt::Display, process::Command};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct VirtualMicConfig {
+    #[serde(default = "default_name")]
+    pub name: String, // Name of the sink the voice is played into, its ports are found by it
+    #[serde(default = "default_description")]
+    pub description: String, // Name other apps show for the microphone
+}
+
+fn default_name() -> String {
+    "live_translate".to_owned()
+}
+
+fn default_description() -> String {
+    "Live Translate Mic".to_owned()
+}
+
+// Why pactl couldn't be run, or what it printed when it failed
+#[derive(Debug)]
+pub enum ErrPactl {
+    IoError(std::io::Error),
+    Failed(String), // What pactl printed
+}
+
+impl Display for ErrPactl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::Failed(message) => write!(f, "pactl failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ErrPactl {}
+
+#[derive(Debug)]
+pub enum ErrVirtualMic {
+    IoError(std::io::Error),
+    CommandFailed(String),
+}
+
+impl Display for ErrVirtualMic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::CommandFailed(message) => write!(f, "pactl failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ErrVirtualMic {}
+
+impl From<std::io::Error> for ErrVirtualMic {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<ErrPactl> for ErrVirtualMic {
+    fn from(value: ErrPactl) -> Self {
+        match value {
+            ErrPactl::IoError(error) => Self::IoError(error),
+            ErrPactl::Failed(message) => Self::CommandFailed(message),
+        }
+    }
+}
+
+// Run pactl, returning what it printed
+// Also used by the pulseaudio client, which maps the error to its own
+pub fn pactl(args: &[&str]) -> Result<String, ErrPactl> {
+    let output = Command::new("pactl")
+        .args(args)
+        .output()
+        .map_err(ErrPactl::IoError)?;
+    if !output.status.success() {
+        return Err(ErrPactl::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+// Microphone other apps can record the voice from, e.g. Discord or Zoom
+// A null sink takes the output, and a source remapped from its monitor shows up as the microphone
+// Made with pactl, so it needs PipeWire for the sink's ports to be reachable through jack
+pub struct VirtualMic {
+    modules: Vec<String>, // Loaded modules, unloaded in reverse
+}
+
+impl VirtualMic {
+    pub fn create(config: &VirtualMicConfig, channels: usize) -> Result<Self, ErrVirtualMic> {
+        // Modules left behind by a crash would make a second device with the same name
+        remove_stale(&config.name);
+
+        let mut virtual_mic = Self { modules: vec![] };
+
+        // The sink is described by its name, which is what jack names its ports after
+        virtual_mic.modules.push(pactl(&[
+            "load-module",
+            "module-null-sink",
+            &format!("sink_name={}", config.name),
+            &format!("sink_properties=device.description={}", config.name),
+            &format!("channels={}", channels),
+        ])?);
+        virtual_mic.modules.push(pactl(&[
+            "load-module",
+            "module-remap-source",
+            &format!("master={}.monitor", config.name),
+            &format!("source_name={}_mic", config.name),
+            &format!(
+                "source_properties='device.description=\"{}\"'",
+                config.description
+            ),
+        ])?);
+        info!("Created virtual microphone \"{}\"", config.description);
+
+        Ok(virtual_mic)
+    }
+
+    // Unload the modules, taking the microphone away from apps using it
+    pub fn remove(&mut self) {
+        for module in self.modules.drain(..).rev() {
+            if let Err(err) = pactl(&["unload-module", &module]) {
+                error!("Could not remove virtual microphone!\n{}", err);
+            }
+        }
+    }
+}
+
+impl Drop for VirtualMic {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+// Unload modules from an earlier run making a device with this name
+fn remove_stale(name: &str) {
+    let modules = match pactl(&["list", "short", "modules"]) {
+        Ok(modules) => modules,
+        Err(err) => {
+            warn!("Could not list audio modules!\n{}", err);
+            return;
+        }
+    };
+
+    let sink = format!("sink_name={}", name);
+    let source = format!("master={}.monitor", name);
+    let stale = modules
+        .lines()
+        .filter(|line| {
+            line.split_whitespace()
+                .any(|arg| arg == sink || arg == source)
+        })
+        .filter_map(|line| line.split_whitespace().next())
+        .collect::<Vec<_>>();
+
+    // The source goes first, as it depends on the sink
+    for module in stale.into_iter().rev() {
+        warn!("Removing virtual microphone left from an earlier run");
+        if let Err(err) = pactl(&["unload-module", module]) {
+            error!("Could not remove old virtual microphone!\n{}", err);
+        }
+    }
+}
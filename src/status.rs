@@ -0,0 +1,127 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{events::EventBus, metrics::Metrics, util::lock, utterance::Utterance};
+
+// An utterance kept in the history, with how long it took to output
+#[derive(Serialize, Clone, Debug)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub utterance: Utterance,
+    pub latency: u64, // Milliseconds from the end of speech to output
+}
+
+// Live state of a pipeline, written by the processing thread and shown by the TUI
+#[derive(Debug, Default)]
+pub struct Status {
+    level: AtomicU32,  // Input level of the last block in dBFS, stored as f32 bits
+    voice: AtomicBool, // Whether the last block was voice
+    frame: AtomicU32,  // Samples processed at a time, independent of the audio server's period
+    period: AtomicU32, // Frames the audio server processes at a time, 0 until it tells
+    recording: AtomicBool,
+    latency: AtomicU64, // Milliseconds from the end of speech to output of the last utterance
+    last: Mutex<Option<Utterance>>,
+    history: Mutex<VecDeque<HistoryEntry>>, // Recent utterances, for clients which connect late
+    history_size: usize,
+    pub metrics: Metrics, // Counters exported for monitoring
+    pub events: EventBus, // What happens to each utterance, for the sinks and the control API
+}
+
+impl Status {
+    pub fn with_history(history_size: usize) -> Self {
+        Self {
+            history_size,
+            ..Default::default()
+        }
+    }
+
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    pub fn set_level(&self, level: f32) {
+        self.level.store(level.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn voice(&self) -> bool {
+        self.voice.load(Ordering::Relaxed)
+    }
+
+    pub fn set_voice(&self, voice: bool) {
+        self.voice.store(voice, Ordering::Relaxed);
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame.load(Ordering::Relaxed)
+    }
+
+    pub fn set_frame(&self, frame: usize) {
+        self.frame.store(frame as u32, Ordering::Relaxed);
+    }
+
+    pub fn period(&self) -> u32 {
+        self.period.load(Ordering::Relaxed)
+    }
+
+    pub fn set_period(&self, period: usize) {
+        self.period.store(period as u32, Ordering::Relaxed);
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Relaxed);
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency.load(Ordering::Relaxed))
+    }
+
+    // Record a finished utterance and how long it took to output
+    pub fn set_last(&self, utterance: &Utterance, latency: Duration) {
+        self.latency
+            .store(latency.as_millis() as u64, Ordering::Relaxed);
+        *lock(&self.last) = Some(utterance.clone());
+
+        if self.history_size > 0 {
+            let mut history = lock(&self.history);
+            history.push_back(HistoryEntry {
+                utterance: utterance.clone(),
+                latency: latency.as_millis() as u64,
+            });
+            while history.len() > self.history_size {
+                history.pop_front();
+            }
+        }
+    }
+
+    // Replace an utterance in the history, e.g. after translating it again
+    pub fn update_history(&self, utterance: &Utterance) {
+        let mut history = lock(&self.history);
+        if let Some(entry) = history
+            .iter_mut()
+            .find(|entry| entry.utterance.id == utterance.id)
+        {
+            entry.utterance = utterance.clone();
+        }
+    }
+
+    // Recent utterances, oldest first
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        lock(&self.history).iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<Utterance> {
+        lock(&self.last).clone()
+    }
+}
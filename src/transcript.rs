@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::utterance::Utterance;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TranscriptConfig {
+    #[serde(default = "default_directory")]
+    pub directory: String, // Directory session files are written into
+    #[serde(default)]
+    pub format: TranscriptFormat,
+    pub max_size: Option<u64>, // Bytes after which a new file is started
+    #[serde(default)]
+    pub daily: bool, // Start a new file when the date changes, in UTC
+}
+
+fn default_directory() -> String {
+    "transcripts".to_owned()
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TranscriptFormat {
+    #[default]
+    Jsonl, // One json object per line
+    Csv,
+}
+
+impl TranscriptFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+// One line of the transcript
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: u64, // Milliseconds since the unix epoch
+    id: u64,
+    language: Option<&'a str>, // Language spoken
+    text: &'a str,
+    translation: Option<&'a str>,
+    output_language: Option<&'a str>,
+    tts_duration: f32, // Seconds of speech queued for the utterance
+    latency: u64,      // Milliseconds from the end of speech until it was output
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speaker: Option<usize>,
+}
+
+const CSV_HEADER: &str =
+    "timestamp,id,language,text,translation,output_language,tts_duration,latency,tags,speaker";
+
+// Quote a field for CSV, doubling any quotes inside it
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+impl Entry<'_> {
+    fn to_csv(&self) -> String {
+        [
+            self.timestamp.to_string(),
+            self.id.to_string(),
+            self.language.unwrap_or_default().to_owned(),
+            csv_field(self.text),
+            csv_field(self.translation.unwrap_or_default()),
+            self.output_language.unwrap_or_default().to_owned(),
+            format!("{:.2}", self.tts_duration),
+            self.latency.to_string(),
+            csv_field(&serde_json::to_string(self.tags).unwrap_or_default()),
+            self.speaker
+                .map(|speaker| speaker.to_string())
+                .unwrap_or_default(),
+        ]
+        .join(",")
+    }
+}
+
+// Date in UTC from milliseconds since the unix epoch, as YYYY-MM-DD
+pub fn utc_date(timestamp: u64) -> String {
+    // Days to civil date, counting in 400 year eras starting in march
+    let days = (timestamp / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Write every utterance of a session to files for review afterwards
+pub struct Transcript {
+    config: TranscriptConfig,
+    pipeline: String,
+    session: u64, // Seconds since the unix epoch when the session started
+    file: Option<File>,
+    date: String, // Date the current file was started on
+    part: usize,  // Files started because the previous one got too big
+    size: u64,    // Bytes written to the current file
+}
+
+impl Transcript {
+    pub fn new(config: &TranscriptConfig, pipeline: &str) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&config.directory)?;
+
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Ok(Self {
+            config: config.clone(),
+            pipeline: pipeline.to_owned(),
+            session,
+            file: None,
+            date: utc_date(session * 1000),
+            part: 0,
+            size: 0,
+        })
+    }
+
+    fn path(&self) -> PathBuf {
+        let part = match self.part {
+            0 => String::new(),
+            part => format!("_{}", part),
+        };
+
+        PathBuf::from(&self.config.directory).join(format!(
+            "{}_{}_{}{}.{}",
+            self.date,
+            self.session,
+            self.pipeline,
+            part,
+            self.config.format.extension()
+        ))
+    }
+
+    // Start a new file if the date changed or the current one is full
+    fn rotate(&mut self, timestamp: u64) {
+        let date = utc_date(timestamp);
+        if self.config.daily && date != self.date {
+            self.date = date;
+            self.part = 0;
+            self.file = None;
+        } else if self
+            .config
+            .max_size
+            .is_some_and(|max_size| self.size >= max_size)
+        {
+            self.part += 1;
+            self.file = None;
+        }
+    }
+
+    // Add an utterance, along with how much speech it queued and how long it took
+    pub fn write(
+        &mut self,
+        utterance: &Utterance,
+        tts_duration: f32,
+        latency: u64,
+    ) -> Result<(), std::io::Error> {
+        self.rotate(utterance.timestamp);
+
+        if self.file.is_none() {
+            let path = self.path();
+            info!("Writing transcript to {}", path.display());
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.size = file.metadata()?.len();
+            if self.config.format == TranscriptFormat::Csv && self.size == 0 {
+                writeln!(file, "{}", CSV_HEADER)?;
+                self.size += CSV_HEADER.len() as u64 + 1;
+            }
+            self.file = Some(file);
+        }
+
+        let entry = Entry {
+            timestamp: utterance.timestamp,
+            id: utterance.id,
+            language: utterance.language.as_deref(),
+            text: utterance.text.trim(),
+            translation: utterance.translation.as_deref().map(str::trim),
+            output_language: utterance.output_language.as_deref(),
+            tts_duration,
+            latency,
+            tags: &utterance.tags,
+            speaker: utterance.speaker,
+        };
+        let line = match self.config.format {
+            TranscriptFormat::Jsonl => serde_json::to_string(&entry)?,
+            TranscriptFormat::Csv => entry.to_csv(),
+        };
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+        }
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+}
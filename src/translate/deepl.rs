@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+use crate::translate::{ErrTranslate, Translator};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DeepLConfig {
+    pub api_key: String,
+    #[serde(default = "default_url")]
+    pub url: String, // Use https://api.deepl.com for pro accounts
+}
+
+fn default_url() -> String {
+    "https://api-free.deepl.com".to_owned()
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    text: [&'a str; 1],
+    target_lang: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_lang: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Translation {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    translations: Vec<Translation>,
+}
+
+// Translate using the DeepL API
+pub struct DeepLTranslator {
+    config: DeepLConfig,
+    http_client: reqwest::blocking::Client,
+}
+
+impl DeepLTranslator {
+    pub fn new(config: DeepLConfig, http_client: reqwest::blocking::Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+}
+
+impl Translator for DeepLTranslator {
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        let response: Response = self
+            .http_client
+            .post(format!(
+                "{}/v2/translate",
+                self.config.url.trim_end_matches('/')
+            ))
+            .header(
+                "Authorization",
+                format!("DeepL-Auth-Key {}", self.config.api_key),
+            )
+            .json(&Request {
+                text: [text],
+                // DeepL expects uppercase language codes
+                target_lang: target.to_uppercase(),
+                source_lang: source.map(|source| source.to_uppercase()),
+            })
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        response
+            .translations
+            .into_iter()
+            .next()
+            .map(|translation| translation.text)
+            .ok_or(ErrTranslate::EmptyResponse)
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+use crate::translate::{ErrTranslate, Translator};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct LibreTranslateConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+fn default_url() -> String {
+    // Piper already uses port 5000
+    "http://localhost:5001".to_owned()
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    q: &'a str,
+    source: &'a str,
+    target: &'a str,
+    format: &'a str,
+    api_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(rename = "translatedText")]
+    translated_text: String,
+}
+
+// Translate using a LibreTranslate server
+pub struct LibreTranslateTranslator {
+    config: LibreTranslateConfig,
+    http_client: reqwest::blocking::Client,
+}
+
+impl LibreTranslateTranslator {
+    pub fn new(config: LibreTranslateConfig, http_client: reqwest::blocking::Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+}
+
+impl Translator for LibreTranslateTranslator {
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        let response: Response = self
+            .http_client
+            .post(format!(
+                "{}/translate",
+                self.config.url.trim_end_matches('/')
+            ))
+            .json(&Request {
+                q: text,
+                source: source.unwrap_or("auto"),
+                target,
+                format: "text",
+                api_key: self.config.api_key.as_deref(),
+            })
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        Ok(response.translated_text)
+    }
+}
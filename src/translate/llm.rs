@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+use crate::translate::{ErrTranslate, Translator};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct LlmConfig {
+    #[serde(default = "default_url")]
+    pub url: String, // Any OpenAI compatible API, e.g. ollama or llama.cpp
+    pub model: String,
+    pub api_key: Option<String>,
+    #[serde(default = "default_prompt")]
+    pub prompt: String, // Instruction for translating, {source} and {target} are replaced
+}
+
+pub fn default_url() -> String {
+    "http://localhost:11434/v1".to_owned()
+}
+
+fn default_prompt() -> String {
+    "Translate the following transcript of live speech from {source} into {target}. \
+     Reply with only the translation."
+        .to_owned()
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    messages: [Message<'a>; 2],
+    temperature: f32,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    choices: Vec<Choice>,
+}
+
+// Fill in the languages of a prompt
+pub fn fill_prompt(prompt: &str, source: Option<&str>, target: &str) -> String {
+    prompt
+        .replace("{source}", source.unwrap_or("the language spoken"))
+        .replace("{target}", target)
+}
+
+// Client for an OpenAI compatible chat completions API
+pub struct ChatClient {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+    http_client: reqwest::blocking::Client,
+}
+
+impl ChatClient {
+    pub fn new(
+        url: &str,
+        model: &str,
+        api_key: Option<&str>,
+        http_client: reqwest::blocking::Client,
+    ) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            model: model.to_owned(),
+            api_key: api_key.map(str::to_owned),
+            http_client,
+        }
+    }
+
+    // Send an instruction and the text it applies to
+    pub fn complete(&self, instruction: &str, text: &str) -> Result<String, ErrTranslate> {
+        let mut request = self
+            .http_client
+            .post(format!("{}/chat/completions", self.url))
+            .json(&Request {
+                model: &self.model,
+                messages: [
+                    Message {
+                        role: "system",
+                        content: instruction,
+                    },
+                    Message {
+                        role: "user",
+                        content: text,
+                    },
+                ],
+                temperature: 0.0,
+            });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response: Response = request.send()?.error_for_status()?.json()?;
+
+        response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content.trim().to_owned())
+            .filter(|content| !content.is_empty())
+            .ok_or(ErrTranslate::EmptyResponse)
+    }
+}
+
+// Translate by instructing a language model through a chat completions API
+pub struct LlmTranslator {
+    config: LlmConfig,
+    chat: ChatClient,
+}
+
+impl LlmTranslator {
+    pub fn new(config: LlmConfig, http_client: reqwest::blocking::Client) -> Self {
+        Self {
+            chat: ChatClient::new(
+                &config.url,
+                &config.model,
+                config.api_key.as_deref(),
+                http_client,
+            ),
+            config,
+        }
+    }
+}
+
+impl Translator for LlmTranslator {
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        self.chat
+            .complete(&fill_prompt(&self.config.prompt, source, target), text)
+    }
+
+    fn summarize(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+        prompt: &str,
+    ) -> Result<Option<String>, ErrTranslate> {
+        self.chat
+            .complete(&fill_prompt(prompt, source, target), text)
+            .map(Some)
+    }
+}
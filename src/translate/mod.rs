@@ -0,0 +1,139 @@
+use std::{fmt::Display, time::Duration};
+
+use serde::Deserialize;
+
+use crate::translate::{
+    deepl::{DeepLConfig, DeepLTranslator},
+    libretranslate::{LibreTranslateConfig, LibreTranslateTranslator},
+    llm::{LlmConfig, LlmTranslator},
+    post_edit::{PostEditConfig, PostEditor},
+};
+
+pub mod deepl;
+pub mod libretranslate;
+pub mod llm;
+#[cfg(feature = "nllb")]
+pub mod nllb;
+pub mod post_edit;
+
+#[derive(Debug)]
+pub enum ErrTranslate {
+    ReqwestError(reqwest::Error),
+    EmptyResponse,
+    #[cfg(feature = "nllb")]
+    NllbError(String),
+    #[cfg(feature = "nllb")]
+    UnknownLanguage(String), // Code the engine has no language for
+}
+
+impl Display for ErrTranslate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReqwestError(error) => write!(f, "{}", error),
+            Self::EmptyResponse => write!(f, "Translation service returned no translation"),
+            #[cfg(feature = "nllb")]
+            Self::NllbError(error) => write!(f, "{}", error),
+            #[cfg(feature = "nllb")]
+            Self::UnknownLanguage(code) => write!(f, "No NLLB language for code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for ErrTranslate {}
+
+impl From<reqwest::Error> for ErrTranslate {
+    fn from(value: reqwest::Error) -> Self {
+        Self::ReqwestError(value)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "engine")]
+pub enum EngineConfig {
+    LibreTranslate(LibreTranslateConfig),
+    DeepL(DeepLConfig),
+    Llm(LlmConfig),
+    #[cfg(feature = "nllb")]
+    Nllb(nllb::NllbConfig),
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TranslateConfig {
+    pub target: String,         // Language to translate into
+    pub source: Option<String>, // Language to translate from, detected if not set
+    #[serde(default)]
+    pub retranslate: usize, // Recent utterances to translate again for captions when the target changes
+    pub summary: Option<SummaryConfig>, // Condense speech when TTS falls behind
+    pub post_edit: Option<PostEditConfig>, // Polish translations with a language model
+    #[serde(default = "default_timeout")]
+    pub timeout: f32, // Seconds to wait for a translation before the utterance goes out untranslated
+    #[serde(flatten)]
+    pub engine: EngineConfig,
+}
+
+// Speak a summary of what was said while the TTS backlog is too long
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SummaryConfig {
+    #[serde(default = "default_backlog")]
+    pub backlog: f32, // Seconds of queued speech which start batching utterances
+    #[serde(default = "default_summary_prompt")]
+    pub prompt: String, // Instruction for engines which can summarize, {source} and {target} are replaced
+}
+
+fn default_timeout() -> f32 {
+    30.0
+}
+
+fn default_backlog() -> f32 {
+    10.0
+}
+
+fn default_summary_prompt() -> String {
+    "Summarize the following transcript of live speech from {source} into {target}, \
+     keeping only the key points in as few words as possible. Reply with only the summary."
+        .to_owned()
+}
+
+pub trait Translator: Send {
+    // Translate text from the source language into the target language
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate>;
+
+    // Condense text while translating it, None if the engine can't follow instructions
+    fn summarize(
+        &mut self,
+        _text: &str,
+        _source: Option<&str>,
+        _target: &str,
+        _prompt: &str,
+    ) -> Result<Option<String>, ErrTranslate> {
+        Ok(None)
+    }
+}
+
+// Create the configured translation engine
+pub fn create_translator(config: &TranslateConfig) -> Result<Box<dyn Translator>, ErrTranslate> {
+    // One client per engine, so connections to it are reused
+    let http_client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs_f32(config.timeout.max(0.0)))
+        .build()?;
+
+    let translator: Box<dyn Translator> = match &config.engine {
+        EngineConfig::LibreTranslate(config) => {
+            Box::new(LibreTranslateTranslator::new(config.clone(), http_client))
+        }
+        EngineConfig::DeepL(config) => Box::new(DeepLTranslator::new(config.clone(), http_client)),
+        EngineConfig::Llm(config) => Box::new(LlmTranslator::new(config.clone(), http_client)),
+        #[cfg(feature = "nllb")]
+        EngineConfig::Nllb(config) => Box::new(nllb::NllbTranslator::new(config)?),
+    };
+
+    Ok(match &config.post_edit {
+        Some(post_edit) => Box::new(PostEditor::new(translator, post_edit.clone())?),
+        None => translator,
+    })
+}
@@ -0,0 +1,192 @@
+use ct2rs::{Config, Device, TranslationOptions};
+use serde::Deserialize;
+
+use crate::{
+    execution::{ExecutionConfig, Provider},
+    translate::{ErrTranslate, Translator},
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct NllbConfig {
+    pub model_path: String, // Directory of a ctranslate2 converted NLLB model
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+}
+
+// FLORES-200 codes for the ISO 639-1 codes whisper detects, where NLLB has the language
+const FLORES_CODES: &[(&str, &str)] = &[
+    ("af", "afr_Latn"),
+    ("am", "amh_Ethi"),
+    ("ar", "arb_Arab"),
+    ("as", "asm_Beng"),
+    ("az", "azj_Latn"),
+    ("ba", "bak_Cyrl"),
+    ("be", "bel_Cyrl"),
+    ("bg", "bul_Cyrl"),
+    ("bn", "ben_Beng"),
+    ("bo", "bod_Tibt"),
+    ("bs", "bos_Latn"),
+    ("ca", "cat_Latn"),
+    ("cs", "ces_Latn"),
+    ("cy", "cym_Latn"),
+    ("da", "dan_Latn"),
+    ("de", "deu_Latn"),
+    ("el", "ell_Grek"),
+    ("en", "eng_Latn"),
+    ("es", "spa_Latn"),
+    ("et", "est_Latn"),
+    ("eu", "eus_Latn"),
+    ("fa", "pes_Arab"),
+    ("fi", "fin_Latn"),
+    ("fo", "fao_Latn"),
+    ("fr", "fra_Latn"),
+    ("gl", "glg_Latn"),
+    ("gu", "guj_Gujr"),
+    ("ha", "hau_Latn"),
+    ("he", "heb_Hebr"),
+    ("hi", "hin_Deva"),
+    ("hr", "hrv_Latn"),
+    ("ht", "hat_Latn"),
+    ("hu", "hun_Latn"),
+    ("hy", "hye_Armn"),
+    ("id", "ind_Latn"),
+    ("is", "isl_Latn"),
+    ("it", "ita_Latn"),
+    ("ja", "jpn_Jpan"),
+    ("jv", "jav_Latn"),
+    ("jw", "jav_Latn"), // Whisper's code for Javanese
+    ("ka", "kat_Geor"),
+    ("kk", "kaz_Cyrl"),
+    ("km", "khm_Khmr"),
+    ("kn", "kan_Knda"),
+    ("ko", "kor_Hang"),
+    ("lb", "ltz_Latn"),
+    ("ln", "lin_Latn"),
+    ("lo", "lao_Laoo"),
+    ("lt", "lit_Latn"),
+    ("lv", "lvs_Latn"),
+    ("mg", "plt_Latn"),
+    ("mi", "mri_Latn"),
+    ("mk", "mkd_Cyrl"),
+    ("ml", "mal_Mlym"),
+    ("mn", "khk_Cyrl"),
+    ("mr", "mar_Deva"),
+    ("ms", "zsm_Latn"),
+    ("mt", "mlt_Latn"),
+    ("my", "mya_Mymr"),
+    ("ne", "npi_Deva"),
+    ("nl", "nld_Latn"),
+    ("nn", "nno_Latn"),
+    ("no", "nob_Latn"),
+    ("oc", "oci_Latn"),
+    ("pa", "pan_Guru"),
+    ("pl", "pol_Latn"),
+    ("ps", "pbt_Arab"),
+    ("pt", "por_Latn"),
+    ("ro", "ron_Latn"),
+    ("ru", "rus_Cyrl"),
+    ("sa", "san_Deva"),
+    ("sd", "snd_Arab"),
+    ("si", "sin_Sinh"),
+    ("sk", "slk_Latn"),
+    ("sl", "slv_Latn"),
+    ("sn", "sna_Latn"),
+    ("so", "som_Latn"),
+    ("sq", "als_Latn"),
+    ("sr", "srp_Cyrl"),
+    ("su", "sun_Latn"),
+    ("sv", "swe_Latn"),
+    ("sw", "swh_Latn"),
+    ("ta", "tam_Taml"),
+    ("te", "tel_Telu"),
+    ("tg", "tgk_Cyrl"),
+    ("th", "tha_Thai"),
+    ("tk", "tuk_Latn"),
+    ("tl", "tgl_Latn"),
+    ("tr", "tur_Latn"),
+    ("tt", "tat_Cyrl"),
+    ("uk", "ukr_Cyrl"),
+    ("ur", "urd_Arab"),
+    ("uz", "uzn_Latn"),
+    ("vi", "vie_Latn"),
+    ("yi", "ydd_Hebr"),
+    ("yo", "yor_Latn"),
+    ("yue", "yue_Hant"),
+    ("zh", "zho_Hans"),
+];
+
+// FLORES-200 code for a language, codes already in that form are passed through
+fn flores_code(code: &str) -> Result<&str, ErrTranslate> {
+    if code.contains('_') {
+        return Ok(code);
+    }
+
+    FLORES_CODES
+        .iter()
+        .find(|(iso, _)| code.eq_ignore_ascii_case(iso))
+        .map(|(_, flores)| *flores)
+        .ok_or_else(|| ErrTranslate::UnknownLanguage(code.to_owned()))
+}
+
+// Translate locally with an NLLB model through ctranslate2
+// Languages are ISO 639-1 codes like whisper's, or FLORES-200 codes, e.g. "jpn_Jpan"
+pub struct NllbTranslator {
+    translator: ct2rs::Translator<ct2rs::tokenizers::auto::Tokenizer>,
+}
+
+impl NllbTranslator {
+    pub fn new(config: &NllbConfig) -> Result<Self, ErrTranslate> {
+        let provider =
+            config
+                .execution
+                .select("NLLB", &[Provider::Cpu, Provider::Cuda], Provider::Cpu);
+
+        let mut ct2_config = Config {
+            device: match provider {
+                Provider::Cuda => Device::CUDA,
+                _ => Device::CPU,
+            },
+            ..Config::default()
+        };
+        if let Some(threads) = config.execution.threads {
+            ct2_config.num_threads_per_replica = threads;
+        }
+
+        let translator = ct2rs::Translator::new(&config.model_path, &ct2_config)
+            .map_err(|err| ErrTranslate::NllbError(err.to_string()))?;
+
+        Ok(Self { translator })
+    }
+}
+
+impl Translator for NllbTranslator {
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        // NLLB reads the language of the text from a token in front of it
+        let text = match source {
+            Some(source) => format!("{} {}", flores_code(source)?, text),
+            None => text.to_owned(),
+        };
+        let target = flores_code(target)?;
+
+        let results = self
+            .translator
+            .translate_batch_with_target_prefix(
+                &[text],
+                &[vec![target]],
+                &TranslationOptions::default(),
+                None,
+            )
+            .map_err(|err| ErrTranslate::NllbError(err.to_string()))?;
+
+        results
+            .into_iter()
+            .next()
+            .map(|(translation, _)| translation)
+            .ok_or(ErrTranslate::EmptyResponse)
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, time::Duration};
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::translate::{
+    ErrTranslate, Translator,
+    llm::{self, ChatClient, fill_prompt},
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PostEditConfig {
+    #[serde(default = "llm::default_url")]
+    pub url: String, // Any OpenAI compatible API, e.g. ollama or llama.cpp
+    pub model: String,
+    pub api_key: Option<String>,
+    #[serde(default = "default_prompt")]
+    pub prompt: String, // Instruction for editing, {source} and {target} are replaced
+    #[serde(default = "default_timeout")]
+    pub timeout: f32, // Seconds to wait before using the unedited translation
+    #[serde(default = "default_context")]
+    pub context: usize, // Previous translations sent along for consistency
+}
+
+fn default_prompt() -> String {
+    "You are given a machine translation of live speech from {source} into {target}, \
+     together with the original and the sentences before it. Rewrite the translation \
+     to read fluently and naturally in a formal register without changing its meaning. \
+     Reply with only the edited translation."
+        .to_owned()
+}
+
+fn default_timeout() -> f32 {
+    2.0
+}
+
+fn default_context() -> usize {
+    3
+}
+
+// Translate with another engine, then have a language model polish the result
+pub struct PostEditor {
+    translator: Box<dyn Translator>,
+    config: PostEditConfig,
+    chat: ChatClient,
+    context: VecDeque<String>, // Recent edited translations
+}
+
+impl PostEditor {
+    pub fn new(
+        translator: Box<dyn Translator>,
+        config: PostEditConfig,
+    ) -> Result<Self, ErrTranslate> {
+        let http_client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs_f32(config.timeout.max(0.0)))
+            .build()?;
+
+        Ok(Self {
+            translator,
+            chat: ChatClient::new(
+                &config.url,
+                &config.model,
+                config.api_key.as_deref(),
+                http_client,
+            ),
+            config,
+            context: VecDeque::new(),
+        })
+    }
+
+    fn edit(
+        &self,
+        text: &str,
+        translation: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        let mut message = String::new();
+        if !self.context.is_empty() {
+            message.push_str("Previous sentences:\n");
+            for previous in &self.context {
+                message.push_str(previous);
+                message.push('\n');
+            }
+            message.push('\n');
+        }
+        message.push_str(&format!(
+            "Original:\n{}\n\nTranslation:\n{}",
+            text, translation
+        ));
+
+        self.chat
+            .complete(&fill_prompt(&self.config.prompt, source, target), &message)
+    }
+}
+
+impl Translator for PostEditor {
+    fn translate(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<String, ErrTranslate> {
+        let translation = self.translator.translate(text, source, target)?;
+
+        // The raw translation is still usable if editing fails or takes too long
+        let translation = match self.edit(text, &translation, source, target) {
+            Ok(edited) => edited,
+            Err(err) => {
+                warn!(
+                    "Could not post-edit translation, using it unedited!\n{}",
+                    err
+                );
+                translation
+            }
+        };
+
+        if self.config.context > 0 {
+            self.context.push_back(translation.clone());
+            while self.context.len() > self.config.context {
+                self.context.pop_front();
+            }
+        }
+
+        Ok(translation)
+    }
+
+    fn summarize(
+        &mut self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+        prompt: &str,
+    ) -> Result<Option<String>, ErrTranslate> {
+        self.translator.summarize(text, source, target, prompt)
+    }
+}
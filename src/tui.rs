@@ -0,0 +1,346 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use log::info;
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, LineGauge, Paragraph},
+};
+
+use live_translate_rs::{controls::Controls, pipeline::Pipeline, util::lock};
+
+// How long to wait for a key before redrawing
+const TICK: Duration = Duration::from_millis(50);
+
+// Log lines kept for display
+const MAX_LOG_LINES: usize = 200;
+
+// Lowest level shown on the meters
+const METER_FLOOR: f32 = -60.0;
+
+// Log output, held while the TUI is shown and written to stderr otherwise
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capturing: Arc<AtomicBool>,
+}
+
+impl LogBuffer {
+    fn lines(&self) -> Vec<String> {
+        lock(&self.lines).iter().cloned().collect()
+    }
+
+    // Stop holding logs and print everything held so far
+    fn release(&self) {
+        self.capturing.store(false, Ordering::SeqCst);
+
+        let mut stderr = std::io::stderr();
+        for line in lock(&self.lines).drain(..) {
+            let _ = writeln!(stderr, "{}", line);
+        }
+    }
+}
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.capturing.load(Ordering::SeqCst) {
+            return std::io::stderr().write(buf);
+        }
+
+        let mut lines = lock(&self.lines);
+        for line in String::from_utf8_lossy(buf).lines() {
+            lines.push_back(line.to_owned());
+        }
+        while lines.len() > MAX_LOG_LINES {
+            lines.pop_front();
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Terminal interface showing pipeline state with keys for the runtime controls
+pub struct Tui {
+    terminal: DefaultTerminal,
+    logs: LogBuffer,
+    selected: usize,       // Pipeline that language changes apply to
+    input: Option<String>, // Target language being typed
+}
+
+impl Tui {
+    pub fn new(logs: LogBuffer) -> Result<Self, std::io::Error> {
+        let terminal = ratatui::try_init()?;
+        logs.capturing.store(true, Ordering::SeqCst);
+
+        Ok(Self {
+            terminal,
+            logs,
+            selected: 0,
+            input: None,
+        })
+    }
+
+    // Redraw, then wait a tick for a key and apply it
+    pub fn update(
+        &mut self,
+        pipelines: &[Pipeline],
+        controls: &Controls,
+        running: &AtomicBool,
+    ) -> Result<(), std::io::Error> {
+        let logs = self.logs.lines();
+        let (selected, input) = (self.selected, self.input.clone());
+        self.terminal.draw(|frame| {
+            draw(
+                frame,
+                pipelines,
+                controls,
+                &logs,
+                selected,
+                input.as_deref(),
+            )
+        })?;
+
+        if !event::poll(TICK)? {
+            return Ok(());
+        }
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            self.handle_key(key, pipelines, controls, running);
+        }
+
+        Ok(())
+    }
+
+    fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        pipelines: &[Pipeline],
+        controls: &Controls,
+        running: &AtomicBool,
+    ) {
+        // Raw mode swallows the interrupt signal
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            running.store(false, Ordering::SeqCst);
+            return;
+        }
+
+        // Typing a target language
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Enter => {
+                    let target = input.trim().to_owned();
+                    if let (false, Some(pipeline)) =
+                        (target.is_empty(), pipelines.get(self.selected))
+                    {
+                        pipeline.set_target(target);
+                    }
+                    self.input = None;
+                }
+                KeyCode::Esc => self.input = None,
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => running.store(false, Ordering::SeqCst),
+            KeyCode::Char('m') => {
+                if controls.toggle_mute() {
+                    info!("Input muted");
+                } else {
+                    info!("Input unmuted");
+                }
+            }
+            KeyCode::Char('p') => {
+                if controls.toggle_pause() {
+                    info!("Output paused");
+                } else {
+                    info!("Output resumed");
+                }
+            }
+            KeyCode::Char('t') => {
+                if controls.toggle_translation() {
+                    info!("Translation enabled");
+                } else {
+                    info!("Translation disabled");
+                }
+            }
+            KeyCode::Char('l') => self.input = Some(String::new()),
+            KeyCode::Tab => self.selected = (self.selected + 1) % pipelines.len().max(1),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+        self.logs.release();
+    }
+}
+
+fn draw(
+    frame: &mut Frame,
+    pipelines: &[Pipeline],
+    controls: &Controls,
+    logs: &[String],
+    selected: usize,
+    input: Option<&str>,
+) {
+    let mut constraints = vec![Constraint::Length(1)];
+    constraints.extend(pipelines.iter().map(|_| Constraint::Length(6)));
+    constraints.extend([Constraint::Min(3), Constraint::Length(1)]);
+    let areas = Layout::vertical(constraints).split(frame.area());
+
+    // Global controls
+    let flag = |on: bool, text: &'static str, color: Color| {
+        if on {
+            Span::styled(text, Style::new().fg(Color::Black).bg(color))
+        } else {
+            Span::styled(text, Style::new().fg(Color::DarkGray))
+        }
+    };
+    let header = Line::from(vec![
+        "live-translate-rs ".bold(),
+        flag(controls.muted(), " MUTED ", Color::Red),
+        " ".into(),
+        flag(controls.output_muted(), " VOICE MUTED ", Color::Red),
+        " ".into(),
+        flag(controls.paused(), " PAUSED ", Color::Yellow),
+        " ".into(),
+        flag(controls.translating(), " TRANSLATE ", Color::Green),
+    ]);
+    frame.render_widget(header, areas[0]);
+
+    for (i, pipeline) in pipelines.iter().enumerate() {
+        draw_pipeline(frame, pipeline, i == selected, areas[i + 1]);
+    }
+
+    // Most recent logs that fit
+    let log_area = areas[pipelines.len() + 1];
+    let shown = logs
+        .iter()
+        .skip(
+            logs.len()
+                .saturating_sub(log_area.height.saturating_sub(2) as usize),
+        )
+        .map(|line| Line::raw(line.as_str()))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(shown).block(Block::bordered().title("Log")),
+        log_area,
+    );
+
+    let footer = match input {
+        Some(input) => Line::from(vec!["Target language: ".bold(), input.into(), "_".into()]),
+        None => Line::raw(
+            "q quit  m mute  p pause  t translation  l target language  tab select pipeline",
+        )
+        .dark_gray(),
+    };
+    frame.render_widget(footer, areas[pipelines.len() + 2]);
+}
+
+fn draw_pipeline(frame: &mut Frame, pipeline: &Pipeline, selected: bool, area: Rect) {
+    let status = &pipeline.status;
+
+    let mut block = Block::bordered().title(pipeline.name.as_str());
+    if selected {
+        block = block.border_style(Style::new().add_modifier(Modifier::BOLD));
+    }
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [meter_area, state_area, text_area, output_area] =
+        Layout::vertical([Constraint::Length(1); 4]).areas(inner);
+
+    // Input level
+    let level = status.level();
+    frame.render_widget(
+        LineGauge::default()
+            .ratio(((level - METER_FLOOR) / -METER_FLOOR).clamp(0.0, 1.0) as f64)
+            .label(format!("{:>4.0} dB", level.max(METER_FLOOR)))
+            .filled_style(Style::new().fg(if status.voice() {
+                Color::Green
+            } else {
+                Color::DarkGray
+            })),
+        meter_area,
+    );
+
+    // Voice activity, recording and output state
+    let queued = pipeline.play_buffer.queued();
+    let state = Line::from(vec![
+        if status.voice() {
+            "Voice".green()
+        } else {
+            "Silence".dark_gray()
+        },
+        "  ".into(),
+        if status.recording() {
+            "Recording".red()
+        } else {
+            "Idle".dark_gray()
+        },
+        format!(
+            "  Queue {:.1}s  Latency {}ms",
+            queued,
+            status.latency().as_millis()
+        )
+        .into(),
+    ]);
+    frame.render_widget(state, state_area);
+
+    // Last utterance
+    let last = status.last();
+    let language = |language: Option<&String>| match language {
+        Some(language) => format!("[{}] ", language),
+        None => String::new(),
+    };
+    let (text, output) = match &last {
+        Some(utterance) => (
+            format!(
+                "{}{}",
+                language(utterance.language.as_ref()),
+                utterance.text.trim()
+            ),
+            match &utterance.translation {
+                Some(translation) => format!(
+                    "{}{}",
+                    language(utterance.output_language.as_ref()),
+                    translation.trim()
+                ),
+                None => String::new(),
+            },
+        ),
+        None => (String::new(), String::new()),
+    };
+    frame.render_widget(
+        Line::from(vec!["Heard      ".dark_gray(), text.into()]),
+        text_area,
+    );
+    frame.render_widget(
+        Line::from(vec!["Translated ".dark_gray(), output.into()]),
+        output_area,
+    );
+}
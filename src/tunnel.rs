@@ -0,0 +1,134 @@
+use std::{
+    process::{Child, Command},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::piper::run_command_with_log;
+
+// How often the ssh process is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Longest wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+fn default_remote_port() -> u16 {
+    5000
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RemoteConfig {
+    pub host: String, // ssh destination, e.g. user@gpu-box
+    #[serde(default = "default_remote_port")]
+    pub port: u16, // Port the server listens on at the remote end
+    #[serde(default)]
+    pub ssh_args: Vec<String>, // Extra arguments for ssh, e.g. ["-p", "2222"]
+}
+
+// SSH port forward to a remote server, reopened whenever it drops
+pub struct Tunnel {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Tunnel {
+    // Forward a local port to the remote server
+    pub fn open(config: &RemoteConfig, local_port: u16) -> Result<Self, std::io::Error> {
+        let running = Arc::new(AtomicBool::new(true));
+
+        let config = config.clone();
+        let running_cloned = running.clone();
+        let thread = thread::Builder::new()
+            .name("ssh_tunnel".to_owned())
+            .spawn(move || tunnel_loop(config, local_port, running_cloned))?;
+
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    // Close the tunnel
+    pub fn close(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            error!("Could not join ssh tunnel thread!");
+        }
+    }
+}
+
+fn spawn_ssh(config: &RemoteConfig, local_port: u16) -> Result<Child, std::io::Error> {
+    run_command_with_log(
+        Command::new("ssh")
+            .args(["-N", "-o", "ExitOnForwardFailure=yes"])
+            .args(["-o", "ServerAliveInterval=5", "-o", "ServerAliveCountMax=3"])
+            .arg("-L")
+            .arg(format!("{}:localhost:{}", local_port, config.port))
+            .args(&config.ssh_args)
+            .arg(&config.host),
+    )
+}
+
+// Keep ssh running until the tunnel is closed
+fn tunnel_loop(config: RemoteConfig, local_port: u16, running: Arc<AtomicBool>) {
+    let mut backoff = Duration::from_secs(1);
+
+    while running.load(Ordering::SeqCst) {
+        let mut ssh = match spawn_ssh(&config, local_port) {
+            Ok(ssh) => ssh,
+            Err(err) => {
+                error!("Could not start ssh!\n{}", err);
+                return;
+            }
+        };
+        info!(
+            "Forwarding localhost:{} to {}:{}",
+            local_port, config.host, config.port
+        );
+        let opened = Instant::now();
+
+        // Watch for the tunnel dropping
+        while running.load(Ordering::SeqCst) {
+            match ssh.try_wait() {
+                Ok(Some(status)) => {
+                    warn!("SSH tunnel to {} closed ({})", config.host, status);
+                    break;
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(err) => {
+                    error!("Could not check ssh tunnel!\n{}", err);
+                    break;
+                }
+            }
+        }
+
+        if !running.load(Ordering::SeqCst) {
+            if let Err(err) = ssh.kill() {
+                error!("Could not kill ssh tunnel!\n{}", err);
+            }
+            let _ = ssh.wait();
+            return;
+        }
+
+        // Back off while the connection keeps failing
+        if opened.elapsed() > MAX_BACKOFF {
+            backoff = Duration::from_secs(1);
+        }
+        warn!("Reconnecting in {}s", backoff.as_secs());
+        let retry = Instant::now();
+        while retry.elapsed() < backoff && running.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
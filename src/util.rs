@@ -1,3 +1,5 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
 pub fn resample(
     samples: Vec<f32>,
     from: usize,
@@ -15,3 +17,117 @@ pub fn resample(
 
     Ok(resampled)
 }
+
+// Resampler keeping its state between calls, for audio which arrives in pieces
+pub struct Resampler {
+    state: speexdsp_resampler::State,
+    from: usize,
+    to: usize,
+}
+
+impl Resampler {
+    pub fn new(from: usize, to: usize) -> Result<Self, speexdsp_resampler::Error> {
+        let mut state = speexdsp_resampler::State::new(1, from, to, 4)?;
+        // Leave out the silence the filter would start with
+        state.skip_zeros();
+
+        Ok(Self { state, from, to })
+    }
+
+    // Resample the next piece, flushing what the filter holds back if it's the last
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        last: bool,
+    ) -> Result<Vec<f32>, speexdsp_resampler::Error> {
+        let mut input = samples.to_vec();
+        if last {
+            input.extend(std::iter::repeat_n(0.0, self.state.get_input_latency()));
+        }
+
+        let mut output =
+            vec![
+                0.0;
+                (input.len() as f64 * self.to as f64 / self.from as f64).ceil() as usize + 16
+            ];
+        let (_, written) = self.state.process_float(0, &input, &mut output)?;
+        output.truncate(written);
+
+        Ok(output)
+    }
+}
+
+// Read a wav file as mono float samples, returning the samples and sample rate
+pub fn read_wav<R: std::io::Read>(reader: R) -> Result<(Vec<f32>, usize), hound::Error> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let frames = reader.duration() as usize;
+    let samples = read_wav_frames(&mut reader, frames)?;
+
+    Ok((samples, reader.spec().sample_rate as usize))
+}
+
+// Read up to a number of frames from a wav as mono float samples, fewer once it ends
+pub fn read_wav_frames<R: std::io::Read>(
+    reader: &mut hound::WavReader<R>,
+    frames: usize,
+) -> Result<Vec<f32>, hound::Error> {
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+    let count = frames * channels;
+
+    // Convert samples to floats
+    let interleaved = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .take(count)
+            .collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            // Scale integers of any bit depth into -1.0 to 1.0
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .take(count)
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok(downmix(&interleaved, channels))
+}
+
+// Read a flac file as mono float samples, returning the samples and sample rate
+pub fn read_flac<R: std::io::Read>(reader: R) -> Result<(Vec<f32>, usize), claxon::Error> {
+    let mut reader = claxon::FlacReader::new(reader)?;
+    let info = reader.streaminfo();
+    let channels = info.channels.max(1) as usize;
+
+    // Scale integers of any bit depth into -1.0 to 1.0
+    let scale = (1_i64 << (info.bits_per_sample - 1)) as f32;
+    let interleaved = reader
+        .samples()
+        .map(|sample| sample.map(|sample| sample as f32 / scale))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((downmix(&interleaved, channels), info.sample_rate as usize))
+}
+
+// Average interleaved channels into mono
+fn downmix(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+// Lowercase word without punctuation, for matching however whisper wrote it
+pub fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Lock a mutex, carrying on with its data if a thread panicked while holding it
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::whisper::{Segment, Transcription};
+
+// What whisper was asked to do with the audio
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Task {
+    Transcribe,
+    Translate,
+}
+
+// A finished transcription passed on to the output sinks
+#[derive(Serialize, Clone, Debug)]
+pub struct Utterance {
+    pub id: u64,
+    pub timestamp: u64, // Milliseconds since the unix epoch
+    pub task: Task,
+    pub language: Option<String>, // Language spoken, detected when whisper is set to auto
+    pub text: String,
+    pub translation: Option<String>, // Set when a translation engine is configured
+    pub output_language: Option<String>, // Language of the output text, if known
+    pub duration: f32,               // Length of the audio in seconds
+    pub segments: Vec<Segment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<usize>, // Who said it when diarization is on, counting from 1
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>, // Set by external tools when it was said, e.g. the agenda item
+}
+
+impl Utterance {
+    pub fn new(id: u64, task: Task, transcription: Transcription) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            id,
+            timestamp,
+            task,
+            language: transcription.language,
+            text: transcription.text,
+            translation: None,
+            output_language: None,
+            duration: transcription.duration,
+            segments: transcription.segments,
+            speaker: transcription.speaker,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    // Text to present to the listener, the translation if there is one
+    pub fn output_text(&self) -> &str {
+        self.translation.as_deref().unwrap_or(&self.text)
+    }
+
+    // Output text for captions, labelled with the speaker when diarization is on
+    pub fn caption_text(&self) -> String {
+        match self.speaker {
+            Some(speaker) => format!("Speaker {}: {}", speaker, self.output_text().trim()),
+            None => self.output_text().trim().to_owned(),
+        }
+    }
+}
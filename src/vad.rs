@@ -0,0 +1,111 @@
+use serde::Deserialize;
+
+use crate::dsp::dynamics;
+
+// What decides whether a frame is voice
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum VadMode {
+    #[default]
+    Webrtc, // WebRTC's VAD, which knows what voice sounds like
+    Energy, // Only how loud the input is, for places where the WebRTC VAD doesn't work
+    And,    // Both, e.g. so loud noise isn't taken for voice
+    Or,     // Either, e.g. so quiet speakers the WebRTC VAD misses are still heard
+}
+
+impl VadMode {
+    // Combine the decisions of the WebRTC VAD and the energy detector
+    pub fn combine(self, webrtc: bool, energy: bool) -> bool {
+        match self {
+            Self::Webrtc => webrtc,
+            Self::Energy => energy,
+            Self::And => webrtc && energy,
+            Self::Or => webrtc || energy,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct VadConfig {
+    #[serde(default)]
+    pub mode: VadMode,
+    #[serde(default)]
+    pub energy: EnergyConfig,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct EnergyConfig {
+    #[serde(default = "default_start")]
+    pub start: f32, // Level in dBFS input has to reach to count as voice
+    #[serde(default = "default_stop")]
+    pub stop: f32, // Level in dBFS input has to fall below to stop counting as voice, at most start
+    #[serde(default = "default_hangover")]
+    pub hangover: f32, // How long in ms input has to stay below stop, so short gaps between words don't count
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        Self {
+            start: default_start(),
+            stop: default_stop(),
+            hangover: default_hangover(),
+        }
+    }
+}
+
+fn default_start() -> f32 {
+    -40.0
+}
+
+fn default_stop() -> f32 {
+    -50.0
+}
+
+fn default_hangover() -> f32 {
+    300.0
+}
+
+// Decides on voice by the level of the input, with separate thresholds to start and stop
+// so a level around one threshold doesn't flip the decision every frame
+pub struct EnergyDetector {
+    config: EnergyConfig,
+    sample_rate: usize,
+    active: bool,
+    quiet: usize, // Samples below the stop threshold since the input was last louder
+}
+
+impl EnergyDetector {
+    pub fn new(config: &EnergyConfig, sample_rate: usize) -> Self {
+        Self {
+            config: config.clone(),
+            sample_rate,
+            active: false,
+            quiet: 0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+        self.active = false;
+        self.quiet = 0;
+    }
+
+    // Whether a block is voice
+    pub fn process(&mut self, block: &[f32]) -> bool {
+        let level = 20.0 * dynamics::rms(block).max(1e-5).log10();
+
+        if level >= self.config.start {
+            self.active = true;
+            self.quiet = 0;
+        } else if !self.active || level >= self.config.start.min(self.config.stop) {
+            self.quiet = 0;
+        } else {
+            self.quiet += block.len();
+            let hangover = (self.config.hangover / 1000.0 * self.sample_rate as f32) as usize;
+            if self.quiet > hangover {
+                self.active = false;
+            }
+        }
+
+        self.active
+    }
+}
@@ -0,0 +1,640 @@
+use std::{fmt::Display, net::ToSocketAddrs};
+
+use log::debug;
+
+use crate::{
+    Config,
+    dsp::echo,
+    hallucination, models, postprocess,
+    sink::SinkConfig,
+    sound::{
+        AudioClientType,
+        audio_jack::{self, InputMix, JackConfig},
+        audio_net::{Codec, NetConfig},
+        reblock,
+    },
+};
+
+// Top level sections of the config file
+const SECTIONS: [&str; 16] = [
+    "general",
+    "hotkeys",
+    "audio",
+    "vad",
+    "whisper",
+    "piper",
+    "translate",
+    "sinks",
+    "transcript",
+    "recording",
+    "diarization",
+    "postprocess",
+    "wake",
+    "room",
+    "pipeline",
+    "command",
+];
+
+// Longest silence_length accepted, about 10 seconds
+const MAX_SILENCE_LENGTH: u32 = 470;
+
+// A problem found in the config, with where it is and how it might be fixed
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean \"{}\"?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// Number of single character edits between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let cost = if a == *b { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// Closest option to a misspelled value, if any is close enough to be a typo
+fn closest<'a>(value: &str, options: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    options
+        .into_iter()
+        .map(|option| (edit_distance(value, option), option))
+        .filter(|(distance, _)| *distance <= value.len().max(3) / 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, option)| option.to_owned())
+}
+
+// Sections which aren't part of the config, usually misspelled and otherwise silently ignored
+pub fn unknown_sections(table: &toml::Table) -> Vec<Problem> {
+    table
+        .keys()
+        .filter(|key| !SECTIONS.contains(&key.as_str()))
+        .map(|key| Problem {
+            path: key.clone(),
+            message: "unknown section".to_owned(),
+            suggestion: closest(key, SECTIONS),
+        })
+        .collect()
+}
+
+// Audio ports available to connect to
+struct Ports {
+    sources: Vec<String>,
+    sinks: Vec<String>,
+}
+
+impl Ports {
+    fn check(&self, path: &str, port: &str, input: bool, problems: &mut Vec<Problem>) {
+        // Patterns may only match ports which appear later
+        if audio_jack::port_regex(port).is_some() {
+            return;
+        }
+
+        let ports = if input { &self.sources } else { &self.sinks };
+        if !ports.iter().any(|known| known == port) {
+            problems.push(Problem {
+                path: path.to_owned(),
+                message: format!("port \"{}\" doesn't exist", port),
+                suggestion: closest(port, ports.iter().map(String::as_str)),
+            });
+        }
+    }
+}
+
+// Ports and channel settings of an audio client
+fn check_jack(path: &str, jack: &JackConfig, ports: Option<&Ports>, problems: &mut Vec<Problem>) {
+    // Port names can be patterns, which have to be valid regexes
+    let lists = [
+        ("extra_input_ports", &jack.extra_input_ports),
+        ("output_ports", &jack.output_ports),
+        ("right_output_ports", &jack.right_output_ports),
+        ("monitor_ports", &jack.monitor_ports),
+    ];
+    let names = std::iter::once((format!("{}.input_port", path), &jack.input_port)).chain(
+        lists.into_iter().flat_map(|(name, list)| {
+            list.iter()
+                .enumerate()
+                .map(move |(i, port)| (format!("{}.{}[{}]", path, name, i), port))
+        }),
+    );
+    for (port_path, port) in names {
+        if let Some(Err(err)) = audio_jack::port_regex(port) {
+            problems.push(Problem {
+                path: port_path,
+                message: format!("invalid port pattern\n{}", err),
+                suggestion: None,
+            });
+        }
+    }
+
+    // Nothing has to exist if the ports are left to a patchbay
+    if let Some(ports) = ports.filter(|_| jack.auto_connect) {
+        ports.check(
+            &format!("{}.input_port", path),
+            &jack.input_port,
+            true,
+            problems,
+        );
+        let port_lists = [
+            ("extra_input_ports", &jack.extra_input_ports, true),
+            ("output_ports", &jack.output_ports, false),
+            ("right_output_ports", &jack.right_output_ports, false),
+        ];
+        for (name, list, input) in port_lists {
+            for (i, port) in list.iter().enumerate() {
+                ports.check(&format!("{}.{}[{}]", path, name, i), port, input, problems);
+            }
+        }
+    }
+
+    let channels = 1 + jack.extra_input_ports.len();
+    if let InputMix::Channel(channel) = jack.input_mix
+        && !(1..=channels).contains(&channel)
+    {
+        problems.push(Problem {
+            path: format!("{}.input_mix", path),
+            message: format!(
+                "channel {} doesn't exist, there are {} input channels",
+                channel, channels
+            ),
+            suggestion: None,
+        });
+    }
+
+    if !(-1.0..=1.0).contains(&jack.pan) {
+        problems.push(Problem {
+            path: format!("{}.pan", path),
+            message: format!(
+                "{} is out of range, it should be between -1.0 and 1.0",
+                jack.pan
+            ),
+            suggestion: None,
+        });
+    }
+
+    for (name, ms) in [("prefill", jack.prefill), ("max_prefill", jack.max_prefill)] {
+        if ms < 0.0 {
+            problems.push(Problem {
+                path: format!("{}.{}", path, name),
+                message: format!("{} ms can't be negative", ms),
+                suggestion: None,
+            });
+        }
+    }
+}
+
+fn check_net(path: &str, net: &NetConfig, problems: &mut Vec<Problem>) {
+    let addresses = std::iter::once(("listen", &net.listen))
+        .chain(net.send_to.iter().map(|address| ("send_to", address)));
+    for (name, address) in addresses {
+        if address.to_socket_addrs().is_err() {
+            problems.push(Problem {
+                path: format!("{}.{}", path, name),
+                message: format!("\"{}\" isn't an address like \"0.0.0.0:5004\"", address),
+                suggestion: None,
+            });
+        }
+    }
+
+    let values = [("sample_rate", net.sample_rate), ("channels", net.channels)];
+    for (name, value) in values {
+        if value == 0 {
+            problems.push(Problem {
+                path: format!("{}.{}", path, name),
+                message: "can't be 0".to_owned(),
+                suggestion: None,
+            });
+        }
+    }
+    if net.payload_type > 127 {
+        problems.push(Problem {
+            path: format!("{}.payload_type", path),
+            message: format!(
+                "{} is out of range, RTP payload types go up to 127",
+                net.payload_type
+            ),
+            suggestion: None,
+        });
+    }
+
+    // Opus, the only codec besides L16, works at a few rates and frame lengths
+    if net.codec != Codec::L16 {
+        if ![8000, 12000, 16000, 24000, 48000].contains(&net.sample_rate) {
+            problems.push(Problem {
+                path: format!("{}.sample_rate", path),
+                message: format!(
+                    "opus can't use {} Hz, only 8000, 12000, 16000, 24000 or 48000",
+                    net.sample_rate
+                ),
+                suggestion: None,
+            });
+        }
+        if ![2.5, 5.0, 10.0, 20.0, 40.0, 60.0].contains(&net.packet) {
+            problems.push(Problem {
+                path: format!("{}.packet", path),
+                message: format!(
+                    "opus can't send {} ms packets, only 2.5, 5, 10, 20, 40 or 60",
+                    net.packet
+                ),
+                suggestion: None,
+            });
+        }
+    } else if net.packet <= 0.0 {
+        problems.push(Problem {
+            path: format!("{}.packet", path),
+            message: format!("{} ms is too short", net.packet),
+            suggestion: None,
+        });
+    }
+}
+
+fn check_model(path: &str, model: &str, problems: &mut Vec<Problem>) {
+    let downloaded = models::model_path(model).exists();
+    if !downloaded && !models::MODELS.contains(&model) {
+        problems.push(Problem {
+            path: path.to_owned(),
+            message: format!("unknown whisper model \"{}\"", model),
+            suggestion: closest(model, models::MODELS),
+        });
+    }
+}
+
+fn check_language(path: &str, language: &str, problems: &mut Vec<Problem>) {
+    if language != "auto" && whisper_rs::get_lang_id(language).is_none() {
+        problems.push(Problem {
+            path: path.to_owned(),
+            message: format!("unknown language \"{}\"", language),
+            suggestion: None,
+        });
+    }
+}
+
+fn check_silence_length(path: &str, silence_length: u32, problems: &mut Vec<Problem>) {
+    if silence_length == 0 || silence_length > MAX_SILENCE_LENGTH {
+        problems.push(Problem {
+            path: path.to_owned(),
+            message: format!(
+                "{} is out of range, it should be between 1 and {} frames",
+                silence_length, MAX_SILENCE_LENGTH
+            ),
+            suggestion: None,
+        });
+    }
+}
+
+// What a sink listens on, sends to or writes to, which two pipelines can't share
+fn sink_target(sink: &SinkConfig) -> Option<(&'static str, &str)> {
+    match sink {
+        SinkConfig::File(file) => Some(("file", &file.path)),
+        SinkConfig::Json(json) => Some(("json directory", &json.directory)),
+        SinkConfig::WebSocket(websocket) => Some(("websocket address", &websocket.address)),
+        SinkConfig::Osc(osc) => Some(("osc address", &osc.address)),
+        SinkConfig::Subtitle(subtitle) => Some(("subtitle file", &subtitle.path)),
+        SinkConfig::Tts | SinkConfig::Stdout => None,
+    }
+}
+
+fn check_timeout(path: &str, seconds: f32, problems: &mut Vec<Problem>) {
+    if !(seconds > 0.0 && seconds.is_finite()) {
+        problems.push(Problem {
+            path: path.to_owned(),
+            message: format!("{} seconds is out of range, it should be above 0", seconds),
+            suggestion: None,
+        });
+    }
+}
+
+impl Config {
+    // Check for values which parse but can't work, reporting every problem found
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        // Port names can only be checked while the audio server is running
+        let ports = match audio_jack::list_ports() {
+            Ok((sources, sinks)) => Some(Ports { sources, sinks }),
+            Err(err) => {
+                debug!("Not checking port names, could not list ports: {}", err);
+                None
+            }
+        };
+
+        match (
+            &self.general.audio_client,
+            &self.audio.jack,
+            &self.audio.file,
+        ) {
+            (AudioClientType::Jack, Some(jack), _) => {
+                check_jack("audio.jack", jack, ports.as_ref(), &mut problems);
+                if let Some(ports) = ports.as_ref().filter(|_| jack.auto_connect) {
+                    for (i, port) in jack.monitor_ports.iter().enumerate() {
+                        let path = format!("audio.jack.monitor_ports[{}]", i);
+                        ports.check(&path, port, false, &mut problems);
+                    }
+                }
+            }
+            (AudioClientType::Jack, None, _) => problems.push(Problem {
+                path: "audio.jack".to_owned(),
+                message: "missing, it's needed by audio_client = \"Jack\"".to_owned(),
+                suggestion: None,
+            }),
+            (AudioClientType::File, _, Some(file)) => {
+                if !file.input.exists() {
+                    problems.push(Problem {
+                        path: "audio.file.input".to_owned(),
+                        message: format!("{} doesn't exist", file.input.display()),
+                        suggestion: None,
+                    });
+                }
+            }
+            (AudioClientType::File, _, None) => problems.push(Problem {
+                path: "audio.file".to_owned(),
+                message: "missing, it's needed by audio_client = \"File\"".to_owned(),
+                suggestion: None,
+            }),
+            (AudioClientType::Net, _, _) => match &self.audio.net {
+                Some(net) => check_net("audio.net", net, &mut problems),
+                None => problems.push(Problem {
+                    path: "audio.net".to_owned(),
+                    message: "missing, it's needed by audio_client = \"Net\"".to_owned(),
+                    suggestion: None,
+                }),
+            },
+            #[cfg(feature = "alsa")]
+            (AudioClientType::Alsa, _, _) => match &self.audio.alsa {
+                Some(alsa) => {
+                    let values = [("sample_rate", alsa.sample_rate), ("period", alsa.period)];
+                    for (name, value) in values {
+                        if value == 0 {
+                            problems.push(Problem {
+                                path: format!("audio.alsa.{}", name),
+                                message: "can't be 0".to_owned(),
+                                suggestion: None,
+                            });
+                        }
+                    }
+                }
+                None => problems.push(Problem {
+                    path: "audio.alsa".to_owned(),
+                    message: "missing, it's needed by audio_client = \"Alsa\"".to_owned(),
+                    suggestion: None,
+                }),
+            },
+            #[cfg(feature = "pulse")]
+            (AudioClientType::Pulse, _, _) => {
+                let pulse = self.audio.pulse.clone().unwrap_or_default();
+                let values = [("sample_rate", pulse.sample_rate), ("period", pulse.period)];
+                for (name, value) in values {
+                    if value == 0 {
+                        problems.push(Problem {
+                            path: format!("audio.pulse.{}", name),
+                            message: "can't be 0".to_owned(),
+                            suggestion: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        if !reblock::FRAME_LENGTHS.contains(&self.audio.frame) {
+            problems.push(Problem {
+                path: "audio.frame".to_owned(),
+                message: format!(
+                    "the VAD can't take {} ms frames, only 10, 20 or 30, {} ms is used instead",
+                    self.audio.frame,
+                    reblock::DEFAULT_FRAME_LENGTH
+                ),
+                suggestion: None,
+            });
+        }
+
+        if let Some(echo) = &self.audio.echo
+            && !(echo.tail > 0.0 && echo.tail <= echo::MAX_TAIL)
+        {
+            problems.push(Problem {
+                path: "audio.echo.tail".to_owned(),
+                message: format!(
+                    "{} ms is out of range, it should be above 0 and at most {} ms",
+                    echo.tail,
+                    echo::MAX_TAIL
+                ),
+                suggestion: None,
+            });
+        }
+
+        if self.vad.energy.stop > self.vad.energy.start {
+            problems.push(Problem {
+                path: "vad.energy.stop".to_owned(),
+                message: format!(
+                    "{} dBFS is above start, {} dBFS is used instead",
+                    self.vad.energy.stop, self.vad.energy.start
+                ),
+                suggestion: None,
+            });
+        }
+
+        for (i, command) in self.commands.iter().enumerate() {
+            let path = format!("command[{}]", i);
+            if command.phrase.trim().is_empty() {
+                problems.push(Problem {
+                    path: format!("{}.phrase", path),
+                    message: "is empty, it can't be said".to_owned(),
+                    suggestion: None,
+                });
+            }
+            if command.target.is_none() && command.voice.is_none() {
+                problems.push(Problem {
+                    path,
+                    message: "does nothing, set a target or voice".to_owned(),
+                    suggestion: None,
+                });
+            }
+        }
+
+        if let Some(wake) = &self.wake
+            && wake.words.iter().all(|word| word.trim().is_empty())
+        {
+            problems.push(Problem {
+                path: "wake.words".to_owned(),
+                message: "has no wake words, nothing would be translated".to_owned(),
+                suggestion: None,
+            });
+        }
+
+        check_model("whisper.model", &self.whisper.model, &mut problems);
+        if let Some(language) = &self.whisper.language {
+            check_language("whisper.language", language, &mut problems);
+        }
+        check_silence_length(
+            "whisper.silence_length",
+            self.whisper.silence_length,
+            &mut problems,
+        );
+        if let Some(timeout) = self.whisper.timeout {
+            check_timeout("whisper.timeout", timeout, &mut problems);
+        }
+        if let Some(config) = &self.whisper.hallucination {
+            for (i, phrase) in config.blocklist.iter().enumerate() {
+                if let Err(err) = hallucination::pattern(phrase) {
+                    problems.push(Problem {
+                        path: format!("whisper.hallucination.blocklist[{}]", i),
+                        message: format!("invalid pattern, {}", err),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        check_timeout("piper.timeout", self.piper.timeout, &mut problems);
+
+        if let Some(translate) = &self.translate {
+            if translate.target.trim().is_empty() {
+                problems.push(Problem {
+                    path: "translate.target".to_owned(),
+                    message: "empty".to_owned(),
+                    suggestion: None,
+                });
+            }
+            check_timeout("translate.timeout", translate.timeout, &mut problems);
+            if let Some(post_edit) = &translate.post_edit {
+                let path = "translate.post_edit.timeout";
+                check_timeout(path, post_edit.timeout, &mut problems);
+            }
+        }
+
+        if let Some(postprocess) = &self.postprocess {
+            let tables = [
+                ("captions", &postprocess.captions),
+                ("speech", &postprocess.speech),
+            ];
+            for (table, replacements) in tables {
+                for (i, replacement) in replacements.iter().enumerate() {
+                    if let Err(err) = postprocess::pattern(replacement) {
+                        problems.push(Problem {
+                            path: format!("postprocess.{}[{}].pattern", table, i),
+                            message: format!("invalid pattern, {}", err),
+                            suggestion: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(diarization) = &self.diarization
+            && diarization.max_speakers == 0
+        {
+            problems.push(Problem {
+                path: "diarization.max_speakers".to_owned(),
+                message: "there has to be at least one speaker".to_owned(),
+                suggestion: None,
+            });
+        }
+
+        // Rooms, each spoken into in its own language
+        for (i, room) in self.rooms.iter().enumerate() {
+            let path = format!("room[{}]", i);
+
+            if self.rooms[..i].iter().any(|other| other.name == room.name) {
+                problems.push(Problem {
+                    path: format!("{}.name", path),
+                    message: format!("\"{}\" is used by more than one room", room.name),
+                    suggestion: None,
+                });
+            }
+            if self.translate.is_none() {
+                problems.push(Problem {
+                    path: format!("{}.target", path),
+                    message: "rooms need a [translate] engine to speak other languages".to_owned(),
+                    suggestion: None,
+                });
+            }
+            if let Some(ports) = &ports {
+                for (j, port) in room.output_ports.iter().enumerate() {
+                    let port_path = format!("{}.output_ports[{}]", path, j);
+                    ports.check(&port_path, port, false, &mut problems);
+                }
+            }
+        }
+
+        // Each pipeline's overrides
+        for (i, pipeline) in self.pipelines.iter().enumerate() {
+            let path = format!("pipeline[{}]", i);
+
+            if self.pipelines[..i]
+                .iter()
+                .any(|other| other.name == pipeline.name)
+            {
+                problems.push(Problem {
+                    path: format!("{}.name", path),
+                    message: format!("\"{}\" is used by more than one pipeline", pipeline.name),
+                    suggestion: None,
+                });
+            }
+
+            if let Some(jack) = &pipeline.apply(self).audio.jack {
+                check_jack(&path, jack, ports.as_ref(), &mut problems);
+            }
+            if let Some(model) = &pipeline.model {
+                check_model(&format!("{}.model", path), model, &mut problems);
+            }
+            if let Some(language) = &pipeline.language {
+                check_language(&format!("{}.language", path), language, &mut problems);
+            }
+            if let Some(silence_length) = pipeline.silence_length {
+                let silence_path = format!("{}.silence_length", path);
+                check_silence_length(&silence_path, silence_length, &mut problems);
+            }
+        }
+
+        // Pipelines without sinks of their own all get [[sinks]], so they'd open them twice
+        let mut targets: Vec<(&str, &str, &str)> = vec![]; // Kind, target and the pipeline using it
+        for (i, pipeline) in self.pipelines.iter().enumerate() {
+            let (path, sinks) = match &pipeline.sinks {
+                Some(sinks) => (format!("pipeline[{}].sinks", i), sinks),
+                None => ("sinks".to_owned(), &self.sinks),
+            };
+            for (j, sink) in sinks.iter().enumerate() {
+                let Some((kind, target)) = sink_target(sink) else {
+                    continue;
+                };
+                match targets
+                    .iter()
+                    .find(|(other_kind, other, _)| *other_kind == kind && *other == target)
+                {
+                    Some((_, _, other_pipeline)) => problems.push(Problem {
+                        path: format!("{}[{}]", path, j),
+                        message: format!(
+                            "{} \"{}\" is shared by pipelines \"{}\" and \"{}\"",
+                            kind, target, other_pipeline, pipeline.name
+                        ),
+                        suggestion: None,
+                    }),
+                    None => targets.push((kind, target, &pipeline.name)),
+                }
+            }
+        }
+
+        problems
+    }
+}
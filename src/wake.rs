@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    util::normalize_word,
+    whisper::{Segment, Transcription},
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct WakeConfig {
+    pub words: Vec<String>, // Phrases which start translating, e.g. "translate", in any case or punctuation
+    #[serde(default = "default_active")]
+    pub active: f32, // Seconds translating stays on after the last utterance
+    #[serde(default = "default_strip")]
+    pub strip: bool, // Leave the wake word and what came before it out of the output
+}
+
+fn default_active() -> f32 {
+    30.0
+}
+
+fn default_strip() -> bool {
+    true
+}
+
+// Normalized words of text, each with the byte offset it ends at
+fn words(text: &str) -> Vec<(String, usize)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(word_start), true) => {
+                words.push((normalize_word(&text[word_start..i]), i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+// Text after a wake word, without the punctuation and spaces between them
+fn after_wake(text: &str) -> &str {
+    text.trim_start_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+}
+
+// Cut a segment to start cut bytes into its text, false if nothing is left of it
+fn cut_segment(segment: &mut Segment, cut: usize) -> bool {
+    let Some(rest) = segment.text.get(cut..).map(after_wake) else {
+        return true;
+    };
+    if rest.is_empty() {
+        return false;
+    }
+    let dropped = segment.text.len() - rest.len();
+    segment.text = format!(" {}", rest);
+
+    // Words left out with the text, the segment now starts where the first one kept does
+    let mut length = 0;
+    segment.words.retain(|word| {
+        length += word.text.len();
+        length > dropped
+    });
+    if let Some(word) = segment.words.first() {
+        segment.start = word.start;
+    }
+
+    true
+}
+
+// Only lets utterances through once a wake word was said, until nothing is said for a while
+// Shared rooms can keep talking among themselves without it all being translated and spoken
+pub struct WakeGate {
+    config: WakeConfig,
+    phrases: Vec<Vec<String>>, // Normalized words of each wake word
+    awake_until: Option<Instant>,
+}
+
+impl WakeGate {
+    pub fn new(config: &WakeConfig) -> Self {
+        let phrases = config
+            .words
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .split_whitespace()
+                    .map(normalize_word)
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|phrase| !phrase.is_empty())
+            .collect();
+
+        Self {
+            config: config.clone(),
+            phrases,
+            awake_until: None,
+        }
+    }
+
+    // Byte offset in text just past the first wake word, None if there is none
+    fn find(&self, text: &str) -> Option<usize> {
+        let words = words(text)
+            .into_iter()
+            .filter(|(word, _)| !word.is_empty())
+            .collect::<Vec<_>>();
+
+        (0..words.len()).find_map(|start| {
+            self.phrases.iter().find_map(|phrase| {
+                let candidate = words.get(start..start + phrase.len())?;
+                candidate
+                    .iter()
+                    .map(|(word, _)| word)
+                    .eq(phrase)
+                    .then(|| candidate[candidate.len() - 1].1)
+            })
+        })
+    }
+
+    // Keep translating for a while from now
+    fn stay_awake(&mut self, now: Instant) {
+        self.awake_until = Some(now + Duration::from_secs_f32(self.config.active.max(0.0)));
+    }
+
+    // Whether a transcription should be output, waking up if it has a wake word
+    // With strip set, the wake word and what came before it are removed from its text and segments
+    pub fn check(&mut self, transcription: &mut Transcription) -> bool {
+        let now = Instant::now();
+        let awake = self.awake_until.is_some_and(|until| now < until);
+
+        match self.find(&transcription.text) {
+            Some(end) => {
+                if !awake {
+                    info!("Woken up, translating");
+                }
+                self.stay_awake(now);
+
+                if self.config.strip {
+                    transcription.text = format!(" {}", after_wake(&transcription.text[end..]));
+
+                    // The text is the segments one after another, so they're cut at the same place
+                    let mut offset = 0;
+                    transcription.segments.retain_mut(|segment| {
+                        let start = offset;
+                        offset += segment.text.len();
+                        offset > end && (start >= end || cut_segment(segment, end - start))
+                    });
+                }
+
+                // Nothing to translate if only the wake word was said
+                !transcription.text.trim().is_empty()
+            }
+            None if awake => {
+                self.stay_awake(now);
+                true
+            }
+            None => {
+                if self.awake_until.take().is_some() {
+                    info!("Nothing said for a while, waiting for the wake word");
+                }
+                info!(
+                    "Not translating \"{}\", no wake word",
+                    transcription.text.trim()
+                );
+                false
+            }
+        }
+    }
+}
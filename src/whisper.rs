@@ -1,31 +1,44 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use log::{info, warn};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use whisper_rs::{
-    DtwParameters, FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters,
-    WhisperError,
+    DtwMode, DtwModelPreset, DtwParameters, FullParams, SamplingStrategy, WhisperContext,
+    WhisperContextParameters, WhisperError, WhisperState, WhisperToken,
+};
+
+use crate::{
+    backlog::BacklogConfig,
+    execution::{ExecutionConfig, Provider},
+    hallucination::HallucinationConfig,
+    models::{self, ErrModel},
+    util::resample,
 };
 
-use crate::util::resample;
+// Most tokens whisper takes as a prompt, half its text context
+const MAX_PROMPT: usize = 224;
+
+// Whisper refuses anything shorter than a second, so shorter audio is padded with silence
+const MIN_SAMPLES: usize = 16000;
 
 #[derive(Debug)]
 pub enum ErrSetupWhisper {
     WhisperError(WhisperError),
-    IoError(std::io::Error),
-    ReqwestError(reqwest::Error),
-    CouldNotDownloadModel(reqwest::Error),
+    ModelError(ErrModel),
 }
 
 impl Display for ErrSetupWhisper {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::WhisperError(whisper_error) => write!(f, "{}", whisper_error),
-            Self::IoError(io_error) => write!(f, "{}", io_error),
-            Self::ReqwestError(reqwest_error) => write!(f, "{}", reqwest_error),
-            Self::CouldNotDownloadModel(error) => {
-                write!(f, "Could not download whisper model!\n{}", error)
-            }
+            Self::ModelError(model_error) => write!(f, "{}", model_error),
         }
     }
 }
@@ -38,15 +51,9 @@ impl From<WhisperError> for ErrSetupWhisper {
     }
 }
 
-impl From<std::io::Error> for ErrSetupWhisper {
-    fn from(value: std::io::Error) -> Self {
-        Self::IoError(value)
-    }
-}
-
-impl From<reqwest::Error> for ErrSetupWhisper {
-    fn from(value: reqwest::Error) -> Self {
-        Self::ReqwestError(value)
+impl From<ErrModel> for ErrSetupWhisper {
+    fn from(value: ErrModel) -> Self {
+        Self::ModelError(value)
     }
 }
 
@@ -54,6 +61,8 @@ impl From<reqwest::Error> for ErrSetupWhisper {
 pub enum ErrTranscribe {
     WhisperError(WhisperError),
     ResampleError(speexdsp_resampler::Error),
+    Timeout(f32),
+    Cancelled,
 }
 
 impl Display for ErrTranscribe {
@@ -66,6 +75,12 @@ impl Display for ErrTranscribe {
             {
                 write!(f, "{:?}", resample_error)
             }
+            Self::Timeout(timeout) => write!(
+                f,
+                "Whisper took longer than {} seconds, gave up on the recording",
+                timeout
+            ),
+            Self::Cancelled => write!(f, "Transcription was cancelled"),
         }
     }
 }
@@ -84,13 +99,295 @@ impl From<speexdsp_resampler::Error> for ErrTranscribe {
     }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct WhisperConfig {
     pub model: String,
-    pub language: Option<String>,
+    pub language: Option<String>, // "auto" detects the language of each utterance
     pub translate: bool,
     pub no_context: bool,
-    pub silence_length: u32, // Silence length in multiples of 21.3333ms
+    pub silence_length: u32, // Silence length in frames, audio.frame ms each
+    #[serde(default = "default_pre_roll")]
+    pub pre_roll: u32, // Audio from before voice was detected kept at the start, in frames
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    pub preset: Option<Preset>, // Decoding settings for a type of content, overriding no_context
+    pub backlog: Option<BacklogConfig>, // Keep recording while whisper is unavailable
+    #[serde(default)]
+    pub gpu_device: i32, // Index of the GPU to run on with CUDA
+    #[serde(default)]
+    pub flash_attn: bool, // Faster attention on the GPU, not supported by every model
+    #[serde(default)]
+    pub decoding: DecodingConfig, // Overrides for the preset's decoding settings
+    pub initial_prompt: Option<String>, // Text whisper continues from, sets the style and context
+    #[serde(default)]
+    pub vocabulary: Vec<String>, // Names and terms to spell the way they're written here
+    #[serde(default)]
+    pub min_length: f32, // Recordings with less speech than this many seconds are discarded
+    pub hallucination: Option<HallucinationConfig>, // Drop text whisper made up instead of speaking it
+    pub timeout: Option<f32>, // Seconds after which a recording is given up on, so a stuck model can't stall the pipeline
+    pub max_length: Option<f32>, // Seconds after which a recording is finished at the next pause, for long monologues
+    #[serde(default)]
+    pub split_segments: bool, // Let whisper split long utterances into timed segments instead of one
+    #[serde(default)]
+    pub word_timestamps: bool, // Time every word, aligned with DTW on models that have a preset for it
+}
+
+fn default_pre_roll() -> u32 {
+    10
+}
+
+impl WhisperConfig {
+    // Prompt biasing the transcription towards the vocabulary, if there is anything to bias it with
+    fn prompt(&self) -> Option<String> {
+        let vocabulary = (!self.vocabulary.is_empty())
+            .then(|| format!("Glossary: {}.", self.vocabulary.join(", ")));
+
+        match (self.initial_prompt.as_deref(), vocabulary) {
+            (Some(prompt), Some(vocabulary)) => Some(format!("{} {}", prompt, vocabulary)),
+            (Some(prompt), None) => Some(prompt.to_owned()),
+            (None, vocabulary) => vocabulary,
+        }
+    }
+
+    // Decoding settings of the preset, with the ones set in the config taking priority
+    fn decoding(&self) -> Decoding {
+        let mut decoding = match self.preset {
+            Some(preset) => preset.decoding(),
+            None => Decoding::new(self.no_context),
+        };
+
+        let config = &self.decoding;
+        // Setting a beam size alone switches to beam search
+        decoding.beam_size = match config.strategy {
+            Some(Strategy::Greedy) => None,
+            Some(Strategy::BeamSearch) => {
+                Some(config.beam_size.or(decoding.beam_size).unwrap_or(5))
+            }
+            None => config.beam_size.or(decoding.beam_size),
+        };
+        decoding.best_of = config.best_of.unwrap_or(decoding.best_of);
+        decoding.temperature = config.temperature.unwrap_or(decoding.temperature);
+        decoding.temperature_inc = config.temperature_inc.unwrap_or(decoding.temperature_inc);
+        decoding.entropy_thold = config.entropy_thold.unwrap_or(decoding.entropy_thold);
+        decoding.logprob_thold = config.logprob_thold.unwrap_or(decoding.logprob_thold);
+        decoding.no_speech_thold = config.no_speech_thold.unwrap_or(decoding.no_speech_thold);
+        decoding.suppress_nst = config
+            .suppress_non_speech_tokens
+            .unwrap_or(decoding.suppress_nst);
+
+        decoding
+    }
+
+    // Alignment heads for DTW word timestamps, only known for the standard models
+    fn dtw_parameters(&self) -> DtwParameters<'static> {
+        if !self.word_timestamps {
+            return DtwParameters::default();
+        }
+
+        // Quantization doesn't change the heads, so the suffix is ignored
+        let model = self
+            .model
+            .split('-')
+            .take_while(|part| !part.starts_with('q'));
+        let preset = match model.collect::<Vec<_>>().join("-").as_str() {
+            "tiny.en" => DtwModelPreset::TinyEn,
+            "tiny" => DtwModelPreset::Tiny,
+            "base.en" => DtwModelPreset::BaseEn,
+            "base" => DtwModelPreset::Base,
+            "small.en" => DtwModelPreset::SmallEn,
+            "small" => DtwModelPreset::Small,
+            "medium.en" => DtwModelPreset::MediumEn,
+            "medium" => DtwModelPreset::Medium,
+            "large-v1" => DtwModelPreset::LargeV1,
+            "large-v2" => DtwModelPreset::LargeV2,
+            "large-v3" => DtwModelPreset::LargeV3,
+            "large-v3-turbo" => DtwModelPreset::LargeV3Turbo,
+            _ => {
+                info!(
+                    "No DTW preset for {}, word timestamps are estimated instead",
+                    self.model
+                );
+                return DtwParameters::default();
+            }
+        };
+
+        DtwParameters {
+            mode: DtwMode::ModelPreset {
+                model_preset: preset,
+            },
+            ..DtwParameters::default()
+        }
+    }
+}
+
+// How whisper picks the tokens of a transcription
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    Greedy,     // Fastest, takes the most likely token each step
+    BeamSearch, // Keeps several candidates, slower but more accurate
+}
+
+// Decoding settings, each overriding the preset's if set
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct DecodingConfig {
+    pub strategy: Option<Strategy>,
+    pub beam_size: Option<i32>, // Candidates kept with beam search, 5 if not set
+    pub best_of: Option<i32>,   // Candidates sampled with greedy when the temperature is raised
+    pub temperature: Option<f32>,
+    pub temperature_inc: Option<f32>,
+    pub entropy_thold: Option<f32>,
+    pub logprob_thold: Option<f32>,
+    pub no_speech_thold: Option<f32>,
+    pub suppress_non_speech_tokens: Option<bool>,
+}
+
+// Decoding settings tuned for a type of content
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Conversation, // Short turns, each decoded on its own
+    Lecture,      // Long speech with recurring terms, slower but more accurate
+    Gaming,       // Shouting over background noise, strict about hallucinations
+}
+
+// Parameters a preset sets
+struct Decoding {
+    beam_size: Option<i32>, // Greedy if not set
+    best_of: i32,
+    temperature: f32,
+    temperature_inc: f32, // Raised by this on each fallback when decoding fails the thresholds
+    no_context: bool,
+    entropy_thold: f32,
+    logprob_thold: f32,
+    no_speech_thold: f32,
+    suppress_nst: bool, // Suppress non speech tokens like music notes
+}
+
+impl Decoding {
+    // Whisper's own defaults
+    fn new(no_context: bool) -> Self {
+        Self {
+            beam_size: None,
+            best_of: 1,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            no_context,
+            entropy_thold: 2.4,
+            logprob_thold: -1.0,
+            no_speech_thold: 0.6,
+            suppress_nst: false,
+        }
+    }
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Self::Conversation, Self::Lecture, Self::Gaming];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Conversation => "conversation",
+            Self::Lecture => "lecture",
+            Self::Gaming => "gaming",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    fn decoding(&self) -> Decoding {
+        match self {
+            Self::Conversation => Decoding {
+                beam_size: None,
+                best_of: 1,
+                temperature: 0.0,
+                temperature_inc: 0.2,
+                no_context: true,
+                entropy_thold: 2.4,
+                logprob_thold: -1.0,
+                no_speech_thold: 0.6,
+                suppress_nst: true,
+            },
+            Self::Lecture => Decoding {
+                beam_size: Some(5),
+                best_of: 1,
+                temperature: 0.0,
+                temperature_inc: 0.2,
+                no_context: false,
+                entropy_thold: 2.4,
+                logprob_thold: -1.0,
+                no_speech_thold: 0.6,
+                suppress_nst: false,
+            },
+            Self::Gaming => Decoding {
+                beam_size: None,
+                best_of: 1,
+                temperature: 0.0,
+                temperature_inc: 0.4,
+                no_context: true,
+                entropy_thold: 2.2,
+                logprob_thold: -0.8,
+                no_speech_thold: 0.4,
+                suppress_nst: true,
+            },
+        }
+    }
+}
+
+// A word of a segment, only timed when word timestamps are enabled
+#[derive(Serialize, Clone, Debug)]
+pub struct Word {
+    pub text: String,
+    pub start: f32, // Start time in seconds
+    pub end: f32,   // End time in seconds
+    pub probability: f32,
+}
+
+// A single segment of transcribed text
+#[derive(Serialize, Clone, Debug)]
+pub struct Segment {
+    pub id: i32,
+    pub start: f32, // Start time in seconds
+    pub end: f32,   // End time in seconds
+    pub text: String,
+    pub tokens: Vec<i32>,
+    pub avg_logprob: f32,
+    pub no_speech_prob: f32, // How likely whisper thinks the segment is silence
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<Word>,
+}
+
+// Result of transcribing an utterance
+#[derive(Serialize, Clone, Debug)]
+pub struct Transcription {
+    pub text: String,
+    pub language: Option<String>, // Language whisper decoded the audio as
+    pub duration: f32,            // Length of the audio in seconds
+    pub segments: Vec<Segment>,
+    pub speaker: Option<usize>, // Set by diarization, counting from 1
+}
+
+// Add a token to the words of a segment, a leading space starts a new word
+// Times are in centiseconds, a word ends where its last token does
+fn push_token(words: &mut Vec<Word>, text: &str, start: i64, end: i64, probability: f32) {
+    let start = start as f32 / 100.0;
+    let end = end as f32 / 100.0;
+
+    match words.last_mut() {
+        Some(word) if !text.starts_with(' ') => {
+            word.text.push_str(text);
+            word.end = word.end.max(end);
+            word.probability = word.probability.min(probability);
+        }
+        _ if text.trim().is_empty() => {}
+        _ => words.push(Word {
+            text: text.trim_start().to_owned(),
+            start,
+            end,
+            probability,
+        }),
+    }
 }
 
 // Load whisper
@@ -98,96 +395,233 @@ pub fn setup_whisper(config: WhisperConfig) -> Result<WhisperContext, ErrSetupWh
     // Tell whisper to use log
     whisper_rs::install_logging_hooks();
 
-    // Get relative path
-    let model_path = format!("whisper/ggml-{}.bin", config.model);
+    // Get the model, downloading it if needed
+    let model_path = models::ensure(&config.model)?;
+
+    // Whisper is built with CUDA, which falls back to the CPU by itself
+    let provider =
+        config
+            .execution
+            .select("Whisper", &[Provider::Cpu, Provider::Cuda], Provider::Cuda);
+
+    // Create the context and load the model
+    let model_path = model_path.to_string_lossy();
+    let params = WhisperContextParameters {
+        use_gpu: provider == Provider::Cuda,
+        flash_attn: config.flash_attn,
+        gpu_device: config.gpu_device,
+        dtw_parameters: config.dtw_parameters(),
+    };
+    match WhisperContext::new_with_params(&model_path, params) {
+        Ok(ctx) => Ok(ctx),
+        // A missing or busy GPU shouldn't stop transcription, just slow it down
+        Err(err) if provider == Provider::Cuda => {
+            warn!(
+                "Could not load whisper on GPU {}, falling back to the CPU\n{}",
+                config.gpu_device, err
+            );
+            Ok(WhisperContext::new_with_params(
+                &model_path,
+                WhisperContextParameters {
+                    use_gpu: false,
+                    flash_attn: false,
+                    gpu_device: 0,
+                    dtw_parameters: config.dtw_parameters(),
+                },
+            )?)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Whisper with a state kept between utterances, so its buffers are only allocated once
+pub struct Transcriber {
+    ctx: Arc<WhisperContext>,
+    state: WhisperState,
+    context: Vec<WhisperToken>, // Tokens of recent utterances, carried over unless no_context is set
+    cancel: Arc<AtomicBool>,    // Aborts a transcription in progress when set
+}
+
+impl Transcriber {
+    pub fn new(ctx: Arc<WhisperContext>, cancel: Arc<AtomicBool>) -> Result<Self, WhisperError> {
+        let state = ctx.create_state()?;
 
-    // Ensure whisper directory exists
-    if let Ok(_) = std::fs::create_dir("whisper") {
-        warn!("Whisper directory didnt exist, creating now");
+        Ok(Self {
+            ctx,
+            state,
+            context: vec![],
+            cancel,
+        })
     }
 
-    // Check model exists
-    if !std::fs::exists(&model_path)? {
-        warn!("Model {} not found, attempting to download", model_path);
+    // Send audio to whisper for transcribing
+    pub fn transcribe(
+        &mut self,
+        whisper_config: &WhisperConfig,
+        samples: Vec<f32>,
+        sample_rate: usize,
+    ) -> Result<Option<Transcription>, ErrTranscribe> {
+        let mut resampled = resample(samples, sample_rate, 16000)?;
+
+        // Length of the recording before padding
+        let duration = resampled.len() as f32 / 16000.0;
+
+        // Prompt with the configured prompt followed by what was said before
+        // Whisper's own carry-over would add the configured prompt again on every utterance
+        let decoding = whisper_config.decoding();
+        if decoding.no_context {
+            self.context.clear();
+        }
+        let mut prompt = match whisper_config.prompt() {
+            Some(prompt) => self.ctx.tokenize(&prompt, MAX_PROMPT)?,
+            None => vec![],
+        };
+        let skip = (prompt.len() + self.context.len()).saturating_sub(MAX_PROMPT);
+        prompt.extend(self.context.iter().skip(skip));
+
+        // Whisper parameters
+        let strategy = match decoding.beam_size {
+            Some(beam_size) => SamplingStrategy::BeamSearch {
+                beam_size,
+                patience: -1.0,
+            },
+            None => SamplingStrategy::Greedy {
+                best_of: decoding.best_of,
+            },
+        };
+        let mut params = FullParams::new(strategy);
+        params.set_language(whisper_config.language.as_deref());
+        params.set_translate(whisper_config.translate);
+        params.set_no_context(true);
+        params.set_temperature(decoding.temperature);
+        params.set_temperature_inc(decoding.temperature_inc);
+        params.set_entropy_thold(decoding.entropy_thold);
+        params.set_logprob_thold(decoding.logprob_thold);
+        params.set_no_speech_thold(decoding.no_speech_thold);
+        params.set_suppress_blank(true);
+        params.set_suppress_nst(decoding.suppress_nst);
+        if !prompt.is_empty() {
+            params.set_tokens(&prompt);
+        }
+        params.set_single_segment(!whisper_config.split_segments);
+        params.set_token_timestamps(whisper_config.word_timestamps);
+        params.set_print_realtime(false);
+        params.set_print_progress(false);
+        if let Some(threads) = whisper_config.execution.threads {
+            params.set_n_threads(threads as i32);
+        }
+
+        // Stop early if cancelled or taking too long
+        let deadline = whisper_config
+            .timeout
+            .map(|timeout| Instant::now() + Duration::from_secs_f32(timeout.max(0.0)));
+        let cancel = self.cancel.clone();
+        params.set_abort_callback_safe(move || {
+            cancel.load(Ordering::Relaxed)
+                || deadline.is_some_and(|deadline| Instant::now() > deadline)
+        });
+
+        // Make sure audio is at least 1 second
+        if resampled.len() < MIN_SAMPLES {
+            resampled.resize(MIN_SAMPLES, 0.0);
+        }
 
-        // Construct url
-        let url = format!(
-            "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-{}.bin?download=true",
-            config.model
-        );
+        // Transcribe
+        let result = self.state.full(params, &resampled);
 
-        // Create model file
-        let mut model_file = std::fs::File::create(&model_path)?;
+        // An aborted transcription may be cut short without an error
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(ErrTranscribe::Cancelled);
+        }
+        if let Some(timeout) = whisper_config.timeout
+            && deadline.is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(ErrTranscribe::Timeout(timeout));
+        }
+        result?;
+
+        // Get the language used, which was detected if set to auto
+        let language = whisper_rs::get_lang_str(self.state.full_lang_id_from_state()?)
+            .map(|language| language.to_owned())
+            .or(whisper_config.language.clone());
+        if whisper_config.language.as_deref() == Some("auto") {
+            info!(
+                "Detected language: {}",
+                language.as_deref().unwrap_or("unknown")
+            );
+        }
 
-        // Download model file
-        let mut download = match reqwest::blocking::get(url) {
-            Ok(download) => download,
-            Err(err) => {
-                return Err(ErrSetupWhisper::CouldNotDownloadModel(err));
+        // Get number of output segments
+        let n_segments = self.state.full_n_segments()?;
+        // Create empty result string to fill
+        let mut result = String::new();
+        let mut segments: Vec<Segment> = vec![];
+
+        // Loop through segments
+        for i in 0..n_segments {
+            let text = self.state.full_get_segment_text(i)?;
+
+            // Collect text tokens, skipping special tokens
+            let mut tokens: Vec<i32> = vec![];
+            let mut words: Vec<Word> = vec![];
+            let mut logprob_sum = 0.0;
+            for j in 0..self.state.full_n_tokens(i)? {
+                let token = self.state.full_get_token_data(i, j)?;
+                if token.id < self.ctx.token_eot() {
+                    tokens.push(token.id);
+                    logprob_sum += token.plog;
+
+                    if whisper_config.word_timestamps {
+                        let text = self.state.full_get_token_text_lossy(i, j)?;
+                        // DTW gives the time a token was said, otherwise whisper's estimate is used
+                        let (start, end) = match token.t_dtw {
+                            t_dtw if t_dtw >= 0 => (t_dtw, t_dtw),
+                            _ => (token.t0, token.t1),
+                        };
+                        push_token(&mut words, &text, start, end, token.p);
+                    }
+                }
             }
-        };
 
-        // Copy contents
-        std::io::copy(&mut download, &mut model_file)?;
+            segments.push(Segment {
+                id: i,
+                // Timestamps are in centiseconds
+                start: self.state.full_get_segment_t0(i)? as f32 / 100.0,
+                end: self.state.full_get_segment_t1(i)? as f32 / 100.0,
+                avg_logprob: if tokens.is_empty() {
+                    0.0
+                } else {
+                    logprob_sum / tokens.len() as f32
+                },
+                no_speech_prob: self.state.full_get_segment_no_speech_prob(i)?,
+                text: text.clone(),
+                tokens,
+                words,
+            });
+
+            // Add each segment to the result string
+            result.push_str(text.as_str());
+        }
 
-        info!("Model {} downloaded", config.model);
-    }
+        // Discard empty results
+        if result.trim().is_empty() {
+            return Ok(None);
+        }
 
-    // Create the context and load the model
-    Ok(WhisperContext::new_with_params(
-        &model_path,
-        WhisperContextParameters {
-            use_gpu: true,
-            flash_attn: false,
-            gpu_device: 0,
-            dtw_parameters: DtwParameters::default(),
-        },
-    )?)
-}
-
-// Send audio to whisper for transcribing
-pub fn transcribe(
-    whisper_config: &WhisperConfig,
-    ctx: &WhisperContext,
-    samples: Vec<f32>,
-) -> Result<Option<String>, ErrTranscribe> {
-    let mut resampled = resample(samples, 48000, 16000)?;
-
-    // Whisper parameters
-    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-    params.set_language(whisper_config.language.as_deref());
-    params.set_translate(whisper_config.translate);
-    params.set_no_context(whisper_config.no_context);
-    params.set_single_segment(true);
-    params.set_print_realtime(false);
-    params.set_print_progress(false);
-
-    // Create whisper state
-    let mut state = ctx.create_state()?;
-
-    // Make sure audio is at least 1 second
-    if resampled.len() < 48000 {
-        resampled.resize(48000, 0.0);
-    }
-
-    // Transcribe
-    state.full(params, &resampled)?;
-
-    // Get number of output segments
-    let n_segments = state.full_n_segments()?;
-    // Create empty result string to fill
-    let mut result = String::new();
-
-    // Loop through segments
-    for i in 0..n_segments {
-        // Add each segment to the result string
-        result.push_str(state.full_get_segment_text(i)?.as_str());
-    }
-
-    // Discard empty results
-    if result.trim().is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(result))
+        // Remember what was said for the next utterance
+        if !decoding.no_context {
+            self.context
+                .extend(segments.iter().flat_map(|segment| &segment.tokens));
+            let excess = self.context.len().saturating_sub(MAX_PROMPT);
+            self.context.drain(..excess);
+        }
+
+        Ok(Some(Transcription {
+            text: result,
+            language,
+            duration,
+            segments,
+            speaker: None,
+        }))
     }
 }
@@ -0,0 +1,240 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use log::{info, warn};
+
+use live_translate_rs::{Config, data_dir, models, piper, sound::audio_jack};
+
+#[derive(Debug)]
+pub enum ErrConfigInit {
+    IoError(std::io::Error),
+    JackError(jack::Error),
+    InvalidConfig(toml::de::Error),
+    NoPorts,
+}
+
+impl Display for ErrConfigInit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(io_error) => write!(f, "{}", io_error),
+            Self::JackError(jack_error) => write!(f, "{}", jack_error),
+            Self::InvalidConfig(toml_error) => {
+                write!(f, "Generated config is invalid!\n{}", toml_error)
+            }
+            Self::NoPorts => write!(f, "No audio ports found, is the JACK server running?"),
+        }
+    }
+}
+
+impl std::error::Error for ErrConfigInit {}
+
+impl From<std::io::Error> for ErrConfigInit {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<jack::Error> for ErrConfigInit {
+    fn from(value: jack::Error) -> Self {
+        Self::JackError(value)
+    }
+}
+
+impl From<toml::de::Error> for ErrConfigInit {
+    fn from(value: toml::de::Error) -> Self {
+        Self::InvalidConfig(value)
+    }
+}
+
+// Ask a question, returning the default for an empty answer
+fn prompt(question: &str, default: &str) -> Result<String, std::io::Error> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+// Ask a yes or no question
+fn confirm(question: &str, default: bool) -> Result<bool, std::io::Error> {
+    let answer = prompt(question, if default { "y" } else { "n" })?;
+    Ok(answer.to_lowercase().starts_with('y'))
+}
+
+// Print numbered options, an answer can be numbers or typed values separated by commas
+fn choose(
+    question: &str,
+    options: &[String],
+    default: &str,
+) -> Result<Vec<String>, std::io::Error> {
+    for (i, option) in options.iter().enumerate() {
+        println!("  {:>3}) {}", i + 1, option);
+    }
+
+    let answer = prompt(question, default)?;
+    Ok(answer
+        .split(',')
+        .map(str::trim)
+        .filter(|choice| !choice.is_empty())
+        .map(|choice| match choice.parse::<usize>() {
+            Ok(i) if (1..=options.len()).contains(&i) => options[i - 1].clone(),
+            _ => choice.to_owned(),
+        })
+        .collect())
+}
+
+// Piper voices, downloaded ones first
+fn piper_voices() -> Vec<String> {
+    let mut voices = std::fs::read_dir(data_dir::get())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".onnx"))
+                .map(str::to_owned)
+        })
+        .collect::<Vec<_>>();
+    voices.sort();
+
+    // Ask the voices repository for every voice that can be downloaded
+    match piper::available_voices() {
+        Ok(mut available) => {
+            available.sort();
+            for voice in available {
+                if !voices.contains(&voice) {
+                    voices.push(voice);
+                }
+            }
+        }
+        Err(err) => warn!(
+            "Could not list piper voices, only showing downloaded ones!\n{}",
+            err
+        ),
+    }
+
+    voices
+}
+
+// Quote a string for TOML
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+// Interactively create a config file
+pub fn config_init(path: &Path) -> Result<(), ErrConfigInit> {
+    if path.exists() && !confirm(&format!("{} exists, overwrite?", path.display()), false)? {
+        return Ok(());
+    }
+
+    // Audio ports
+    let (sources, sinks) = audio_jack::list_ports()?;
+    if sources.is_empty() || sinks.is_empty() {
+        return Err(ErrConfigInit::NoPorts);
+    }
+    println!("\nPorts to record from:");
+    let input_port = choose("Input port", &sources, "1")?
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| sources[0].clone());
+    println!("\nPorts to play to:");
+    let output_ports = choose("Output ports, separated by commas", &sinks, "1")?;
+
+    // Speech recognition
+    println!("\nWhisper models:");
+    let models = models::MODELS
+        .iter()
+        .map(|model| {
+            if models::model_path(model).exists() {
+                format!("{} (downloaded)", model)
+            } else {
+                model.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    let model = choose("Model", &models, "large-v3-turbo")?
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(" (downloaded)")
+        .to_owned();
+    let language = prompt(
+        "\nLanguage spoken, e.g. \"de\", or \"auto\" to detect it",
+        "auto",
+    )?;
+    let translate = confirm("Have whisper translate the speech into english?", true)?;
+
+    // Voice, only offering ones for the language spoken back
+    let spoken = if translate { "en" } else { language.as_str() };
+    let voices = piper_voices()
+        .into_iter()
+        .filter(|voice| spoken == "auto" || voice.starts_with(spoken))
+        .collect::<Vec<_>>();
+    println!("\nPiper voices:");
+    let voice = choose("Voice", &voices, voices.first().map_or("", |voice| voice))?
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+
+    let output_ports = output_ports
+        .iter()
+        .map(|port| format!("    {},\n", quote(port)))
+        .collect::<String>();
+    let content = format!(
+        r#"[general]
+push_to_talk = false
+ptt_key = "Delete"
+audio_client = "Jack"
+
+[audio.jack]
+input_port = {}
+output_ports = [
+{}]
+
+[whisper]
+model = {}
+language = {} # Or "auto" to detect the language of each utterance
+translate = {}
+no_context = false
+silence_length = 10
+
+[piper]
+model = {}
+
+[[sinks]]
+type = "Tts"
+"#,
+        quote(&input_port),
+        output_ports,
+        quote(&model),
+        quote(&language),
+        translate,
+        quote(&voice),
+    );
+
+    // Make sure the program will accept what was written
+    toml::from_str::<Config>(&content)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+
+    info!(
+        "Config written to {}, see config.example.toml for more options",
+        path.display()
+    );
+
+    Ok(())
+}